@@ -0,0 +1,45 @@
+"""Generate the CPU-vs-GPU scaling plot from CSV produced by the Rust benchmark.
+
+Pipeline:
+    1. Run the benchmark (writes scripts/cpu_gpu_scaling.csv):
+           cargo test --release -p fluid_engine -- --ignored scaling --nocapture
+    2. Run this script (writes docs/Fluid_Simulation/figures/cpu_gpu_scaling.png):
+           python scripts/plot_cpu_gpu_scaling.py
+"""
+
+import os
+import pandas as pd
+import matplotlib.pyplot as plt
+
+script_dir = os.path.dirname(__file__)
+csv_path = os.path.join(script_dir, 'cpu_gpu_scaling.csv')
+out_dir = os.path.join(script_dir, '..', 'docs', 'Fluid_Simulation', 'figures')
+os.makedirs(out_dir, exist_ok=True)
+out_path = os.path.join(out_dir, 'cpu_gpu_scaling.png')
+
+plt.rcParams.update({
+    'font.size': 12,
+    'font.family': 'serif',
+    'lines.linewidth': 2.0,
+})
+
+df = pd.read_csv(csv_path)
+cpu = df[df['backend'] == 'CPU'].sort_values('n')
+gpu = df[df['backend'] == 'GPU'].sort_values('n')
+
+fig, ax = plt.subplots(figsize=(7, 5))
+
+ax.plot(cpu['n'], cpu['time_ms'], color='black', marker='o', linestyle='-', markersize=7, label='CPU (rayon)')
+ax.plot(gpu['n'], gpu['time_ms'], color='gray', marker='s', linestyle='--', markersize=7, label='GPU (Vulkan)')
+
+ax.set_xlabel('Liczba cząstek $N$')
+ax.set_ylabel('Czas kroku [ms]')
+ax.set_xscale('log')
+ax.set_yscale('log')
+ax.grid(True, which='both', linestyle=':', alpha=0.7)
+ax.legend()
+
+fig.tight_layout()
+plt.savefig(out_path, dpi=300)
+plt.close()
+print(f'wrote: {out_path}')
@@ -8,19 +8,20 @@ layout(buffer_reference, scalar) readonly buffer CameraDataRef {
     mat4 projectionMatrix;
 };
 
-layout(buffer_reference, scalar) readonly buffer BoxVerticesRef {
-    vec3 vertices[8];
+// Box corners (indexed) or obstacle outlines (line list).
+layout(buffer_reference, scalar) readonly buffer LineVerticesRef {
+    vec3 vertices[];
 };
 
 layout(push_constant) uniform PushConstants {
     uint64_t camera_addr;
-    uint64_t box_vertices_addr;
+    uint64_t vertices_addr;
 } push;
 
 void main() {
     CameraDataRef camera = CameraDataRef(push.camera_addr);
-    BoxVerticesRef box = BoxVerticesRef(push.box_vertices_addr);
+    LineVerticesRef lines = LineVerticesRef(push.vertices_addr);
     
-    vec3 pos = box.vertices[gl_VertexIndex];
+    vec3 pos = lines.vertices[gl_VertexIndex];
     gl_Position = camera.projectionMatrix * camera.viewMatrix * vec4(pos, 1.0);
 }
\ No newline at end of file
@@ -0,0 +1,14 @@
+#version 460
+
+layout(location = 0) in vec4 inColor;
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    vec2 coord = gl_PointCoord * 2.0 - 1.0;
+    float r2 = dot(coord, coord);
+    if (r2 > 1.0) discard;
+
+    // Soft edge, so overlapping sprites blend into a continuous froth.
+    float alpha = inColor.a * (1.0 - r2) * (1.0 - r2);
+    f_color = vec4(inColor.rgb, alpha);
+}
@@ -0,0 +1,49 @@
+#version 460
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_scalar_block_layout : require
+#extension GL_EXT_shader_explicit_arithmetic_types_int64 : require
+
+// Point sprites for the spray, foam and bubble particles. The whole ring
+// buffer is drawn; free slots are moved outside the clip volume.
+
+layout(buffer_reference, scalar) readonly buffer CameraDataRef {
+    mat4 view;
+    mat4 proj;
+    mat4 inv_view_proj;
+    vec3 camera_pos;
+};
+
+layout(location = 0) in vec4 position;
+layout(location = 1) in vec4 velocity;
+
+layout(location = 0) out vec4 outColor;
+
+layout(push_constant) uniform PushConstants {
+    uint64_t camera_addr;
+    float particle_radius;
+    float viewport_height;
+} push;
+
+void main() {
+    if (position.w <= 0.0) {
+        gl_Position = vec4(2.0, 2.0, 2.0, 1.0);
+        gl_PointSize = 1.0;
+        outColor = vec4(0.0);
+        return;
+    }
+
+    CameraDataRef camera = CameraDataRef(push.camera_addr);
+
+    vec4 viewPos = camera.view * vec4(position.xyz, 1.0);
+    gl_Position = camera.proj * viewPos;
+
+    // velocity.w: 0 spray, 1 foam, 2 bubble. Foam fades out over its last second.
+    float kind = velocity.w;
+    float fade = clamp(position.w, 0.0, 1.0);
+    float size = kind > 1.5 ? 0.6 : (kind > 0.5 ? 0.8 : 0.5);
+    outColor = kind > 1.5
+        ? vec4(0.75, 0.9, 1.0, 0.35 * fade)
+        : vec4(1.0, 1.0, 1.0, (kind > 0.5 ? 0.8 : 0.6) * fade);
+
+    gl_PointSize = size * push.particle_radius * camera.proj[1][1] * push.viewport_height / max(viewPos.z, 1e-3);
+}
@@ -0,0 +1,69 @@
+#ifndef BOUNDARY_GLSL
+#define BOUNDARY_GLSL
+
+// Akinci boundary particles: position in xyz, ψ = ρ₀ · volume in w. Sorted by
+// cell on the CPU (`BoundaryParticles`) into two dense grids over their
+// bounding boxes, so the 27-cell walk never visits a cell twice. The walls
+// are stored in the box frame, as sampled at `lo..hi`, and follow the box
+// here; mesh samples are static and stored in world space. Boundary particles
+// carry no velocity or pressure acceleration of their own. Expects
+// common.glsl (sim_params) to be included first.
+
+#define BOUNDARY_WALLS 0u
+#define BOUNDARY_STATIC 1u
+
+// Mirrors `GpuBoundaryGrid` in particle.rs.
+struct BoundaryGrid {
+    ivec4 origin; // w: first cell in boundary_cell_ranges
+    ivec4 dims;
+    vec4 lo;      // w: cell size
+    vec4 hi;
+};
+
+layout(std430, set = 0, binding = 9) readonly buffer BoundaryParticles { vec4 boundary_particles[]; };
+layout(std430, set = 0, binding = 10) readonly buffer BoundaryCells { uvec2 boundary_cell_ranges[]; };
+layout(std430, set = 0, binding = 11) readonly buffer BoundaryGrids { BoundaryGrid boundary_grids[2]; };
+
+// The layer the walls are sampled as in the current box, one particle radius
+// outside its extents.
+void boundary_wall_layer(out vec3 lo, out vec3 hi) {
+    lo = sim_params.box_min.xyz - sim_params.particle_radius;
+    hi = sim_params.box_max.xyz + sim_params.particle_radius;
+}
+
+// Cell of the world position `pos` in grid `g`. For the walls, `pos` is taken
+// into the box frame and mapped from the current layer onto the sampled one.
+ivec3 boundary_cell(uint g, vec3 pos) {
+    BoundaryGrid grid = boundary_grids[g];
+    if (g == BOUNDARY_WALLS) {
+        vec3 lo, hi;
+        boundary_wall_layer(lo, hi);
+        pos = grid.lo.xyz + (to_box_frame(pos) - lo) * (grid.hi.xyz - grid.lo.xyz) / (hi - lo);
+    }
+    return ivec3(floor(pos / grid.lo.w));
+}
+
+// [start, end) of the boundary particles in `cell` of grid `g`; empty outside
+// the grid.
+uvec2 boundary_cell_range(uint g, ivec3 cell) {
+    BoundaryGrid grid = boundary_grids[g];
+    ivec3 c = cell - grid.origin.xyz;
+    if (any(lessThan(c, ivec3(0))) || any(greaterThanEqual(c, grid.dims.xyz))) return uvec2(0);
+    return boundary_cell_ranges[grid.origin.w + (c.z * grid.dims.y + c.y) * grid.dims.x + c.x];
+}
+
+// World position of boundary particle `b` of grid `g`. A wall sample moves
+// with its wall: each axis of the sampled layer is mapped onto the current
+// one, then turned with the box.
+vec3 boundary_position(uint g, uint b) {
+    vec3 p = boundary_particles[b].xyz;
+    if (g == BOUNDARY_WALLS) {
+        BoundaryGrid grid = boundary_grids[g];
+        vec3 lo, hi;
+        boundary_wall_layer(lo, hi);
+        p = from_box_frame(lo + (p - grid.lo.xyz) * (hi - lo) / (grid.hi.xyz - grid.lo.xyz));
+    }
+    return p;
+}
+
+#endif
@@ -8,6 +8,18 @@ struct Entry {
     uint index;
 };
 
+// Mirrors `GpuPhase` in particle.rs. Row 0 is the base fluid, whose
+// properties are the globals below; only its colour comes from the table.
+#define MAX_PHASES 8
+
+struct Phase {
+    vec4 color;
+    float rest_density;
+    float viscosity;
+    float surface_tension;
+    float _pad;
+};
+
 layout(std140, set = 0, binding = 2) uniform SimulationParams {
     float particle_radius;
     float particle_mass;
@@ -18,15 +30,111 @@ layout(std140, set = 0, binding = 2) uniform SimulationParams {
     float dt;
     uint density_iterations;
     uint divergence_iterations;
-    float _pad0;
-    float _pad1;
-    float _pad2;
+    float surface_tension;
+    float adhesion;
+    float vorticity_confinement;
+    uint kernel;                // KERNEL_*
+    uint viscosity_mode;        // VISCOSITY_*
+    uint viscosity_iterations;
+    float viscosity_tolerance;
+    uint pressure_solver;       // `PressureSolver`; picks the passes on the CPU
+    float stiffness;            // WCSPH Tait B [Pa]
+    uint backend;               // `SimulationBackend`; BACKEND_*
+    uint grid_transfer;         // TRANSFER_* in flip.glsl
+    float flip_ratio;
+    uint grid_iterations;
+    float max_density_error;    // η, fraction of the rest density
+    float max_divergence_error; // η_v, fraction of the rest density per second
     vec4 gravity;
     vec4 box_min;
     vec4 box_max;
     ivec4 grid_res;
+    vec4 box_rotation;          // quaternion xyzw, about the box centre
+    vec4 box_velocity;
+    vec4 box_angular_velocity;
+    vec4 box_growth;
+    Phase phases[MAX_PHASES];
 } sim_params;
 
+// Mirrors `SimulationBackend` in particle.rs.
+#define BACKEND_SPH 0u
+#define BACKEND_FLIP 1u
+
+// Mirrors `ViscosityMode` in particle.rs. In implicit mode the viscosities
+// are kinematic [m²/s] and the viscosity_cg passes solve for them.
+#define VISCOSITY_EXPLICIT 0u
+#define VISCOSITY_IMPLICIT 1u
+
+// Mirrors `ParticleCounter` in particle.rs. Only the first `num_particles`
+// entries of the per-particle buffers are live; the rest is spare capacity.
+// Scalars instead of uvec3/uvec4 keep the std430 layout identical to the
+// indirect command structs.
+layout(std430, set = 0, binding = 12) buffer ParticleCounter {
+    uint num_particles;
+    uint num_removed;
+    uint dispatch_x;
+    uint dispatch_y;
+    uint dispatch_z;
+    uint draw_vertex_count;
+    uint draw_instance_count;
+    uint draw_first_vertex;
+    uint draw_first_instance;
+} counter;
+
+// Live particles store phase + 1 in position.w; dead ones have w = 0.
+uint particle_phase(vec4 position) {
+    return uint(max(position.w - 1.0, 0.0) + 0.5);
+}
+
+float phase_rest_density(uint phase) {
+    return phase == 0u ? sim_params.target_density : sim_params.phases[phase].rest_density;
+}
+
+// Every particle fills the same volume, so the mass scales with the rest density.
+float phase_mass(uint phase) {
+    return sim_params.particle_mass * phase_rest_density(phase) / sim_params.target_density;
+}
+
+float phase_viscosity(uint phase) {
+    return phase == 0u ? sim_params.viscosity : sim_params.phases[phase].viscosity;
+}
+
+float phase_surface_tension(uint phase) {
+    return phase == 0u ? sim_params.surface_tension : sim_params.phases[phase].surface_tension;
+}
+
+vec3 quat_rotate(vec4 q, vec3 v) {
+    vec3 t = 2.0 * cross(q.xyz, v);
+    return v + q.w * t + cross(q.xyz, t);
+}
+
+vec3 box_centre() {
+    return 0.5 * (sim_params.box_min.xyz + sim_params.box_max.xyz);
+}
+
+// World position to the frame of the collision box, where its walls are the
+// axis-aligned planes of box_min / box_max, and back.
+vec3 to_box_frame(vec3 p) {
+    vec4 q = sim_params.box_rotation;
+    return box_centre() + quat_rotate(vec4(-q.xyz, q.w), p - box_centre());
+}
+
+vec3 from_box_frame(vec3 p) {
+    return box_centre() + quat_rotate(sim_params.box_rotation, p - box_centre());
+}
+
+// World-space bounds of the rotated box, spanned by the density volume;
+// `SimulationParams::domain` on the CPU.
+void domain_bounds(out vec3 lo, out vec3 hi) {
+    vec3 half_size = 0.5 * (sim_params.box_max.xyz - sim_params.box_min.xyz);
+    vec4 q = sim_params.box_rotation;
+    vec3 half_bounds = abs(quat_rotate(q, vec3(half_size.x, 0.0, 0.0)))
+        + abs(quat_rotate(q, vec3(0.0, half_size.y, 0.0)))
+        + abs(quat_rotate(q, vec3(0.0, 0.0, half_size.z)));
+    lo = box_centre() - half_bounds;
+    hi = box_centre() + half_bounds;
+}
+
 uint get_cell_hash(ivec3 grid_pos, uint table_size) {
     uint p1 = 73856093;
     uint p2 = 19349663;
@@ -34,11 +142,15 @@ uint get_cell_hash(ivec3 grid_pos, uint table_size) {
     return (uint(grid_pos.x) * p1 ^ uint(grid_pos.y) * p2 ^ uint(grid_pos.z) * p3) % table_size;
 }
 
-// --- CUBIC SPLINE ---
-float kernel_w(float r, float h) {
-    float q = r / h;
-    if (q >= 1.0) return 0.0;
+// Mirrors `SphKernel` in particle.rs; selected by sim_params.kernel. Every
+// kernel has compact support h, and kernel_grad returns ∇W for the same pair.
+#define KERNEL_CUBIC_SPLINE 0u
+#define KERNEL_WENDLAND_C2 1u
+#define KERNEL_WENDLAND_C4 2u
+#define KERNEL_POLY6_SPIKY 3u
 
+// --- CUBIC SPLINE ---
+float cubic_spline_w(float q, float h) {
     float h3 = h * h * h;
     float k = 8.0 / (PI * h3);
 
@@ -53,23 +165,107 @@ float kernel_w(float r, float h) {
     }
 }
 
-vec3 kernel_grad(vec3 r_vec, float r, float h) {
-    float q = r / h;
-    if (q >= 1.0 || r < 1e-6) return vec3(0.0);
-
+float cubic_spline_dw(float q, float h) {
     float h3 = h * h * h;
     float k = 8.0 / (PI * h3);
 
-    float grad_factor;
-
     if (q <= 0.5) {
-        grad_factor = k * (18.0 * q * q - 12.0 * q);
+        return k * (18.0 * q * q - 12.0 * q);
     } else {
         float one_minus_q = 1.0 - q;
-        grad_factor = k * -6.0 * one_minus_q * one_minus_q;
+        return k * -6.0 * one_minus_q * one_minus_q;
+    }
+}
+
+// --- WENDLAND C2 / C4 (Dehnen & Aly 2012) ---
+float wendland_c2_w(float q, float h) {
+    float k = 21.0 / (2.0 * PI * h * h * h);
+    float one_minus_q = 1.0 - q;
+    float one_minus_q_2 = one_minus_q * one_minus_q;
+    return k * one_minus_q_2 * one_minus_q_2 * (1.0 + 4.0 * q);
+}
+
+float wendland_c2_dw(float q, float h) {
+    float k = 21.0 / (2.0 * PI * h * h * h);
+    float one_minus_q = 1.0 - q;
+    return k * -20.0 * q * one_minus_q * one_minus_q * one_minus_q;
+}
+
+float wendland_c4_w(float q, float h) {
+    float k = 495.0 / (32.0 * PI * h * h * h);
+    float one_minus_q = 1.0 - q;
+    float one_minus_q_2 = one_minus_q * one_minus_q;
+    return k * one_minus_q_2 * one_minus_q_2 * one_minus_q_2 * (1.0 + 6.0 * q + 35.0 / 3.0 * q * q);
+}
+
+float wendland_c4_dw(float q, float h) {
+    float k = 495.0 / (32.0 * PI * h * h * h);
+    float one_minus_q = 1.0 - q;
+    float one_minus_q_2 = one_minus_q * one_minus_q;
+    return k * -56.0 / 3.0 * q * one_minus_q_2 * one_minus_q_2 * one_minus_q * (1.0 + 5.0 * q);
+}
+
+// --- POLY6 / SPIKY (Müller et al. 2003) ---
+float poly6_w(float q, float h) {
+    float k = 315.0 / (64.0 * PI * h * h * h);
+    float one_minus_q2 = 1.0 - q * q;
+    return k * one_minus_q2 * one_minus_q2 * one_minus_q2;
+}
+
+float spiky_dw(float q, float h) {
+    float k = 15.0 / (PI * h * h * h);
+    float one_minus_q = 1.0 - q;
+    return k * -3.0 * one_minus_q * one_minus_q;
+}
+
+float kernel_w(float r, float h) {
+    float q = r / h;
+    if (q >= 1.0) return 0.0;
+
+    switch (sim_params.kernel) {
+        case KERNEL_WENDLAND_C2: return wendland_c2_w(q, h);
+        case KERNEL_WENDLAND_C4: return wendland_c4_w(q, h);
+        case KERNEL_POLY6_SPIKY: return poly6_w(q, h);
+        default: return cubic_spline_w(q, h);
+    }
+}
+
+vec3 kernel_grad(vec3 r_vec, float r, float h) {
+    float q = r / h;
+    if (q >= 1.0 || r < 1e-6) return vec3(0.0);
+
+    // dW/dq; the chain rule adds 1 / h and r_vec / r the direction.
+    float grad_factor;
+    switch (sim_params.kernel) {
+        case KERNEL_WENDLAND_C2: grad_factor = wendland_c2_dw(q, h); break;
+        case KERNEL_WENDLAND_C4: grad_factor = wendland_c4_dw(q, h); break;
+        case KERNEL_POLY6_SPIKY: grad_factor = spiky_dw(q, h); break;
+        default: grad_factor = cubic_spline_dw(q, h); break;
     }
 
     return (grad_factor / (h * r)) * r_vec;
 }
 
+// --- AKINCI 2013 COHESION / ADHESION SPLINES ---
+float cohesion_kernel(float r, float h) {
+    if (r > h || r <= 0.0) return 0.0;
+
+    float k = 32.0 / (PI * pow(h, 9.0));
+    float spline = (h - r) * (h - r) * (h - r) * r * r * r;
+
+    if (2.0 * r > h) {
+        return k * spline;
+    } else {
+        float h3 = h * h * h;
+        return k * (2.0 * spline - h3 * h3 / 64.0);
+    }
+}
+
+float adhesion_kernel(float r, float h) {
+    if (r > h || 2.0 * r <= h) return 0.0;
+
+    float k = 0.007 / pow(h, 3.25);
+    return k * pow(max(-4.0 * r * r / h + 6.0 * r - 2.0 * h, 0.0), 0.25);
+}
+
 #endif
\ No newline at end of file
@@ -0,0 +1,70 @@
+#ifndef DENSITY_VOLUME_GLSL
+#define DENSITY_VOLUME_GLSL
+
+// Sampling of the splatted density texture, for the render modes that find
+// the surface in the volume. Expects common.glsl (sim_params) and
+// water_shading.glsl to be included first.
+
+layout(set = 0, binding = 0) uniform usampler3D densityTex;
+
+const float DENSITY_THRESHOLD  = 0.01;
+const float DENSITY_OFFSET     = 300.0;
+const float DENSITY_MULTIPLIER = 1.0;
+
+vec2 intersectAABB(vec3 ro, vec3 rd, vec3 bMin, vec3 bMax) {
+    vec3 t1 = (bMin - ro) / rd;
+    vec3 t2 = (bMax - ro) / rd;
+    return vec2(max(max(min(t1.x,t2.x), min(t1.y,t2.y)), min(t1.z,t2.z)),
+    min(min(max(t1.x,t2.x), max(t1.y,t2.y)), max(t1.z,t2.z)));
+}
+
+float getDensity(vec3 worldPos, vec3 boxMin, vec3 boxMax) {
+    vec3 uvw = (worldPos - boxMin) / (boxMax - boxMin);
+    if (any(lessThan(uvw, vec3(0.0))) || any(greaterThan(uvw, vec3(1.0)))) return 0.0;
+
+    vec3 gridPos = uvw * vec3(sim_params.grid_res.xyz) - 0.5;
+    ivec3 iobase = ivec3(floor(gridPos));
+    vec3  f      = fract(gridPos);
+
+    float v000 = float(texelFetch(densityTex, clamp(iobase + ivec3(0,0,0), ivec3(0), ivec3(sim_params.grid_res.xyz)-1), 0).r);
+    float v100 = float(texelFetch(densityTex, clamp(iobase + ivec3(1,0,0), ivec3(0), ivec3(sim_params.grid_res.xyz)-1), 0).r);
+    float v010 = float(texelFetch(densityTex, clamp(iobase + ivec3(0,1,0), ivec3(0), ivec3(sim_params.grid_res.xyz)-1), 0).r);
+    float v110 = float(texelFetch(densityTex, clamp(iobase + ivec3(1,1,0), ivec3(0), ivec3(sim_params.grid_res.xyz)-1), 0).r);
+    float v001 = float(texelFetch(densityTex, clamp(iobase + ivec3(0,0,1), ivec3(0), ivec3(sim_params.grid_res.xyz)-1), 0).r);
+    float v101 = float(texelFetch(densityTex, clamp(iobase + ivec3(1,0,1), ivec3(0), ivec3(sim_params.grid_res.xyz)-1), 0).r);
+    float v011 = float(texelFetch(densityTex, clamp(iobase + ivec3(0,1,1), ivec3(0), ivec3(sim_params.grid_res.xyz)-1), 0).r);
+    float v111 = float(texelFetch(densityTex, clamp(iobase + ivec3(1,1,1), ivec3(0), ivec3(sim_params.grid_res.xyz)-1), 0).r);
+
+    float v0 = mix(mix(v000, v100, f.x), mix(v010, v110, f.x), f.y);
+    float v1 = mix(mix(v001, v101, f.x), mix(v011, v111, f.x), f.y);
+    return max(0.0, (mix(v0, v1, f.z) - DENSITY_OFFSET) * DENSITY_MULTIPLIER);
+}
+
+vec3 calcNormal(vec3 p, vec3 bMin, vec3 bMax) {
+    float eps = 0.018;
+    vec2  h   = vec2(eps, 0.0);
+    return normalize(vec3(
+                     getDensity(p + h.xyy, bMin, bMax) - getDensity(p - h.xyy, bMin, bMax),
+                     getDensity(p + h.yxy, bMin, bMax) - getDensity(p - h.yxy, bMin, bMax),
+                     getDensity(p + h.yyx, bMin, bMax) - getDensity(p - h.yyx, bMin, bMax)
+                     ));
+}
+
+float calcThickness(vec3 startP, vec3 dir, float tMax, vec3 bMin, vec3 bMax) {
+    float thickness = 0.0;
+    float dStep = 0.015;
+    float t = 0.0;
+
+    for (int i = 0; i < 64 && t < tMax; i++, t += dStep) {
+        if (getDensity(startP + dir * t, bMin, bMax) > DENSITY_THRESHOLD)
+        thickness += dStep;
+    }
+    return thickness;
+}
+
+// `tExit` is the distance left along the ray to the far side of the box.
+vec3 shadeWater(vec3 surfacePos, vec3 rayDir, vec3 N, float tExit, vec3 boxMin, vec3 boxMax) {
+    return shadeWaterSurface(rayDir, N, calcThickness(surfacePos, rayDir, tExit, boxMin, boxMax));
+}
+
+#endif
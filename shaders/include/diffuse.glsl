@@ -0,0 +1,56 @@
+#ifndef DIFFUSE_GLSL
+#define DIFFUSE_GLSL
+
+// Spray, foam and bubbles (Ihmsen et al. 2012). Mirrors entities/diffuse.rs.
+
+#define DIFFUSE_SPRAY 0.0
+#define DIFFUSE_FOAM 1.0
+#define DIFFUSE_BUBBLE 2.0
+
+// position.w is the remaining lifetime, <= 0 for a free slot; velocity.w the kind.
+struct DiffuseParticle {
+    vec4 position;
+    vec4 velocity;
+};
+
+layout(std140, set = 0, binding = 9) uniform DiffuseParams {
+    float trapped_air_min;
+    float trapped_air_max;
+    float wave_crest_min;
+    float wave_crest_max;
+    float kinetic_energy_min;
+    float kinetic_energy_max;
+    float trapped_air_rate;
+    float wave_crest_rate;
+    float lifetime;
+    float buoyancy;
+    float drag;
+    uint spray_neighbors;
+    uint bubble_neighbors;
+} diffuse_params;
+
+layout(push_constant) uniform DiffusePushConstants {
+    float dt;
+    uint seed;
+} push;
+
+// Potential clamped to [min, max] and mapped to [0, 1];
+// `DiffuseParams::clamp_potential` on the CPU.
+float clamp_potential(float value, float lo, float hi) {
+    return (min(value, hi) - min(value, lo)) / (hi - lo);
+}
+
+// PCG hash, one well-mixed uint per call.
+uint pcg_hash(uint v) {
+    uint state = v * 747796405u + 2891336453u;
+    uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Uniform float in [0, 1), advancing `state`.
+float random(inout uint state) {
+    state = pcg_hash(state);
+    return float(state >> 8) / 16777216.0;
+}
+
+#endif
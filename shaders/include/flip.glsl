@@ -0,0 +1,132 @@
+#ifndef FLIP_GLSL
+#define FLIP_GLSL
+
+// Shared by flip_p2g.comp, flip_grid.comp and flip_g2p.comp, the FLIP/PIC/APIC
+// backend. The MAC grid spans the box in its own frame with `dims` cells. Node
+// (i, j, k) holds the three faces at the cell's min corner: u at
+// (i, j + ½, k + ½), v at (i + ½, j, k + ½) and w at (i + ½, j + ½, k), in
+// cell units from box_min. There are dims + 1 nodes per axis, so every face
+// exists, plus a few past the max walls that no cell owns.
+
+// Mirrors `GridTransfer` in particle.rs.
+#define TRANSFER_PIC 0u
+#define TRANSFER_FLIP 1u
+#define TRANSFER_APIC 2u
+
+// The particle-to-grid sums are fixed-point so that atomicAdd on ints can
+// accumulate them; `FLIP_TRANSFER_SCALE` on the CPU.
+#define FLIP_TRANSFER_SCALE 65536.0
+
+#define CELL_AIR 0u
+#define CELL_FLUID 1u
+
+// Stages of flip_grid.comp.
+#define GRID_NORMALIZE 0u       // per node: velocities from the sums, walls
+#define GRID_DIVERGENCE 1u      // per cell
+#define GRID_JACOBI 2u          // per cell, from half `iteration & 1` into the other
+#define GRID_PROJECT 3u         // per node, with the pressure of `iteration` sweeps
+
+// Mirrors `FlipPushConstants` in flip.rs.
+layout(push_constant) uniform FlipPushConstants {
+    ivec4 dims;                 // cells per axis; `SimulationParams::flip_grid_dims`
+    uint stage;                 // GRID_*, flip_grid.comp only
+    uint iteration;
+} flip;
+
+// `SimulationParams::flip_cell_size`.
+vec3 cell_size() {
+    return (sim_params.box_max.xyz - sim_params.box_min.xyz) / vec3(flip.dims.xyz);
+}
+
+uint cell_count() {
+    return uint(flip.dims.x * flip.dims.y * flip.dims.z);
+}
+
+uint node_count() {
+    ivec3 n = flip.dims.xyz + 1;
+    return uint(n.x * n.y * n.z);
+}
+
+bool in_cells(ivec3 c) {
+    return all(greaterThanEqual(c, ivec3(0))) && all(lessThan(c, flip.dims.xyz));
+}
+
+bool in_nodes(ivec3 n) {
+    return all(greaterThanEqual(n, ivec3(0))) && all(lessThanEqual(n, flip.dims.xyz));
+}
+
+uint cell_index(ivec3 c) {
+    return uint((c.z * flip.dims.y + c.y) * flip.dims.x + c.x);
+}
+
+uint node_index(ivec3 n) {
+    ivec3 d = flip.dims.xyz + 1;
+    return uint((n.z * d.y + n.y) * d.x + n.x);
+}
+
+ivec3 cell_coords(uint k) {
+    ivec3 d = flip.dims.xyz;
+    return ivec3(int(k) % d.x, (int(k) / d.x) % d.y, int(k) / (d.x * d.y));
+}
+
+ivec3 node_coords(uint k) {
+    ivec3 d = flip.dims.xyz + 1;
+    return ivec3(int(k) % d.x, (int(k) / d.x) % d.y, int(k) / (d.x * d.y));
+}
+
+ivec3 unit_axis(int axis) {
+    ivec3 e = ivec3(0);
+    e[axis] = 1;
+    return e;
+}
+
+// Whether node `n` holds a face of `axis` that lies on the box or in it.
+bool is_face(ivec3 n, int axis) {
+    for (int b = 0; b < 3; b++) {
+        if (b != axis && n[b] >= flip.dims[b]) return false;
+    }
+    return true;
+}
+
+// World vectors to the box's axes and back, for velocities.
+vec3 to_box_axes(vec3 v) {
+    vec4 q = sim_params.box_rotation;
+    return quat_rotate(vec4(-q.xyz, q.w), v);
+}
+
+vec3 from_box_axes(vec3 v) {
+    return quat_rotate(sim_params.box_rotation, v);
+}
+
+// Cell of a box-frame position, clamped onto the grid.
+ivec3 particle_cell(vec3 local) {
+    ivec3 c = ivec3(floor((local - sim_params.box_min.xyz) / cell_size()));
+    return clamp(c, ivec3(0), flip.dims.xyz - 1);
+}
+
+// Trilinear stencil of the `axis` faces around a box-frame position: the
+// lower corner node and the position within the stencil, in cell units.
+void face_stencil(vec3 local, int axis, out ivec3 base, out vec3 frac) {
+    vec3 t = (local - sim_params.box_min.xyz) / cell_size() - 0.5;
+    t[axis] += 0.5;
+    base = ivec3(floor(t));
+    frac = t - vec3(base);
+}
+
+ivec3 stencil_corner(int corner) {
+    return ivec3(corner & 1, (corner >> 1) & 1, corner >> 2);
+}
+
+float stencil_weight(vec3 frac, ivec3 o) {
+    vec3 w = mix(1.0 - frac, frac, vec3(o));
+    return w.x * w.y * w.z;
+}
+
+// Gradient of `stencil_weight` with respect to the particle position.
+vec3 stencil_weight_gradient(vec3 frac, ivec3 o, vec3 cell) {
+    vec3 w = mix(1.0 - frac, frac, vec3(o));
+    vec3 dw = mix(vec3(-1.0), vec3(1.0), vec3(o)) / cell;
+    return vec3(dw.x * w.y * w.z, w.x * dw.y * w.z, w.x * w.y * dw.z);
+}
+
+#endif
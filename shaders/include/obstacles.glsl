@@ -0,0 +1,97 @@
+#ifndef OBSTACLES_GLSL
+#define OBSTACLES_GLSL
+
+// Shape ids, see `OBSTACLE_*` in src/entities/obstacle.rs.
+#define OBSTACLE_NONE 0
+#define OBSTACLE_SPHERE 1
+#define OBSTACLE_CAPSULE 2
+#define OBSTACLE_BOX 3
+#define OBSTACLE_MESH 4
+
+#define OBSTACLE_NORMAL_EPS 1e-3
+
+// Fixed-point scale of the impulses handed to obstacles, see
+// `OBSTACLE_IMPULSE_SCALE`: 1 unit = 1 µN·s.
+#define OBSTACLE_IMPULSE_SCALE 1e6
+
+// Mirrors `GpuObstacle`.
+struct Obstacle {
+    vec4 position;
+    vec4 rotation;          // quaternion xyzw
+    vec4 extent;            // sphere: r | capsule: r, half height | box: half extents | mesh: SDF origin, cell size
+    vec4 angular_velocity;
+    vec4 velocity;
+    ivec4 shape;            // x: shape id, y: offset into sdf_values
+    ivec4 sdf_dims;
+};
+
+layout(std430, set = 0, binding = 5) readonly buffer Obstacles { Obstacle obstacles[]; };
+layout(std430, set = 0, binding = 6) readonly buffer ObstacleSdf { float sdf_values[]; };
+
+float sdf_node(Obstacle o, ivec3 n) {
+    ivec3 dims = o.sdf_dims.xyz;
+    return sdf_values[o.shape.y + (n.z * dims.y + n.y) * dims.x + n.x];
+}
+
+// Trilinear lookup; outside the grid the distance to its bounds is added.
+float mesh_distance(Obstacle o, vec3 q) {
+    vec3 origin = o.extent.xyz;
+    float cell = o.extent.w;
+    vec3 c = clamp(q, origin, origin + vec3(o.sdf_dims.xyz - 1) * cell);
+    vec3 g = (c - origin) / cell;
+    ivec3 i0 = clamp(ivec3(floor(g)), ivec3(0), o.sdf_dims.xyz - 2);
+    vec3 t = g - vec3(i0);
+
+    float x00 = mix(sdf_node(o, i0 + ivec3(0, 0, 0)), sdf_node(o, i0 + ivec3(1, 0, 0)), t.x);
+    float x10 = mix(sdf_node(o, i0 + ivec3(0, 1, 0)), sdf_node(o, i0 + ivec3(1, 1, 0)), t.x);
+    float x01 = mix(sdf_node(o, i0 + ivec3(0, 0, 1)), sdf_node(o, i0 + ivec3(1, 0, 1)), t.x);
+    float x11 = mix(sdf_node(o, i0 + ivec3(0, 1, 1)), sdf_node(o, i0 + ivec3(1, 1, 1)), t.x);
+    float value = mix(mix(x00, x10, t.y), mix(x01, x11, t.y), t.z);
+
+    return value + length(q - c);
+}
+
+// Signed distance from a point in the obstacle's local frame.
+float obstacle_local_distance(Obstacle o, vec3 q) {
+    switch (o.shape.x) {
+        case OBSTACLE_SPHERE:
+            return length(q) - o.extent.x;
+        case OBSTACLE_CAPSULE:
+            return length(q - vec3(0.0, clamp(q.y, -o.extent.y, o.extent.y), 0.0)) - o.extent.x;
+        case OBSTACLE_BOX: {
+            vec3 d = abs(q) - o.extent.xyz;
+            return length(max(d, vec3(0.0))) + min(max(d.x, max(d.y, d.z)), 0.0);
+        }
+        case OBSTACLE_MESH:
+            return mesh_distance(o, q);
+        default:
+            return 3.4e38;
+    }
+}
+
+vec3 obstacle_to_local(Obstacle o, vec3 p) {
+    return quat_rotate(vec4(-o.rotation.xyz, o.rotation.w), p - o.position.xyz);
+}
+
+float obstacle_distance(Obstacle o, vec3 p) {
+    return obstacle_local_distance(o, obstacle_to_local(o, p));
+}
+
+// Outward normal from central differences of the distance.
+vec3 obstacle_normal(Obstacle o, vec3 p) {
+    vec3 q = obstacle_to_local(o, p);
+    vec3 e = vec3(OBSTACLE_NORMAL_EPS, 0.0, 0.0);
+    vec3 gradient = vec3(
+        obstacle_local_distance(o, q + e.xyz) - obstacle_local_distance(o, q - e.xyz),
+        obstacle_local_distance(o, q + e.yxz) - obstacle_local_distance(o, q - e.yxz),
+        obstacle_local_distance(o, q + e.zyx) - obstacle_local_distance(o, q - e.zyx)
+    );
+    float len = length(gradient);
+    return quat_rotate(o.rotation, len > 0.0 ? gradient / len : vec3(0.0, 1.0, 0.0));
+}
+
+vec3 obstacle_velocity(Obstacle o, vec3 p) {
+    return o.velocity.xyz + cross(o.angular_velocity.xyz, p - o.position.xyz);
+}
+
+#endif
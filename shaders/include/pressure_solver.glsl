@@ -0,0 +1,38 @@
+#ifndef PRESSURE_SOLVER_GLSL
+#define PRESSURE_SOLVER_GLSL
+
+// Shared by pressure_update.comp and pressure_convergence.comp, which stop
+// the DFSPH density and divergence solves once the average error is below η
+// (Bender & Koschier 2015), without a round trip to the CPU.
+
+// One partial sum of the compression residual per workgroup of
+// pressure_update.comp.
+layout(std430, set = 0, binding = 13) buffer PressurePartials { float pressure_partials[]; };
+
+// Stages of pressure_convergence.comp.
+#define CHECK_BEGIN 0u          // before the first iteration of a solve
+#define CHECK_ITERATION 1u      // after every pressure_update.comp
+
+// Mirrors `PressureSolve` in particle.rs.
+#define SOLVE_DENSITY 0u
+#define SOLVE_DIVERGENCE 1u
+
+#define PRESSURE_GROUP_SIZE 256
+
+shared float pressure_scratch[PRESSURE_GROUP_SIZE];
+
+// Tree reduction over the workgroup; every invocation must call it.
+float pressure_workgroup_sum(float value) {
+    uint lane = gl_LocalInvocationID.x;
+    pressure_scratch[lane] = value;
+    barrier();
+    for (uint stride = PRESSURE_GROUP_SIZE / 2u; stride > 0u; stride /= 2u) {
+        if (lane < stride) {
+            pressure_scratch[lane] += pressure_scratch[lane + stride];
+        }
+        barrier();
+    }
+    return pressure_scratch[0];
+}
+
+#endif
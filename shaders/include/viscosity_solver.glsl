@@ -0,0 +1,54 @@
+#ifndef VISCOSITY_SOLVER_GLSL
+#define VISCOSITY_SOLVER_GLSL
+
+// Shared by viscosity_cg.comp and viscosity_cg_reduce.comp, which together
+// run a Jacobi-preconditioned conjugate gradient on the implicit viscosity
+// system of Weiler et al. 2018.
+
+// Mirrors `ViscositySolverState` in particle.rs.
+layout(std430, set = 0, binding = 11) buffer ViscositySolver {
+    float rz;
+    float alpha;
+    float beta;
+    float rhs_norm2;
+    float residual_norm2;
+    uint iterations;
+    uint converged;
+    uint _pad;
+} solver;
+
+// One vec4 per workgroup of viscosity_cg.comp; which dot products the
+// components hold depends on the stage that wrote them.
+layout(std430, set = 0, binding = 10) buffer Partials { vec4 partials[]; };
+
+// Stages of viscosity_cg.comp.
+#define CG_INIT 0u          // diagonal, r = b - A x, p = z; partials (rz, rr, bb)
+#define CG_APPLY 1u         // Ap; partials (pAp)
+#define CG_UPDATE 2u        // x += alpha p, r -= alpha Ap; partials (rz, rr)
+#define CG_DIRECTION 3u     // p = z + beta p
+
+// Stages of viscosity_cg_reduce.comp, each following the viscosity_cg.comp
+// stage it sums up.
+#define REDUCE_INIT 0u
+#define REDUCE_ALPHA 1u
+#define REDUCE_BETA 2u
+
+#define CG_GROUP_SIZE 256
+
+shared vec4 cg_scratch[CG_GROUP_SIZE];
+
+// Tree reduction over the workgroup; every invocation must call it.
+vec4 cg_workgroup_sum(vec4 value) {
+    uint lane = gl_LocalInvocationID.x;
+    cg_scratch[lane] = value;
+    barrier();
+    for (uint stride = CG_GROUP_SIZE / 2u; stride > 0u; stride /= 2u) {
+        if (lane < stride) {
+            cg_scratch[lane] += cg_scratch[lane + stride];
+        }
+        barrier();
+    }
+    return cg_scratch[0];
+}
+
+#endif
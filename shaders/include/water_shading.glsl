@@ -0,0 +1,108 @@
+#ifndef WATER_SHADING_GLSL
+#define WATER_SHADING_GLSL
+
+// Water surface shading shared by every fluid render mode: sky
+// reflection/refraction, thickness-based absorption, SSS and foam.
+
+layout(set = 0, binding = 1) uniform sampler2D skyboxTex;
+
+const float IOR_WATER = 1.333;
+const float IOR_AIR   = 1.0;
+
+const vec3  ABSORPTION_COEFF = vec3(0.45, 0.085, 0.025);
+
+const vec3  SCATTER_COLOR    = vec3(0.04, 0.18, 0.28);
+const float SCATTER_STRENGTH = 0.35;
+
+const vec3  SUN_DIR          = normalize(vec3(0.4, 1.0, 0.3));
+const vec3  SUN_COLOR        = vec3(1.0, 0.95, 0.85);
+
+const vec3  FOAM_COLOR       = vec3(0.92, 0.96, 1.0);
+
+// 0=normal  2=normals  3=thickness  4=reflection  5=refraction+SSS+foam
+const int   DEBUG_MODE       = 0;
+
+// ============================================================
+// UTILITY
+// ============================================================
+
+float fresnel(float cosTheta, float F0) {
+    return F0 + (1.0 - F0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
+}
+
+vec3 sampleSkybox(vec3 dir) {
+    dir = normalize(dir);
+    vec2 uv = vec2(atan(dir.z, dir.x) * 0.15915 + 0.5,
+    asin(clamp(dir.y, -1.0, 1.0)) * 0.31831 + 0.5);
+    return texture(skyboxTex, uv).rgb;
+}
+
+
+float ggxD(float NdotH, float roughness) {
+    float a  = roughness * roughness;
+    float a2 = a * a;
+    float d  = NdotH * NdotH * (a2 - 1.0) + 1.0;
+    return a2 / (3.14159 * d * d);
+}
+
+// Shades a surface point hit by `rayDir`. `N` is the outward normal and
+// `thickness` the length of water behind the surface along the ray.
+vec3 shadeWaterSurface(vec3 rayDir, vec3 N, float thickness) {
+    if (DEBUG_MODE == 2) return N * 0.5 + 0.5;
+
+    vec3 V = -rayDir;
+    vec3 L = SUN_DIR;
+    vec3 H = normalize(L + V);
+
+    float NdotV = max(dot(N, V), 0.0);
+    float NdotL = max(dot(N, L), 0.0);
+    float NdotH = max(dot(N, H), 0.0);
+
+    float F0 = pow((IOR_AIR - IOR_WATER) / (IOR_AIR + IOR_WATER), 2.0); // ~0.02
+    float F  = fresnel(NdotV, F0);
+    F = clamp(F, 0.0, 1.0);
+
+    vec3 reflDir    = reflect(rayDir, N);
+    vec3 reflection = sampleSkybox(reflDir);
+    float specGGX = ggxD(NdotH, 0.04) * NdotL;
+    reflection += SUN_COLOR * clamp(specGGX * 0.15, 0.0, 3.0);
+
+    if (DEBUG_MODE == 4) return reflection;
+
+    if (DEBUG_MODE == 3) return vec3(clamp(thickness * 1.5, 0.0, 1.0));
+    vec3  absorption = exp(-ABSORPTION_COEFF * thickness * 8.0);
+
+    vec3 refrDir = refract(rayDir, N, IOR_AIR / IOR_WATER);
+    if (dot(refrDir, refrDir) < 0.01) refrDir = rayDir;
+    vec3 background = sampleSkybox(normalize(refrDir));
+
+    float sssWrapped = max(0.0, dot(N, L) * 0.5 + 0.5);
+    float sssBack    = max(0.0, dot(-N, L));
+    float sssFactor  = (sssWrapped * 0.6 + sssBack * 0.4) * (1.0 - exp(-thickness * 3.0));
+    vec3  sss        = SCATTER_COLOR * SUN_COLOR * sssFactor * SCATTER_STRENGTH;
+
+
+    float foamMask = smoothstep(0.0, 0.3, thickness);
+    foamMask *= smoothstep(0.5, 0.95, N.y);
+
+
+    vec3 refracted = background * absorption + sss;
+
+    if (DEBUG_MODE == 5) {
+        vec3 c = refracted;
+        c = mix(c, FOAM_COLOR * (NdotL * 0.7 + 0.3), foamMask * 0.7);
+        c += SCATTER_COLOR * 0.04;
+        return c / (c + vec3(1.0));
+    }
+
+    vec3 waterColor = mix(refracted, reflection, F);
+
+
+    waterColor = mix(waterColor, FOAM_COLOR * (NdotL * 0.7 + 0.3), foamMask * 0.7);
+
+    waterColor += SCATTER_COLOR * 0.04;
+
+    return waterColor / (waterColor + vec3(1.0));
+}
+
+#endif
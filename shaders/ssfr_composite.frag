@@ -0,0 +1,67 @@
+#version 460
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_scalar_block_layout : require
+#extension GL_EXT_shader_explicit_arithmetic_types_int64 : require
+#extension GL_GOOGLE_include_directive : enable
+#include "include/water_shading.glsl"
+
+layout(buffer_reference, scalar) readonly buffer CameraDataRef {
+    mat4 view;
+    mat4 proj;
+    mat4 inv_view_proj;
+    vec3 camera_pos;
+};
+
+// Smoothed eye depth and accumulated thickness from the screen-space passes.
+layout(set = 0, binding = 0) uniform sampler2D fluidDepthTex;
+layout(set = 0, binding = 2) uniform sampler2D thicknessTex;
+
+layout(location = 0) out vec4 outColor;
+
+layout(push_constant) uniform PushConstants {
+    uint64_t camera_addr;
+} push;
+
+// View-space position of pixel `px`. The main viewport is flipped, so
+// framebuffer y grows downwards while NDC y grows upwards.
+vec3 viewPosAt(ivec2 px, ivec2 size, vec2 focal) {
+    px = clamp(px, ivec2(0), size - 1);
+    float depth = texelFetch(fluidDepthTex, px, 0).r;
+    vec2 ndc = vec2((float(px.x) + 0.5) / float(size.x) * 2.0 - 1.0,
+                    1.0 - (float(px.y) + 0.5) / float(size.y) * 2.0);
+    return vec3(ndc * depth / focal, depth);
+}
+
+void main() {
+    ivec2 px   = ivec2(gl_FragCoord.xy);
+    ivec2 size = textureSize(fluidDepthTex, 0);
+    if (texelFetch(fluidDepthTex, px, 0).r <= 0.0) discard;
+
+    CameraDataRef camera = CameraDataRef(push.camera_addr);
+    vec2 focal = vec2(camera.proj[0][0], camera.proj[1][1]);
+
+    vec3 P = viewPosAt(px, size, focal);
+
+    // Take the one-sided difference with the smaller depth step, so normals
+    // do not smear across silhouettes.
+    vec3 ddx  = viewPosAt(px + ivec2(1, 0), size, focal) - P;
+    vec3 ddx2 = P - viewPosAt(px - ivec2(1, 0), size, focal);
+    if (abs(ddx2.z) < abs(ddx.z)) ddx = ddx2;
+    vec3 ddy  = viewPosAt(px + ivec2(0, 1), size, focal) - P;
+    vec3 ddy2 = P - viewPosAt(px - ivec2(0, 1), size, focal);
+    if (abs(ddy2.z) < abs(ddy.z)) ddy = ddy2;
+
+    vec3 n = normalize(cross(ddx, ddy));
+    if (dot(n, P) > 0.0) n = -n;
+
+    // The view matrix is a rigid transform, so its transpose rotates back.
+    mat3 viewToWorld = transpose(mat3(camera.view));
+    vec3 N        = normalize(viewToWorld * n);
+    vec3 rayDir   = normalize(viewToWorld * P);
+
+    float thickness = texelFetch(thicknessTex, px, 0).r;
+    outColor = vec4(shadeWaterSurface(rayDir, N, thickness), 1.0);
+
+    vec4 clip = camera.proj * vec4(P, 1.0);
+    gl_FragDepth = clip.z / clip.w;
+}
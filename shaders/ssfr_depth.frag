@@ -0,0 +1,36 @@
+#version 460
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_scalar_block_layout : require
+#extension GL_EXT_shader_explicit_arithmetic_types_int64 : require
+
+layout(buffer_reference, scalar) readonly buffer CameraDataRef {
+    mat4 view;
+    mat4 proj;
+    mat4 inv_view_proj;
+    vec3 camera_pos;
+};
+
+layout(location = 0) in vec3 inViewPos;
+
+// Linear eye depth of the nearest sphere; 0 where there is no fluid.
+layout(location = 0) out float outDepth;
+
+layout(push_constant) uniform PushConstants {
+    uint64_t camera_addr;
+    float particle_radius;
+    float viewport_height;
+} push;
+
+void main() {
+    vec2 coord = gl_PointCoord * 2.0 - 1.0;
+    float r2 = dot(coord, coord);
+    if (r2 > 1.0) discard;
+
+    CameraDataRef camera = CameraDataRef(push.camera_addr);
+
+    vec3 viewPos = inViewPos - vec3(0.0, 0.0, sqrt(1.0 - r2) * push.particle_radius);
+    vec4 clip = camera.proj * vec4(viewPos, 1.0);
+
+    outDepth = viewPos.z;
+    gl_FragDepth = clip.z / clip.w;
+}
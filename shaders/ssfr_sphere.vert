@@ -0,0 +1,35 @@
+#version 460
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_scalar_block_layout : require
+#extension GL_EXT_shader_explicit_arithmetic_types_int64 : require
+
+// Point sprites covering each particle's projected sphere, shared by the
+// screen-space depth and thickness passes.
+
+layout(buffer_reference, scalar) readonly buffer CameraDataRef {
+    mat4 view;
+    mat4 proj;
+    mat4 inv_view_proj;
+    vec3 camera_pos;
+};
+
+layout(location = 0) in vec4 position;
+
+layout(location = 0) out vec3 outViewPos;
+
+layout(push_constant) uniform PushConstants {
+    uint64_t camera_addr;
+    float particle_radius;
+    float viewport_height;
+} push;
+
+void main() {
+    CameraDataRef camera = CameraDataRef(push.camera_addr);
+
+    vec4 viewPos = camera.view * vec4(position.xyz, 1.0);
+    outViewPos = viewPos.xyz;
+    gl_Position = camera.proj * viewPos;
+
+    // Projected diameter in pixels; view space looks down +z.
+    gl_PointSize = push.particle_radius * camera.proj[1][1] * push.viewport_height / max(viewPos.z, 1e-3);
+}
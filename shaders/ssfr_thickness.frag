@@ -0,0 +1,19 @@
+#version 460
+#extension GL_EXT_shader_explicit_arithmetic_types_int64 : require
+
+// Accumulated additively: each sprite adds the chord through its sphere.
+layout(location = 0) out float outThickness;
+
+layout(push_constant) uniform PushConstants {
+    uint64_t camera_addr;
+    float particle_radius;
+    float viewport_height;
+} push;
+
+void main() {
+    vec2 coord = gl_PointCoord * 2.0 - 1.0;
+    float r2 = dot(coord, coord);
+    if (r2 > 1.0) discard;
+
+    outThickness = 2.0 * sqrt(1.0 - r2) * push.particle_radius;
+}
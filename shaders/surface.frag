@@ -0,0 +1,26 @@
+#version 460
+#extension GL_EXT_scalar_block_layout : require
+#extension GL_EXT_shader_explicit_arithmetic_types_int64 : require
+#extension GL_GOOGLE_include_directive : enable
+#include "include/common.glsl"
+#include "include/water_shading.glsl"
+#include "include/density_volume.glsl"
+
+layout(location = 0) in vec3 inWorldPos;
+layout(location = 1) in vec3 inCameraPos;
+layout(location = 2) in vec3 inNormal;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    vec3 boxMin, boxMax;
+    domain_bounds(boxMin, boxMax);
+
+    vec3 rayDir = normalize(inWorldPos - inCameraPos);
+    vec3 N = normalize(inNormal);
+    // Seen from inside the fluid.
+    if (dot(N, rayDir) > 0.0) N = -N;
+
+    float tExit = intersectAABB(inWorldPos, rayDir, boxMin, boxMax).y;
+    outColor = vec4(shadeWater(inWorldPos, rayDir, N, max(tExit, 0.0), boxMin, boxMax), 1.0);
+}
@@ -6,8 +6,12 @@ static GLOBAL: tracy_client::ProfiledAllocator<System> =
     tracy_client::ProfiledAllocator::new(System, 100);
 
 fn main() -> anyhow::Result<()> {
-    let mut engine =
-        Engine::new().map_err(|e| anyhow::anyhow!("Failed to initialize Engine: {}", e))?;
+    // Optional scene file: `fluid_engine scenes/dam_break.toml`.
+    let engine = match std::env::args_os().nth(1) {
+        Some(scene_path) => Engine::from_scene_file(scene_path),
+        None => Engine::new(),
+    };
+    let mut engine = engine.map_err(|e| anyhow::anyhow!("Failed to initialize Engine: {}", e))?;
     engine.run();
     Ok(())
 }
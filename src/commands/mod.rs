@@ -3,6 +3,7 @@
 use crate::core::scene::Scene;
 
 pub mod camera_commands;
+pub mod simulation_commands;
 
 pub trait Command {
     fn execute(&self, scene: &mut Scene, dt: f32);
@@ -0,0 +1,37 @@
+use crate::commands::Command;
+use crate::core::scene::Scene;
+
+pub struct TogglePauseCommand;
+impl Command for TogglePauseCommand {
+    fn execute(&self, scene: &mut Scene, _dt: f32) {
+        scene.playback.toggle_pause();
+    }
+}
+
+pub struct StepSimulationCommand;
+impl Command for StepSimulationCommand {
+    fn execute(&self, scene: &mut Scene, _dt: f32) {
+        scene.playback.request_step();
+    }
+}
+
+pub struct ResetSimulationCommand;
+impl Command for ResetSimulationCommand {
+    fn execute(&self, scene: &mut Scene, _dt: f32) {
+        scene.playback.request_reset();
+    }
+}
+
+pub struct SaveCheckpointCommand;
+impl Command for SaveCheckpointCommand {
+    fn execute(&self, scene: &mut Scene, _dt: f32) {
+        scene.playback.request_save();
+    }
+}
+
+pub struct LoadCheckpointCommand;
+impl Command for LoadCheckpointCommand {
+    fn execute(&self, scene: &mut Scene, _dt: f32) {
+        scene.playback.request_load();
+    }
+}
@@ -0,0 +1,484 @@
+//! Versioned binary checkpoint of the full simulation state.
+//!
+//! Layout (all little-endian):
+//!
+//! | field      | type                                                           |
+//! |------------|----------------------------------------------------------------|
+//! | magic      | `b"FLUIDCHK"`                                                  |
+//! | version    | `u32` (`CHECKPOINT_VERSION`)                                   |
+//! | count      | `u32` live particle count                                      |
+//! | params     | `SimulationParams` field by field, padding skipped             |
+//! | phases     | `u32` n, then `n × [f32; 6]` (colour, density, viscosity, γ)   |
+//! | kernel     | `u32` (`SphKernel`)                                            |
+//! | viscosity  | `u32` mode (`ViscosityMode`), `u32` iterations, `f32` tolerance|
+//! | pressure   | `u32` solver (`PressureSolver`), `f32` Tait stiffness          |
+//! | backend    | `u32` (`SimulationBackend`), `u32` transfer (`GridTransfer`),  |
+//! |            | `f32` FLIP ratio, `u32` grid iterations                        |
+//! | thresholds | `f32` `max_density_error`, `f32` `max_divergence_error`        |
+//! | boundary   | `CollisionBox::checkpoint_state` (`[f32; 10]`)                 |
+//! | obstacles  | `u32` n, then `n × Obstacle::checkpoint_state` (`[f32; 11]`)   |
+//! | bodies     | `u32` n, then `n × RigidBody::checkpoint_state` (`[f32; 13]`)  |
+//! | camera     | `Camera::checkpoint_state` (`[f32; 11]`)                       |
+//! | positions  | `count × [f32; 4]` (`position_a`)                              |
+//! | velocities | `count × [f32; 4]` (`velocity_a`)                              |
+//! | pressures  | `count × f32`                                                  |
+//! | densities  | `count × f32`                                                  |
+//!
+//! Particle arrays are stored in GPU buffer order, so a restored run continues
+//! exactly where the running app would after its next neighbor search.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use glam::{IVec3, Vec3};
+use vulkano::buffer::Subbuffer;
+use crate::entities::camera::Camera;
+use crate::entities::collision::CollisionBox;
+use crate::entities::particle::{GpuPhase, GridTransfer, ParticleCounter, PressureSolver, SimulationBackend, SimulationParams, SphKernel, ViscosityMode, MAX_PHASES};
+use crate::errors::application_error::ApplicationError;
+
+pub const CHECKPOINT_MAGIC: [u8; 8] = *b"FLUIDCHK";
+pub const CHECKPOINT_VERSION: u32 = 11;
+
+/// Per-particle solver state, in GPU buffer order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParticleState {
+    pub positions: Vec<[f32; 4]>,
+    pub velocities: Vec<[f32; 4]>,
+    pub pressures: Vec<f32>,
+    pub densities: Vec<f32>,
+}
+
+impl ParticleState {
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+}
+
+/// Host-visible copies of the particle buffers recorded by
+/// `Simulation::record_snapshot`. Readable once the frame that recorded the
+/// copies has finished on the GPU. The copies span the whole capacity; the
+/// counter says how much of it was live.
+pub struct PendingSnapshot {
+    pub(crate) counter: Subbuffer<[ParticleCounter]>,
+    pub(crate) positions: Subbuffer<[[f32; 4]]>,
+    pub(crate) velocities: Subbuffer<[[f32; 4]]>,
+    pub(crate) pressures: Subbuffer<[f32]>,
+    pub(crate) densities: Subbuffer<[f32]>,
+}
+
+impl PendingSnapshot {
+    /// Returns `None` while the copies are still in flight.
+    pub fn try_read(&self) -> Option<ParticleState> {
+        let positions = self.positions.read().ok()?;
+        let n = (self.counter.read().ok()?[0].count as usize).min(positions.len());
+        Some(ParticleState {
+            positions: positions[..n].to_vec(),
+            velocities: self.velocities.read().ok()?[..n].to_vec(),
+            pressures: self.pressures.read().ok()?[..n].to_vec(),
+            densities: self.densities.read().ok()?[..n].to_vec(),
+        })
+    }
+}
+
+pub struct Checkpoint {
+    pub particles: ParticleState,
+    pub params: SimulationParams,
+    pub boundary: CollisionBox,
+    /// Pose of every scene obstacle; the shapes come from the scene.
+    pub obstacle_states: Vec<[f32; 11]>,
+    /// Pose and velocities of every rigid body.
+    pub body_states: Vec<[f32; 13]>,
+    pub camera: Camera,
+}
+
+impl Checkpoint {
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ApplicationError> {
+        let path = path.as_ref();
+        File::create(path).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            self.write_to(&mut writer)?;
+            writer.flush()
+        }).map_err(|e| {
+            ApplicationError::ResourceLoadError(format!("Failed to write checkpoint {}: {}", path.display(), e))
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ApplicationError> {
+        let path = path.as_ref();
+        File::open(path)
+            .and_then(|file| Self::read_from(&mut BufReader::new(file)))
+            .map_err(|e| {
+                ApplicationError::ResourceLoadError(format!("Failed to read checkpoint {}: {}", path.display(), e))
+            })
+    }
+
+    pub fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+        let count = self.particles.len();
+        assert!(
+            self.particles.velocities.len() == count
+                && self.particles.pressures.len() == count
+                && self.particles.densities.len() == count,
+            "particle arrays must have the same length"
+        );
+
+        w.write_all(&CHECKPOINT_MAGIC)?;
+        write_u32(w, CHECKPOINT_VERSION)?;
+        write_u32(w, count as u32)?;
+
+        let p = &self.params;
+        write_f32s(w, &[p.particle_radius, p.particle_mass, p.smoothing_radius, p.target_density])?;
+        write_f32s(w, &[p.viscosity, p.relax_factor, p.dt])?;
+        write_f32s(w, &[p.surface_tension, p.adhesion])?;
+        write_f32s(w, &[p.vorticity_confinement])?;
+        write_u32(w, p.density_solver_iterations)?;
+        write_u32(w, p.divergence_solver_iterations)?;
+        write_f32s(w, &p.gravity[..3])?;
+        write_f32s(w, &p.box_min[..3])?;
+        write_f32s(w, &p.box_max[..3])?;
+        for &r in &p.grid_res[..3] {
+            w.write_all(&r.to_le_bytes())?;
+        }
+        let phases = &p.phases[..p.phase_count()];
+        write_u32(w, phases.len() as u32)?;
+        for phase in phases {
+            write_f32s(w, &phase.color[..3])?;
+            write_f32s(w, &[phase.rest_density, phase.viscosity, phase.surface_tension])?;
+        }
+        write_u32(w, p.kernel)?;
+        write_u32(w, p.viscosity_mode)?;
+        write_u32(w, p.viscosity_iterations)?;
+        write_f32s(w, &[p.viscosity_tolerance])?;
+        write_u32(w, p.pressure_solver)?;
+        write_f32s(w, &[p.stiffness])?;
+        write_u32(w, p.backend)?;
+        write_u32(w, p.grid_transfer)?;
+        write_f32s(w, &[p.flip_ratio])?;
+        write_u32(w, p.grid_iterations)?;
+        write_f32s(w, &[p.max_density_error, p.max_divergence_error])?;
+
+        write_f32s(w, &self.boundary.checkpoint_state())?;
+        write_u32(w, self.obstacle_states.len() as u32)?;
+        write_f32s(w, self.obstacle_states.as_flattened())?;
+        write_u32(w, self.body_states.len() as u32)?;
+        write_f32s(w, self.body_states.as_flattened())?;
+        write_f32s(w, &self.camera.checkpoint_state())?;
+
+        write_f32s(w, self.particles.positions.as_flattened())?;
+        write_f32s(w, self.particles.velocities.as_flattened())?;
+        write_f32s(w, &self.particles.pressures)?;
+        write_f32s(w, &self.particles.densities)
+    }
+
+    pub fn read_from(r: &mut impl Read) -> io::Result<Self> {
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if magic != CHECKPOINT_MAGIC {
+            return Err(invalid_data("not a fluid engine checkpoint".to_string()));
+        }
+        let version = read_u32(r)?;
+        // Version 1 predates surface tension and adhesion, version 2 vorticity
+        // confinement, version 3 obstacles, version 4 rigid bodies, version 5
+        // phases, version 6 the kernel choice, version 7 the viscosity mode,
+        // version 8 the pressure solver, version 9 the FLIP backend and
+        // version 10 the solver error thresholds; missing terms read as off,
+        // every particle as the base fluid, the kernel as the cubic spline,
+        // viscosity as explicit, the solver as DFSPH, the backend as SPH and
+        // the thresholds as 0, i.e. every iteration runs.
+        if !(1..=CHECKPOINT_VERSION).contains(&version) {
+            return Err(invalid_data(format!(
+                "unsupported checkpoint version {version} (expected 1 to {CHECKPOINT_VERSION})"
+            )));
+        }
+        let count = read_u32(r)? as usize;
+
+        let [particle_radius, particle_mass, smoothing_radius, target_density] = read_array(r)?;
+        let [viscosity, relax_factor, dt] = read_array(r)?;
+        let [surface_tension, adhesion] = if version >= 2 { read_array(r)? } else { [0.0; 2] };
+        let [vorticity_confinement] = if version >= 3 { read_array(r)? } else { [0.0] };
+        let density_iterations = read_u32(r)?;
+        let divergence_iterations = read_u32(r)?;
+        let gravity = Vec3::from_array(read_array(r)?);
+        let box_min = Vec3::from_array(read_array(r)?);
+        let box_max = Vec3::from_array(read_array(r)?);
+        let mut grid_res = [0i32; 3];
+        for res in &mut grid_res {
+            *res = read_u32(r)? as i32;
+        }
+        let mut params = SimulationParams::new(
+            particle_radius,
+            particle_mass,
+            smoothing_radius,
+            target_density,
+            viscosity,
+            relax_factor,
+            dt,
+            density_iterations,
+            divergence_iterations,
+            gravity,
+            box_min,
+            box_max,
+            IVec3::from_array(grid_res),
+        );
+        params.surface_tension = surface_tension;
+        params.adhesion = adhesion;
+        params.vorticity_confinement = vorticity_confinement;
+        if version >= 6 {
+            let n = read_u32(r)? as usize;
+            if !(1..=MAX_PHASES).contains(&n) {
+                return Err(invalid_data(format!("phase count {n} is outside 1 to {MAX_PHASES}")));
+            }
+            let rows: Vec<GpuPhase> = read_f32s(r, 6 * n)?
+                .chunks_exact(6)
+                .map(|c| GpuPhase::new([c[0], c[1], c[2]], c[3], c[4], c[5]))
+                .collect();
+            params.set_phases(rows[0].color[..3].try_into().unwrap(), &rows[1..]);
+        }
+        if version >= 7 {
+            let kernel = read_u32(r)?;
+            if SphKernel::from_index(kernel) as u32 != kernel {
+                return Err(invalid_data(format!("unknown kernel {kernel}")));
+            }
+            params.kernel = kernel;
+        }
+        if version >= 8 {
+            let mode = read_u32(r)?;
+            if ViscosityMode::from_index(mode) as u32 != mode {
+                return Err(invalid_data(format!("unknown viscosity mode {mode}")));
+            }
+            params.viscosity_mode = mode;
+            params.viscosity_iterations = read_u32(r)?;
+            let [tolerance] = read_array(r)?;
+            params.viscosity_tolerance = tolerance;
+        }
+        if version >= 9 {
+            let solver = read_u32(r)?;
+            if PressureSolver::from_index(solver) as u32 != solver {
+                return Err(invalid_data(format!("unknown pressure solver {solver}")));
+            }
+            params.pressure_solver = solver;
+            let [stiffness] = read_array(r)?;
+            params.stiffness = stiffness;
+        }
+        if version >= 10 {
+            let backend = read_u32(r)?;
+            if SimulationBackend::from_index(backend) as u32 != backend {
+                return Err(invalid_data(format!("unknown backend {backend}")));
+            }
+            let transfer = read_u32(r)?;
+            if GridTransfer::from_index(transfer) as u32 != transfer {
+                return Err(invalid_data(format!("unknown grid transfer {transfer}")));
+            }
+            params.backend = backend;
+            params.grid_transfer = transfer;
+            let [flip_ratio] = read_array(r)?;
+            params.flip_ratio = flip_ratio;
+            params.grid_iterations = read_u32(r)?;
+        }
+        if version >= 11 {
+            let [max_density_error, max_divergence_error] = read_array(r)?;
+            params.max_density_error = max_density_error;
+            params.max_divergence_error = max_divergence_error;
+        }
+
+        let boundary = CollisionBox::from_checkpoint_state(read_array(r)?);
+        let obstacle_states = if version >= 4 {
+            let n = read_u32(r)? as usize;
+            read_f32s(r, 11 * n)?.chunks_exact(11).map(|c| std::array::from_fn(|i| c[i])).collect()
+        } else {
+            Vec::new()
+        };
+        let body_states = if version >= 5 {
+            let n = read_u32(r)? as usize;
+            read_f32s(r, 13 * n)?.chunks_exact(13).map(|c| std::array::from_fn(|i| c[i])).collect()
+        } else {
+            Vec::new()
+        };
+        let camera = Camera::from_checkpoint_state(read_array(r)?);
+
+        let positions = read_f32s(r, 4 * count)?.chunks_exact(4).map(|c| [c[0], c[1], c[2], c[3]]).collect();
+        let velocities = read_f32s(r, 4 * count)?.chunks_exact(4).map(|c| [c[0], c[1], c[2], c[3]]).collect();
+        let pressures = read_f32s(r, count)?;
+        let densities = read_f32s(r, count)?;
+
+        Ok(Self {
+            particles: ParticleState { positions, velocities, pressures, densities },
+            params,
+            boundary,
+            obstacle_states,
+            body_states,
+            camera,
+        })
+    }
+}
+
+// ── Encoding helpers ─────────────────────────────────────────────────────────
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn write_u32(w: &mut impl Write, value: u32) -> io::Result<()> {
+    w.write_all(&value.to_le_bytes())
+}
+
+fn write_f32s(w: &mut impl Write, values: &[f32]) -> io::Result<()> {
+    let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+    w.write_all(&bytes)
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_f32s(r: &mut impl Read, len: usize) -> io::Result<Vec<f32>> {
+    let mut bytes = vec![0u8; 4 * len];
+    r.read_exact(&mut bytes)?;
+    Ok(bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect())
+}
+
+fn read_array<const N: usize>(r: &mut impl Read) -> io::Result<[f32; N]> {
+    let values = read_f32s(r, N)?;
+    Ok(std::array::from_fn(|i| values[i]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities::Actor;
+
+    fn checkpoint() -> Checkpoint {
+        let mut params = SimulationParams::new(
+            0.02, 0.064, 0.08, 1000.0, 0.15, 0.5, 0.005, 4, 6,
+            Vec3::new(0.0, -9.81, 0.0),
+            Vec3::new(-1.5, 0.0, -1.0),
+            Vec3::new(0.8, 4.0, 1.0),
+            IVec3::new(128, 64, 32),
+        );
+        params.surface_tension = 0.4;
+        params.adhesion = 1.5;
+        params.vorticity_confinement = 0.3;
+        params.set_kernel(SphKernel::WendlandC4);
+        params.set_viscosity_mode(ViscosityMode::Implicit);
+        params.viscosity_iterations = 40;
+        params.viscosity_tolerance = 1e-4;
+        params.set_pressure_solver(PressureSolver::Pbf);
+        params.stiffness = 20000.0;
+        params.set_backend(SimulationBackend::Flip);
+        params.set_grid_transfer(GridTransfer::Apic);
+        params.flip_ratio = 0.9;
+        params.grid_iterations = 70;
+        params.max_density_error = 0.002;
+        params.max_divergence_error = 0.0075;
+        params.set_phases([0.1, 0.3, 0.9], &[GpuPhase::new([0.9, 0.7, 0.1], 800.0, 0.4, 0.2)]);
+
+        let mut boundary = CollisionBox::new(Vec3::new(-1.5, 0.0, -1.0), Vec3::new(0.8, 4.0, 1.0));
+        boundary.wave_amplitude = 0.3;
+        boundary.wave_frequency = 0.5;
+        boundary.update(0.37);
+
+        let mut camera = Camera::new(Vec3::new(0.0, 1.5, -3.5));
+        camera.rotate(10.0, 20.0, 0.0);
+        camera.fov(70.0);
+
+        let n = 5;
+        Checkpoint {
+            particles: ParticleState {
+                positions: (0..n).map(|i| [i as f32, 0.5, -0.25, 1.0]).collect(),
+                velocities: (0..n).map(|i| [0.0, -(i as f32), 0.125, 0.0]).collect(),
+                pressures: (0..n).map(|i| i as f32 * 10.0).collect(),
+                densities: (0..n).map(|i| 1000.0 + i as f32).collect(),
+            },
+            params,
+            boundary,
+            obstacle_states: vec![std::array::from_fn(|i| i as f32 * 0.5); 2],
+            body_states: vec![std::array::from_fn(|i| 1.0 - i as f32 * 0.25)],
+            camera,
+        }
+    }
+
+    #[test]
+    fn round_trip_preserves_everything() {
+        let original = checkpoint();
+        let mut bytes = Vec::new();
+        original.write_to(&mut bytes).unwrap();
+
+        let restored = Checkpoint::read_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(restored.particles, original.particles);
+
+        let (a, b) = (&original.params, &restored.params);
+        assert_eq!(a.particle_mass, b.particle_mass);
+        assert_eq!(a.surface_tension, b.surface_tension);
+        assert_eq!(a.adhesion, b.adhesion);
+        assert_eq!(a.vorticity_confinement, b.vorticity_confinement);
+        assert_eq!(a.divergence_solver_iterations, b.divergence_solver_iterations);
+        assert_eq!(a.box_max, b.box_max);
+        assert_eq!(a.grid_res, b.grid_res);
+        assert_eq!(a.phases, b.phases);
+        assert_eq!(b.kernel(), SphKernel::WendlandC4);
+        assert_eq!(b.viscosity_mode(), ViscosityMode::Implicit);
+        assert_eq!(a.viscosity_iterations, b.viscosity_iterations);
+        assert_eq!(a.viscosity_tolerance, b.viscosity_tolerance);
+        assert_eq!(b.pressure_solver(), PressureSolver::Pbf);
+        assert_eq!(a.stiffness, b.stiffness);
+        assert_eq!(b.backend(), SimulationBackend::Flip);
+        assert_eq!(b.grid_transfer(), GridTransfer::Apic);
+        assert_eq!(a.flip_ratio, b.flip_ratio);
+        assert_eq!(a.grid_iterations, b.grid_iterations);
+        assert_eq!(a.max_density_error, b.max_density_error);
+        assert_eq!(a.max_divergence_error, b.max_divergence_error);
+
+        assert_eq!(restored.boundary.checkpoint_state(), original.boundary.checkpoint_state());
+        assert_eq!(restored.obstacle_states, original.obstacle_states);
+        assert_eq!(restored.body_states, original.body_states);
+        assert_eq!(restored.camera.checkpoint_state(), original.camera.checkpoint_state());
+        assert_eq!(restored.camera.location(), original.camera.location());
+        assert!(restored.camera.forward().abs_diff_eq(original.camera.forward(), 1e-6));
+    }
+
+    #[test]
+    fn version_10_files_run_every_iteration() {
+        let original = checkpoint();
+        let mut bytes = Vec::new();
+        original.write_to(&mut bytes).unwrap();
+
+        // Version 10 is the same file without the two thresholds.
+        let p = &original.params;
+        let thresholds: Vec<u8> = [p.max_density_error, p.max_divergence_error].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let at = bytes.windows(thresholds.len()).position(|w| w == thresholds).unwrap();
+        bytes.drain(at..at + thresholds.len());
+        bytes[8..12].copy_from_slice(&10u32.to_le_bytes());
+
+        let restored = Checkpoint::read_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(restored.params.max_density_error, 0.0);
+        assert_eq!(restored.params.max_divergence_error, 0.0);
+        assert_eq!(restored.params.grid_iterations, p.grid_iterations);
+        assert_eq!(restored.particles, original.particles);
+        assert_eq!(restored.camera.checkpoint_state(), original.camera.checkpoint_state());
+    }
+
+    #[test]
+    fn rejects_foreign_and_future_files() {
+        let mut bytes = Vec::new();
+        checkpoint().write_to(&mut bytes).unwrap();
+
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] = b'X';
+        let error = Checkpoint::read_from(&mut wrong_magic.as_slice()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let mut future = bytes.clone();
+        future[8..12].copy_from_slice(&(CHECKPOINT_VERSION + 1).to_le_bytes());
+        let error = Checkpoint::read_from(&mut future.as_slice()).err().unwrap();
+        assert!(error.to_string().contains("version"), "{error}");
+
+        let truncated = &bytes[..bytes.len() - 1];
+        let error = Checkpoint::read_from(&mut &truncated[..]).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
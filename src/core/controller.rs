@@ -1,10 +1,14 @@
 use std::collections::HashSet;
 use winit::keyboard::KeyCode;
 use crate::commands::camera_commands::{MoveForwardCameraCommand, MoveRightCameraCommand, MoveUpCameraCommand, RotateCameraCommand};
+use crate::commands::simulation_commands::{LoadCheckpointCommand, ResetSimulationCommand, SaveCheckpointCommand, StepSimulationCommand, TogglePauseCommand};
 use crate::commands::Command;
 
 pub struct Controller {
     pressed_keys: HashSet<KeyCode>,
+    // Keys that went down since the last `get_triggered_commands`; OS key
+    // repeat does not re-trigger them.
+    just_pressed: HashSet<KeyCode>,
     mouse_delta: (f32, f32),
 }
 pub enum KeyboardAction {
@@ -15,12 +19,17 @@ impl Controller {
     pub fn new() -> Self {
         Self {
             pressed_keys: HashSet::new(),
+            just_pressed: HashSet::new(),
             mouse_delta: (0.0, 0.0)
         }
     }
     pub fn update_key(&mut self, key: KeyCode, action: KeyboardAction) {
         match action {
-            KeyboardAction::Pressed => { self.pressed_keys.insert(key); },
+            KeyboardAction::Pressed => {
+                if self.pressed_keys.insert(key) {
+                    self.just_pressed.insert(key);
+                }
+            },
             KeyboardAction::Released => { self.pressed_keys.remove(&key); },
         }
     }
@@ -64,4 +73,22 @@ impl Controller {
 
         commands
     }
-}
\ No newline at end of file
+    /// One-shot commands for keys pressed since the last call:
+    /// `P` pause / resume, `N` step, `R` reset, `F5` save and `F9` load a checkpoint.
+    pub fn get_triggered_commands(&mut self) -> Vec<Box<dyn Command>> {
+        let mut commands: Vec<Box<dyn Command>> = Vec::new();
+
+        for key in self.just_pressed.drain() {
+            match key {
+                KeyCode::KeyP => commands.push(Box::new(TogglePauseCommand)),
+                KeyCode::KeyN => commands.push(Box::new(StepSimulationCommand)),
+                KeyCode::KeyR => commands.push(Box::new(ResetSimulationCommand)),
+                KeyCode::F5 => commands.push(Box::new(SaveCheckpointCommand)),
+                KeyCode::F9 => commands.push(Box::new(LoadCheckpointCommand)),
+                _ => {}
+            }
+        }
+
+        commands
+    }
+}
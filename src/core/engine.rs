@@ -1,3 +1,4 @@
+use std::path::Path;
 use log::{info, debug, error};
 use simple_logger::SimpleLogger;
 use winit::application::ApplicationHandler;
@@ -5,6 +6,7 @@ use winit::dpi::PhysicalSize;
 use winit::event::{DeviceEvent, DeviceId, ElementState, MouseButton, WindowEvent};
 use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop};
 use winit::keyboard::{KeyCode, PhysicalKey};
+#[cfg(target_os = "windows")]
 use winit::platform::windows::WindowAttributesExtWindows;
 use winit::window::{CursorGrabMode, Icon, WindowAttributes, WindowId};
 use crate::core::controller::{Controller, KeyboardAction};
@@ -40,14 +42,24 @@ pub struct Engine {
 
 impl Engine {
     pub fn new() -> Result<Self, ApplicationError> {
+        Self::with_scene(|| Ok(Scene::new()))
+    }
+
+    /// Same as `new`, but loads the scene from a TOML scene file.
+    pub fn from_scene_file(path: impl AsRef<Path>) -> Result<Self, ApplicationError> {
+        Self::with_scene(|| Scene::from_file(path))
+    }
+
+    fn with_scene(load_scene: impl FnOnce() -> Result<Scene, ApplicationError>) -> Result<Self, ApplicationError> {
         SimpleLogger::new().init().unwrap();
         info!("[Engine] Initializing Engine Core...");
 
+        let scene = load_scene()?;
+
         let event_loop = EventLoop::new()
             .map_err(|e| ApplicationError::EventLoopInitializationError(e))?;
 
         event_loop.set_control_flow(ControlFlow::Poll);
-        let scene = Scene::new();
 
         Ok(Self {
             renderer: None,
@@ -75,8 +87,12 @@ impl ApplicationHandler for Engine {
 
             let window_attributes = WindowAttributes::default()
                 .with_inner_size(PhysicalSize::new(1920, 1080))
-                .with_window_icon(load_icon("assets/logo.png").ok())
-                .with_taskbar_icon(load_icon("assets/logo.png").ok());
+                .with_window_icon(load_icon("assets/logo.png").ok());
+
+            // The taskbar icon is a Windows-only attribute; X11 and Wayland take
+            // the window icon (or the .desktop entry) instead.
+            #[cfg(target_os = "windows")]
+            let window_attributes = window_attributes.with_taskbar_icon(load_icon("assets/logo.png").ok());
 
 
             let window = match event_loop.create_window(window_attributes) {
@@ -175,6 +191,9 @@ impl ApplicationHandler for Engine {
                     for command in self.controller.get_active_commands() {
                         command.execute(&mut self.scene, safe_dt);
                     }
+                    for command in self.controller.get_triggered_commands() {
+                        command.execute(&mut self.scene, safe_dt);
+                    }
                     if let Some(cmd) = self.controller.get_mouse_command() {
                         cmd.execute(&mut self.scene, safe_dt);
                     }
@@ -0,0 +1,472 @@
+//! Per-frame particle export for ParaView and Blender.
+//!
+//! Each exported frame is one file named `<dir>/particles_<index>.<ext>`
+//! holding positions plus per-particle velocity, density and pressure:
+//!
+//! * `Vtk` — legacy VTK, binary `POLYDATA` with one vertex cell per particle.
+//! * `Vtu` — XML `UnstructuredGrid` (ASCII), one `VTK_VERTEX` per particle.
+//! * `Ply` — `binary_little_endian` PLY with `vx vy vz density pressure`
+//!   vertex properties, for Blender's PLY importer / Geometry Nodes.
+//!
+//! VTK and VTU series also get a `particles.pvd` collection so ParaView picks
+//! up the simulation time of every frame.
+//!
+//! The marching-cubes surface can be written alongside as
+//! `<dir>/surface_<index>.<ext>`, an indexed `Obj` or binary `Ply` mesh with
+//! per-vertex normals. It is the mesh the surface render mode draws, read
+//! back from `GpuSurfaceMesh` and welded on the CPU.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use glam::Vec4;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::DrawIndirectCommand;
+use crate::core::checkpoint::ParticleState;
+use crate::cpu::marching_cubes::SurfaceMesh;
+use crate::entities::surface::SurfaceVertex;
+use crate::errors::application_error::ApplicationError;
+
+const FILE_PREFIX: &str = "particles";
+const SURFACE_FILE_PREFIX: &str = "surface";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Vtk,
+    Vtu,
+    Ply,
+}
+
+impl ExportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Vtk => "vtk",
+            ExportFormat::Vtu => "vtu",
+            ExportFormat::Ply => "ply",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SurfaceFormat {
+    Obj,
+    Ply,
+}
+
+impl SurfaceFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            SurfaceFormat::Obj => "obj",
+            SurfaceFormat::Ply => "ply",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportSettings {
+    pub dir: PathBuf,
+    /// Write the particle point cloud.
+    pub particles: bool,
+    pub format: ExportFormat,
+    /// Also write the marching-cubes surface, in this format.
+    pub surface_format: Option<SurfaceFormat>,
+    /// Export every N-th frame that advanced the solver.
+    pub every_n_frames: u32,
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("export"),
+            particles: true,
+            format: ExportFormat::Vtu,
+            surface_format: None,
+            every_n_frames: 1,
+        }
+    }
+}
+
+/// Host-visible copy of the marching-cubes triangle soup, recorded by
+/// `GpuSceneResources::record_surface_readback`. Readable once the frame that
+/// recorded the copy has finished on the GPU.
+pub struct PendingSurfaceMesh {
+    pub(crate) draw_command: Subbuffer<[DrawIndirectCommand]>,
+    pub(crate) vertices: Subbuffer<[SurfaceVertex]>,
+}
+
+impl PendingSurfaceMesh {
+    /// Returns `None` while the copy is still in flight.
+    pub fn try_read(&self) -> Option<SurfaceMesh> {
+        let vertex_count = self.draw_command.read().ok()?[0].vertex_count as usize;
+        let vertices = self.vertices.read().ok()?;
+        let corners = vertices[..vertex_count.min(vertices.len())]
+            .iter()
+            .map(|v| (Vec4::from(v.position).truncate(), Vec4::from(v.normal).truncate()));
+        Some(SurfaceMesh::weld(corners))
+    }
+}
+
+/// Writes a numbered frame sequence according to `ExportSettings`.
+pub struct ParticleExporter {
+    settings: ExportSettings,
+    frames_seen: u32,
+    /// `(time, file name)` of every written frame, for the `.pvd` collection.
+    written: Vec<(f32, String)>,
+    surfaces_written: usize,
+}
+
+impl ParticleExporter {
+    pub fn new(settings: ExportSettings) -> Self {
+        Self { settings, frames_seen: 0, written: Vec::new(), surfaces_written: 0 }
+    }
+
+    pub fn settings(&self) -> &ExportSettings {
+        &self.settings
+    }
+
+    /// Counts a rendered frame; true for every `every_n_frames`-th one,
+    /// starting with the first.
+    pub fn tick(&mut self) -> bool {
+        let due = self.frames_seen.is_multiple_of(self.settings.every_n_frames.max(1));
+        self.frames_seen += 1;
+        due
+    }
+
+    /// Writes the next frame of the sequence and returns its path.
+    pub fn write_frame(&mut self, state: &ParticleState, time: f32) -> Result<PathBuf, ApplicationError> {
+        let dir = &self.settings.dir;
+        let name = format!("{}_{:05}.{}", FILE_PREFIX, self.written.len(), self.settings.format.extension());
+        let path = dir.join(&name);
+
+        self.write_file(&path, state, time).map_err(|e| {
+            ApplicationError::ResourceLoadError(format!("Failed to export {}: {}", path.display(), e))
+        })?;
+
+        self.written.push((time, name));
+        if self.settings.format != ExportFormat::Ply {
+            let pvd = dir.join(format!("{FILE_PREFIX}.pvd"));
+            File::create(&pvd)
+                .and_then(|file| write_pvd(&mut BufWriter::new(file), &self.written))
+                .map_err(|e| {
+                    ApplicationError::ResourceLoadError(format!("Failed to export {}: {}", pvd.display(), e))
+                })?;
+        }
+        Ok(path)
+    }
+
+    /// Writes the next surface mesh of the sequence and returns its path.
+    /// Fails if `surface_format` is unset.
+    pub fn write_surface(&mut self, mesh: &SurfaceMesh, time: f32) -> Result<PathBuf, ApplicationError> {
+        let format = self.settings.surface_format.ok_or_else(|| {
+            ApplicationError::ResourceLoadError("Surface export is disabled".to_string())
+        })?;
+        let name = format!("{}_{:05}.{}", SURFACE_FILE_PREFIX, self.surfaces_written, format.extension());
+        let path = self.settings.dir.join(name);
+
+        fs::create_dir_all(&self.settings.dir)
+            .and_then(|_| File::create(&path))
+            .and_then(|file| {
+                let mut w = BufWriter::new(file);
+                match format {
+                    SurfaceFormat::Obj => write_obj(&mut w, mesh, time)?,
+                    SurfaceFormat::Ply => write_surface_ply(&mut w, mesh, time)?,
+                }
+                w.flush()
+            })
+            .map_err(|e| {
+                ApplicationError::ResourceLoadError(format!("Failed to export {}: {}", path.display(), e))
+            })?;
+
+        self.surfaces_written += 1;
+        Ok(path)
+    }
+
+    fn write_file(&self, path: &Path, state: &ParticleState, time: f32) -> io::Result<()> {
+        fs::create_dir_all(&self.settings.dir)?;
+        let mut w = BufWriter::new(File::create(path)?);
+        match self.settings.format {
+            ExportFormat::Vtk => write_vtk(&mut w, state, time)?,
+            ExportFormat::Vtu => write_vtu(&mut w, state)?,
+            ExportFormat::Ply => write_ply(&mut w, state, time)?,
+        }
+        w.flush()
+    }
+}
+
+// ── Writers ──────────────────────────────────────────────────────────────────
+
+/// Legacy VTK. The legacy binary format is big-endian by specification.
+pub fn write_vtk(w: &mut impl Write, state: &ParticleState, time: f32) -> io::Result<()> {
+    let n = state.len();
+    writeln!(w, "# vtk DataFile Version 3.0")?;
+    writeln!(w, "Fluid Engine particles, t = {time}")?;
+    writeln!(w, "BINARY")?;
+    writeln!(w, "DATASET POLYDATA")?;
+
+    writeln!(w, "POINTS {n} float")?;
+    write_be_f32s(w, state.positions.iter().flat_map(|p| [p[0], p[1], p[2]]))?;
+    writeln!(w)?;
+
+    writeln!(w, "VERTICES {n} {}", 2 * n)?;
+    let cells: Vec<u8> = (0..n as i32).flat_map(|i| [1i32, i]).flat_map(i32::to_be_bytes).collect();
+    w.write_all(&cells)?;
+    writeln!(w)?;
+
+    writeln!(w, "POINT_DATA {n}")?;
+    writeln!(w, "VECTORS velocity float")?;
+    write_be_f32s(w, state.velocities.iter().flat_map(|v| [v[0], v[1], v[2]]))?;
+    writeln!(w)?;
+    for (name, values) in [("density", &state.densities), ("pressure", &state.pressures)] {
+        writeln!(w, "SCALARS {name} float 1")?;
+        writeln!(w, "LOOKUP_TABLE default")?;
+        write_be_f32s(w, values.iter().copied())?;
+        writeln!(w)?;
+    }
+    Ok(())
+}
+
+/// VTK XML unstructured grid, ASCII.
+pub fn write_vtu(w: &mut impl Write, state: &ParticleState) -> io::Result<()> {
+    let n = state.len();
+    writeln!(w, r#"<?xml version="1.0"?>"#)?;
+    writeln!(w, r#"<VTKFile type="UnstructuredGrid" version="0.1" byte_order="LittleEndian">"#)?;
+    writeln!(w, "  <UnstructuredGrid>")?;
+    writeln!(w, r#"    <Piece NumberOfPoints="{n}" NumberOfCells="{n}">"#)?;
+
+    writeln!(w, r#"      <PointData Scalars="density" Vectors="velocity">"#)?;
+    writeln!(w, r#"        <DataArray type="Float32" Name="velocity" NumberOfComponents="3" format="ascii">"#)?;
+    for v in &state.velocities {
+        writeln!(w, "{} {} {}", v[0], v[1], v[2])?;
+    }
+    writeln!(w, "        </DataArray>")?;
+    for (name, values) in [("density", &state.densities), ("pressure", &state.pressures)] {
+        writeln!(w, r#"        <DataArray type="Float32" Name="{name}" format="ascii">"#)?;
+        for value in values {
+            writeln!(w, "{value}")?;
+        }
+        writeln!(w, "        </DataArray>")?;
+    }
+    writeln!(w, "      </PointData>")?;
+
+    writeln!(w, "      <Points>")?;
+    writeln!(w, r#"        <DataArray type="Float32" NumberOfComponents="3" format="ascii">"#)?;
+    for p in &state.positions {
+        writeln!(w, "{} {} {}", p[0], p[1], p[2])?;
+    }
+    writeln!(w, "        </DataArray>")?;
+    writeln!(w, "      </Points>")?;
+
+    // One VTK_VERTEX (type 1) cell per particle.
+    writeln!(w, "      <Cells>")?;
+    writeln!(w, r#"        <DataArray type="Int32" Name="connectivity" format="ascii">"#)?;
+    for i in 0..n {
+        writeln!(w, "{i}")?;
+    }
+    writeln!(w, "        </DataArray>")?;
+    writeln!(w, r#"        <DataArray type="Int32" Name="offsets" format="ascii">"#)?;
+    for i in 1..=n {
+        writeln!(w, "{i}")?;
+    }
+    writeln!(w, "        </DataArray>")?;
+    writeln!(w, r#"        <DataArray type="UInt8" Name="types" format="ascii">"#)?;
+    for _ in 0..n {
+        writeln!(w, "1")?;
+    }
+    writeln!(w, "        </DataArray>")?;
+    writeln!(w, "      </Cells>")?;
+
+    writeln!(w, "    </Piece>")?;
+    writeln!(w, "  </UnstructuredGrid>")?;
+    writeln!(w, "</VTKFile>")
+}
+
+/// Binary little-endian PLY, one vertex per particle.
+pub fn write_ply(w: &mut impl Write, state: &ParticleState, time: f32) -> io::Result<()> {
+    writeln!(w, "ply")?;
+    writeln!(w, "format binary_little_endian 1.0")?;
+    writeln!(w, "comment Fluid Engine particles, t = {time}")?;
+    writeln!(w, "element vertex {}", state.len())?;
+    for property in ["x", "y", "z", "vx", "vy", "vz", "density", "pressure"] {
+        writeln!(w, "property float {property}")?;
+    }
+    writeln!(w, "end_header")?;
+
+    let mut bytes = Vec::with_capacity(state.len() * 8 * 4);
+    let attributes = state.positions.iter()
+        .zip(&state.velocities)
+        .zip(state.densities.iter().zip(&state.pressures));
+    for ((p, v), (density, pressure)) in attributes {
+        for value in [p[0], p[1], p[2], v[0], v[1], v[2], *density, *pressure] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    w.write_all(&bytes)
+}
+
+/// ParaView collection listing every written frame with its simulation time.
+pub fn write_pvd(w: &mut impl Write, frames: &[(f32, String)]) -> io::Result<()> {
+    writeln!(w, r#"<?xml version="1.0"?>"#)?;
+    writeln!(w, r#"<VTKFile type="Collection" version="0.1">"#)?;
+    writeln!(w, "  <Collection>")?;
+    for (time, file) in frames {
+        writeln!(w, r#"    <DataSet timestep="{time}" file="{file}"/>"#)?;
+    }
+    writeln!(w, "  </Collection>")?;
+    writeln!(w, "</VTKFile>")?;
+    w.flush()
+}
+
+/// Wavefront OBJ with per-vertex normals; indices are 1-based.
+pub fn write_obj(w: &mut impl Write, mesh: &SurfaceMesh, time: f32) -> io::Result<()> {
+    writeln!(w, "# Fluid Engine surface, t = {time}")?;
+    for p in &mesh.positions {
+        writeln!(w, "v {} {} {}", p.x, p.y, p.z)?;
+    }
+    for n in &mesh.normals {
+        writeln!(w, "vn {} {} {}", n.x, n.y, n.z)?;
+    }
+    for tri in mesh.indices.chunks_exact(3) {
+        let [a, b, c] = [tri[0] + 1, tri[1] + 1, tri[2] + 1];
+        writeln!(w, "f {a}//{a} {b}//{b} {c}//{c}")?;
+    }
+    Ok(())
+}
+
+/// Binary little-endian PLY triangle mesh with `nx ny nz` vertex normals.
+pub fn write_surface_ply(w: &mut impl Write, mesh: &SurfaceMesh, time: f32) -> io::Result<()> {
+    writeln!(w, "ply")?;
+    writeln!(w, "format binary_little_endian 1.0")?;
+    writeln!(w, "comment Fluid Engine surface, t = {time}")?;
+    writeln!(w, "element vertex {}", mesh.positions.len())?;
+    for property in ["x", "y", "z", "nx", "ny", "nz"] {
+        writeln!(w, "property float {property}")?;
+    }
+    writeln!(w, "element face {}", mesh.triangle_count())?;
+    writeln!(w, "property list uchar uint vertex_indices")?;
+    writeln!(w, "end_header")?;
+
+    let mut bytes = Vec::with_capacity(mesh.positions.len() * 6 * 4 + mesh.triangle_count() * 13);
+    for (p, n) in mesh.positions.iter().zip(&mesh.normals) {
+        for value in [p.x, p.y, p.z, n.x, n.y, n.z] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    for tri in mesh.indices.chunks_exact(3) {
+        bytes.push(3);
+        for index in tri {
+            bytes.extend_from_slice(&index.to_le_bytes());
+        }
+    }
+    w.write_all(&bytes)
+}
+
+fn write_be_f32s(w: &mut impl Write, values: impl Iterator<Item = f32>) -> io::Result<()> {
+    let bytes: Vec<u8> = values.flat_map(f32::to_be_bytes).collect();
+    w.write_all(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+    use super::*;
+
+    fn state() -> ParticleState {
+        ParticleState {
+            positions: vec![[0.0, 1.0, 2.0, 1.0], [3.0, 4.0, 5.0, 1.0], [6.0, 7.0, 8.0, 1.0]],
+            velocities: vec![[0.5, 0.0, 0.0, 0.0], [0.0, -1.5, 0.0, 0.0], [0.0, 0.0, 2.5, 0.0]],
+            densities: vec![1000.0, 1001.0, 999.0],
+            pressures: vec![0.0, 10.0, 20.0],
+        }
+    }
+
+    #[test]
+    fn ply_header_and_payload_size() {
+        let mut bytes = Vec::new();
+        write_ply(&mut bytes, &state(), 0.25).unwrap();
+
+        let header_end = bytes.windows(11).position(|w| w == b"end_header\n").unwrap() + 11;
+        let header = std::str::from_utf8(&bytes[..header_end]).unwrap();
+        assert!(header.contains("element vertex 3"));
+        assert!(header.contains("property float pressure"));
+        assert_eq!(bytes.len() - header_end, 3 * 8 * 4);
+
+        // Second vertex, vy.
+        let offset = header_end + (8 + 4) * 4;
+        assert_eq!(f32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()), -1.5);
+    }
+
+    #[test]
+    fn vtk_is_big_endian_polydata() {
+        let mut bytes = Vec::new();
+        write_vtk(&mut bytes, &state(), 0.0).unwrap();
+
+        let points = bytes.windows(15).position(|w| w == b"POINTS 3 float\n").unwrap() + 15;
+        assert_eq!(f32::from_be_bytes(bytes[points + 4..points + 8].try_into().unwrap()), 1.0);
+        let text = String::from_utf8_lossy(&bytes);
+        assert!(text.contains("VERTICES 3 6"));
+        assert!(text.contains("SCALARS pressure float 1"));
+    }
+
+    #[test]
+    fn vtu_lists_every_particle() {
+        let mut bytes = Vec::new();
+        write_vtu(&mut bytes, &state()).unwrap();
+        let text = String::from_utf8(bytes).unwrap();
+        assert!(text.contains(r#"NumberOfPoints="3" NumberOfCells="3""#));
+        assert!(text.contains("6 7 8\n"));
+        assert!(text.contains("0 -1.5 0\n"));
+    }
+
+    fn tetrahedron() -> SurfaceMesh {
+        let positions = vec![Vec3::ZERO, Vec3::X, Vec3::Y, Vec3::Z];
+        SurfaceMesh {
+            normals: positions.iter().map(|p| (*p - Vec3::splat(0.25)).normalize()).collect(),
+            positions,
+            indices: vec![0, 2, 1, 0, 1, 3, 0, 3, 2, 1, 2, 3],
+        }
+    }
+
+    #[test]
+    fn obj_is_one_based_with_normals() {
+        let mut bytes = Vec::new();
+        write_obj(&mut bytes, &tetrahedron(), 0.5).unwrap();
+        let text = String::from_utf8(bytes).unwrap();
+        assert_eq!(text.lines().filter(|l| l.starts_with("v ")).count(), 4);
+        assert_eq!(text.lines().filter(|l| l.starts_with("vn ")).count(), 4);
+        assert!(text.contains("f 1//1 3//3 2//2\n"), "{text}");
+    }
+
+    #[test]
+    fn surface_ply_header_and_payload_size() {
+        let mut bytes = Vec::new();
+        write_surface_ply(&mut bytes, &tetrahedron(), 0.0).unwrap();
+
+        let header_end = bytes.windows(11).position(|w| w == b"end_header\n").unwrap() + 11;
+        let header = std::str::from_utf8(&bytes[..header_end]).unwrap();
+        assert!(header.contains("element vertex 4"));
+        assert!(header.contains("element face 4"));
+        assert_eq!(bytes.len() - header_end, 4 * 6 * 4 + 4 * (1 + 3 * 4));
+    }
+
+    #[test]
+    fn exporter_numbers_frames_and_writes_collection() {
+        let dir = std::env::temp_dir().join(format!("fluid_engine_export_{}", std::process::id()));
+        let mut exporter = ParticleExporter::new(ExportSettings {
+            dir: dir.clone(),
+            format: ExportFormat::Vtk,
+            every_n_frames: 2,
+            ..ExportSettings::default()
+        });
+
+        let due: Vec<bool> = (0..5).map(|_| exporter.tick()).collect();
+        assert_eq!(due, [true, false, true, false, true]);
+
+        exporter.write_frame(&state(), 0.0).unwrap();
+        let second = exporter.write_frame(&state(), 0.1).unwrap();
+        assert_eq!(second, dir.join("particles_00001.vtk"));
+
+        let pvd = fs::read_to_string(dir.join("particles.pvd")).unwrap();
+        assert!(pvd.contains(r#"timestep="0.1" file="particles_00001.vtk""#), "{pvd}");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
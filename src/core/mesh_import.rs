@@ -0,0 +1,279 @@
+//! Triangle mesh import for scene files.
+//!
+//! * `obj` — Wavefront OBJ. Only `v` and `f` records are read; faces with more
+//!   than three corners are fanned, and `v/vt/vn` corners as well as negative
+//!   (relative) indices are accepted.
+//! * `stl` — binary or ASCII STL. STL stores every triangle with its own
+//!   corners, so bit-identical corners are welded on load.
+//!
+//! Meshes are used as obstacles (baked into a `MeshSdf` or sampled into
+//! boundary particles) and as the shape of `[[fluid]]` blocks. Both expect
+//! counter-clockwise triangles seen from outside.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use glam::{Quat, Vec3};
+use crate::errors::application_error::ApplicationError;
+
+/// Header plus triangle count, then 50 bytes per triangle.
+const STL_HEADER_LEN: usize = 84;
+const STL_TRIANGLE_LEN: usize = 50;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TriangleMesh {
+    pub vertices: Vec<Vec3>,
+    pub triangles: Vec<[u32; 3]>,
+}
+
+impl TriangleMesh {
+    /// Reads an `.obj` or `.stl` file, picked by extension.
+    pub fn load(path: &Path) -> Result<Self, ApplicationError> {
+        let bytes = fs::read(path).map_err(|e| {
+            ApplicationError::ResourceLoadError(format!("Failed to read mesh {}: {}", path.display(), e))
+        })?;
+        let extension = path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase);
+
+        let mesh = match extension.as_deref() {
+            Some("obj") => Self::parse_obj(&String::from_utf8_lossy(&bytes)),
+            Some("stl") => Self::parse_stl(&bytes),
+            _ => Err("unsupported mesh format, expected .obj or .stl".to_string()),
+        };
+        let mesh = mesh.map_err(|e| ApplicationError::ResourceLoadError(format!("{}: {}", path.display(), e)))?;
+        if mesh.triangles.is_empty() {
+            return Err(ApplicationError::ResourceLoadError(format!("{}: mesh has no triangles", path.display())));
+        }
+        Ok(mesh)
+    }
+
+    pub fn parse_obj(source: &str) -> Result<Self, String> {
+        let mut mesh = Self::default();
+
+        for (line_no, line) in source.lines().enumerate() {
+            let at_line = |message: String| format!("line {}: {}", line_no + 1, message);
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("v") => {
+                    let coords: Vec<f32> = tokens
+                        .take(3)
+                        .map(|t| t.parse::<f32>().map_err(|e| at_line(format!("bad vertex coordinate '{t}': {e}"))))
+                        .collect::<Result<_, _>>()?;
+                    if coords.len() < 3 {
+                        return Err(at_line("vertex needs three coordinates".to_string()));
+                    }
+                    mesh.vertices.push(Vec3::from_slice(&coords));
+                }
+                Some("f") => {
+                    let corners: Vec<u32> = tokens
+                        .map(|t| obj_index(t, mesh.vertices.len()).map_err(at_line))
+                        .collect::<Result<_, _>>()?;
+                    if corners.len() < 3 {
+                        return Err(at_line("face needs at least three corners".to_string()));
+                    }
+                    for k in 1..corners.len() - 1 {
+                        mesh.triangles.push([corners[0], corners[k], corners[k + 1]]);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(mesh)
+    }
+
+    pub fn parse_stl(bytes: &[u8]) -> Result<Self, String> {
+        // ASCII files start with "solid", but so do the headers of some binary
+        // exporters; the binary size check decides.
+        let binary_len = bytes
+            .get(80..STL_HEADER_LEN)
+            .map(|n| STL_HEADER_LEN + STL_TRIANGLE_LEN * u32::from_le_bytes(n.try_into().unwrap()) as usize);
+        if binary_len == Some(bytes.len()) || !bytes.starts_with(b"solid") {
+            Self::parse_binary_stl(bytes)
+        } else {
+            Self::parse_ascii_stl(&String::from_utf8_lossy(bytes))
+        }
+    }
+
+    fn parse_binary_stl(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < STL_HEADER_LEN {
+            return Err("binary STL is shorter than its header".to_string());
+        }
+        let count = u32::from_le_bytes(bytes[80..STL_HEADER_LEN].try_into().unwrap()) as usize;
+        if bytes.len() < STL_HEADER_LEN + count * STL_TRIANGLE_LEN {
+            return Err(format!("binary STL declares {count} triangles but is truncated"));
+        }
+
+        let mut welder = Welder::default();
+        for record in bytes[STL_HEADER_LEN..].chunks_exact(STL_TRIANGLE_LEN).take(count) {
+            // 12 bytes of facet normal, three corners, 2 bytes of attributes.
+            let corner = |k: usize| {
+                let at = 12 + 12 * k;
+                let f = |i: usize| f32::from_le_bytes(record[at + 4 * i..at + 4 * i + 4].try_into().unwrap());
+                Vec3::new(f(0), f(1), f(2))
+            };
+            welder.push([corner(0), corner(1), corner(2)]);
+        }
+        Ok(welder.mesh)
+    }
+
+    fn parse_ascii_stl(source: &str) -> Result<Self, String> {
+        let mut welder = Welder::default();
+        let mut corners = Vec::with_capacity(3);
+
+        for (line_no, line) in source.lines().enumerate() {
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("vertex") => {
+                    let coords: Vec<f32> = tokens
+                        .map(|t| t.parse::<f32>())
+                        .collect::<Result<_, _>>()
+                        .map_err(|e| format!("line {}: bad vertex coordinate: {e}", line_no + 1))?;
+                    if coords.len() != 3 {
+                        return Err(format!("line {}: vertex needs three coordinates", line_no + 1));
+                    }
+                    corners.push(Vec3::from_slice(&coords));
+                }
+                Some("endloop") => {
+                    let [a, b, c] = corners[..] else {
+                        return Err(format!("line {}: facet needs exactly three vertices", line_no + 1));
+                    };
+                    welder.push([a, b, c]);
+                    corners.clear();
+                }
+                _ => {}
+            }
+        }
+        if welder.mesh.triangles.is_empty() {
+            return Err("ASCII STL holds no facets".to_string());
+        }
+        Ok(welder.mesh)
+    }
+
+    /// Scales, then rotates, then translates every vertex.
+    pub fn transformed(&self, scale: f32, rotation: Quat, translation: Vec3) -> Self {
+        Self {
+            vertices: self.vertices.iter().map(|&v| rotation * (v * scale) + translation).collect(),
+            triangles: self.triangles.clone(),
+        }
+    }
+
+    /// Axis-aligned bounds of the vertices actually used by triangles.
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        self.triangles
+            .iter()
+            .flatten()
+            .map(|&i| self.vertices[i as usize])
+            .fold((Vec3::MAX, Vec3::MIN), |(lo, hi), v| (lo.min(v), hi.max(v)))
+    }
+}
+
+/// Resolves one `v`, `v/vt`, `v//vn` or `v/vt/vn` face corner to a 0-based
+/// vertex index.
+fn obj_index(token: &str, vertex_count: usize) -> Result<u32, String> {
+    let index_token = token.split('/').next().unwrap_or_default();
+    let index: i64 = index_token.parse().map_err(|e| format!("bad face index '{token}': {e}"))?;
+    let resolved = match index {
+        i if i > 0 => i - 1,
+        i if i < 0 => vertex_count as i64 + i,
+        _ => -1,
+    };
+    if resolved < 0 || resolved >= vertex_count as i64 {
+        return Err(format!("face index {index} refers to a missing vertex"));
+    }
+    Ok(resolved as u32)
+}
+
+/// Builds an indexed mesh from triangle soup, merging bit-identical corners.
+#[derive(Default)]
+struct Welder {
+    mesh: TriangleMesh,
+    ids: HashMap<[u32; 3], u32>,
+}
+
+impl Welder {
+    fn push(&mut self, corners: [Vec3; 3]) {
+        let triangle = corners.map(|v| {
+            *self.ids.entry(v.to_array().map(f32::to_bits)).or_insert_with(|| {
+                self.mesh.vertices.push(v);
+                self.mesh.vertices.len() as u32 - 1
+            })
+        });
+        self.mesh.triangles.push(triangle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::export::write_obj;
+    use crate::cpu::marching_cubes::SurfaceMesh;
+
+    #[test]
+    fn obj_faces_are_fanned_and_indices_resolved() {
+        let mesh = TriangleMesh::parse_obj(
+            "# quad and a relative triangle\n\
+             v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+             vt 0 0\nvn 0 0 1\n\
+             f 1/1/1 2/1/1 3/1/1 4/1/1\n\
+             v 0 0 1\n\
+             f -1 -5 -4\n",
+        ).unwrap();
+        assert_eq!(mesh.vertices.len(), 5);
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 2, 3], [4, 0, 1]]);
+
+        let error = TriangleMesh::parse_obj("v 0 0 0\nf 1 2 3\n").unwrap_err();
+        assert!(error.starts_with("line 2:"), "{error}");
+    }
+
+    #[test]
+    fn exported_surface_reads_back() {
+        let positions = vec![Vec3::ZERO, Vec3::X, Vec3::Y, Vec3::Z];
+        let surface = SurfaceMesh {
+            normals: positions.iter().map(|p| (*p - Vec3::splat(0.25)).normalize()).collect(),
+            positions: positions.clone(),
+            indices: vec![0, 2, 1, 0, 1, 3, 0, 3, 2, 1, 2, 3],
+        };
+        let mut bytes = Vec::new();
+        write_obj(&mut bytes, &surface, 0.0).unwrap();
+
+        let mesh = TriangleMesh::parse_obj(&String::from_utf8(bytes).unwrap()).unwrap();
+        assert_eq!(mesh.vertices, positions);
+        assert_eq!(mesh.triangles, vec![[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]]);
+    }
+
+    #[test]
+    fn binary_and_ascii_stl_weld_to_the_same_mesh() {
+        let facets = [
+            [Vec3::ZERO, Vec3::Y, Vec3::X],
+            [Vec3::ZERO, Vec3::X, Vec3::Z],
+        ];
+
+        let mut ascii = String::from("solid pair\n");
+        for facet in &facets {
+            ascii.push_str("  facet normal 0 0 0\n    outer loop\n");
+            for v in facet {
+                ascii.push_str(&format!("      vertex {} {} {}\n", v.x, v.y, v.z));
+            }
+            ascii.push_str("    endloop\n  endfacet\n");
+        }
+        ascii.push_str("endsolid pair\n");
+
+        // A binary header that starts with "solid", as some exporters write.
+        let mut binary = b"solid".to_vec();
+        binary.resize(80, 0);
+        binary.extend((facets.len() as u32).to_le_bytes());
+        for facet in &facets {
+            binary.extend([0u8; 12]);
+            for v in facet {
+                binary.extend(v.to_array().iter().flat_map(|c| c.to_le_bytes()));
+            }
+            binary.extend([0u8; 2]);
+        }
+
+        let from_ascii = TriangleMesh::parse_stl(ascii.as_bytes()).unwrap();
+        let from_binary = TriangleMesh::parse_stl(&binary).unwrap();
+        assert_eq!(from_ascii, from_binary);
+        assert_eq!(from_ascii.vertices.len(), 4);
+        assert_eq!(from_ascii.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+        assert!(TriangleMesh::parse_stl(&binary[..100]).is_err());
+    }
+}
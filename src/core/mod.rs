@@ -1,5 +1,10 @@
 
 
+pub mod checkpoint;
 pub mod engine;
+pub mod export;
+pub mod mesh_import;
 pub mod scene;
-mod controller;
\ No newline at end of file
+pub mod scene_file;
+mod controller;
+pub mod simulation;
\ No newline at end of file
@@ -1,87 +1,408 @@
-use glam::{IVec3, Vec3};
-use log::info;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use glam::{IVec3, Vec2, Vec3};
+use log::{info, warn};
+use crate::core::scene_file::{
+    BackendKind, BoundaryMotionDescription, EmitterDescription, EmitterKind, GridTransferKind, InterpolationKind, KernelKind, KeyframeDescription,
+    MeshBoundary, ObstacleDescription, ObstacleKind, PressureSolverKind, RigidBodyDescription, RigidBodyKind, SceneDescription, ViscosityModeKind,
+};
+use crate::cpu::boundary::sample_mesh;
+use crate::cpu::mesh_sdf::MeshSdf;
+use crate::entities::boundary_motion::{BoundaryMotion, Interpolation, Keyframe};
 use crate::entities::camera::Camera;
 use crate::entities::collision::CollisionBox;
-use crate::entities::particle::{ParticleGenerator, SimulationParams};
+use crate::entities::diffuse::DiffuseSettings;
+use crate::entities::emitter::{Emitter, EmitterShape};
+use crate::entities::obstacle::{Obstacle, ObstacleShape};
+use crate::entities::particle::{GpuPhase, GridTransfer, ParticleGenerator, PressureSolver, SimulationBackend, SimulationParams, SphKernel, ViscosityMode};
+use crate::entities::rigid_body::RigidBody;
+use crate::errors::application_error::ApplicationError;
+use crate::utils::constants::{DEFAULT_CHECKPOINT_PATH, DEFAULT_EMITTED_PARTICLES};
 
 pub struct Scene {
     pub initial_positions: Vec<[f32; 3]>,
+    /// Phase of each initial particle, indexing `sim_params.phases`.
+    pub initial_phases: Vec<u32>,
+    /// Names of the phases after the base fluid, in phase-table order.
+    pub phase_names: Vec<String>,
     pub sim_params: SimulationParams,
     pub camera: Camera,
     pub boundary: CollisionBox,
+    pub obstacles: Vec<Obstacle>,
+    /// World-space boundary particles of the `boundary = "particles"` meshes.
+    pub mesh_boundary: Vec<Vec3>,
+    pub emitters: Vec<Emitter>,
+    pub sinks: Vec<Obstacle>,
+    pub rigid_bodies: Vec<RigidBody>,
+    /// Particles the simulation has room for, the initial ones included.
+    pub particle_capacity: u32,
+    /// Spray, foam and bubbles; toggled and tuned from the UI at runtime.
+    pub diffuse: DiffuseSettings,
+    pub sky_hdri: PathBuf,
+    pub playback: Playback,
 }
 
-impl Scene {
-    pub fn new() -> Self {
-        let particle_radius = 0.020;
-        let target_density = 1000.0;
-
-        let box_min = Vec3::new(-1.5, 0.0, -1.0);
-        let box_max = Vec3::new(0.8, 4.0, 1.0);
-        let mut collision_box = CollisionBox::new(box_min, box_max);
-        collision_box.wave_amplitude = 0.3;
-        collision_box.wave_frequency = 0.5;
-
+/// Run / pause / single-step state of the solver, driven by the keyboard and
+/// the UI panel and consumed by the renderer once per frame.
+pub struct Playback {
+    pub paused: bool,
+    /// Substeps advanced by one "step" request.
+    pub substeps_per_step: u32,
+    /// Target of checkpoint save / load requests.
+    pub checkpoint_path: PathBuf,
+    pending_substeps: u32,
+    reset_requested: bool,
+    save_requested: bool,
+    load_requested: bool,
+}
 
-        let spawn_pos = Vec3::new(-1.0, 1.0, -0.8);
+impl Playback {
+    pub fn new() -> Self {
+        Self {
+            paused: false,
+            substeps_per_step: 1,
+            checkpoint_path: PathBuf::from(DEFAULT_CHECKPOINT_PATH),
+            pending_substeps: 0,
+            reset_requested: false,
+            save_requested: false,
+            load_requested: false,
+        }
+    }
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+    /// Pauses and queues `substeps_per_step` substeps for the next frame.
+    pub fn request_step(&mut self) {
+        self.paused = true;
+        self.pending_substeps += self.substeps_per_step.max(1);
+    }
+    pub fn request_reset(&mut self) {
+        self.reset_requested = true;
+        self.pending_substeps = 0;
+    }
+    pub fn request_save(&mut self) {
+        self.save_requested = true;
+    }
+    pub fn request_load(&mut self) {
+        self.load_requested = true;
+        self.pending_substeps = 0;
+    }
+    pub fn take_reset(&mut self) -> bool {
+        std::mem::take(&mut self.reset_requested)
+    }
+    pub fn take_save(&mut self) -> bool {
+        std::mem::take(&mut self.save_requested)
+    }
+    pub fn take_load(&mut self) -> bool {
+        std::mem::take(&mut self.load_requested)
+    }
+    pub fn take_pending_substeps(&mut self) -> u32 {
+        std::mem::take(&mut self.pending_substeps)
+    }
+}
 
-        let water_width = 1.0;
-        let water_height = 2.0;
-        let water_depth = 0.8;
+impl Scene {
+    /// The built-in scene; same as loading an empty scene file.
+    pub fn new() -> Self {
+        Self::from_description(&SceneDescription::default())
+    }
 
-        let spacing = particle_radius * 2.0;
+    /// Loads and validates a TOML scene file (see `scenes/default.toml`).
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ApplicationError> {
+        let path = path.as_ref();
+        let description = SceneDescription::load(path)?;
 
-        let (initial_positions, particle_mass) = ParticleGenerator::generate_volume(
-            spawn_pos,
-            water_width,
-            water_height,
-            water_depth,
-            particle_radius,
-            target_density,
-            spacing,
-            0.01
-        );
+        if !description.sky.hdri.is_file() {
+            return Err(ApplicationError::ResourceLoadError(format!(
+                "Sky HDRI not found: {}",
+                description.sky.hdri.display()
+            )));
+        }
 
-        let mut camera = Camera::new(Vec3::new(0.0, 1.5, -3.5));
-        camera.rotate(0.0, 0.0, 0.0);
+        info!("[Scene] Loaded scene file {}.", path.display());
+        Ok(Self::from_description(&description))
+    }
 
+    /// Builds a scene from a description that passed `SceneDescription::validate`
+    /// with its mesh files loaded.
+    pub fn from_description(description: &SceneDescription) -> Self {
+        let sim = &description.simulation;
+        let particle_radius = sim.particle_radius;
+        let target_density = sim.target_density;
 
-        let smoothing_radius = particle_radius * 4.0;
+        let box_min = Vec3::from_array(description.boundary.min);
+        let box_max = Vec3::from_array(description.boundary.max);
+        let mut collision_box = CollisionBox::new(box_min, box_max);
+        collision_box.wave_amplitude = description.boundary.wave_amplitude;
+        collision_box.wave_frequency = description.boundary.wave_frequency;
+        collision_box.set_motion(description.boundary.motion.as_ref().map(build_motion));
 
+        let spacing = sim.particle_spacing();
+        let obstacles: Vec<Obstacle> = description.obstacles.iter()
+            .map(|obstacle| build_obstacle(obstacle, particle_radius))
+            .collect();
+        // Sampled at the particle radius, twice as dense as the box walls, as
+        // meshes have sharper features.
+        let mesh_boundary: Vec<Vec3> = description.obstacles.iter()
+            .filter(|obstacle| obstacle.boundary == MeshBoundary::Particles)
+            .flat_map(|obstacle| {
+                let mesh = obstacle.local_mesh().transformed(1.0, obstacle.rotation(), Vec3::from_array(obstacle.position));
+                sample_mesh(&mesh.vertices, &mesh.triangles, particle_radius)
+            })
+            .collect();
 
-        let dt = 0.005;
+        let mut fluid: Vec<([f32; 3], u32)> = Vec::new();
+        // Emitted fluid has no block to take its mass from.
+        let mut particle_mass = target_density * spacing.powi(3);
+        for block in &description.fluid_blocks.0 {
+            let (positions, mass) = if block.is_mesh() {
+                let mesh = block.world_mesh();
+                ParticleGenerator::generate_mesh_volume(
+                    &mesh.vertices,
+                    &mesh.triangles,
+                    particle_radius,
+                    target_density,
+                    spacing,
+                    block.jitter
+                )
+            } else {
+                ParticleGenerator::generate_volume(
+                    Vec3::from_array(block.origin),
+                    block.size[0],
+                    block.size[1],
+                    block.size[2],
+                    particle_radius,
+                    target_density,
+                    spacing,
+                    block.jitter
+                )
+            };
+            let phase = description.phase_index(block.phase.as_deref());
+            fluid.extend(positions.into_iter().map(|p| (p, phase)));
+            particle_mass = mass;
+        }
+        let rigid_bodies: Vec<RigidBody> = description.rigid_bodies.iter().map(build_rigid_body).collect();
+        // Fluid filled into an obstacle would be shot out of it on the first step.
+        let solids: Vec<Obstacle> = obstacles.iter().cloned().chain(rigid_bodies.iter().map(RigidBody::obstacle)).collect();
+        fluid.retain(|(p, _)| {
+            solids.iter().all(|o| o.distance(Vec3::from_array(*p)) >= particle_radius)
+        });
+        remove_near(&mut fluid, &mesh_boundary, spacing);
+        let (initial_positions, initial_phases): (Vec<[f32; 3]>, Vec<u32>) = fluid.into_iter().unzip();
 
-        let viscosity = 0.15;
-        let relax_factor = 0.5;
+        let emitters: Vec<Emitter> = description.emitters.iter()
+            .map(|emitter| {
+                let mut built = build_emitter(emitter, spacing);
+                built.phase = description.phase_index(emitter.phase.as_deref());
+                built
+            })
+            .collect();
+        let sinks: Vec<Obstacle> = description.sinks.iter()
+            .map(|sink| build_obstacle(sink, particle_radius))
+            .collect();
 
-        let density_iterations = 4;
-        let divergence_iterations = 4;
+        let initial_count = initial_positions.len() as u32;
+        let particle_capacity = match sim.max_particles {
+            Some(max) if max < initial_count => {
+                warn!("[Scene] max_particles = {max} is below the {initial_count} initial particles; raising it.");
+                initial_count
+            }
+            Some(max) => max,
+            None if emitters.is_empty() => initial_count,
+            None => initial_count + DEFAULT_EMITTED_PARTICLES,
+        };
 
-        let grid_res = IVec3::new(128, 128, 128);
+        let camera_desc = &description.camera;
+        let mut camera = Camera::new(Vec3::from_array(camera_desc.position));
+        camera.rotate(camera_desc.rotation[0], camera_desc.rotation[1], camera_desc.rotation[2]);
+        camera.fov(camera_desc.fov);
 
-        let sim_params = SimulationParams::new(
+        let mut sim_params = SimulationParams::new(
             particle_radius,
             particle_mass,
-            smoothing_radius,
+            sim.smoothing_radius(),
             target_density,
-            viscosity,
-            relax_factor,
-            dt,
-            density_iterations,
-            divergence_iterations,
-            Vec3::new(0.0, -9.81, 0.0),
+            sim.viscosity,
+            sim.relax_factor,
+            sim.dt,
+            sim.density_iterations,
+            sim.divergence_iterations,
+            Vec3::from_array(sim.gravity),
             box_min,
             box_max,
-            grid_res,
+            IVec3::from_array(sim.grid_resolution),
         );
+        sim_params.surface_tension = sim.surface_tension;
+        sim_params.adhesion = sim.adhesion;
+        sim_params.vorticity_confinement = sim.vorticity_confinement;
+        sim_params.set_kernel(match sim.kernel {
+            KernelKind::CubicSpline => SphKernel::CubicSpline,
+            KernelKind::WendlandC2 => SphKernel::WendlandC2,
+            KernelKind::WendlandC4 => SphKernel::WendlandC4,
+            KernelKind::Poly6Spiky => SphKernel::Poly6Spiky,
+        });
+        sim_params.set_viscosity_mode(match sim.viscosity_mode {
+            ViscosityModeKind::Explicit => ViscosityMode::Explicit,
+            ViscosityModeKind::Implicit => ViscosityMode::Implicit,
+        });
+        sim_params.viscosity_iterations = sim.viscosity_iterations;
+        sim_params.viscosity_tolerance = sim.viscosity_tolerance;
+        sim_params.set_pressure_solver(match sim.pressure_solver {
+            PressureSolverKind::Dfsph => PressureSolver::Dfsph,
+            PressureSolverKind::Wcsph => PressureSolver::Wcsph,
+            PressureSolverKind::Pcisph => PressureSolver::Pcisph,
+            PressureSolverKind::Iisph => PressureSolver::Iisph,
+            PressureSolverKind::Pbf => PressureSolver::Pbf,
+        });
+        sim_params.stiffness = sim.stiffness;
+        sim_params.set_backend(match sim.backend {
+            BackendKind::Sph => SimulationBackend::Sph,
+            BackendKind::Flip => SimulationBackend::Flip,
+        });
+        sim_params.set_grid_transfer(match sim.grid_transfer {
+            GridTransferKind::Pic => GridTransfer::Pic,
+            GridTransferKind::Flip => GridTransfer::Flip,
+            GridTransferKind::Apic => GridTransfer::Apic,
+        });
+        sim_params.flip_ratio = sim.flip_ratio;
+        sim_params.grid_iterations = sim.grid_iterations;
+        let phases: Vec<GpuPhase> = description.phases.iter()
+            .map(|phase| GpuPhase::new(
+                phase.color,
+                phase.density,
+                phase.viscosity.unwrap_or(sim.viscosity),
+                phase.surface_tension.unwrap_or(sim.surface_tension),
+            ))
+            .collect();
+        sim_params.set_phases(sim.color, &phases);
+        collision_box.apply_to(&mut sim_params);
 
-        info!("[Scene] Created new scene with {} particles.", initial_positions.len());
+        info!(
+            "[Scene] Created new scene with {} particles in {} phases (capacity {}), {} obstacles, {} mesh boundary particles, {} emitters, {} sinks and {} rigid bodies.",
+            initial_positions.len(),
+            sim_params.phase_count(),
+            particle_capacity,
+            obstacles.len(),
+            mesh_boundary.len(),
+            emitters.len(),
+            sinks.len(),
+            rigid_bodies.len()
+        );
 
         Self {
             initial_positions,
+            initial_phases,
+            phase_names: description.phases.iter().map(|phase| phase.name.clone()).collect(),
             sim_params,
             camera,
             boundary: collision_box,
+            obstacles,
+            mesh_boundary,
+            emitters,
+            sinks,
+            rigid_bodies,
+            particle_capacity,
+            diffuse: description.diffuse.settings(),
+            sky_hdri: description.sky.hdri.clone(),
+            playback: Playback::new(),
+        }
+    }
+
+    /// What the fluid collides with: the obstacles, then one per rigid body
+    /// in its current pose. `Simulation::read_obstacle_forces` reports in
+    /// this order.
+    pub fn solid_obstacles(&self) -> Vec<Obstacle> {
+        self.obstacles.iter().cloned().chain(self.rigid_bodies.iter().map(RigidBody::obstacle)).collect()
+    }
+}
+
+/// Mesh obstacles are baked at half the particle spacing, fine enough for the
+/// trilinear SDF to resolve shells one particle thick.
+fn build_obstacle(description: &ObstacleDescription, particle_radius: f32) -> Obstacle {
+    let shape = match description.shape {
+        ObstacleKind::Sphere => ObstacleShape::Sphere { radius: description.radius },
+        ObstacleKind::Capsule => ObstacleShape::Capsule {
+            radius: description.radius,
+            half_height: description.half_height,
+        },
+        ObstacleKind::Box => ObstacleShape::Box { half_extents: Vec3::from_array(description.half_extents) },
+        ObstacleKind::Mesh => {
+            let mesh = description.local_mesh();
+            let sdf = MeshSdf::bake(&mesh.vertices, &mesh.triangles, particle_radius, description.thickness);
+            ObstacleShape::Mesh(Arc::new(sdf))
         }
+    };
+    let mut obstacle = Obstacle::new(shape, Vec3::from_array(description.position), description.rotation());
+    obstacle.angular_velocity = Vec3::from_array(description.angular_velocity.map(f32::to_radians));
+    obstacle
+}
+
+fn build_rigid_body(description: &RigidBodyDescription) -> RigidBody {
+    let position = Vec3::from_array(description.position);
+    let mut body = match description.shape {
+        RigidBodyKind::Sphere => RigidBody::sphere(description.radius, description.density, position, description.rotation()),
+        RigidBodyKind::Box => RigidBody::cuboid(
+            Vec3::from_array(description.half_extents),
+            description.density,
+            position,
+            description.rotation(),
+        ),
+    };
+    body.set_initial_velocity(
+        Vec3::from_array(description.velocity),
+        Vec3::from_array(description.angular_velocity.map(f32::to_radians)),
+    );
+    body
+}
+
+fn build_motion(description: &BoundaryMotionDescription) -> BoundaryMotion {
+    let keys = |keys: &[KeyframeDescription]| -> Vec<Keyframe> {
+        keys.iter().map(|k| Keyframe { time: k.time, value: Vec3::from_array(k.value) }).collect()
+    };
+    BoundaryMotion {
+        translation: keys(&description.translation),
+        rotation: keys(&description.rotation),
+        interpolation: match description.interpolation {
+            InterpolationKind::Linear => Interpolation::Linear,
+            InterpolationKind::Smooth => Interpolation::Smooth,
+        },
+        looped: description.looped,
+    }
+}
+
+fn build_emitter(description: &EmitterDescription, spacing: f32) -> Emitter {
+    let shape = match description.shape {
+        EmitterKind::Nozzle => EmitterShape::Nozzle { radius: description.radius },
+        EmitterKind::Plane => EmitterShape::Plane { half_size: Vec2::from_array(description.size) * 0.5 },
+    };
+    Emitter::new(
+        shape,
+        Vec3::from_array(description.position),
+        Vec3::from_array(description.direction),
+        description.speed,
+        spacing,
+        description.rate,
+    )
+}
+
+/// Drops the particles closer than `distance` to any of `samples`.
+fn remove_near(particles: &mut Vec<([f32; 3], u32)>, samples: &[Vec3], distance: f32) {
+    if samples.is_empty() {
+        return;
     }
-}
\ No newline at end of file
+    let cell = |p: Vec3| (p / distance).floor().as_ivec3();
+    let mut grid: HashMap<IVec3, Vec<Vec3>> = HashMap::new();
+    for &sample in samples {
+        grid.entry(cell(sample)).or_default().push(sample);
+    }
+
+    particles.retain(|(p, _)| {
+        let p = Vec3::from_array(*p);
+        let c = cell(p);
+        (-1..=1).flat_map(|z| (-1..=1).flat_map(move |y| (-1..=1).map(move |x| c + IVec3::new(x, y, z))))
+            .filter_map(|c| grid.get(&c))
+            .flatten()
+            .all(|sample| sample.distance(p) >= distance)
+    });
+}
@@ -0,0 +1,1306 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use glam::{EulerRot, Quat, Vec3};
+use serde::Deserialize;
+use crate::core::mesh_import::TriangleMesh;
+use crate::entities::diffuse::{DiffuseParams, DiffuseSettings};
+use crate::entities::particle::{DEFAULT_FLUID_COLOR, MAX_PHASES};
+use crate::errors::application_error::ApplicationError;
+use crate::utils::constants::{DEFAULT_DIFFUSE_PARTICLES, DEFAULT_SKY_HDRI};
+
+/// Declarative scene, deserialized from a TOML file (see `scenes/`).
+///
+/// Every field has a default matching the built-in scene, so a file only lists
+/// what it changes. `[[fluid]]` blocks replace the default block as a whole;
+/// `fluid = []` starts empty, for scenes filled by `[[emitter]]`s.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SceneDescription {
+    pub simulation: SimulationDescription,
+    pub boundary: BoundaryDescription,
+    /// Fluids besides the base one of `[simulation]`, referred to by name
+    /// from `[[fluid]]` blocks and `[[emitter]]`s.
+    #[serde(rename = "phase")]
+    pub phases: Vec<PhaseDescription>,
+    #[serde(rename = "fluid")]
+    pub fluid_blocks: FluidBlocks,
+    #[serde(rename = "obstacle")]
+    pub obstacles: Vec<ObstacleDescription>,
+    #[serde(rename = "emitter")]
+    pub emitters: Vec<EmitterDescription>,
+    /// Regions that remove the fluid entering them, with the same shapes as
+    /// obstacles. Sinks are static and do not collide.
+    #[serde(rename = "sink")]
+    pub sinks: Vec<ObstacleDescription>,
+    #[serde(rename = "rigid_body")]
+    pub rigid_bodies: Vec<RigidBodyDescription>,
+    pub diffuse: DiffuseDescription,
+    pub camera: CameraDescription,
+    pub sky: SkyDescription,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimulationDescription {
+    pub particle_radius: f32,
+    pub target_density: f32,
+    /// Defaults to `4 * particle_radius`.
+    pub smoothing_radius: Option<f32>,
+    /// Smoothing kernel of every solver pass; `h` is its support radius.
+    pub kernel: KernelKind,
+    /// A blend factor for the explicit mode, the kinematic viscosity [m²/s]
+    /// for the implicit one. Also the default of every `[[phase]]`.
+    pub viscosity: f32,
+    pub viscosity_mode: ViscosityModeKind,
+    /// Conjugate gradient iteration cap and relative tolerance of the
+    /// implicit viscosity solve.
+    pub viscosity_iterations: u32,
+    pub viscosity_tolerance: f32,
+    /// Akinci surface tension γ; 0 disables it.
+    pub surface_tension: f32,
+    /// Akinci fluid-wall adhesion β; 0 disables it.
+    pub adhesion: f32,
+    /// Vorticity confinement strength ε; 0 disables it.
+    pub vorticity_confinement: f32,
+    pub pressure_solver: PressureSolverKind,
+    /// Tait stiffness B [Pa] of WCSPH; larger is less compressible but needs
+    /// a smaller `dt`.
+    pub stiffness: f32,
+    /// `flip` replaces the SPH pressure solve with a FLIP/PIC/APIC grid pass;
+    /// the grid cells are about `smoothing_radius` wide.
+    pub backend: BackendKind,
+    pub grid_transfer: GridTransferKind,
+    /// Share of FLIP in the FLIP transfer's blend with PIC.
+    pub flip_ratio: f32,
+    /// Jacobi sweeps of the grid's pressure projection.
+    pub grid_iterations: u32,
+    pub relax_factor: f32,
+    pub dt: f32,
+    /// Iterations of the density solve; divergence iterations only apply to
+    /// DFSPH, and WCSPH takes neither.
+    pub density_iterations: u32,
+    pub divergence_iterations: u32,
+    pub gravity: [f32; 3],
+    pub grid_resolution: [i32; 3],
+    /// Room for particles, emitted ones included. Defaults to the initial
+    /// particles, plus `DEFAULT_EMITTED_PARTICLES` if there are emitters.
+    pub max_particles: Option<u32>,
+    /// RGB the base fluid's particles are drawn with.
+    pub color: [f32; 3],
+}
+
+impl Default for SimulationDescription {
+    fn default() -> Self {
+        Self {
+            particle_radius: 0.02,
+            target_density: 1000.0,
+            smoothing_radius: None,
+            kernel: KernelKind::CubicSpline,
+            viscosity: 0.15,
+            viscosity_mode: ViscosityModeKind::Explicit,
+            viscosity_iterations: 100,
+            viscosity_tolerance: 1e-3,
+            surface_tension: 0.0,
+            adhesion: 0.0,
+            vorticity_confinement: 0.0,
+            pressure_solver: PressureSolverKind::Dfsph,
+            stiffness: 5000.0,
+            backend: BackendKind::Sph,
+            grid_transfer: GridTransferKind::Flip,
+            flip_ratio: 0.95,
+            grid_iterations: 50,
+            relax_factor: 0.5,
+            dt: 0.005,
+            density_iterations: 4,
+            divergence_iterations: 4,
+            gravity: [0.0, -9.81, 0.0],
+            grid_resolution: [128, 128, 128],
+            max_particles: None,
+            color: DEFAULT_FLUID_COLOR,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KernelKind {
+    #[default]
+    CubicSpline,
+    WendlandC2,
+    WendlandC4,
+    /// Poly6 for the density, Spiky for the gradients.
+    Poly6Spiky,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ViscosityModeKind {
+    /// XSPH velocity blend; unstable above a viscosity of about 0.5.
+    #[default]
+    Explicit,
+    /// Weiler et al. 2018, solved by conjugate gradient on the GPU.
+    Implicit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PressureSolverKind {
+    /// Divergence-free SPH: density and divergence solves.
+    #[default]
+    Dfsph,
+    /// Weakly compressible, pressure from the Tait equation of state.
+    Wcsph,
+    /// Predictive-corrective incompressible SPH.
+    Pcisph,
+    /// Implicit incompressible SPH.
+    Iisph,
+    /// Position based fluids.
+    Pbf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendKind {
+    #[default]
+    Sph,
+    /// Hybrid particle-grid solver on a MAC grid over the box.
+    Flip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GridTransferKind {
+    /// Particles take the grid velocity; stable but dissipative.
+    Pic,
+    /// Particles take the grid's velocity change, blended with PIC.
+    #[default]
+    Flip,
+    /// Affine particle-in-cell (Jiang et al. 2015).
+    Apic,
+}
+
+impl SimulationDescription {
+    pub fn smoothing_radius(&self) -> f32 {
+        self.smoothing_radius.unwrap_or(self.particle_radius * 4.0)
+    }
+    pub fn particle_spacing(&self) -> f32 {
+        self.particle_radius * 2.0
+    }
+}
+
+/// Collision box. The min-x wall oscillates as `wave_amplitude * sin(2π f t)`;
+/// `[boundary.motion]` moves and rotates the whole box.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BoundaryDescription {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+    pub wave_amplitude: f32,
+    pub wave_frequency: f32,
+    pub motion: Option<BoundaryMotionDescription>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InterpolationKind {
+    #[default]
+    Linear,
+    Smooth,
+}
+
+/// Keyframed box motion: `translation` offsets and `rotation` XYZ Euler
+/// angles [deg] about the box centre, each a list of `{ time, value }` keys.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BoundaryMotionDescription {
+    pub interpolation: InterpolationKind,
+    #[serde(rename = "loop")]
+    pub looped: bool,
+    pub translation: Vec<KeyframeDescription>,
+    pub rotation: Vec<KeyframeDescription>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyframeDescription {
+    pub time: f32,
+    pub value: [f32; 3],
+}
+
+impl Default for BoundaryDescription {
+    fn default() -> Self {
+        Self {
+            min: [-1.5, 0.0, -1.0],
+            max: [0.8, 4.0, 1.0],
+            wave_amplitude: 0.3,
+            wave_frequency: 0.5,
+            motion: None,
+        }
+    }
+}
+
+/// Additional fluid with its own rest `density` in kg/m³. Particles of every
+/// phase fill the same volume, so a lighter phase rises through a heavier
+/// one. Surface tension only acts within a phase, which keeps the phases from
+/// mixing. `viscosity` and `surface_tension` default to those of `[simulation]`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PhaseDescription {
+    pub name: String,
+    pub density: f32,
+    #[serde(default)]
+    pub viscosity: Option<f32>,
+    #[serde(default)]
+    pub surface_tension: Option<f32>,
+    pub color: [f32; 3],
+}
+
+/// Block of fluid filled on a `2 * particle_radius` lattice: an axis-aligned
+/// box of `size` at `origin`, or the inside of a closed triangle mesh. A mesh
+/// comes from an `.obj` / `.stl` file (`mesh`) or inline `vertices` and
+/// `triangles`; it is scaled, rotated and then moved to `origin`. `phase`
+/// names a `[[phase]]`; the block is the base fluid without it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FluidBlock {
+    pub origin: [f32; 3],
+    #[serde(default)]
+    pub size: [f32; 3],
+    #[serde(default = "FluidBlock::default_jitter")]
+    pub jitter: f32,
+    /// Relative paths are resolved against the scene file.
+    #[serde(default)]
+    pub mesh: Option<PathBuf>,
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    /// Rotation of the mesh about x, y, z in degrees, applied in that order.
+    #[serde(default)]
+    pub rotation: [f32; 3],
+    #[serde(default)]
+    pub vertices: Vec<[f32; 3]>,
+    #[serde(default)]
+    pub triangles: Vec<[u32; 3]>,
+    #[serde(default)]
+    pub phase: Option<String>,
+}
+
+impl FluidBlock {
+    fn default_jitter() -> f32 {
+        0.01
+    }
+
+    pub fn is_mesh(&self) -> bool {
+        self.mesh.is_some() || !self.triangles.is_empty()
+    }
+
+    /// The mesh in world space; empty for box blocks.
+    pub fn world_mesh(&self) -> TriangleMesh {
+        let [x, y, z] = self.rotation.map(f32::to_radians);
+        inline_mesh(&self.vertices, &self.triangles)
+            .transformed(self.scale, Quat::from_euler(EulerRot::XYZ, x, y, z), Vec3::from_array(self.origin))
+    }
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(transparent)]
+pub struct FluidBlocks(pub Vec<FluidBlock>);
+
+impl Default for FluidBlocks {
+    fn default() -> Self {
+        Self(vec![FluidBlock {
+            origin: [-1.0, 1.0, -0.8],
+            size: [1.0, 2.0, 0.8],
+            jitter: FluidBlock::default_jitter(),
+            mesh: None,
+            scale: default_scale(),
+            rotation: [0.0; 3],
+            vertices: Vec::new(),
+            triangles: Vec::new(),
+            phase: None,
+        }])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ObstacleKind {
+    Sphere,
+    /// Runs along its local y axis.
+    Capsule,
+    Box,
+    /// Triangle mesh, baked into a signed distance field at load.
+    Mesh,
+}
+
+/// How a mesh obstacle is represented to the solver.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MeshBoundary {
+    /// Baked SDF with the obstacles' push-out response; may spin.
+    #[default]
+    Sdf,
+    /// The SDF plus Akinci boundary particles on the surface, like the box
+    /// walls: fluid next to it gets full kernel support. Static.
+    Particles,
+}
+
+/// Solid obstacle inside the box. Only the keys of its `shape` are read:
+/// `radius` (sphere, capsule), `half_height` (capsule), `half_extents` (box),
+/// and for meshes either a `mesh` file or inline `vertices` and `triangles`,
+/// plus `scale`, `thickness` and `boundary`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ObstacleDescription {
+    pub shape: ObstacleKind,
+    #[serde(default)]
+    pub position: [f32; 3],
+    /// Rotation about x, y, z in degrees, applied in that order.
+    #[serde(default)]
+    pub rotation: [f32; 3],
+    /// Spin around `position` in degrees per second; zero for a static obstacle.
+    #[serde(default)]
+    pub angular_velocity: [f32; 3],
+    #[serde(default)]
+    pub radius: f32,
+    #[serde(default)]
+    pub half_height: f32,
+    #[serde(default)]
+    pub half_extents: [f32; 3],
+    #[serde(default)]
+    pub vertices: Vec<[f32; 3]>,
+    /// Counter-clockwise seen from outside, indices into `vertices`.
+    #[serde(default)]
+    pub triangles: Vec<[u32; 3]>,
+    /// `.obj` or `.stl` file read instead of `vertices` and `triangles`.
+    /// Relative paths are resolved against the scene file.
+    #[serde(default)]
+    pub mesh: Option<PathBuf>,
+    /// Uniform scale of the mesh vertices.
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    /// Treats the mesh as a shell of this half-width instead of a closed
+    /// solid; needed for open meshes. 0 uses the inside of a closed mesh.
+    #[serde(default)]
+    pub thickness: f32,
+    #[serde(default)]
+    pub boundary: MeshBoundary,
+}
+
+impl ObstacleDescription {
+    /// The mesh in the obstacle's local frame, scaled.
+    pub fn local_mesh(&self) -> TriangleMesh {
+        inline_mesh(&self.vertices, &self.triangles).transformed(self.scale, Quat::IDENTITY, Vec3::ZERO)
+    }
+
+    pub fn rotation(&self) -> Quat {
+        let [x, y, z] = self.rotation.map(f32::to_radians);
+        Quat::from_euler(EulerRot::XYZ, x, y, z)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmitterKind {
+    /// Round jet of `radius`.
+    Nozzle,
+    /// Rectangular inflow of `size`, width horizontal.
+    Plane,
+}
+
+/// Fluid source: emits particles at `position` along `direction` with
+/// `speed`. `rate` in particles per second defaults to (and is capped at) the
+/// rate that keeps the emitted fluid at rest density. `phase` names the
+/// `[[phase]]` it emits; the base fluid without it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmitterDescription {
+    pub shape: EmitterKind,
+    pub position: [f32; 3],
+    #[serde(default = "EmitterDescription::default_direction")]
+    pub direction: [f32; 3],
+    pub speed: f32,
+    #[serde(default)]
+    pub radius: f32,
+    #[serde(default)]
+    pub size: [f32; 2],
+    #[serde(default)]
+    pub rate: Option<f32>,
+    #[serde(default)]
+    pub phase: Option<String>,
+}
+
+impl EmitterDescription {
+    fn default_direction() -> [f32; 3] {
+        [0.0, -1.0, 0.0]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RigidBodyKind {
+    Sphere,
+    Box,
+}
+
+/// Free solid of uniform `density` in kg/m³, pushed around by the fluid and
+/// gravity. Lighter than the fluid floats, heavier sinks. `radius` (sphere)
+/// or `half_extents` (box) set its size.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RigidBodyDescription {
+    pub shape: RigidBodyKind,
+    pub position: [f32; 3],
+    /// Rotation about x, y, z in degrees, applied in that order.
+    #[serde(default)]
+    pub rotation: [f32; 3],
+    pub density: f32,
+    /// Initial velocity in m/s.
+    #[serde(default)]
+    pub velocity: [f32; 3],
+    /// Initial spin in degrees per second.
+    #[serde(default)]
+    pub angular_velocity: [f32; 3],
+    #[serde(default)]
+    pub radius: f32,
+    #[serde(default)]
+    pub half_extents: [f32; 3],
+}
+
+impl RigidBodyDescription {
+    pub fn rotation(&self) -> Quat {
+        let [x, y, z] = self.rotation.map(f32::to_radians);
+        Quat::from_euler(EulerRot::XYZ, x, y, z)
+    }
+}
+
+fn inline_mesh(vertices: &[[f32; 3]], triangles: &[[u32; 3]]) -> TriangleMesh {
+    TriangleMesh {
+        vertices: vertices.iter().map(|v| Vec3::from_array(*v)).collect(),
+        triangles: triangles.to_vec(),
+    }
+}
+
+/// Spray, foam and bubbles (Ihmsen et al. 2012). Each `[min, max]` range is
+/// where a potential starts to generate particles and where it saturates.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiffuseDescription {
+    pub enabled: bool,
+    /// Ring-buffer slots. Defaults to `DEFAULT_DIFFUSE_PARTICLES`.
+    pub max_particles: Option<u32>,
+    pub trapped_air: [f32; 2],
+    pub wave_crest: [f32; 2],
+    /// Kinetic energy per unit mass [J/kg].
+    pub kinetic_energy: [f32; 2],
+    /// Particles per second from a saturated trapped-air or wave-crest potential.
+    pub trapped_air_rate: f32,
+    pub wave_crest_rate: f32,
+    /// Mean foam lifetime in seconds.
+    pub lifetime: f32,
+    /// Bubble buoyancy, as a multiple of -gravity.
+    pub buoyancy: f32,
+    /// Share of the velocity difference to the fluid a bubble loses per frame, in [0, 1].
+    pub drag: f32,
+}
+
+impl Default for DiffuseDescription {
+    fn default() -> Self {
+        let params = DiffuseParams::default();
+        Self {
+            enabled: false,
+            max_particles: None,
+            trapped_air: [params.trapped_air_min, params.trapped_air_max],
+            wave_crest: [params.wave_crest_min, params.wave_crest_max],
+            kinetic_energy: [params.kinetic_energy_min, params.kinetic_energy_max],
+            trapped_air_rate: params.trapped_air_rate,
+            wave_crest_rate: params.wave_crest_rate,
+            lifetime: params.lifetime,
+            buoyancy: params.buoyancy,
+            drag: params.drag,
+        }
+    }
+}
+
+impl DiffuseDescription {
+    pub fn settings(&self) -> DiffuseSettings {
+        DiffuseSettings {
+            enabled: self.enabled,
+            capacity: self.max_particles.unwrap_or(DEFAULT_DIFFUSE_PARTICLES),
+            params: DiffuseParams {
+                trapped_air_min: self.trapped_air[0],
+                trapped_air_max: self.trapped_air[1],
+                wave_crest_min: self.wave_crest[0],
+                wave_crest_max: self.wave_crest[1],
+                kinetic_energy_min: self.kinetic_energy[0],
+                kinetic_energy_max: self.kinetic_energy[1],
+                trapped_air_rate: self.trapped_air_rate,
+                wave_crest_rate: self.wave_crest_rate,
+                lifetime: self.lifetime,
+                buoyancy: self.buoyancy,
+                drag: self.drag,
+                ..DiffuseParams::default()
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraDescription {
+    pub position: [f32; 3],
+    /// Pitch, yaw, roll in degrees.
+    pub rotation: [f32; 3],
+    pub fov: f32,
+}
+
+impl Default for CameraDescription {
+    fn default() -> Self {
+        Self {
+            position: [0.0, 1.5, -3.5],
+            rotation: [0.0, 0.0, 0.0],
+            fov: 60.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SkyDescription {
+    /// Equirectangular HDRI. Relative paths are resolved against the scene file.
+    pub hdri: PathBuf,
+}
+
+impl Default for SkyDescription {
+    fn default() -> Self {
+        Self { hdri: PathBuf::from(DEFAULT_SKY_HDRI) }
+    }
+}
+
+impl SceneDescription {
+    pub fn parse(source: &str) -> Result<Self, ApplicationError> {
+        toml::from_str(source).map_err(|e| ApplicationError::InvalidScene(e.to_string()))
+    }
+
+    /// Reads, parses and validates a scene file, and reads the mesh files it
+    /// refers to.
+    pub fn load(path: &Path) -> Result<Self, ApplicationError> {
+        let source = fs::read_to_string(path).map_err(|e| {
+            ApplicationError::ResourceLoadError(format!("Failed to read scene file {}: {}", path.display(), e))
+        })?;
+
+        let in_file = |e| match e {
+            ApplicationError::InvalidScene(message) => {
+                ApplicationError::InvalidScene(format!("{}: {}", path.display(), message))
+            }
+            other => other,
+        };
+
+        let mut description = Self::parse(&source).map_err(in_file)?;
+        if let Some(dir) = path.parent() {
+            let resolve = |file: &mut PathBuf| {
+                if file.is_relative() {
+                    *file = dir.join(&*file);
+                }
+            };
+            resolve(&mut description.sky.hdri);
+            description.fluid_blocks.0.iter_mut().filter_map(|b| b.mesh.as_mut()).for_each(resolve);
+            description.obstacles.iter_mut().chain(&mut description.sinks).filter_map(|o| o.mesh.as_mut()).for_each(resolve);
+        }
+        description.load_meshes().map_err(in_file)?;
+        description.validate().map_err(in_file)?;
+        Ok(description)
+    }
+
+    /// Index of the phase called `name` in the phase table: 0 for the base
+    /// fluid (`None`), `i + 1` for `phases[i]`. Unknown names are caught by
+    /// `validate`.
+    pub fn phase_index(&self, name: Option<&str>) -> u32 {
+        name.and_then(|name| self.phases.iter().position(|p| p.name == name))
+            .map_or(0, |i| i as u32 + 1)
+    }
+
+    /// Reads every `mesh` file into the `vertices` and `triangles` of its
+    /// block, obstacle or sink.
+    pub fn load_meshes(&mut self) -> Result<(), ApplicationError> {
+        let meshes = self.fluid_blocks.0.iter_mut()
+            .enumerate()
+            .map(|(i, b)| (format!("fluid[{i}]"), &b.mesh, &mut b.vertices, &mut b.triangles))
+            .chain(self.obstacles.iter_mut()
+                .enumerate()
+                .map(|(i, o)| (format!("obstacle[{i}]"), &o.mesh, &mut o.vertices, &mut o.triangles)))
+            .chain(self.sinks.iter_mut()
+                .enumerate()
+                .map(|(i, o)| (format!("sink[{i}]"), &o.mesh, &mut o.vertices, &mut o.triangles)));
+
+        for (name, file, vertices, triangles) in meshes {
+            let Some(file) = file else { continue };
+            if !triangles.is_empty() {
+                return Err(ApplicationError::InvalidScene(format!("{name} sets both mesh and triangles")));
+            }
+            let mesh = TriangleMesh::load(file)?;
+            *vertices = mesh.vertices.iter().map(|v| v.to_array()).collect();
+            *triangles = mesh.triangles;
+        }
+        Ok(())
+    }
+
+    /// Checks everything `Scene::from_description` relies on and reports all
+    /// problems at once, e.g. `boundary.min.y must be below boundary.max.y`.
+    /// Mesh files that were not loaded yet are not checked.
+    pub fn validate(&self) -> Result<(), ApplicationError> {
+        let mut errors = Vec::new();
+        let sim = &self.simulation;
+        let axes = ["x", "y", "z"];
+
+        if !positive(sim.particle_radius) {
+            errors.push("simulation.particle_radius must be positive".to_string());
+        }
+        if !positive(sim.target_density) {
+            errors.push("simulation.target_density must be positive".to_string());
+        }
+        if !positive(sim.dt) {
+            errors.push("simulation.dt must be positive".to_string());
+        }
+        if !(sim.viscosity.is_finite() && sim.viscosity >= 0.0) {
+            errors.push("simulation.viscosity must be non-negative".to_string());
+        }
+        if sim.viscosity_iterations == 0 {
+            errors.push("simulation.viscosity_iterations must be at least 1".to_string());
+        }
+        if !(positive(sim.viscosity_tolerance) && sim.viscosity_tolerance < 1.0) {
+            errors.push("simulation.viscosity_tolerance must be in (0, 1)".to_string());
+        }
+        if !positive(sim.stiffness) {
+            errors.push("simulation.stiffness must be positive".to_string());
+        }
+        if !(0.0..=1.0).contains(&sim.flip_ratio) {
+            errors.push("simulation.flip_ratio must be in [0, 1]".to_string());
+        }
+        if sim.grid_iterations == 0 {
+            errors.push("simulation.grid_iterations must be at least 1".to_string());
+        }
+        if !(sim.surface_tension.is_finite() && sim.surface_tension >= 0.0) {
+            errors.push("simulation.surface_tension must be non-negative".to_string());
+        }
+        if !(sim.adhesion.is_finite() && sim.adhesion >= 0.0) {
+            errors.push("simulation.adhesion must be non-negative".to_string());
+        }
+        if !(sim.vorticity_confinement.is_finite() && sim.vorticity_confinement >= 0.0) {
+            errors.push("simulation.vorticity_confinement must be non-negative".to_string());
+        }
+        if !(positive(sim.relax_factor) && sim.relax_factor <= 1.0) {
+            errors.push("simulation.relax_factor must be in (0, 1]".to_string());
+        }
+        if !(sim.smoothing_radius().is_finite() && sim.smoothing_radius() > sim.particle_radius) {
+            errors.push("simulation.smoothing_radius must be larger than particle_radius".to_string());
+        }
+        if sim.density_iterations == 0 || sim.divergence_iterations == 0 {
+            errors.push("simulation.density_iterations and divergence_iterations must be at least 1".to_string());
+        }
+        if sim.grid_resolution.iter().any(|&r| r <= 0) {
+            errors.push("simulation.grid_resolution must be positive on every axis".to_string());
+        }
+        if sim.gravity.iter().any(|g| !g.is_finite()) {
+            errors.push("simulation.gravity must be finite".to_string());
+        }
+        if sim.max_particles == Some(0) {
+            errors.push("simulation.max_particles must be at least 1".to_string());
+        }
+        if !valid_color(sim.color) {
+            errors.push("simulation.color components must be in [0, 1]".to_string());
+        }
+
+        if self.phases.len() >= MAX_PHASES {
+            errors.push(format!("at most {} [[phase]]s are supported", MAX_PHASES - 1));
+        }
+        for (i, phase) in self.phases.iter().enumerate() {
+            if phase.name.is_empty() {
+                errors.push(format!("phase[{i}].name must not be empty"));
+            } else if self.phases[..i].iter().any(|p| p.name == phase.name) {
+                errors.push(format!("phase[{i}].name \"{}\" is already taken", phase.name));
+            }
+            if !positive(phase.density) {
+                errors.push(format!("phase[{i}].density must be positive"));
+            }
+            if phase.viscosity.is_some_and(|v| !(v.is_finite() && v >= 0.0)) {
+                errors.push(format!("phase[{i}].viscosity must be non-negative"));
+            }
+            if phase.surface_tension.is_some_and(|v| !(v.is_finite() && v >= 0.0)) {
+                errors.push(format!("phase[{i}].surface_tension must be non-negative"));
+            }
+            if !valid_color(phase.color) {
+                errors.push(format!("phase[{i}].color components must be in [0, 1]"));
+            }
+        }
+        let phase_names = self.fluid_blocks.0.iter()
+            .enumerate()
+            .map(|(i, b)| (format!("fluid[{i}]"), &b.phase))
+            .chain(self.emitters.iter().enumerate().map(|(i, e)| (format!("emitter[{i}]"), &e.phase)));
+        for (name, phase) in phase_names {
+            if let Some(phase) = phase
+                && !self.phases.iter().any(|p| &p.name == phase)
+            {
+                errors.push(format!("{name}.phase \"{phase}\" is not a [[phase]]"));
+            }
+        }
+
+        let boundary = &self.boundary;
+        for (axis, (min, max)) in boundary.min.iter().zip(boundary.max).enumerate() {
+            if !(min.is_finite() && max.is_finite() && *min < max) {
+                errors.push(format!("boundary.min.{0} must be below boundary.max.{0}", axes[axis]));
+            }
+        }
+        if !(boundary.wave_amplitude.is_finite() && boundary.wave_amplitude >= 0.0) {
+            errors.push("boundary.wave_amplitude must be non-negative".to_string());
+        } else if boundary.min[0] + boundary.wave_amplitude >= boundary.max[0] {
+            errors.push("boundary.wave_amplitude would push the moving wall past boundary.max.x".to_string());
+        }
+        if !(boundary.wave_frequency.is_finite() && boundary.wave_frequency >= 0.0) {
+            errors.push("boundary.wave_frequency must be non-negative".to_string());
+        }
+        if let Some(motion) = &boundary.motion {
+            if motion.translation.is_empty() && motion.rotation.is_empty() {
+                errors.push("boundary.motion needs at least one translation or rotation key".to_string());
+            }
+            for (track, keys) in [("translation", &motion.translation), ("rotation", &motion.rotation)] {
+                if keys.iter().any(|k| !k.time.is_finite() || k.value.iter().any(|v| !v.is_finite())) {
+                    errors.push(format!("boundary.motion.{track} keys must be finite"));
+                }
+                if keys.windows(2).any(|w| w[1].time <= w[0].time) {
+                    errors.push(format!("boundary.motion.{track} key times must increase"));
+                }
+            }
+        }
+
+        if self.fluid_blocks.0.is_empty() && self.emitters.is_empty() {
+            errors.push("at least one [[fluid]] block or [[emitter]] is required".to_string());
+        }
+        let spacing = sim.particle_spacing();
+        for (i, block) in self.fluid_blocks.0.iter().enumerate() {
+            if !(block.jitter.is_finite() && block.jitter >= 0.0) {
+                errors.push(format!("fluid[{i}].jitter must be non-negative"));
+            }
+            if block.is_mesh() {
+                if !(positive(block.scale) && block.rotation.iter().all(|r| r.is_finite())) {
+                    errors.push(format!("fluid[{i}].scale must be positive and rotation finite"));
+                }
+                let valid = check_mesh(&format!("fluid[{i}]"), &block.vertices, &block.triangles, &mut errors);
+                if valid && !block.triangles.is_empty() {
+                    let (lo, hi) = block.world_mesh().bounds();
+                    for (axis, name) in axes.iter().enumerate() {
+                        if !(lo[axis] >= boundary.min[axis] && hi[axis] <= boundary.max[axis]) {
+                            errors.push(format!("fluid[{i}] lies outside the boundary along {name}"));
+                        }
+                    }
+                }
+                continue;
+            }
+            for (axis, name) in axes.iter().enumerate() {
+                let (start, size) = (block.origin[axis], block.size[axis]);
+                if !(size.is_finite() && size >= spacing) {
+                    errors.push(format!("fluid[{i}].size.{name} must be at least one particle spacing ({spacing})"));
+                }
+                if !(start >= boundary.min[axis] && start + size <= boundary.max[axis]) {
+                    errors.push(format!("fluid[{i}] lies outside the boundary along {name}"));
+                }
+            }
+        }
+
+        for (i, obstacle) in self.obstacles.iter().enumerate() {
+            check_obstacle(&format!("obstacle[{i}]"), obstacle, &mut errors);
+            if obstacle.boundary == MeshBoundary::Particles {
+                if obstacle.shape != ObstacleKind::Mesh {
+                    errors.push(format!("obstacle[{i}].boundary = \"particles\" needs shape = \"mesh\""));
+                }
+                if obstacle.angular_velocity != [0.0; 3] {
+                    errors.push(format!("obstacle[{i}] with boundary = \"particles\" cannot spin"));
+                }
+            }
+        }
+
+        for (i, emitter) in self.emitters.iter().enumerate() {
+            let inside = (0..3).all(|a| emitter.position[a] > boundary.min[a] && emitter.position[a] < boundary.max[a]);
+            if !inside {
+                errors.push(format!("emitter[{i}].position must lie inside the boundary"));
+            }
+            let direction = Vec3::from_array(emitter.direction);
+            if !(direction.is_finite() && direction.length_squared() > 0.0) {
+                errors.push(format!("emitter[{i}].direction must be finite and non-zero"));
+            }
+            if !positive(emitter.speed) {
+                errors.push(format!("emitter[{i}].speed must be positive"));
+            }
+            match emitter.shape {
+                EmitterKind::Nozzle if !positive(emitter.radius) => {
+                    errors.push(format!("emitter[{i}].radius must be positive"));
+                }
+                EmitterKind::Plane if !emitter.size.iter().all(|&e| positive(e)) => {
+                    errors.push(format!("emitter[{i}].size must be positive on both axes"));
+                }
+                _ => {}
+            }
+            if emitter.rate.is_some_and(|rate| !positive(rate)) {
+                errors.push(format!("emitter[{i}].rate must be positive"));
+            }
+        }
+
+        for (i, sink) in self.sinks.iter().enumerate() {
+            check_obstacle(&format!("sink[{i}]"), sink, &mut errors);
+            if sink.angular_velocity != [0.0; 3] {
+                errors.push(format!("sink[{i}] cannot spin"));
+            }
+            if sink.boundary == MeshBoundary::Particles {
+                errors.push(format!("sink[{i}] cannot have boundary = \"particles\""));
+            }
+        }
+
+        for (i, body) in self.rigid_bodies.iter().enumerate() {
+            let finite = |v: &[f32]| v.iter().all(|c| c.is_finite());
+            if !(finite(&body.rotation) && finite(&body.velocity) && finite(&body.angular_velocity)) {
+                errors.push(format!("rigid_body[{i}] rotation, velocity and angular_velocity must be finite"));
+            }
+            if !positive(body.density) {
+                errors.push(format!("rigid_body[{i}].density must be positive"));
+            }
+            match body.shape {
+                RigidBodyKind::Sphere if !positive(body.radius) => {
+                    errors.push(format!("rigid_body[{i}].radius must be positive"));
+                }
+                RigidBodyKind::Box if !body.half_extents.iter().all(|&e| positive(e)) => {
+                    errors.push(format!("rigid_body[{i}].half_extents must be positive on every axis"));
+                }
+                _ => {}
+            }
+            let inside = (0..3).all(|a| body.position[a] > boundary.min[a] && body.position[a] < boundary.max[a]);
+            if !inside {
+                errors.push(format!("rigid_body[{i}].position must lie inside the boundary"));
+            }
+        }
+
+        let diffuse = &self.diffuse;
+        let ranges = [
+            ("trapped_air", diffuse.trapped_air),
+            ("wave_crest", diffuse.wave_crest),
+            ("kinetic_energy", diffuse.kinetic_energy),
+        ];
+        for (name, [min, max]) in ranges {
+            if !(min.is_finite() && min >= 0.0 && max.is_finite() && min < max) {
+                errors.push(format!("diffuse.{name} must be a [min, max] range with 0 <= min < max"));
+            }
+        }
+        let non_negative = [
+            ("trapped_air_rate", diffuse.trapped_air_rate),
+            ("wave_crest_rate", diffuse.wave_crest_rate),
+            ("buoyancy", diffuse.buoyancy),
+        ];
+        for (name, value) in non_negative {
+            if !(value.is_finite() && value >= 0.0) {
+                errors.push(format!("diffuse.{name} must be non-negative"));
+            }
+        }
+        if !positive(diffuse.lifetime) {
+            errors.push("diffuse.lifetime must be positive".to_string());
+        }
+        if !(0.0..=1.0).contains(&diffuse.drag) {
+            errors.push("diffuse.drag must be in [0, 1]".to_string());
+        }
+        if diffuse.max_particles == Some(0) {
+            errors.push("diffuse.max_particles must be at least 1".to_string());
+        }
+
+        if !(positive(self.camera.fov) && self.camera.fov < 180.0) {
+            errors.push("camera.fov must be in (0, 180) degrees".to_string());
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ApplicationError::InvalidScene(errors.join("; ")))
+        }
+    }
+}
+
+fn positive(value: f32) -> bool {
+    value.is_finite() && value > 0.0
+}
+
+fn valid_color(color: [f32; 3]) -> bool {
+    color.iter().all(|c| (0.0..=1.0).contains(c))
+}
+
+/// Pose and shape checks shared by obstacles and sinks.
+fn check_obstacle(name: &str, obstacle: &ObstacleDescription, errors: &mut Vec<String>) {
+    let finite = |v: &[f32]| v.iter().all(|c| c.is_finite());
+    if !(finite(&obstacle.position) && finite(&obstacle.rotation) && finite(&obstacle.angular_velocity)) {
+        errors.push(format!("{name} position, rotation and angular_velocity must be finite"));
+    }
+    match obstacle.shape {
+        ObstacleKind::Sphere | ObstacleKind::Capsule if !positive(obstacle.radius) => {
+            errors.push(format!("{name}.radius must be positive"));
+        }
+        ObstacleKind::Box if !obstacle.half_extents.iter().all(|&e| positive(e)) => {
+            errors.push(format!("{name}.half_extents must be positive on every axis"));
+        }
+        _ => {}
+    }
+    if obstacle.shape == ObstacleKind::Capsule && !(obstacle.half_height.is_finite() && obstacle.half_height >= 0.0) {
+        errors.push(format!("{name}.half_height must be non-negative"));
+    }
+    if obstacle.shape == ObstacleKind::Mesh {
+        check_mesh(name, &obstacle.vertices, &obstacle.triangles, errors);
+        if obstacle.mesh.is_none() && obstacle.triangles.is_empty() {
+            errors.push(format!("{name} needs a mesh file or at least one triangle"));
+        }
+        if !positive(obstacle.scale) {
+            errors.push(format!("{name}.scale must be positive"));
+        }
+        if !(obstacle.thickness.is_finite() && obstacle.thickness >= 0.0) {
+            errors.push(format!("{name}.thickness must be non-negative"));
+        }
+    }
+}
+
+/// Index and coordinate checks shared by mesh blocks and obstacles; true if
+/// the mesh can be built.
+fn check_mesh(name: &str, vertices: &[[f32; 3]], triangles: &[[u32; 3]], errors: &mut Vec<String>) -> bool {
+    let before = errors.len();
+    if triangles.iter().flatten().any(|&v| v as usize >= vertices.len()) {
+        errors.push(format!("{name}.triangles index past the end of vertices"));
+    }
+    if !vertices.iter().flatten().all(|c| c.is_finite()) {
+        errors.push(format!("{name}.vertices must be finite"));
+    }
+    errors.len() == before
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_file_is_the_default_scene() {
+        let description = SceneDescription::parse("").unwrap();
+        assert_eq!(description.simulation.particle_radius, 0.02);
+        assert_eq!(description.simulation.kernel, KernelKind::CubicSpline);
+        assert_eq!(description.simulation.viscosity_mode, ViscosityModeKind::Explicit);
+        assert_eq!(description.simulation.pressure_solver, PressureSolverKind::Dfsph);
+        assert_eq!(description.fluid_blocks.0.len(), 1);
+        description.validate().unwrap();
+    }
+
+    #[test]
+    fn bundled_scenes_are_valid() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes");
+        let mut count = 0;
+        for entry in fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|ext| ext == "toml") {
+                SceneDescription::load(&path).unwrap_or_else(|e| panic!("{e}"));
+                count += 1;
+            }
+        }
+        assert!(count > 0, "no scenes found in {}", dir.display());
+    }
+
+    #[test]
+    fn validation_reports_every_problem() {
+        let description = SceneDescription::parse(
+            r#"
+            [simulation]
+            viscosity_mode = "implicit"
+            viscosity_tolerance = 0.0
+            pressure_solver = "wcsph"
+            stiffness = -1.0
+
+            [boundary]
+            min = [0.0, 2.0, 0.0]
+            max = [1.0, 1.0, 1.0]
+            wave_amplitude = 0.0
+
+            [[fluid]]
+            origin = [0.5, 0.0, 0.5]
+            size = [2.0, 0.5, 0.2]
+            "#,
+        ).unwrap();
+
+        let message = description.validate().unwrap_err().to_string();
+        assert!(message.contains("boundary.min.y must be below boundary.max.y"), "{message}");
+        assert!(message.contains("fluid[0] lies outside the boundary along x"), "{message}");
+        assert!(message.contains("simulation.viscosity_tolerance must be in (0, 1)"), "{message}");
+        assert!(message.contains("simulation.stiffness must be positive"), "{message}");
+    }
+
+    #[test]
+    fn obstacles_are_validated_per_shape() {
+        let description = SceneDescription::parse(
+            r#"
+            [[obstacle]]
+            shape = "capsule"
+            half_height = 0.2
+
+            [[obstacle]]
+            shape = "mesh"
+            vertices = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0]]
+            triangles = [[0, 1, 2]]
+            "#,
+        ).unwrap();
+
+        let message = description.validate().unwrap_err().to_string();
+        assert!(message.contains("obstacle[0].radius must be positive"), "{message}");
+        assert!(message.contains("obstacle[1].triangles index past the end of vertices"), "{message}");
+        assert!(SceneDescription::parse("[[obstacle]]\nshape = \"cone\"\n").is_err());
+    }
+
+    #[test]
+    fn mesh_fluid_blocks_are_bounded_by_their_transformed_mesh() {
+        let tetrahedron = r#"
+            vertices = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]
+            triangles = [[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]]
+        "#;
+        let scene = |origin: &str, scale: f32| {
+            SceneDescription::parse(&format!(
+                "[boundary]\nmin = [0.0, 0.0, 0.0]\nmax = [1.0, 1.0, 1.0]\nwave_amplitude = 0.0\n\
+                 [[fluid]]\norigin = {origin}\nscale = {scale}\n{tetrahedron}"
+            )).unwrap()
+        };
+
+        scene("[0.1, 0.1, 0.1]", 0.5).validate().unwrap();
+        let message = scene("[0.1, 0.1, 0.1]", 1.0).validate().unwrap_err().to_string();
+        assert!(message.contains("fluid[0] lies outside the boundary along x"), "{message}");
+        assert!(!message.contains("size"), "{message}");
+
+        let mut both = SceneDescription::parse(&format!("[[obstacle]]\nshape = \"mesh\"\nmesh = \"cup.obj\"\n{tetrahedron}")).unwrap();
+        let error = both.load_meshes().unwrap_err().to_string();
+        assert!(error.contains("obstacle[0] sets both mesh and triangles"), "{error}");
+    }
+
+    #[test]
+    fn particle_boundaries_must_be_static_meshes() {
+        let description = SceneDescription::parse(
+            r#"
+            [[obstacle]]
+            shape = "sphere"
+            radius = 0.1
+            boundary = "particles"
+
+            [[obstacle]]
+            shape = "mesh"
+            mesh = "cup.stl"
+            angular_velocity = [0.0, 45.0, 0.0]
+            boundary = "particles"
+            "#,
+        ).unwrap();
+
+        let message = description.validate().unwrap_err().to_string();
+        assert!(message.contains(r#"obstacle[0].boundary = "particles" needs shape = "mesh""#), "{message}");
+        assert!(message.contains(r#"obstacle[1] with boundary = "particles" cannot spin"#), "{message}");
+    }
+
+    #[test]
+    fn boundary_motion_is_validated() {
+        let description = SceneDescription::parse(
+            r#"
+            [boundary.motion]
+            interpolation = "smooth"
+            loop = true
+            rotation = [
+                { time = 0.0, value = [0.0, 0.0, 0.0] },
+                { time = 1.0, value = [0.0, 0.0, 15.0] },
+                { time = 1.0, value = [0.0, 0.0, -15.0] },
+            ]
+            translation = [{ time = 0.0, value = [0.0, inf, 0.0] }]
+            "#,
+        ).unwrap();
+        let motion = description.boundary.motion.as_ref().unwrap();
+        assert_eq!(motion.interpolation, InterpolationKind::Smooth);
+        assert!(motion.looped);
+
+        let message = description.validate().unwrap_err().to_string();
+        assert!(message.contains("boundary.motion.rotation key times must increase"), "{message}");
+        assert!(message.contains("boundary.motion.translation keys must be finite"), "{message}");
+        assert!(!message.contains("boundary.motion.rotation keys must be finite"), "{message}");
+
+        let empty = SceneDescription::parse("[boundary.motion]\nloop = true\n").unwrap();
+        let message = empty.validate().unwrap_err().to_string();
+        assert!(message.contains("needs at least one translation or rotation key"), "{message}");
+    }
+
+    #[test]
+    fn emitters_and_sinks_are_validated() {
+        let description = SceneDescription::parse(
+            r#"
+            fluid = []
+
+            [[emitter]]
+            shape = "nozzle"
+            position = [0.0, 5.0, 0.0]
+            direction = [0.0, 0.0, 0.0]
+            speed = 1.0
+
+            [[emitter]]
+            shape = "plane"
+            position = [0.0, 1.0, 0.0]
+            size = [0.5, 0.0]
+            speed = 1.0
+            rate = -3.0
+
+            [[sink]]
+            shape = "box"
+            half_extents = [0.2, 0.2, 0.2]
+            angular_velocity = [0.0, 30.0, 0.0]
+            "#,
+        ).unwrap();
+
+        let message = description.validate().unwrap_err().to_string();
+        assert!(message.contains("emitter[0].position must lie inside the boundary"), "{message}");
+        assert!(message.contains("emitter[0].direction must be finite and non-zero"), "{message}");
+        assert!(message.contains("emitter[0].radius must be positive"), "{message}");
+        assert!(message.contains("emitter[1].size must be positive on both axes"), "{message}");
+        assert!(message.contains("emitter[1].rate must be positive"), "{message}");
+        assert!(message.contains("sink[0] cannot spin"), "{message}");
+        assert!(!message.contains("[[fluid]]"), "{message}");
+
+        let empty = SceneDescription::parse("fluid = []\n").unwrap();
+        let message = empty.validate().unwrap_err().to_string();
+        assert!(message.contains("at least one [[fluid]] block or [[emitter]] is required"), "{message}");
+    }
+
+    #[test]
+    fn rigid_bodies_are_validated() {
+        let description = SceneDescription::parse(
+            r#"
+            [[rigid_body]]
+            shape = "sphere"
+            position = [0.0, 0.5, 0.0]
+            density = 500.0
+            angular_velocity = [0.0, 90.0, 0.0]
+
+            [[rigid_body]]
+            shape = "box"
+            position = [0.0, 9.0, 0.0]
+            half_extents = [0.1, 0.1, 0.1]
+            density = 0.0
+            "#,
+        ).unwrap();
+        assert_eq!(description.rigid_bodies[0].angular_velocity, [0.0, 90.0, 0.0]);
+
+        let message = description.validate().unwrap_err().to_string();
+        assert!(message.contains("rigid_body[0].radius must be positive"), "{message}");
+        assert!(message.contains("rigid_body[1].density must be positive"), "{message}");
+        assert!(message.contains("rigid_body[1].position must lie inside the boundary"), "{message}");
+        assert!(SceneDescription::parse("[[rigid_body]]\nshape = \"capsule\"\nposition = [0.0, 0.5, 0.0]\ndensity = 1.0\n").is_err());
+    }
+
+    #[test]
+    fn phases_are_validated_and_resolved() {
+        let description = SceneDescription::parse(
+            r#"
+            [[phase]]
+            name = "oil"
+            density = 800.0
+            color = [0.9, 0.7, 0.1]
+
+            [[phase]]
+            name = "oil"
+            density = -1.0
+            surface_tension = 0.5
+            color = [2.0, 0.0, 0.0]
+
+            [[fluid]]
+            origin = [-1.0, 1.0, -0.8]
+            size = [1.0, 0.5, 0.8]
+            phase = "oil"
+
+            [[fluid]]
+            origin = [-1.0, 2.0, -0.8]
+            size = [1.0, 0.5, 0.8]
+            phase = "honey"
+            "#,
+        ).unwrap();
+        assert_eq!(description.phase_index(None), 0);
+        assert_eq!(description.phase_index(Some("oil")), 1);
+        assert_eq!(description.phases[0].viscosity, None);
+
+        let message = description.validate().unwrap_err().to_string();
+        assert!(message.contains(r#"phase[1].name "oil" is already taken"#), "{message}");
+        assert!(message.contains("phase[1].density must be positive"), "{message}");
+        assert!(message.contains("phase[1].color components must be in [0, 1]"), "{message}");
+        assert!(message.contains(r#"fluid[1].phase "honey" is not a [[phase]]"#), "{message}");
+        assert!(!message.contains("fluid[0]"), "{message}");
+
+        let crowded: String = (0..MAX_PHASES)
+            .map(|i| format!("[[phase]]\nname = \"p{i}\"\ndensity = 900.0\ncolor = [0.5, 0.5, 0.5]\n"))
+            .collect();
+        let message = SceneDescription::parse(&crowded).unwrap().validate().unwrap_err().to_string();
+        assert!(message.contains(&format!("at most {} [[phase]]s are supported", MAX_PHASES - 1)), "{message}");
+    }
+
+    #[test]
+    fn diffuse_section_is_validated_and_converted() {
+        let description = SceneDescription::parse(
+            r#"
+            [diffuse]
+            enabled = true
+            max_particles = 1000
+            trapped_air = [4.0, 2.0]
+            lifetime = 0.0
+            drag = 1.5
+            "#,
+        ).unwrap();
+        let message = description.validate().unwrap_err().to_string();
+        assert!(message.contains("diffuse.trapped_air must be a [min, max] range"), "{message}");
+        assert!(message.contains("diffuse.lifetime must be positive"), "{message}");
+        assert!(message.contains("diffuse.drag must be in [0, 1]"), "{message}");
+        assert!(!message.contains("diffuse.wave_crest"), "{message}");
+
+        let settings = description.diffuse.settings();
+        assert!(settings.enabled);
+        assert_eq!(settings.capacity, 1000);
+        assert_eq!((settings.params.trapped_air_min, settings.params.trapped_air_max), (4.0, 2.0));
+        assert_eq!(settings.params.spray_neighbors, DiffuseParams::default().spray_neighbors);
+
+        let defaults = SceneDescription::default().diffuse.settings();
+        assert!(!defaults.enabled);
+        assert_eq!(defaults.capacity, DEFAULT_DIFFUSE_PARTICLES);
+        assert_eq!(defaults.params, DiffuseParams::default());
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        let error = SceneDescription::parse("[simulation]\nparticle_radus = 0.01\n").unwrap_err();
+        assert!(error.to_string().contains("particle_radus"), "{error}");
+        let error = SceneDescription::parse("[simulation]\nkernel = \"gaussian\"\n").unwrap_err();
+        assert!(error.to_string().contains("gaussian"), "{error}");
+        let error = SceneDescription::parse("[simulation]\nviscosity_mode = \"sph\"\n").unwrap_err();
+        assert!(error.to_string().contains("sph"), "{error}");
+        let error = SceneDescription::parse("[simulation]\npressure_solver = \"mps\"\n").unwrap_err();
+        assert!(error.to_string().contains("mps"), "{error}");
+        let error = SceneDescription::parse("[simulation]\ngrid_transfer = \"mpm\"\n").unwrap_err();
+        assert!(error.to_string().contains("mpm"), "{error}");
+    }
+
+    #[test]
+    fn flip_backend_is_parsed_and_validated() {
+        let description = SceneDescription::parse(
+            r#"
+            [simulation]
+            backend = "flip"
+            grid_transfer = "apic"
+            grid_iterations = 80
+            "#,
+        ).unwrap();
+        assert_eq!(description.simulation.backend, BackendKind::Flip);
+        assert_eq!(description.simulation.grid_transfer, GridTransferKind::Apic);
+        assert_eq!(description.simulation.grid_iterations, 80);
+        description.validate().unwrap();
+
+        let description = SceneDescription::parse("[simulation]\nflip_ratio = 1.5\ngrid_iterations = 0\n").unwrap();
+        let message = description.validate().unwrap_err().to_string();
+        assert!(message.contains("simulation.flip_ratio must be in [0, 1]"), "{message}");
+        assert!(message.contains("simulation.grid_iterations must be at least 1"), "{message}");
+    }
+}
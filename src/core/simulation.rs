@@ -0,0 +1,845 @@
+use std::sync::Arc;
+use glam::{IVec3, Vec3};
+use vulkano::buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::allocator::{StandardCommandBufferAllocator, StandardCommandBufferAllocatorCreateInfo};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBufferUsage, CopyBufferInfo, PrimaryAutoCommandBuffer, PrimaryCommandBufferAbstract};
+use vulkano::descriptor_set::allocator::{StandardDescriptorSetAllocator, StandardDescriptorSetAllocatorCreateInfo};
+use vulkano::device::DeviceFeatures;
+use vulkano::instance::{InstanceCreateInfo, InstanceExtensions};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter};
+use vulkano::sync::GpuFuture;
+use vulkano_util::context::{VulkanoConfig, VulkanoContext};
+use crate::core::checkpoint::{ParticleState, PendingSnapshot};
+use crate::cpu::boundary::BoundaryParticles;
+use crate::entities::emitter::EmittedParticle;
+use crate::entities::obstacle::{Obstacle, ObstacleForce};
+use crate::entities::particle::{particle_phase, GpuPhysicsData, ParticleCounter, PressureSolver, SimulationBackend, ViscosityMode, ViscositySolverState};
+use crate::renderer::pipelines::{ComputePipelines, ComputeStep, Dfsph, FlipProjection, Iisph, Pbf, Pcisph, PressureSolverStep, Wcsph};
+
+pub use crate::entities::particle::{ParticleGenerator, SimulationParams};
+pub use crate::renderer::pipelines::SortAlgorithm;
+pub use crate::cpu::CpuSimulation;
+
+// Fixed-point scales — must match the constants in stats.comp.
+pub const DENSITY_SCALE: f32 = 1.0;
+pub const DIVERGENCE_SCALE: f32 = 10.0;
+
+/// Aggregated solver statistics written by `stats.comp` at the end of every step.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SimulationStats {
+    pub max_speed: f32,
+    pub avg_density_error: f32,
+    pub avg_divergence_error: f32,
+    /// Live particles at the end of the step.
+    pub particle_count: u32,
+    /// Conjugate gradient iterations and relative residual of the last
+    /// implicit viscosity solve; 0 in explicit mode.
+    pub viscosity_iterations: u32,
+    pub viscosity_residual: f32,
+    /// Density and divergence solver iterations of the last substep; the
+    /// DFSPH solves stop short of the cap once their error is below η.
+    pub density_iterations: u32,
+    pub divergence_iterations: u32,
+}
+
+/// Creates a compute-only `VulkanoContext` (no surface, no swapchain) suitable
+/// for running a `Simulation` on a headless device such as lavapipe.
+///
+/// `ext_debug_utils` is required because `RadixSorter::execute` emits debug
+/// labels for RenderDoc.
+pub fn create_headless_context() -> Arc<VulkanoContext> {
+    let config = VulkanoConfig {
+        instance_create_info: InstanceCreateInfo {
+            enabled_extensions: InstanceExtensions {
+                ext_debug_utils: true,
+                ..InstanceExtensions::default()
+            },
+            ..Default::default()
+        },
+        device_features: DeviceFeatures {
+            scalar_block_layout: true,
+            buffer_device_address: true,
+            shader_int64: true,
+            ..DeviceFeatures::empty()
+        },
+        ..VulkanoConfig::default()
+    };
+    Arc::new(VulkanoContext::new(config))
+}
+
+/// The fluid solver on the GPU, SPH or FLIP, independent of any window or
+/// renderer.
+///
+/// Owns the particle buffers, the compute pipelines and the `SimulationParams`
+/// uniform. The interactive `Renderer` records its substeps through
+/// `record_step`, headless callers use `step` / `run_substeps`, which submit
+/// and wait on the graphics queue of the given context.
+///
+/// The buffers are allocated for a fixed capacity; the live particle count
+/// only exists on the GPU. Emitted particles are appended and particles in a
+/// sink removed when the neighbor structure is rebuilt, and every pass is
+/// dispatched indirectly over the live count.
+pub struct Simulation {
+    context: Arc<VulkanoContext>,
+    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+
+    physics_data: GpuPhysicsData,
+    boundary: BoundaryParticles,
+    mesh_boundary: Vec<Vec3>,
+    obstacles: Vec<Obstacle>,
+    sinks: Vec<Obstacle>,
+    /// Emitted particles waiting for the next `record_init`.
+    pending_emission: Vec<EmittedParticle>,
+    pipelines: ComputePipelines,
+    sim_params_buffer: Subbuffer<SimulationParams>,
+    params: SimulationParams,
+    /// Simulated time covered by the last recorded step, over which the
+    /// obstacle impulses were summed.
+    impulse_time: f32,
+
+    needs_init: bool,
+    /// The APIC gradients belong to particles that have since been replaced
+    /// or moved by the SPH backend; `record_init` clears them.
+    affine_stale: bool,
+}
+
+impl Simulation {
+    pub fn new(context: Arc<VulkanoContext>, initial_positions: &[[f32; 3]], params: SimulationParams) -> Self {
+        Self::with_capacity(context, initial_positions, &[], params, initial_positions.len() as u32)
+    }
+
+    /// Allocates room for `capacity` particles, so that emitters can add to
+    /// `initial_positions`. The capacity is raised to fit them.
+    /// `initial_phases` indexes `params.phases` per particle; particles past
+    /// its end (all of them if it is empty) are the base fluid.
+    pub fn with_capacity(
+        context: Arc<VulkanoContext>,
+        initial_positions: &[[f32; 3]],
+        initial_phases: &[u32],
+        params: SimulationParams,
+        capacity: u32,
+    ) -> Self {
+        let capacity = capacity.max(initial_positions.len() as u32).max(1);
+        let device = context.device().clone();
+        let memory_allocator = context.memory_allocator().clone();
+
+        let command_buffer_allocator = Arc::new(StandardCommandBufferAllocator::new(
+            device.clone(),
+            StandardCommandBufferAllocatorCreateInfo::default()
+        ));
+        let descriptor_set_allocator = Arc::new(StandardDescriptorSetAllocator::new(
+            device.clone(),
+            StandardDescriptorSetAllocatorCreateInfo::default()
+        ));
+
+        let boundary = BoundaryParticles::for_box(&params);
+        let mut physics_data = GpuPhysicsData::new(memory_allocator.clone(), initial_positions.to_vec(), initial_phases, capacity, &boundary);
+        if params.backend() == SimulationBackend::Flip {
+            physics_data.reserve_flip_grid(memory_allocator.clone(), params.flip_grid_dims());
+        }
+
+        let sim_params_buffer = Buffer::from_data(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::UNIFORM_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            params,
+        ).expect("Failed to create simulation params buffer");
+
+        let mut pipelines = ComputePipelines::new(
+            device,
+            memory_allocator,
+            physics_data.grid_entries.len() as u32,
+        );
+        pipelines.prepare(descriptor_set_allocator.clone(), &physics_data, &sim_params_buffer);
+        pipelines.pcisph_pressure.set_delta(params.pcisph_delta());
+
+        Self {
+            context,
+            command_buffer_allocator,
+            descriptor_set_allocator,
+            physics_data,
+            boundary,
+            mesh_boundary: Vec::new(),
+            obstacles: Vec::new(),
+            sinks: Vec::new(),
+            pending_emission: Vec::new(),
+            pipelines,
+            sim_params_buffer,
+            params,
+            impulse_time: 0.0,
+            needs_init: true,
+            affine_stale: false,
+        }
+    }
+
+    /// Convenience constructor that also creates its own headless context.
+    pub fn headless(initial_positions: &[[f32; 3]], params: SimulationParams) -> Self {
+        Self::new(create_headless_context(), initial_positions, params)
+    }
+
+    pub fn context(&self) -> &Arc<VulkanoContext> {
+        &self.context
+    }
+    /// Reads back the live particle count; blocks until the GPU is done.
+    pub fn particle_count(&self) -> u32 {
+        self.read_buffer(&self.physics_data.particle_counter)[0].count
+    }
+    pub fn capacity(&self) -> u32 {
+        self.physics_data.capacity
+    }
+    pub fn params(&self) -> &SimulationParams {
+        &self.params
+    }
+    /// Also resamples the boundary particles when the particle size, the
+    /// kernel or the rest density changed, or the box outgrew
+    /// `MAX_WALL_STRETCH`; the next step then rebuilds the neighbor structure.
+    /// Moving the box only moves its wall samples on the GPU. The FLIP grid
+    /// follows the box.
+    pub fn set_params(&mut self, params: SimulationParams) {
+        if params.backend() != self.params.backend() {
+            self.affine_stale = true;
+            self.needs_init = true;
+        }
+        self.params = params;
+        if let Ok(mut gpu_params) = self.sim_params_buffer.write() {
+            *gpu_params = params;
+        }
+        if params.pressure_solver() == PressureSolver::Pcisph {
+            self.pipelines.pcisph_pressure.set_delta(params.pcisph_delta());
+        }
+        if !self.boundary.matches(&params) {
+            let _s = tracy_client::span!("boundary_resample");
+            self.resample_boundary();
+        }
+        if params.backend() == SimulationBackend::Flip {
+            self.resize_flip_grid();
+        }
+    }
+    fn resize_flip_grid(&mut self) {
+        let dims = self.params.flip_grid_dims();
+        if self.physics_data.reserve_flip_grid(self.context.memory_allocator().clone(), dims) {
+            self.pipelines.prepare_flip(self.descriptor_set_allocator.clone(), &self.physics_data, &self.sim_params_buffer);
+        }
+        self.pipelines.flip.set_dims(dims);
+    }
+    /// Adds static boundary particles from meshes (`Scene::mesh_boundary`) to
+    /// the box walls' and resamples.
+    pub fn set_mesh_boundary(&mut self, samples: &[Vec3]) {
+        self.mesh_boundary = samples.to_vec();
+        let _s = tracy_client::span!("boundary_resample");
+        self.resample_boundary();
+    }
+    fn resample_boundary(&mut self) {
+        self.boundary = BoundaryParticles::for_box_and_meshes(&self.params, &self.mesh_boundary);
+        self.physics_data.set_boundary(self.context.memory_allocator().clone(), &self.boundary);
+        self.pipelines.prepare_boundary(self.descriptor_set_allocator.clone(), &self.physics_data, &self.sim_params_buffer);
+        self.needs_init = true;
+    }
+    /// Uploads the obstacles' current poses. Adding or removing obstacles, or
+    /// swapping a mesh, reallocates the obstacle buffers.
+    pub fn set_obstacles(&mut self, obstacles: &[Obstacle]) {
+        if Obstacle::same_layout(&self.obstacles, obstacles) {
+            self.physics_data.write_obstacles(obstacles);
+        } else {
+            let _s = tracy_client::span!("obstacle_upload");
+            self.physics_data.set_obstacles(self.context.memory_allocator().clone(), obstacles);
+            self.pipelines.prepare_obstacles(self.descriptor_set_allocator.clone(), &self.physics_data, &self.sim_params_buffer);
+        }
+        self.obstacles = obstacles.to_vec();
+    }
+    /// Replaces the sinks. Particles inside one are removed whenever the
+    /// neighbor structure is rebuilt, i.e. at the start of every step.
+    pub fn set_sinks(&mut self, sinks: &[Obstacle]) {
+        self.physics_data.set_sinks(self.context.memory_allocator().clone(), sinks);
+        self.pipelines.prepare_sinks(self.descriptor_set_allocator.clone(), &self.physics_data, &self.sim_params_buffer);
+        self.sinks = sinks.to_vec();
+    }
+    /// Queues particles to be appended at the start of the next step.
+    /// Whatever does not fit into the capacity is dropped on the GPU.
+    pub fn emit(&mut self, particles: &[EmittedParticle]) {
+        if !particles.is_empty() {
+            self.pending_emission.extend_from_slice(particles);
+            self.needs_init = true;
+        }
+    }
+    pub fn boundary_particle_count(&self) -> u32 {
+        self.boundary.len() as u32
+    }
+    pub fn set_sort_algorithm(&mut self, sort_algorithm: SortAlgorithm) {
+        self.pipelines.neighbor_search.sort_algorithm = sort_algorithm;
+    }
+
+    pub(crate) fn physics_data(&self) -> &GpuPhysicsData {
+        &self.physics_data
+    }
+    pub(crate) fn sim_params_buffer(&self) -> &Subbuffer<SimulationParams> {
+        &self.sim_params_buffer
+    }
+    #[cfg(test)]
+    pub(crate) fn pipelines(&self) -> &ComputePipelines {
+        &self.pipelines
+    }
+
+    /// Records whatever `record` emits into a one-off command buffer and waits
+    /// for it. Lets tests dispatch a single `ComputeStep` in isolation.
+    #[cfg(test)]
+    pub(crate) fn submit<F>(&self, record: F)
+    where
+        F: FnOnce(&mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>),
+    {
+        let mut builder = self.begin_commands();
+        record(&mut builder);
+        self.submit_and_wait(builder);
+    }
+
+    /// Records one frame of simulation: emission, sinks and neighbor structure
+    /// rebuild, then as many substeps of `params.dt` as fit into `frame_dt`,
+    /// then the stats reduction. Returns the number of substeps recorded.
+    pub fn record_step<Cb>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<Cb>,
+        frame_dt: f32,
+    ) -> u32 {
+        self.record_init(builder);
+        self.record_clear_impulses(builder);
+
+        let mut substeps = 0;
+        let mut step = 0.0;
+        while step < frame_dt {
+            self.record_substep(builder);
+            step += self.params.dt;
+            substeps += 1;
+        }
+
+        self.impulse_time = substeps as f32 * self.params.dt;
+        self.record_stats(builder);
+        self.needs_init = false;
+        substeps
+    }
+
+    /// Advances the simulation by `frame_dt` seconds on the same code path as the
+    /// interactive app and blocks until the GPU is done.
+    pub fn step(&mut self, frame_dt: f32) {
+        let mut builder = self.begin_commands();
+        self.record_step(&mut builder, frame_dt);
+        self.submit_and_wait(builder);
+    }
+
+    /// Runs exactly `n_substeps` substeps in one command buffer. The neighbor
+    /// structure is only rebuilt up-front when the particle state was replaced
+    /// (creation, upload, emission) or sinks need applying, since every
+    /// substep leaves it synchronized.
+    /// `run_substeps(0)` therefore just evaluates densities for the current state.
+    pub fn run_substeps(&mut self, n_substeps: u32) {
+        let mut builder = self.begin_commands();
+        self.record_substeps(&mut builder, n_substeps);
+        self.submit_and_wait(builder);
+    }
+
+    /// Records exactly `n_substeps` substeps followed by the stats reduction,
+    /// rebuilding the neighbor structure first only when needed. The recorded
+    /// counterpart of `run_substeps`, used for single-stepping while paused.
+    pub fn record_substeps<Cb>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<Cb>,
+        n_substeps: u32,
+    ) {
+        if self.needs_init || !self.sinks.is_empty() {
+            self.record_init(builder);
+        }
+        self.record_clear_impulses(builder);
+        for _ in 0..n_substeps {
+            self.record_substep(builder);
+        }
+        self.impulse_time = n_substeps as f32 * self.params.dt;
+        self.record_stats(builder);
+        self.needs_init = false;
+    }
+
+    /// Records the surface normals of the current state, which the substeps
+    /// only keep up to date while surface tension is on. Safe to call between
+    /// steps, since the viscosity pass recomputes them before using them.
+    pub fn record_surface_normals<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>) {
+        let _s = tracy_client::span!("surface_normals");
+        self.pipelines.surface_normals.execute(builder);
+    }
+
+    /// Records a reset of the particle state to `initial_positions` of
+    /// `initial_phases` (at rest, zero pressure), dropping emitted particles
+    /// that are still queued. The next step rebuilds the neighbor structure.
+    pub fn record_reset<Cb>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<Cb>,
+        initial_positions: &[[f32; 3]],
+        initial_phases: &[u32],
+    ) {
+        self.pending_emission.clear();
+        self.physics_data.record_reset(self.context.memory_allocator().clone(), builder, initial_positions, initial_phases);
+        self.needs_init = true;
+    }
+
+    /// Resets the particle state to `initial_positions` of `initial_phases`
+    /// and blocks until done.
+    pub fn reset(&mut self, initial_positions: &[[f32; 3]], initial_phases: &[u32]) {
+        let mut builder = self.begin_commands();
+        self.record_reset(&mut builder, initial_positions, initial_phases);
+        self.submit_and_wait(builder);
+    }
+
+    /// Records copies of the particle state into host-visible buffers; read
+    /// them with `PendingSnapshot::try_read` once the frame has finished.
+    pub fn record_snapshot<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>) -> PendingSnapshot {
+        let data = &self.physics_data;
+        let snapshot = PendingSnapshot {
+            counter: self.create_readback_buffer(data.particle_counter.len()),
+            positions: self.create_readback_buffer(data.position_a.len()),
+            velocities: self.create_readback_buffer(data.velocity_a.len()),
+            pressures: self.create_readback_buffer(data.pressures.len()),
+            densities: self.create_readback_buffer(data.densities.len()),
+        };
+        builder.copy_buffer(CopyBufferInfo::buffers(data.particle_counter.clone(), snapshot.counter.clone())).unwrap();
+        builder.copy_buffer(CopyBufferInfo::buffers(data.position_a.clone(), snapshot.positions.clone())).unwrap();
+        builder.copy_buffer(CopyBufferInfo::buffers(data.velocity_a.clone(), snapshot.velocities.clone())).unwrap();
+        builder.copy_buffer(CopyBufferInfo::buffers(data.pressures.clone(), snapshot.pressures.clone())).unwrap();
+        builder.copy_buffer(CopyBufferInfo::buffers(data.densities.clone(), snapshot.densities.clone())).unwrap();
+        snapshot
+    }
+
+    /// Records an upload of a previously captured particle state. The next step
+    /// rebuilds the neighbor structure from it.
+    pub fn record_restore<Cb>(&mut self, builder: &mut AutoCommandBufferBuilder<Cb>, state: &ParticleState) {
+        assert!(state.len() as u32 <= self.physics_data.capacity, "checkpoint holds more particles than the capacity");
+        self.pending_emission.clear();
+
+        let data = &self.physics_data;
+        data.record_set_count(self.context.memory_allocator().clone(), builder, state.len() as u32);
+        if state.is_empty() {
+            self.needs_init = true;
+            return;
+        }
+        builder.copy_buffer(CopyBufferInfo::buffers(self.create_upload_buffer(&state.positions), data.position_a.clone())).unwrap();
+        builder.copy_buffer(CopyBufferInfo::buffers(self.create_upload_buffer(&state.velocities), data.velocity_a.clone())).unwrap();
+        builder.copy_buffer(CopyBufferInfo::buffers(self.create_upload_buffer(&state.pressures), data.pressures.clone())).unwrap();
+        builder.copy_buffer(CopyBufferInfo::buffers(self.create_upload_buffer(&state.densities), data.densities.clone())).unwrap();
+        builder.fill_buffer(data.pressure_accelerations.clone().reinterpret::<[u32]>(), 0).unwrap();
+        self.affine_stale = true;
+        self.needs_init = true;
+    }
+
+    /// Reads back the particle state; blocks until the GPU is done.
+    pub fn snapshot(&self) -> ParticleState {
+        let mut builder = self.begin_commands();
+        let snapshot = self.record_snapshot(&mut builder);
+        self.submit_and_wait(builder);
+        snapshot.try_read().expect("Snapshot buffers still in use after wait")
+    }
+
+    /// Uploads a particle state captured by `snapshot` or loaded from a
+    /// `Checkpoint`; blocks until the GPU is done.
+    pub fn restore(&mut self, state: &ParticleState) {
+        let mut builder = self.begin_commands();
+        self.record_restore(&mut builder, state);
+        self.submit_and_wait(builder);
+    }
+
+    /// The `read_*` helpers return the live particles only.
+    pub fn read_positions(&self) -> Vec<[f32; 3]> {
+        self.read_live(&self.physics_data.position_a)
+            .into_iter()
+            .map(|p| [p[0], p[1], p[2]])
+            .collect()
+    }
+    pub fn read_phases(&self) -> Vec<u32> {
+        self.read_live(&self.physics_data.position_a)
+            .into_iter()
+            .map(|p| particle_phase(p[3]))
+            .collect()
+    }
+    pub fn read_velocities(&self) -> Vec<[f32; 3]> {
+        self.read_live(&self.physics_data.velocity_a)
+            .into_iter()
+            .map(|v| [v[0], v[1], v[2]])
+            .collect()
+    }
+    pub fn read_densities(&self) -> Vec<f32> {
+        self.read_live(&self.physics_data.densities)
+    }
+    pub fn read_pressures(&self) -> Vec<f32> {
+        self.read_live(&self.physics_data.pressures)
+    }
+    /// Copies the counter along with `source`, so the live prefix costs one
+    /// submission and one wait.
+    fn read_live<T>(&self, source: &Subbuffer<[T]>) -> Vec<T>
+    where
+        T: BufferContents + Copy,
+    {
+        let counter = self.create_readback_buffer::<ParticleCounter>(self.physics_data.particle_counter.len());
+        let staging = self.create_readback_buffer::<T>(source.len());
+
+        let mut builder = self.begin_commands();
+        builder.copy_buffer(CopyBufferInfo::buffers(self.physics_data.particle_counter.clone(), counter.clone())).unwrap();
+        builder.copy_buffer(CopyBufferInfo::buffers(source.clone(), staging.clone())).unwrap();
+        self.submit_and_wait(builder);
+
+        let data = staging.read().expect("Failed to map readback buffer");
+        let n = (counter.read().expect("Failed to map readback buffer")[0].count as usize).min(data.len());
+        data[..n].to_vec()
+    }
+
+    /// Reads the stats of the last recorded step. Returns `None` while the
+    /// buffer is still in use by the GPU.
+    pub fn read_stats(&self) -> Option<SimulationStats> {
+        let stats = self.physics_data.stats_buffer.read().ok()?;
+        let viscosity = match self.params.viscosity_mode() {
+            ViscosityMode::Explicit => ViscositySolverState::default(),
+            ViscosityMode::Implicit => self.physics_data.viscosity_solver.read().ok()?[0],
+        };
+        let pressure = self.physics_data.pressure_solver.read().ok()?[0];
+        let (density_iterations, divergence_iterations) = pressure.iterations(&self.params);
+        let n = stats[3].max(1) as f32;
+        Some(SimulationStats {
+            max_speed: f32::from_bits(stats[0]),
+            avg_density_error: stats[1] as f32 / (DENSITY_SCALE * n),
+            avg_divergence_error: stats[2] as f32 / (DIVERGENCE_SCALE * n),
+            particle_count: stats[3],
+            viscosity_iterations: viscosity.iterations,
+            viscosity_residual: viscosity.relative_residual(),
+            density_iterations,
+            divergence_iterations,
+        })
+    }
+
+    /// Reads the force and torque the fluid exerted on each obstacle, averaged
+    /// over the last recorded step, in the order of `set_obstacles`. Returns
+    /// `None` while the buffer is still in use by the GPU.
+    pub fn read_obstacle_forces(&self) -> Option<Vec<ObstacleForce>> {
+        let impulses = self.physics_data.obstacle_impulses.read().ok()?;
+        Some(impulses
+            .chunks_exact(8)
+            .take(self.obstacles.len())
+            .map(|c| {
+                let linear = IVec3::new(c[0], c[1], c[2]);
+                let angular = IVec3::new(c[4], c[5], c[6]);
+                ObstacleForce::from_impulses([linear, angular], self.impulse_time)
+            })
+            .collect())
+    }
+
+    fn record_init<Cb>(&mut self, builder: &mut AutoCommandBufferBuilder<Cb>) {
+        if self.affine_stale {
+            self.physics_data.record_clear_affine(builder);
+            self.affine_stale = false;
+        }
+        if !self.pending_emission.is_empty() {
+            let _s = tracy_client::span!("emit");
+            let batch = GpuPhysicsData::upload_buffer(
+                self.context.memory_allocator().clone(),
+                self.pending_emission.drain(..).map(|p| p.to_gpu()).collect::<Vec<_>>(),
+            );
+            self.pipelines.emit.prepare_with_particles(self.descriptor_set_allocator.clone(), &self.physics_data, batch);
+            self.pipelines.emit.execute(builder);
+        }
+        if !self.sinks.is_empty() {
+            let _s = tracy_client::span!("sink");
+            self.pipelines.sink.execute(builder);
+        }
+        {
+            let _s = tracy_client::span!("neighbor_search_init");
+            self.pipelines.neighbor_search.execute(builder);
+        }
+        if !self.sinks.is_empty() {
+            // The reorder left the survivors compacted in the b buffers; the
+            // canonical a buffers must not keep the removed ones in between.
+            let data = &self.physics_data;
+            builder.copy_buffer(CopyBufferInfo::buffers(data.position_b.clone(), data.position_a.clone())).unwrap();
+            builder.copy_buffer(CopyBufferInfo::buffers(data.velocity_b.clone(), data.velocity_a.clone())).unwrap();
+        }
+        {
+            let _s = tracy_client::span!("density_alpha_init");
+            self.pipelines.density_alpha.execute(builder);
+        }
+    }
+
+    fn record_substep<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>) {
+        let _substep = tracy_client::span!("substep");
+
+        if self.params.max_surface_tension() > 0.0 {
+            let _s = tracy_client::span!("surface_normals");
+            self.pipelines.surface_normals.execute(builder);
+        }
+        {
+            let _s = tracy_client::span!("viscosity");
+            self.pipelines.viscosity.execute(builder);
+        }
+        if self.params.viscosity_mode() == ViscosityMode::Implicit {
+            let _s = tracy_client::span!("viscosity_solver");
+            self.pipelines.viscosity_solver.execute(builder);
+            for _ in 0..self.params.viscosity_iterations {
+                self.pipelines.viscosity_solver.execute_iteration(builder);
+            }
+        }
+        if self.params.vorticity_confinement > 0.0 {
+            let _s = tracy_client::span!("vorticity_confinement");
+            self.pipelines.vorticity.execute(builder);
+            self.pipelines.vorticity_confinement.execute(builder);
+        }
+        if self.params.backend() == SimulationBackend::Flip {
+            self.record_pressure_substep(&FlipProjection, builder, self.params.grid_iterations, 0);
+            return;
+        }
+        let density_iters = self.params.density_solver_iterations;
+        let divergence_iters = self.params.divergence_solver_iterations;
+        match self.params.pressure_solver() {
+            PressureSolver::Dfsph => self.record_pressure_substep(&Dfsph, builder, density_iters, divergence_iters),
+            PressureSolver::Wcsph => self.record_pressure_substep(&Wcsph, builder, density_iters, divergence_iters),
+            PressureSolver::Pcisph => self.record_pressure_substep(&Pcisph, builder, density_iters, divergence_iters),
+            PressureSolver::Iisph => self.record_pressure_substep(&Iisph, builder, density_iters, divergence_iters),
+            PressureSolver::Pbf => self.record_pressure_substep(&Pbf, builder, density_iters, divergence_iters),
+        }
+    }
+
+    /// Records the pressure part of a substep: `solver`'s density solve, the
+    /// integration and neighbor search in between, then its divergence solve.
+    fn record_pressure_substep<S: PressureSolverStep, Cb>(
+        &self,
+        solver: &S,
+        builder: &mut AutoCommandBufferBuilder<Cb>,
+        density_iters: u32,
+        divergence_iters: u32,
+    ) {
+        {
+            let _s = tracy_client::span!("density_solver");
+            solver.record_density_solve(&self.pipelines, &self.physics_data, builder, density_iters);
+        }
+        {
+            let _s = tracy_client::span!("pressure_integration");
+            self.pipelines.pressure_integration.execute(builder);
+        }
+        {
+            let _s = tracy_client::span!("neighbor_search_post_integrate");
+            self.pipelines.neighbor_search.execute(builder);
+        }
+        {
+            let _s = tracy_client::span!("density_alpha_post_integrate");
+            self.pipelines.density_alpha.execute(builder);
+        }
+        {
+            let _s = tracy_client::span!("divergence_solver");
+            solver.record_divergence_solve(&self.pipelines, &self.physics_data, builder, divergence_iters);
+        }
+        {
+            let _s = tracy_client::span!("divergence_integration");
+            self.pipelines.divergence_integration.execute(builder);
+        }
+    }
+
+    fn record_clear_impulses<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>) {
+        let impulses = self.physics_data.obstacle_impulses.clone().reinterpret::<[u32]>();
+        builder.fill_buffer(impulses, 0).unwrap();
+    }
+
+    fn record_stats<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>) {
+        let _s = tracy_client::span!("stats");
+        self.pipelines.stats.execute(builder);
+    }
+
+    fn begin_commands(&self) -> AutoCommandBufferBuilder<PrimaryAutoCommandBuffer> {
+        AutoCommandBufferBuilder::primary(
+            self.command_buffer_allocator.clone(),
+            self.context.graphics_queue().queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        ).unwrap()
+    }
+
+    fn submit_and_wait(&self, builder: AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) {
+        builder.build().unwrap()
+            .execute(self.context.graphics_queue().clone())
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+    }
+
+    pub(crate) fn read_buffer<T>(&self, source: &Subbuffer<[T]>) -> Vec<T>
+    where
+        T: BufferContents + Copy,
+    {
+        let staging = self.create_readback_buffer::<T>(source.len());
+
+        let mut builder = self.begin_commands();
+        builder.copy_buffer(CopyBufferInfo::buffers(source.clone(), staging.clone())).unwrap();
+        self.submit_and_wait(builder);
+
+        let data = staging.read().expect("Failed to map readback buffer");
+        data.to_vec()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn write_buffer<T>(&self, destination: &Subbuffer<[T]>, data: &[T])
+    where
+        T: BufferContents + Copy,
+    {
+        let staging = self.create_upload_buffer(data);
+
+        let mut builder = self.begin_commands();
+        builder.copy_buffer(CopyBufferInfo::buffers(staging, destination.clone())).unwrap();
+        self.submit_and_wait(builder);
+    }
+
+    fn create_readback_buffer<T>(&self, len: u64) -> Subbuffer<[T]>
+    where
+        T: BufferContents,
+    {
+        Buffer::new_slice::<T>(
+            self.context.memory_allocator().clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..Default::default()
+            },
+            len,
+        ).expect("Failed to create readback buffer")
+    }
+
+    fn create_upload_buffer<T>(&self, data: &[T]) -> Subbuffer<[T]>
+    where
+        T: BufferContents + Copy,
+    {
+        Buffer::from_iter(
+            self.context.memory_allocator().clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            data.iter().copied(),
+        ).expect("Failed to create upload buffer")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::{IVec3, Quat, Vec3};
+    use crate::entities::obstacle::ObstacleShape;
+
+    fn block() -> (Vec<[f32; 3]>, SimulationParams) {
+        let radius = 0.02;
+        let (positions, mass) = ParticleGenerator::generate_volume(
+            Vec3::new(-0.2, 0.05, -0.2), 0.3, 0.3, 0.3, radius, 1000.0, 2.0 * radius, 0.0,
+        );
+        let params = SimulationParams::new(
+            radius, mass, 4.0 * radius, 1000.0, 0.15, 0.5, 0.005, 4, 4,
+            Vec3::new(0.0, -9.81, 0.0),
+            Vec3::new(-0.5, 0.0, -0.5),
+            Vec3::new(0.5, 1.0, 0.5),
+            IVec3::splat(128),
+        );
+        (positions, params)
+    }
+
+    #[test]
+    fn reset_restores_initial_state() {
+        let (positions, params) = block();
+
+        let mut sim = Simulation::headless(&positions, params);
+        sim.run_substeps(20);
+        assert_ne!(sim.read_positions(), positions);
+
+        sim.reset(&positions, &[]);
+        assert_eq!(sim.read_positions(), positions);
+        assert!(sim.read_velocities().iter().all(|v| *v == [0.0; 3]));
+        assert!(sim.read_pressures().iter().all(|&p| p == 0.0));
+
+        // The neighbor structure is rebuilt from the restored positions.
+        sim.run_substeps(1);
+        assert!(sim.read_positions().iter().all(|p| p.iter().all(|c| c.is_finite())));
+    }
+
+    #[test]
+    fn snapshot_restore_round_trip() {
+        let (positions, params) = block();
+
+        let ctx = create_headless_context();
+        let mut settled = Simulation::new(ctx.clone(), &positions, params);
+        settled.run_substeps(20);
+        let state = settled.snapshot();
+
+        let mut resumed = Simulation::new(ctx, &positions, params);
+        resumed.restore(&state);
+        assert_eq!(resumed.snapshot(), state);
+
+        resumed.run_substeps(1);
+        assert!(resumed.read_positions().iter().all(|p| p.iter().all(|c| c.is_finite())));
+    }
+
+    #[test]
+    fn emission_and_sinks_change_the_live_count() {
+        let (positions, params) = block();
+        let initial = positions.len() as u32;
+
+        let mut sim = Simulation::with_capacity(create_headless_context(), &positions, &[], params, initial + 100);
+        assert_eq!(sim.capacity(), initial + 100);
+        assert_eq!(sim.particle_count(), initial);
+
+        // 150 particles above the block; the 50 that do not fit are dropped.
+        let emitted: Vec<EmittedParticle> = (0..150)
+            .map(|i| EmittedParticle {
+                position: Vec3::new(-0.45 + 0.04 * (i % 10) as f32, 0.8, -0.45 + 0.04 * (i / 10) as f32),
+                velocity: Vec3::ZERO,
+                phase: 0,
+            })
+            .collect();
+        sim.emit(&emitted);
+        sim.run_substeps(1);
+        assert_eq!(sim.particle_count(), initial + 100);
+        assert_eq!(sim.read_positions().len(), (initial + 100) as usize);
+        assert!(sim.read_positions().iter().all(|p| p.iter().all(|c| c.is_finite())));
+
+        // A sink around the emitted layer takes it out again.
+        sim.set_sinks(&[Obstacle::new(
+            ObstacleShape::Box { half_extents: Vec3::new(0.5, 0.2, 0.5) },
+            Vec3::new(0.0, 0.8, 0.0),
+            Quat::IDENTITY,
+        )]);
+        sim.run_substeps(1);
+        assert_eq!(sim.particle_count(), initial);
+        assert!(sim.read_positions().iter().all(|p| p[1] < 0.6));
+        assert_eq!(sim.read_stats().unwrap().particle_count, initial);
+    }
+
+    #[test]
+    fn dfsph_iterations_stop_on_the_gpu_below_eta() {
+        let (positions, mut params) = block();
+        params.density_solver_iterations = 20;
+        params.divergence_solver_iterations = 20;
+
+        let mut sim = Simulation::headless(&positions, params);
+        sim.run_substeps(1);
+        let stats = sim.read_stats().unwrap();
+        assert_eq!((stats.density_iterations, stats.divergence_iterations), (20, 20));
+
+        // Every check passes, so only the minimum runs.
+        params.max_density_error = 1.0;
+        params.max_divergence_error = 1.0;
+        sim.set_params(params);
+        sim.run_substeps(1);
+        let stats = sim.read_stats().unwrap();
+        assert_eq!((stats.density_iterations, stats.divergence_iterations), (2, 1));
+        assert!(sim.read_positions().iter().all(|p| p.iter().all(|c| c.is_finite())));
+    }
+}
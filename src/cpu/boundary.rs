@@ -0,0 +1,459 @@
+use std::collections::HashSet;
+use glam::{IVec3, Vec3};
+use rayon::prelude::*;
+use crate::cpu::kernel::{cell_coords, kernel_w};
+use crate::cpu::steps::to_box_frame;
+use crate::entities::particle::{GpuBoundaryGrid, SimulationParams};
+
+/// Share by which the box may grow or shrink along an axis before its walls
+/// are sampled again. Up to there the samples spread or bunch up with it.
+pub const MAX_WALL_STRETCH: f32 = 0.2;
+
+const WALLS: usize = 0;
+const STATIC: usize = 1;
+
+/// A dense cell grid over one set of samples.
+#[derive(Clone, Copy, Default)]
+struct Grid {
+    origin: IVec3,
+    dims: IVec3,
+    /// Where the grid's cells start in `cell_ranges`.
+    cell_offset: usize,
+    cell_size: f32,
+}
+
+impl Grid {
+    fn cell_index(&self, cell: IVec3) -> Option<usize> {
+        let c = cell - self.origin;
+        if c.cmplt(IVec3::ZERO).any() || c.cmpge(self.dims).any() {
+            return None;
+        }
+        Some(self.cell_offset + ((c.z * self.dims.y + c.y) * self.dims.x + c.x) as usize)
+    }
+}
+
+/// Boundary sampled as particles (Akinci et al. 2012).
+///
+/// Every sample carries a volume-based mass `ψ_b = ρ₀ / Σ_k W_bk`, so densely
+/// sampled regions do not over-weight the wall. The fluid passes add
+/// `Σ_b ψ_b W_ib` to the density and the matching gradient terms to α, the
+/// source terms and the pressure accelerations.
+///
+/// The samples are sorted by cell once on the CPU and uploaded as-is, in two
+/// grids. The box walls are kept in the box frame, as sampled at `lo..hi`;
+/// the passes map them onto the current walls axis by axis and turn them with
+/// the box, so moving, turning or waving the box never resamples them. Mesh
+/// samples are static and kept in world space. Unlike the fluid, each grid is
+/// dense over its samples' bounding box instead of hashed: it never changes
+/// during a step, and hash collisions between the 27 neighbor cells would
+/// count some samples twice.
+pub struct BoundaryParticles {
+    /// Position in `xyz`, ψ in `w`, sorted by cell: the walls first, then the
+    /// static samples.
+    pub particles: Vec<[f32; 4]>,
+    /// `[start, end)` into `particles` for every cell of both grids, x fastest.
+    pub cell_ranges: Vec<[u32; 2]>,
+    grids: [Grid; 2],
+    wall_count: usize,
+    /// The wall layer as sampled, in the box frame.
+    lo: Vec3,
+    hi: Vec3,
+    h: f32,
+    key: [f32; 4],
+}
+
+impl BoundaryParticles {
+    /// Samples the walls of the simulation box at particle spacing. The layer
+    /// sits one particle radius outside the walls, where the next lattice layer
+    /// of fluid resting against the wall would be.
+    pub fn for_box(params: &SimulationParams) -> Self {
+        Self::for_box_and_meshes(params, &[])
+    }
+
+    /// The box walls plus static samples of mesh boundaries (see `sample_mesh`).
+    /// Each set's ψ only counts its own samples.
+    pub fn for_box_and_meshes(params: &SimulationParams, mesh_samples: &[Vec3]) -> Self {
+        let h = params.smoothing_radius;
+        let (lo, hi) = wall_layer(params);
+        let walls = sample_box(lo, hi, 2.0 * params.particle_radius);
+
+        let mut boundary = Self {
+            particles: Vec::with_capacity(walls.len() + mesh_samples.len()),
+            cell_ranges: Vec::new(),
+            grids: [Grid::default(); 2],
+            wall_count: walls.len(),
+            lo,
+            hi,
+            h,
+            key: Self::key(params),
+        };
+        // The mapped walls' neighbors can lie up to h / (1 - MAX_WALL_STRETCH)
+        // apart in the sampled layer; a cell that wide keeps them in the 27.
+        boundary.add_grid(WALLS, &walls, h / (1.0 - MAX_WALL_STRETCH), params);
+        boundary.add_grid(STATIC, mesh_samples, h, params);
+        boundary
+    }
+
+    /// Sorts `samples` into grid `g`, in the grid's own frame, and computes
+    /// their ψ.
+    fn add_grid(&mut self, g: usize, samples: &[Vec3], cell_size: f32, params: &SimulationParams) {
+        let first = self.particles.len();
+        let cell_offset = self.cell_ranges.len();
+        if samples.is_empty() {
+            self.grids[g] = Grid { cell_offset, cell_size, ..Grid::default() };
+            return;
+        }
+
+        let cells: Vec<IVec3> = samples.iter().map(|&p| cell_coords(p, cell_size)).collect();
+        let origin = cells.iter().copied().reduce(IVec3::min).unwrap();
+        let dims = cells.iter().copied().reduce(IVec3::max).unwrap() - origin + IVec3::ONE;
+        let grid = Grid { origin, dims, cell_offset, cell_size };
+
+        let mut sorted: Vec<(usize, Vec3)> = cells.iter().zip(samples).map(|(&c, &p)| (grid.cell_index(c).unwrap(), p)).collect();
+        sorted.par_sort_unstable_by(|a, b| a.0.cmp(&b.0).then(a.1.to_array().partial_cmp(&b.1.to_array()).unwrap()));
+
+        self.cell_ranges.resize(cell_offset + dims.element_product() as usize, [0; 2]);
+        for (k, &(cell, _)) in sorted.iter().enumerate() {
+            if k == 0 || sorted[k - 1].0 != cell {
+                self.cell_ranges[cell][0] = (first + k) as u32;
+            }
+            self.cell_ranges[cell][1] = (first + k) as u32 + 1;
+        }
+        self.particles.extend(sorted.iter().map(|&(_, p)| [p.x, p.y, p.z, 0.0]));
+        self.grids[g] = grid;
+
+        let h = self.h;
+        let rho_0 = params.target_density;
+        let kernel = params.kernel();
+        let psi: Vec<f32> = self.particles[first..]
+            .par_iter()
+            .map(|p| {
+                let p = Vec3::new(p[0], p[1], p[2]);
+                let mut delta = 0.0;
+                self.for_each_in_grid(g, cell_coords(p, cell_size), |q, _| {
+                    let r = p.distance(q);
+                    if r <= h {
+                        delta += kernel_w(kernel, r, h);
+                    }
+                });
+                rho_0 / delta
+            })
+            .collect();
+        for (p, psi) in self.particles[first..].iter_mut().zip(psi) {
+            p[3] = psi;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.particles.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.particles.is_empty()
+    }
+
+    /// Both grids as laid out in the shaders' `BoundaryGrids` buffer.
+    pub fn grid_header(&self) -> [GpuBoundaryGrid; 2] {
+        self.grids.map(|grid| GpuBoundaryGrid {
+            origin: grid.origin.extend(grid.cell_offset as i32).to_array(),
+            dims: grid.dims.extend(0).to_array(),
+            lo: self.lo.extend(grid.cell_size).to_array(),
+            hi: self.hi.extend(0.0).to_array(),
+        })
+    }
+
+    /// False once `params` changed the sampling or the kernel, or stretched
+    /// the box by more than `MAX_WALL_STRETCH` along an axis, i.e. when
+    /// `for_box` has to run again. The pose of the box does not matter.
+    pub fn matches(&self, params: &SimulationParams) -> bool {
+        let (lo, hi) = wall_layer(params);
+        let stretch = (hi - lo) / (self.hi - self.lo);
+        self.key == Self::key(params)
+            && stretch.cmpge(Vec3::splat(1.0 - MAX_WALL_STRETCH)).all()
+            && stretch.cmple(Vec3::splat(1.0 + MAX_WALL_STRETCH)).all()
+    }
+
+    fn key(params: &SimulationParams) -> [f32; 4] {
+        [params.particle_radius, params.smoothing_radius, params.target_density, params.kernel as f32]
+    }
+
+    /// Calls `f(psi, r_vec, r)` for every boundary particle within the smoothing
+    /// radius of `pos_i`, walking the walls' and then the static grid's 27
+    /// surrounding cells in the same order as the shaders. `params` places the
+    /// walls.
+    pub fn for_each_neighbor<F>(&self, params: &SimulationParams, pos_i: Vec3, mut f: F)
+    where
+        F: FnMut(f32, Vec3, f32),
+    {
+        let h = self.h;
+        for g in [WALLS, STATIC] {
+            self.for_each_in_grid(g, self.cell(g, params, pos_i), |p, psi| {
+                let r_vec = pos_i - self.position(g, params, p);
+                let r2 = r_vec.dot(r_vec);
+                if r2 > h * h {
+                    return;
+                }
+
+                f(psi, r_vec, r2.sqrt());
+            });
+        }
+    }
+
+    /// World positions of all samples with the walls placed by `params`.
+    pub fn positions(&self, params: &SimulationParams) -> Vec<Vec3> {
+        self.particles
+            .iter()
+            .enumerate()
+            .map(|(b, p)| self.position(if b < self.wall_count { WALLS } else { STATIC }, params, Vec3::new(p[0], p[1], p[2])))
+            .collect()
+    }
+
+    /// Calls `f(p, psi)` for the samples of grid `g` in `cell` and its 26
+    /// neighbors, with `p` in the grid's frame.
+    fn for_each_in_grid<F>(&self, g: usize, cell: IVec3, mut f: F)
+    where
+        F: FnMut(Vec3, f32),
+    {
+        let grid = &self.grids[g];
+        for z in -1..=1 {
+            for y in -1..=1 {
+                for x in -1..=1 {
+                    let Some(index) = grid.cell_index(cell + IVec3::new(x, y, z)) else {
+                        continue;
+                    };
+                    let [start, end] = self.cell_ranges[index];
+
+                    for p in &self.particles[start as usize..end as usize] {
+                        f(Vec3::new(p[0], p[1], p[2]), p[3]);
+                    }
+                }
+            }
+        }
+    }
+
+    /// `boundary_cell` in boundary.glsl.
+    fn cell(&self, g: usize, params: &SimulationParams, pos: Vec3) -> IVec3 {
+        let grid = &self.grids[g];
+        let mut pos = pos;
+        if g == WALLS {
+            let (lo, hi) = wall_layer(params);
+            pos = self.lo + (to_box_frame(params, pos) - lo) * (self.hi - self.lo) / (hi - lo);
+        }
+        (pos / grid.cell_size).floor().as_ivec3()
+    }
+
+    /// `boundary_position` in boundary.glsl.
+    fn position(&self, g: usize, params: &SimulationParams, p: Vec3) -> Vec3 {
+        if g == WALLS {
+            let (lo, hi) = wall_layer(params);
+            return from_box_frame(params, lo + (p - self.lo) * (hi - lo) / (self.hi - self.lo));
+        }
+        p
+    }
+}
+
+/// `boundary_wall_layer` in boundary.glsl: the box extents grown by a
+/// particle radius, in the box frame.
+fn wall_layer(params: &SimulationParams) -> (Vec3, Vec3) {
+    let r = params.particle_radius;
+    (Vec3::from_slice(&params.box_min[..3]) - r, Vec3::from_slice(&params.box_max[..3]) + r)
+}
+
+fn box_centre(params: &SimulationParams) -> Vec3 {
+    (Vec3::from_slice(&params.box_min[..3]) + Vec3::from_slice(&params.box_max[..3])) * 0.5
+}
+
+/// `from_box_frame` in common.glsl.
+fn from_box_frame(params: &SimulationParams, p: Vec3) -> Vec3 {
+    box_centre(params) + params.box_rotation() * (p - box_centre(params))
+}
+
+/// One layer of samples on each face of the box, spaced as close to `spacing`
+/// as the extents allow. Edges and corners are sampled once.
+pub fn sample_box(min: Vec3, max: Vec3, spacing: f32) -> Vec<Vec3> {
+    let extent = max - min;
+    let n = (extent / spacing).round().max(Vec3::ONE).as_ivec3();
+    let step = extent / n.as_vec3();
+    let at = |x: i32, y: i32, z: i32| min + step * Vec3::new(x as f32, y as f32, z as f32);
+
+    let mut samples = Vec::new();
+    for x in [0, n.x] {
+        for y in 0..=n.y {
+            for z in 0..=n.z {
+                samples.push(at(x, y, z));
+            }
+        }
+    }
+    for y in [0, n.y] {
+        for x in 1..n.x {
+            for z in 0..=n.z {
+                samples.push(at(x, y, z));
+            }
+        }
+    }
+    for z in [0, n.z] {
+        for x in 1..n.x {
+            for y in 1..n.y {
+                samples.push(at(x, y, z));
+            }
+        }
+    }
+    samples
+}
+
+/// Samples every triangle on a barycentric lattice no coarser than
+/// `spacing`, merging samples closer than a hundredth of it. Shared edges are
+/// sampled by both triangles at their own density; ψ evens that out.
+pub fn sample_mesh(vertices: &[Vec3], triangles: &[[u32; 3]], spacing: f32) -> Vec<Vec3> {
+    let mut seen = HashSet::new();
+    let mut samples = Vec::new();
+
+    for t in triangles {
+        let [a, b, c] = t.map(|i| vertices[i as usize]);
+        let longest = a.distance(b).max(b.distance(c)).max(c.distance(a));
+        let n = (longest / spacing).ceil().max(1.0) as u32;
+        for i in 0..=n {
+            for j in 0..=n - i {
+                let p = a + (b - a) * (i as f32 / n as f32) + (c - a) * (j as f32 / n as f32);
+                if seen.insert((p / (0.01 * spacing)).round().as_ivec3()) {
+                    samples.push(p);
+                }
+            }
+        }
+    }
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> SimulationParams {
+        SimulationParams::new(
+            0.02, 0.064, 0.08, 1000.0, 0.15, 0.5, 0.005, 4, 4,
+            Vec3::new(0.0, -9.81, 0.0),
+            Vec3::new(-0.3, 0.0, -0.2),
+            Vec3::new(0.3, 0.48, 0.2),
+            IVec3::splat(128),
+        )
+    }
+
+    #[test]
+    fn box_samples_are_unique_and_on_the_walls() {
+        let params = params();
+        let min = Vec3::from_slice(&params.box_min[..3]);
+        let max = Vec3::from_slice(&params.box_max[..3]);
+        let samples = sample_box(min, max, 0.04);
+
+        let n = IVec3::new(15, 12, 10);
+        let expected = (n + IVec3::ONE).element_product() - (n - IVec3::ONE).element_product();
+        assert_eq!(samples.len() as i32, expected);
+
+        for p in &samples {
+            let on_wall = (0..3).any(|a| (p[a] - min[a]).abs() < 1e-5 || (p[a] - max[a]).abs() < 1e-5);
+            assert!(on_wall, "{p} is not on a wall");
+        }
+        let mut keys: Vec<[i32; 3]> = samples.iter().map(|p| (*p * 1000.0).round().as_ivec3().to_array()).collect();
+        keys.sort_unstable();
+        keys.dedup();
+        assert_eq!(keys.len(), samples.len());
+    }
+
+    /// Neighbors of `probe` through the grids and by brute force over every
+    /// sample placed by `params`.
+    fn neighbor_counts(boundary: &BoundaryParticles, params: &SimulationParams, probe: Vec3) -> (usize, usize) {
+        let h = params.smoothing_radius;
+        let mut found = 0;
+        boundary.for_each_neighbor(params, probe, |_, _, _| found += 1);
+        let expected = boundary.positions(params).iter().filter(|p| probe.distance_squared(**p) <= h * h).count();
+        (found, expected)
+    }
+
+    #[test]
+    fn grid_finds_same_neighbors_as_brute_force() {
+        let params = params();
+        let boundary = BoundaryParticles::for_box(&params);
+        let (found, expected) = neighbor_counts(&boundary, &params, Vec3::new(-0.28, 0.03, 0.1));
+        assert_eq!(found, expected);
+        assert!(found > 0);
+    }
+
+    #[test]
+    fn walls_follow_the_box_without_resampling() {
+        let params = params();
+        let boundary = BoundaryParticles::for_box(&params);
+        let psi_sum = |params: &SimulationParams, probe: Vec3| {
+            let mut sum = 0.0;
+            boundary.for_each_neighbor(params, probe, |psi, _, r| sum += psi * kernel_w(params.kernel(), r, params.smoothing_radius));
+            sum
+        };
+        let probe = Vec3::new(-0.28, 0.03, 0.1);
+        let rest = psi_sum(&params, probe);
+
+        // Moved and turned: the same samples, carried along rigidly.
+        let mut moved = params;
+        let shift = Vec3::new(0.5, -0.2, 0.1);
+        let rotation = glam::Quat::from_rotation_z(0.4);
+        moved.box_min = (Vec3::from_slice(&params.box_min[..3]) + shift).extend(0.0).to_array();
+        moved.box_max = (Vec3::from_slice(&params.box_max[..3]) + shift).extend(0.0).to_array();
+        moved.box_rotation = rotation.to_array();
+        assert!(boundary.matches(&moved));
+        let centre = box_centre(&moved);
+        let moved_probe = centre + rotation * (probe + shift - centre);
+        assert!((psi_sum(&moved, moved_probe) - rest).abs() < 1e-3 * rest, "{} vs {rest}", psi_sum(&moved, moved_probe));
+
+        // The wave wall pulled back by a tenth of the box; the probe stays
+        // next to it.
+        let mut waved = moved;
+        waved.box_min[0] -= 0.06;
+        assert!(boundary.matches(&waved));
+        let waved_probe = from_box_frame(&waved, Vec3::new(waved.box_min[0] + 0.02, 0.03 + shift.y, 0.1 + shift.z));
+        let (found, expected) = neighbor_counts(&boundary, &waved, waved_probe);
+        assert_eq!(found, expected);
+        assert!(found > 0);
+
+        let mut stretched = params;
+        stretched.box_min[0] -= 0.3;
+        assert!(!boundary.matches(&stretched), "a box stretched by half needs new walls");
+        let mut other_kernel = params;
+        other_kernel.set_kernel(crate::entities::particle::SphKernel::WendlandC2);
+        assert!(!boundary.matches(&other_kernel), "ψ depends on the kernel");
+    }
+
+    #[test]
+    fn mesh_samples_cover_the_surface_without_duplicates() {
+        let vertices = [Vec3::ZERO, Vec3::new(0.3, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.3), Vec3::new(0.3, 0.0, 0.3)];
+        let spacing = 0.02;
+        let samples = sample_mesh(&vertices, &[[0, 2, 1], [1, 2, 3]], spacing);
+
+        // Both triangles split 0.3 * √2 into 22 steps and share their diagonal.
+        assert_eq!(samples.len(), 2 * 23 * 24 / 2 - 23);
+        for k in 0..100 {
+            let q = Vec3::new(0.3 * (k % 10) as f32 / 9.0, 0.0, 0.3 * (k / 10) as f32 / 9.0);
+            let nearest = samples.iter().map(|p| p.distance(q)).fold(f32::MAX, f32::min);
+            assert!(nearest <= spacing, "{q} is {nearest} from the nearest sample");
+        }
+    }
+
+    #[test]
+    fn flat_wall_psi_is_uniform_and_grows_where_sampling_is_sparse() {
+        let params = params();
+        let boundary = BoundaryParticles::for_box(&params);
+        let psi_at = |q: Vec3| {
+            boundary
+                .particles
+                .iter()
+                .min_by(|a, b| {
+                    let da = q.distance(Vec3::new(a[0], a[1], a[2]));
+                    let db = q.distance(Vec3::new(b[0], b[1], b[2]));
+                    da.total_cmp(&db)
+                })
+                .unwrap()[3]
+        };
+
+        let floor = psi_at(Vec3::new(0.0, 0.0, 0.0));
+        let floor_off_centre = psi_at(Vec3::new(0.12, 0.0, -0.08));
+        let corner = psi_at(Vec3::new(-0.32, -0.02, -0.22));
+        assert!((floor - floor_off_centre).abs() < 1e-3 * floor, "{floor} vs {floor_off_centre}");
+        assert!(corner > floor, "corner ψ {corner} should exceed the flat-wall ψ {floor}");
+    }
+}
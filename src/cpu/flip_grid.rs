@@ -0,0 +1,106 @@
+use glam::{IVec3, Vec3};
+use crate::entities::particle::SimulationParams;
+
+// CPU ports of the helpers in shaders/include/flip.glsl, for the FLIP steps in
+// steps.rs. Same layout: node (i, j, k) holds the u, v and w faces at the min
+// corner of cell (i, j, k), with dims + 1 nodes per axis.
+
+pub const CELL_AIR: u32 = 0;
+pub const CELL_FLUID: u32 = 1;
+
+/// The MAC grid over the box, in the box frame, as the `flip` push constants
+/// and `cell_size` describe it to the shaders.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MacGrid {
+    pub dims: IVec3,
+    pub cell: Vec3,
+    pub origin: Vec3,
+}
+
+impl MacGrid {
+    pub fn new(params: &SimulationParams, dims: IVec3) -> Self {
+        Self {
+            dims,
+            cell: params.flip_cell_size(dims),
+            origin: Vec3::from_slice(&params.box_min[..3]),
+        }
+    }
+
+    pub fn cell_count(&self) -> usize {
+        (self.dims.x * self.dims.y * self.dims.z) as usize
+    }
+    pub fn node_count(&self) -> usize {
+        let n = self.dims + IVec3::ONE;
+        (n.x * n.y * n.z) as usize
+    }
+
+    pub fn in_cells(&self, c: IVec3) -> bool {
+        c.cmpge(IVec3::ZERO).all() && c.cmplt(self.dims).all()
+    }
+    pub fn in_nodes(&self, n: IVec3) -> bool {
+        n.cmpge(IVec3::ZERO).all() && n.cmple(self.dims).all()
+    }
+
+    pub fn cell_index(&self, c: IVec3) -> usize {
+        ((c.z * self.dims.y + c.y) * self.dims.x + c.x) as usize
+    }
+    pub fn node_index(&self, n: IVec3) -> usize {
+        let d = self.dims + IVec3::ONE;
+        ((n.z * d.y + n.y) * d.x + n.x) as usize
+    }
+    pub fn cell_coords(&self, k: usize) -> IVec3 {
+        let d = self.dims;
+        let k = k as i32;
+        IVec3::new(k % d.x, (k / d.x) % d.y, k / (d.x * d.y))
+    }
+    pub fn node_coords(&self, k: usize) -> IVec3 {
+        let d = self.dims + IVec3::ONE;
+        let k = k as i32;
+        IVec3::new(k % d.x, (k / d.x) % d.y, k / (d.x * d.y))
+    }
+
+    /// Whether node `n` holds a face of `axis` that lies on the box or in it.
+    pub fn is_face(&self, n: IVec3, axis: usize) -> bool {
+        (0..3).all(|b| b == axis || n[b] < self.dims[b])
+    }
+
+    /// Cell of a box-frame position, clamped onto the grid.
+    pub fn particle_cell(&self, local: Vec3) -> IVec3 {
+        ((local - self.origin) / self.cell).floor().as_ivec3().clamp(IVec3::ZERO, self.dims - IVec3::ONE)
+    }
+
+    /// Trilinear stencil of the `axis` faces around a box-frame position: the
+    /// lower corner node and the position within the stencil, in cell units.
+    pub fn face_stencil(&self, local: Vec3, axis: usize) -> (IVec3, Vec3) {
+        let mut t = (local - self.origin) / self.cell - 0.5;
+        t[axis] += 0.5;
+        let base = t.floor();
+        (base.as_ivec3(), t - base)
+    }
+}
+
+pub fn unit_axis(axis: usize) -> IVec3 {
+    let mut e = IVec3::ZERO;
+    e[axis] = 1;
+    e
+}
+
+pub fn stencil_corner(corner: i32) -> IVec3 {
+    IVec3::new(corner & 1, (corner >> 1) & 1, corner >> 2)
+}
+
+fn corner_weights(frac: Vec3, o: IVec3) -> Vec3 {
+    Vec3::select(o.cmpeq(IVec3::ONE), frac, Vec3::ONE - frac)
+}
+
+pub fn stencil_weight(frac: Vec3, o: IVec3) -> f32 {
+    let w = corner_weights(frac, o);
+    w.x * w.y * w.z
+}
+
+/// Gradient of `stencil_weight` with respect to the particle position.
+pub fn stencil_weight_gradient(frac: Vec3, o: IVec3, cell: Vec3) -> Vec3 {
+    let w = corner_weights(frac, o);
+    let dw = Vec3::select(o.cmpeq(IVec3::ONE), Vec3::ONE, -Vec3::ONE) / cell;
+    Vec3::new(dw.x * w.y * w.z, w.x * dw.y * w.z, w.x * w.y * dw.z)
+}
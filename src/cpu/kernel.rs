@@ -0,0 +1,220 @@
+use std::f32::consts::PI;
+use glam::{IVec3, Vec3};
+use crate::entities::particle::SphKernel;
+
+// CPU ports of the helpers in shaders/include/common.glsl. Keep them bit-for-bit
+// equivalent (same branches, same epsilons) so CPU and GPU results are comparable.
+
+pub fn cell_coords(pos: Vec3, h: f32) -> IVec3 {
+    (pos / h).floor().as_ivec3()
+}
+
+pub fn cell_hash(cell: IVec3, table_size: u32) -> u32 {
+    let p1: u32 = 73856093;
+    let p2: u32 = 19349663;
+    let p3: u32 = 83492791;
+    ((cell.x as u32).wrapping_mul(p1) ^ (cell.y as u32).wrapping_mul(p2) ^ (cell.z as u32).wrapping_mul(p3)) % table_size
+}
+
+// --- CUBIC SPLINE ---
+fn cubic_spline_w(q: f32, h: f32) -> f32 {
+    let h3 = h * h * h;
+    let k = 8.0 / (PI * h3);
+
+    if q <= 0.5 {
+        let q2 = q * q;
+        let q3 = q2 * q;
+        k * (6.0 * q3 - 6.0 * q2 + 1.0)
+    } else {
+        let one_minus_q = 1.0 - q;
+        k * 2.0 * one_minus_q * one_minus_q * one_minus_q
+    }
+}
+
+fn cubic_spline_dw(q: f32, h: f32) -> f32 {
+    let h3 = h * h * h;
+    let k = 8.0 / (PI * h3);
+
+    if q <= 0.5 {
+        k * (18.0 * q * q - 12.0 * q)
+    } else {
+        let one_minus_q = 1.0 - q;
+        k * -6.0 * one_minus_q * one_minus_q
+    }
+}
+
+// --- WENDLAND C2 / C4 (Dehnen & Aly 2012) ---
+fn wendland_c2_w(q: f32, h: f32) -> f32 {
+    let k = 21.0 / (2.0 * PI * h * h * h);
+    let one_minus_q = 1.0 - q;
+    let one_minus_q_2 = one_minus_q * one_minus_q;
+    k * one_minus_q_2 * one_minus_q_2 * (1.0 + 4.0 * q)
+}
+
+fn wendland_c2_dw(q: f32, h: f32) -> f32 {
+    let k = 21.0 / (2.0 * PI * h * h * h);
+    let one_minus_q = 1.0 - q;
+    k * -20.0 * q * one_minus_q * one_minus_q * one_minus_q
+}
+
+fn wendland_c4_w(q: f32, h: f32) -> f32 {
+    let k = 495.0 / (32.0 * PI * h * h * h);
+    let one_minus_q = 1.0 - q;
+    let one_minus_q_2 = one_minus_q * one_minus_q;
+    k * one_minus_q_2 * one_minus_q_2 * one_minus_q_2 * (1.0 + 6.0 * q + 35.0 / 3.0 * q * q)
+}
+
+fn wendland_c4_dw(q: f32, h: f32) -> f32 {
+    let k = 495.0 / (32.0 * PI * h * h * h);
+    let one_minus_q = 1.0 - q;
+    let one_minus_q_2 = one_minus_q * one_minus_q;
+    k * -56.0 / 3.0 * q * one_minus_q_2 * one_minus_q_2 * one_minus_q * (1.0 + 5.0 * q)
+}
+
+// --- POLY6 / SPIKY (Müller et al. 2003) ---
+fn poly6_w(q: f32, h: f32) -> f32 {
+    let k = 315.0 / (64.0 * PI * h * h * h);
+    let one_minus_q2 = 1.0 - q * q;
+    k * one_minus_q2 * one_minus_q2 * one_minus_q2
+}
+
+fn spiky_dw(q: f32, h: f32) -> f32 {
+    let k = 15.0 / (PI * h * h * h);
+    let one_minus_q = 1.0 - q;
+    k * -3.0 * one_minus_q * one_minus_q
+}
+
+pub fn kernel_w(kernel: SphKernel, r: f32, h: f32) -> f32 {
+    let q = r / h;
+    if q >= 1.0 {
+        return 0.0;
+    }
+
+    match kernel {
+        SphKernel::CubicSpline => cubic_spline_w(q, h),
+        SphKernel::WendlandC2 => wendland_c2_w(q, h),
+        SphKernel::WendlandC4 => wendland_c4_w(q, h),
+        SphKernel::Poly6Spiky => poly6_w(q, h),
+    }
+}
+
+pub fn kernel_grad(kernel: SphKernel, r_vec: Vec3, r: f32, h: f32) -> Vec3 {
+    let q = r / h;
+    if q >= 1.0 || r < 1e-6 {
+        return Vec3::ZERO;
+    }
+
+    let grad_factor = match kernel {
+        SphKernel::CubicSpline => cubic_spline_dw(q, h),
+        SphKernel::WendlandC2 => wendland_c2_dw(q, h),
+        SphKernel::WendlandC4 => wendland_c4_dw(q, h),
+        SphKernel::Poly6Spiky => spiky_dw(q, h),
+    };
+
+    (grad_factor / (h * r)) * r_vec
+}
+
+// --- AKINCI 2013 COHESION / ADHESION SPLINES ---
+pub fn cohesion_kernel(r: f32, h: f32) -> f32 {
+    if r > h || r <= 0.0 {
+        return 0.0;
+    }
+
+    let k = 32.0 / (PI * h.powf(9.0));
+    let spline = (h - r) * (h - r) * (h - r) * r * r * r;
+
+    if 2.0 * r > h {
+        k * spline
+    } else {
+        let h3 = h * h * h;
+        k * (2.0 * spline - h3 * h3 / 64.0)
+    }
+}
+
+pub fn adhesion_kernel(r: f32, h: f32) -> f32 {
+    if r > h || 2.0 * r <= h {
+        return 0.0;
+    }
+
+    let k = 0.007 / h.powf(3.25);
+    k * (-4.0 * r * r / h + 6.0 * r - 2.0 * h).max(0.0).powf(0.25)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_kernel_integrates_to_one() {
+        let h = 0.08;
+        let n = 64;
+        let dx = 2.0 * h / n as f32;
+        for kernel in SphKernel::ALL {
+            let mut sum = 0.0f64;
+            for z in 0..n {
+                for y in 0..n {
+                    for x in 0..n {
+                        let p = Vec3::new(x as f32 + 0.5, y as f32 + 0.5, z as f32 + 0.5) * dx - Vec3::splat(h);
+                        sum += kernel_w(kernel, p.length(), h) as f64;
+                    }
+                }
+            }
+            let integral = sum * (dx as f64).powi(3);
+            assert!((integral - 1.0).abs() < 1e-2, "{kernel:?}: integral = {integral}");
+        }
+    }
+
+    #[test]
+    fn gradient_matches_finite_difference() {
+        let h = 0.08;
+        let eps = 1e-4;
+        // Spiky is the gradient of its own kernel, not of Poly6.
+        let spiky_w = |r: f32| if r < h { 15.0 / (PI * h.powi(6)) * (h - r).powi(3) } else { 0.0 };
+        for kernel in SphKernel::ALL {
+            for r_vec in [Vec3::new(0.01, 0.02, -0.005), Vec3::new(-0.03, 0.04, 0.02), Vec3::new(0.0, -0.06, 0.01)] {
+                let w = |r: f32| if kernel == SphKernel::Poly6Spiky { spiky_w(r) } else { kernel_w(kernel, r, h) };
+                let grad = kernel_grad(kernel, r_vec, r_vec.length(), h);
+                for axis in 0..3 {
+                    let mut offset = Vec3::ZERO;
+                    offset[axis] = eps;
+                    let fd = (w((r_vec + offset).length()) - w((r_vec - offset).length())) / (2.0 * eps);
+                    assert!((grad[axis] - fd).abs() < 1e-2 * grad.length().max(1.0), "{kernel:?} axis {axis}: {} vs {fd}", grad[axis]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn kernels_vanish_smoothly_at_the_support() {
+        let h = 0.08;
+        for kernel in SphKernel::ALL {
+            let r = 0.999 * h;
+            assert!(kernel_w(kernel, r, h) < 1e-3 * kernel_w(kernel, 0.0, h), "{kernel:?}");
+            assert!(kernel_grad(kernel, Vec3::X * r, r, h).length() < 1e-2 * kernel_w(kernel, 0.0, h) / h, "{kernel:?}");
+            assert_eq!(kernel_w(kernel, h, h), 0.0);
+            assert_eq!(SphKernel::from_index(kernel as u32), kernel);
+        }
+    }
+
+    #[test]
+    fn cohesion_is_repulsive_up_close_and_vanishes_at_support() {
+        let h = 0.08;
+        assert!(cohesion_kernel(0.1 * h, h) < 0.0);
+        assert!(cohesion_kernel(0.75 * h, h) > 0.0);
+        assert_eq!(cohesion_kernel(h, h), 0.0);
+        assert_eq!(adhesion_kernel(0.4 * h, h), 0.0);
+        assert!(adhesion_kernel(0.75 * h, h) > 0.0);
+
+        // Both pieces of the cohesion spline meet at h / 2.
+        let (below, above) = (cohesion_kernel(0.5 * h - 1e-5, h), cohesion_kernel(0.5 * h + 1e-5, h));
+        assert!((below - above).abs() < 1e-3 * above.abs(), "{below} vs {above}");
+    }
+
+    #[test]
+    fn hash_matches_glsl_wrapping() {
+        // uint(-1) * p1 ^ uint(0) * p2 ^ uint(2) * p3 evaluated with 32-bit wrap-around.
+        let expected = (u32::MAX.wrapping_mul(73856093) ^ 2u32.wrapping_mul(83492791)) % 1024;
+        assert_eq!(cell_hash(IVec3::new(-1, 0, 2), 1024), expected);
+        assert_eq!(cell_coords(Vec3::new(-0.01, 0.0, 0.17), 0.08), IVec3::new(-1, 0, 2));
+    }
+}
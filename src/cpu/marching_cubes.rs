@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use glam::Vec3;
+
+/// Entries per triangle-table row: up to five triangles, `-1`-terminated.
+pub const TRIANGLE_TABLE_ROW: usize = 16;
+
+/// Corner `c` of a cell sits at offset `(c & 1, (c >> 1) & 1, (c >> 2) & 1)`.
+#[cfg(test)]
+pub fn corner_offset(corner: usize) -> [usize; 3] {
+    [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1]
+}
+
+/// The twelve cell edges as corner pairs. Edge `4 * axis + k` runs along
+/// `axis`, starting at the `k`-th corner whose `axis` bit is clear.
+pub fn edges() -> [[usize; 2]; 12] {
+    std::array::from_fn(|e| {
+        let (axis, k) = (e / 4, e % 4);
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        let base = ((k & 1) << u) | ((k >> 1) << v);
+        [base, base | (1 << axis)]
+    })
+}
+
+/// Marching-cubes triangle table indexed by the inside-corner mask (bit `c`
+/// set when corner `c` is above the iso level), listing edge indices.
+///
+/// Generated rather than transcribed: on every cell face the crossings are
+/// paired so that inside corners are never joined across the face (the
+/// ambiguous case only depends on the face's own corners, so neighbouring
+/// cells agree and the surface is watertight). The segments are chained into
+/// loops and fanned into triangles wound counter-clockwise seen from the
+/// outside, i.e. the geometric normal points away from the fluid.
+pub fn triangle_table() -> [[i32; TRIANGLE_TABLE_ROW]; 256] {
+    let edges = edges();
+    let edge_between = |a: usize, b: usize| {
+        edges.iter().position(|e| (e[0] == a && e[1] == b) || (e[0] == b && e[1] == a)).unwrap()
+    };
+
+    let mut table = [[-1; TRIANGLE_TABLE_ROW]; 256];
+    for (mask, row) in table.iter_mut().enumerate() {
+        let inside = |c: usize| mask & (1 << c) != 0;
+
+        // next[e] = edge that follows e along the surface boundary.
+        let mut next = [usize::MAX; 12];
+        for axis in 0..3 {
+            for side in 0..2 {
+                let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+                let corner = |cu: usize, cv: usize| (side << axis) | (cu << u) | (cv << v);
+                // Counter-clockwise seen from outside the cell.
+                let mut ring = [corner(0, 0), corner(1, 0), corner(1, 1), corner(0, 1)];
+                if side == 0 {
+                    ring.reverse();
+                }
+
+                // Each entry edge (outside → inside) is joined to the next exit
+                // edge (inside → outside) along the ring.
+                for i in 0..4 {
+                    if inside(ring[i]) || !inside(ring[(i + 1) % 4]) {
+                        continue;
+                    }
+                    let exit = (1..4)
+                        .map(|k| (i + k) % 4)
+                        .find(|&j| inside(ring[j]) && !inside(ring[(j + 1) % 4]))
+                        .unwrap();
+                    let from = edge_between(ring[i], ring[(i + 1) % 4]);
+                    next[from] = edge_between(ring[exit], ring[(exit + 1) % 4]);
+                }
+            }
+        }
+
+        let mut visited = [false; 12];
+        let mut len = 0;
+        for start in 0..12 {
+            if next[start] == usize::MAX || visited[start] {
+                continue;
+            }
+            let mut ring = Vec::new();
+            let mut e = start;
+            while !visited[e] {
+                visited[e] = true;
+                ring.push(e as i32);
+                e = next[e];
+            }
+            for i in 1..ring.len() - 1 {
+                row[len..len + 3].copy_from_slice(&[ring[0], ring[i], ring[i + 1]]);
+                len += 3;
+            }
+        }
+    }
+    table
+}
+
+/// Splatted density at which the surface sits; `DENSITY_OFFSET` in the
+/// raymarcher and `ISO_LEVEL` in `marching_cubes.comp`.
+#[cfg(test)]
+pub const ISO_LEVEL: f32 = 300.0;
+
+/// Scalar grid sampled at voxel centres of a box, as produced by the density
+/// splat (`splat_density.comp`). Only the tests polygonize on the CPU; the
+/// renderer and the export use the mesh of `marching_cubes.comp`.
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct DensityGrid {
+    pub values: Vec<f32>,
+    pub res: [usize; 3],
+    pub box_min: Vec3,
+    pub box_max: Vec3,
+}
+
+/// Indexed triangle mesh; triangles are wound counter-clockwise seen from
+/// outside the fluid and `normals` point outwards.
+#[derive(Debug, Clone, Default)]
+pub struct SurfaceMesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub indices: Vec<u32>,
+}
+
+impl SurfaceMesh {
+    /// Indexes the triangle soup of `marching_cubes.comp`, given as the
+    /// `(position, normal)` of every corner. A vertex on a shared cell edge
+    /// comes out bit for bit the same from every cell that emits it, so equal
+    /// positions are merged.
+    pub fn weld(corners: impl IntoIterator<Item = (Vec3, Vec3)>) -> Self {
+        let mut mesh = SurfaceMesh::default();
+        let mut welded: HashMap<[u32; 3], u32> = HashMap::new();
+        for (position, normal) in corners {
+            let index = *welded.entry(position.to_array().map(f32::to_bits)).or_insert_with(|| {
+                mesh.positions.push(position);
+                mesh.normals.push(normal);
+                mesh.positions.len() as u32 - 1
+            });
+            mesh.indices.push(index);
+        }
+        mesh
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+}
+
+#[cfg(test)]
+impl DensityGrid {
+    pub fn cell_size(&self) -> Vec3 {
+        (self.box_max - self.box_min) / Vec3::new(self.res[0] as f32, self.res[1] as f32, self.res[2] as f32)
+    }
+
+    /// Value at voxel `p`; zero outside the grid so the surface closes at the
+    /// box walls.
+    pub fn sample(&self, p: [i64; 3]) -> f32 {
+        let [rx, ry, rz] = self.res.map(|r| r as i64);
+        if p[0] < 0 || p[1] < 0 || p[2] < 0 || p[0] >= rx || p[1] >= ry || p[2] >= rz {
+            return 0.0;
+        }
+        self.values[((p[2] * ry + p[1]) * rx + p[0]) as usize]
+    }
+
+    fn node_position(&self, p: [i64; 3]) -> Vec3 {
+        let p = Vec3::new(p[0] as f32, p[1] as f32, p[2] as f32);
+        self.box_min + (p + 0.5) * self.cell_size()
+    }
+
+    /// Central-difference gradient in grid units.
+    fn gradient(&self, [x, y, z]: [i64; 3]) -> Vec3 {
+        Vec3::new(
+            self.sample([x + 1, y, z]) - self.sample([x - 1, y, z]),
+            self.sample([x, y + 1, z]) - self.sample([x, y - 1, z]),
+            self.sample([x, y, z + 1]) - self.sample([x, y, z - 1]),
+        )
+    }
+
+    /// Extracts the `iso` level set, the CPU mirror of `marching_cubes.comp`.
+    /// Vertices on shared cell edges are welded, so a closed surface comes out
+    /// as a closed indexed mesh.
+    pub fn polygonize(&self, iso: f32) -> SurfaceMesh {
+        let table = triangle_table();
+        let edges = edges();
+        let mut mesh = SurfaceMesh::default();
+        let mut welded: HashMap<([i64; 3], usize), u32> = HashMap::new();
+
+        let [rx, ry, rz] = self.res.map(|r| r as i64);
+        // One layer of empty cells around the grid closes the surface at the walls.
+        for z in -1..rz {
+            for y in -1..ry {
+                for x in -1..rx {
+                    let node = |c: usize| {
+                        let [ox, oy, oz] = corner_offset(c);
+                        [x + ox as i64, y + oy as i64, z + oz as i64]
+                    };
+                    let mask = (0..8).filter(|&c| self.sample(node(c)) > iso).fold(0, |m, c| m | (1 << c));
+                    if mask == 0 || mask == 0xff {
+                        continue;
+                    }
+
+                    for &edge in table[mask].iter().take_while(|&&e| e >= 0) {
+                        let [a, b] = edges[edge as usize];
+                        let (pa, pb) = (node(a), node(b));
+                        let key = (pa, edge as usize / 4);
+                        let index = *welded.entry(key).or_insert_with(|| {
+                            let (va, vb) = (self.sample(pa), self.sample(pb));
+                            let t = ((iso - va) / (vb - va)).clamp(0.0, 1.0);
+                            let gradient = self.gradient(pa).lerp(self.gradient(pb), t);
+                            mesh.positions.push(self.node_position(pa).lerp(self.node_position(pb), t));
+                            mesh.normals.push((-gradient).try_normalize().unwrap_or(Vec3::Y));
+                            mesh.positions.len() as u32 - 1
+                        });
+                        mesh.indices.push(index);
+                    }
+                }
+            }
+        }
+        mesh
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sphere_grid(res: usize, radius: f32) -> DensityGrid {
+        let mut grid = DensityGrid {
+            values: Vec::with_capacity(res * res * res),
+            res: [res; 3],
+            box_min: Vec3::splat(-1.0),
+            box_max: Vec3::splat(1.0),
+        };
+        for z in 0..res {
+            for y in 0..res {
+                for x in 0..res {
+                    let p = grid.node_position([x as i64, y as i64, z as i64]);
+                    grid.values.push((radius - p.length()) * 1000.0);
+                }
+            }
+        }
+        grid
+    }
+
+    /// Every undirected edge is used exactly twice, once in each direction.
+    fn assert_closed(mesh: &SurfaceMesh) {
+        let mut directed = HashMap::new();
+        for tri in mesh.indices.chunks_exact(3) {
+            for i in 0..3 {
+                *directed.entry((tri[i], tri[(i + 1) % 3])).or_insert(0) += 1;
+            }
+        }
+        for (&(a, b), &count) in &directed {
+            assert_eq!(count, 1, "edge {a}->{b} used {count} times");
+            assert_eq!(directed.get(&(b, a)), Some(&1), "edge {a}->{b} has no twin");
+        }
+    }
+
+    fn signed_volume(mesh: &SurfaceMesh) -> f32 {
+        mesh.indices.chunks_exact(3).map(|t| {
+            let [a, b, c] = [0, 1, 2].map(|i| mesh.positions[t[i] as usize]);
+            a.dot(b.cross(c)) / 6.0
+        }).sum()
+    }
+
+    #[test]
+    fn table_fits_rows_and_is_complementary() {
+        let table = triangle_table();
+        assert!(table[0].iter().all(|&e| e < 0));
+        assert!(table[255].iter().all(|&e| e < 0));
+        for (mask, row) in table.iter().enumerate() {
+            let len = row.iter().take_while(|&&e| e >= 0).count();
+            assert_eq!(len % 3, 0, "mask {mask:#04x}");
+            assert!(len < TRIANGLE_TABLE_ROW, "mask {mask:#04x} needs {len} entries");
+
+            // Exactly the edges with a sign change are used.
+            let crossed: Vec<usize> = edges().iter().enumerate()
+                .filter(|(_, [a, b])| (mask >> a) & 1 != (mask >> b) & 1)
+                .map(|(e, _)| e)
+                .collect();
+            let mut used: Vec<usize> = row[..len].iter().map(|&e| e as usize).collect();
+            used.sort();
+            used.dedup();
+            assert_eq!(used, crossed, "mask {mask:#04x}");
+        }
+    }
+
+    #[test]
+    fn sphere_is_closed_and_outward_facing() {
+        let radius = 0.6;
+        let mesh = sphere_grid(24, radius).polygonize(0.0);
+        assert!(mesh.triangle_count() > 500);
+        assert_closed(&mesh);
+
+        let volume = signed_volume(&mesh);
+        let exact = 4.0 / 3.0 * std::f32::consts::PI * radius.powi(3);
+        assert!((volume - exact).abs() < 0.05 * exact, "volume {volume}, expected {exact}");
+
+        for (p, n) in mesh.positions.iter().zip(&mesh.normals) {
+            assert!((p.length() - radius).abs() < 0.05, "vertex {p} off the sphere");
+            assert!(n.dot(p.normalize()) > 0.9, "normal {n} at {p}");
+        }
+    }
+
+    #[test]
+    fn welding_the_soup_restores_the_indexed_mesh() {
+        let mesh = sphere_grid(16, 0.6).polygonize(0.0);
+        let soup = mesh.indices.iter().map(|&i| (mesh.positions[i as usize], mesh.normals[i as usize]));
+        let welded = SurfaceMesh::weld(soup);
+        assert_eq!(welded.positions, mesh.positions);
+        assert_eq!(welded.normals, mesh.normals);
+        assert_eq!(welded.indices, mesh.indices);
+        assert_closed(&welded);
+    }
+
+    #[test]
+    fn fluid_touching_the_walls_is_closed() {
+        // A full grid still yields a closed box hugging the walls.
+        let grid = DensityGrid {
+            values: vec![1000.0; 6 * 5 * 4],
+            res: [6, 5, 4],
+            box_min: Vec3::ZERO,
+            box_max: Vec3::new(0.6, 0.5, 0.4),
+        };
+        let mesh = grid.polygonize(300.0);
+        assert_closed(&mesh);
+        assert!(signed_volume(&mesh) > 0.0);
+    }
+}
@@ -0,0 +1,344 @@
+use std::f32::consts::PI;
+use glam::{IVec3, Vec3};
+use rayon::prelude::*;
+
+/// Signed distance to a triangle mesh, baked onto a regular grid of nodes and
+/// sampled trilinearly. Used by mesh obstacles on both the CPU and the GPU
+/// (`obstacles.glsl` reads the same `values`).
+///
+/// Closed meshes get their sign from the generalized winding number, so small
+/// holes and flipped triangles do not turn the inside out. With a positive
+/// `thickness` the mesh is treated as a shell of that half-width instead, which
+/// is what open geometry such as a funnel needs.
+#[derive(Debug)]
+pub struct MeshSdf {
+    /// First node and node spacing, in the mesh's local frame.
+    pub origin: Vec3,
+    pub cell_size: f32,
+    /// Node count along each axis.
+    pub dims: IVec3,
+    /// Distance at every node, x fastest.
+    pub values: Vec<f32>,
+    /// Unique triangle edges, for the wireframe.
+    pub edges: Vec<[Vec3; 2]>,
+}
+
+impl MeshSdf {
+    pub fn bake(vertices: &[Vec3], triangles: &[[u32; 3]], cell_size: f32, thickness: f32) -> Self {
+        assert!(!triangles.is_empty(), "mesh SDF needs at least one triangle");
+        assert!(cell_size > 0.0, "mesh SDF cell size must be positive");
+        let tris: Vec<[Vec3; 3]> = triangles.iter().map(|t| t.map(|i| vertices[i as usize])).collect();
+        let bvh = TriangleBvh::new(tris);
+
+        let (lo, hi) = (bvh.nodes[0].lo, bvh.nodes[0].hi);
+        let pad = thickness + 4.0 * cell_size;
+        let origin = lo - pad;
+        let dims = ((hi - lo + 2.0 * pad) / cell_size).ceil().as_ivec3() + IVec3::ONE;
+
+        let values = (0..dims.element_product() as usize)
+            .into_par_iter()
+            .map(|k| {
+                let k = k as i32;
+                let node = IVec3::new(k % dims.x, (k / dims.x) % dims.y, k / (dims.x * dims.y));
+                let p = origin + node.as_vec3() * cell_size;
+                let distance = bvh.distance(p);
+                if thickness > 0.0 {
+                    distance - thickness
+                } else if bvh.winding_number(p) > 0.5 {
+                    -distance
+                } else {
+                    distance
+                }
+            })
+            .collect();
+
+        let mut edge_ids: Vec<(u32, u32)> = triangles
+            .iter()
+            .flat_map(|t| [(t[0], t[1]), (t[1], t[2]), (t[2], t[0])])
+            .map(|(a, b)| (a.min(b), a.max(b)))
+            .collect();
+        edge_ids.sort_unstable();
+        edge_ids.dedup();
+        let edges = edge_ids.into_iter().map(|(a, b)| [vertices[a as usize], vertices[b as usize]]).collect();
+
+        Self { origin, cell_size, dims, values, edges }
+    }
+
+    /// Trilinear distance at `p`. Outside the grid, the distance to the grid's
+    /// bounds is added to the value at the nearest point on them.
+    pub fn distance(&self, p: Vec3) -> f32 {
+        let hi = self.origin + (self.dims - IVec3::ONE).as_vec3() * self.cell_size;
+        let c = p.clamp(self.origin, hi);
+        let g = (c - self.origin) / self.cell_size;
+        let i0 = g.floor().as_ivec3().clamp(IVec3::ZERO, self.dims - 2);
+        let t = g - i0.as_vec3();
+
+        let node = |x: i32, y: i32, z: i32| {
+            let n = i0 + IVec3::new(x, y, z);
+            self.values[((n.z * self.dims.y + n.y) * self.dims.x + n.x) as usize]
+        };
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let x00 = lerp(node(0, 0, 0), node(1, 0, 0), t.x);
+        let x10 = lerp(node(0, 1, 0), node(1, 1, 0), t.x);
+        let x01 = lerp(node(0, 0, 1), node(1, 0, 1), t.x);
+        let x11 = lerp(node(0, 1, 1), node(1, 1, 1), t.x);
+        let value = lerp(lerp(x00, x10, t.y), lerp(x01, x11, t.y), t.z);
+
+        value + p.distance(c)
+    }
+}
+
+/// Distance from `p` to the closest point on a triangle (Ericson, *Real-Time
+/// Collision Detection*, 5.1.5).
+fn point_triangle_distance(p: Vec3, [a, b, c]: &[Vec3; 3]) -> f32 {
+    let (ab, ac, ap) = (*b - *a, *c - *a, p - *a);
+    let (d1, d2) = (ab.dot(ap), ac.dot(ap));
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return p.distance(*a);
+    }
+    let bp = p - *b;
+    let (d3, d4) = (ab.dot(bp), ac.dot(bp));
+    if d3 >= 0.0 && d4 <= d3 {
+        return p.distance(*b);
+    }
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return p.distance(*a + ab * (d1 / (d1 - d3)));
+    }
+    let cp = p - *c;
+    let (d5, d6) = (ab.dot(cp), ac.dot(cp));
+    if d6 >= 0.0 && d5 <= d6 {
+        return p.distance(*c);
+    }
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return p.distance(*a + ac * (d2 / (d2 - d6)));
+    }
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+        return p.distance(*b + (*c - *b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6))));
+    }
+    let denom = 1.0 / (va + vb + vc);
+    p.distance(*a + ab * (vb * denom) + ac * (vc * denom))
+}
+
+/// Solid angle of a triangle seen from `p` (Van Oosterom & Strackee 1983);
+/// positive from behind an outward-facing triangle.
+fn solid_angle(p: Vec3, t: &[Vec3; 3]) -> f32 {
+    let [a, b, c] = t.map(|v| v - p);
+    let (la, lb, lc) = (a.length(), b.length(), c.length());
+    let det = a.dot(b.cross(c));
+    let denom = la * lb * lc + a.dot(b) * lc + b.dot(c) * la + c.dot(a) * lb;
+    2.0 * det.atan2(denom)
+}
+
+/// Triangles per BVH leaf.
+const LEAF_SIZE: usize = 4;
+/// Clusters farther than this many of their radii away are approximated by a
+/// single dipole in the winding number (Barill et al. 2018 use 2).
+const FAR_FIELD_RATIO: f32 = 2.0;
+
+/// Bounding volume hierarchy over the triangles, so a bake costs a few
+/// triangle tests per node instead of one per triangle. Closest-point queries
+/// prune by box distance; the winding number replaces far clusters by their
+/// area-weighted normal sum ("fast winding numbers", Barill et al. 2018).
+struct TriangleBvh {
+    tris: Vec<[Vec3; 3]>,
+    nodes: Vec<BvhNode>,
+}
+
+struct BvhNode {
+    lo: Vec3,
+    hi: Vec3,
+    /// Leaves own `tris[start..start + count]`; inner nodes have `count == 0`,
+    /// their first child right after them and the second at `right`.
+    start: usize,
+    count: usize,
+    right: usize,
+    /// Area-weighted centroid, Σ area · normal, and the distance from the
+    /// centroid to the farthest corner.
+    center: Vec3,
+    area_normal: Vec3,
+    radius: f32,
+}
+
+impl TriangleBvh {
+    fn new(mut tris: Vec<[Vec3; 3]>) -> Self {
+        let mut nodes = Vec::with_capacity(2 * tris.len() / LEAF_SIZE + 1);
+        let len = tris.len();
+        Self::build(&mut tris, 0, len, &mut nodes);
+        Self { tris, nodes }
+    }
+
+    fn build(tris: &mut [[Vec3; 3]], start: usize, end: usize, nodes: &mut Vec<BvhNode>) -> usize {
+        let slice = &mut tris[start..end];
+        let (lo, hi) = slice.iter().flatten().fold((Vec3::MAX, Vec3::MIN), |(lo, hi), &v| (lo.min(v), hi.max(v)));
+
+        let mut area_normal = Vec3::ZERO;
+        let mut weighted = Vec3::ZERO;
+        let mut area = 0.0;
+        for [a, b, c] in slice.iter() {
+            let n = 0.5 * (*b - *a).cross(*c - *a);
+            area_normal += n;
+            weighted += n.length() * (*a + *b + *c) / 3.0;
+            area += n.length();
+        }
+        let center = if area > 0.0 { weighted / area } else { (lo + hi) * 0.5 };
+        let radius = slice.iter().flatten().map(|v| v.distance(center)).fold(0.0, f32::max);
+
+        let index = nodes.len();
+        nodes.push(BvhNode { lo, hi, start, count: end - start, right: 0, center, area_normal, radius });
+        if end - start <= LEAF_SIZE {
+            return index;
+        }
+
+        let axis = (hi - lo).max_position();
+        let mid = (end - start) / 2;
+        let centroid = |t: &[Vec3; 3]| t[0][axis] + t[1][axis] + t[2][axis];
+        slice.select_nth_unstable_by(mid, |a, b| centroid(a).total_cmp(&centroid(b)));
+
+        nodes[index].count = 0;
+        Self::build(tris, start, start + mid, nodes);
+        nodes[index].right = Self::build(tris, start + mid, end, nodes);
+        index
+    }
+
+    /// Unsigned distance from `p` to the closest triangle.
+    fn distance(&self, p: Vec3) -> f32 {
+        let box_distance2 = |n: &BvhNode| (n.lo - p).max(p - n.hi).max(Vec3::ZERO).length_squared();
+        let mut best = f32::MAX;
+        let mut stack = vec![0];
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if box_distance2(node) >= best * best {
+                continue;
+            }
+            if node.count > 0 {
+                for t in &self.tris[node.start..node.start + node.count] {
+                    best = best.min(point_triangle_distance(p, t));
+                }
+                continue;
+            }
+            // Push the farther child first so the nearer one tightens `best`.
+            let (near, far) = (index + 1, node.right);
+            if box_distance2(&self.nodes[near]) <= box_distance2(&self.nodes[far]) {
+                stack.extend([far, near]);
+            } else {
+                stack.extend([near, far]);
+            }
+        }
+        best
+    }
+
+    /// Generalized winding number: 1 inside a closed, outward-facing mesh, 0
+    /// outside, and in between near holes.
+    fn winding_number(&self, p: Vec3) -> f32 {
+        let mut total = 0.0;
+        let mut stack = vec![0];
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let to_center = node.center - p;
+            let r = to_center.length();
+            if r > FAR_FIELD_RATIO * node.radius {
+                total += node.area_normal.dot(to_center) / (r * r * r);
+            } else if node.count > 0 {
+                total += self.tris[node.start..node.start + node.count].iter().map(|t| solid_angle(p, t)).sum::<f32>();
+            } else {
+                stack.extend([index + 1, node.right]);
+            }
+        }
+        total / (4.0 * PI)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Outward-facing unit cube centred on the origin.
+    fn cube() -> (Vec<Vec3>, Vec<[u32; 3]>) {
+        let vertices = (0..8)
+            .map(|i| Vec3::new((i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32) - 0.5)
+            .collect();
+        let triangles = vec![
+            [0, 2, 1], [1, 2, 3], [4, 5, 6], [5, 7, 6],
+            [0, 1, 4], [1, 5, 4], [2, 6, 3], [3, 6, 7],
+            [0, 4, 2], [2, 4, 6], [1, 3, 5], [3, 7, 5],
+        ];
+        (vertices, triangles)
+    }
+
+    #[test]
+    fn closed_cube_matches_the_analytic_box() {
+        let (vertices, triangles) = cube();
+        let cell = 0.05;
+        let sdf = MeshSdf::bake(&vertices, &triangles, cell, 0.0);
+        assert_eq!(sdf.edges.len(), 18);
+
+        let analytic = |p: Vec3| {
+            let d = p.abs() - 0.5;
+            d.max(Vec3::ZERO).length() + d.max_element().min(0.0)
+        };
+        for p in [
+            Vec3::ZERO,
+            Vec3::new(0.3, -0.1, 0.2),
+            Vec3::new(0.55, 0.0, 0.0),
+            Vec3::new(-0.6, 0.7, 0.1),
+            Vec3::new(3.0, 0.0, 0.0),
+        ] {
+            let (baked, exact) = (sdf.distance(p), analytic(p));
+            assert!((baked - exact).abs() < cell, "{p}: baked {baked}, exact {exact}");
+        }
+    }
+
+    #[test]
+    fn bvh_queries_match_brute_force() {
+        // UV sphere of radius 0.5, outward-facing.
+        let (rings, segments) = (12, 24);
+        let mut vertices = vec![Vec3::new(0.0, 0.5, 0.0), Vec3::new(0.0, -0.5, 0.0)];
+        for i in 1..rings {
+            let theta = PI * i as f32 / rings as f32;
+            for j in 0..segments {
+                let phi = 2.0 * PI * j as f32 / segments as f32;
+                vertices.push(0.5 * Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin()));
+            }
+        }
+        let ring = |i: u32, j: u32| 2 + (i - 1) * segments + j % segments;
+        let mut triangles = Vec::new();
+        for j in 0..segments {
+            triangles.push([0, ring(1, j + 1), ring(1, j)]);
+            triangles.push([1, ring(rings - 1, j), ring(rings - 1, j + 1)]);
+            for i in 1..rings - 1 {
+                triangles.push([ring(i, j), ring(i, j + 1), ring(i + 1, j)]);
+                triangles.push([ring(i, j + 1), ring(i + 1, j + 1), ring(i + 1, j)]);
+            }
+        }
+        let tris: Vec<[Vec3; 3]> = triangles.iter().map(|t: &[u32; 3]| t.map(|i| vertices[i as usize])).collect();
+        let bvh = TriangleBvh::new(tris.clone());
+
+        for k in 0..200 {
+            let f = k as f32;
+            let p = Vec3::new((f * 0.37).sin(), (f * 0.73).cos(), (f * 1.19).sin()) * 0.9;
+            let exact = tris.iter().map(|t| point_triangle_distance(p, t)).fold(f32::MAX, f32::min);
+            assert!((bvh.distance(p) - exact).abs() < 1e-6, "{p}");
+
+            let winding = bvh.winding_number(p);
+            let inside = p.length() < 0.45;
+            if inside || p.length() > 0.55 {
+                assert!((winding - inside as u8 as f32).abs() < 0.1, "{p}: winding {winding}");
+            }
+        }
+    }
+
+    #[test]
+    fn open_sheet_is_a_shell() {
+        let vertices = [Vec3::new(-1.0, 0.0, -1.0), Vec3::new(1.0, 0.0, -1.0), Vec3::new(-1.0, 0.0, 1.0), Vec3::new(1.0, 0.0, 1.0)];
+        let sdf = MeshSdf::bake(&vertices, &[[0, 2, 1], [1, 2, 3]], 0.05, 0.1);
+
+        assert!(sdf.distance(Vec3::ZERO) < -0.09);
+        assert!((sdf.distance(Vec3::new(0.2, 0.3, 0.0)) - 0.2).abs() < 0.01);
+        assert!((sdf.distance(Vec3::new(0.0, -0.3, 0.4)) - 0.2).abs() < 0.01);
+    }
+}
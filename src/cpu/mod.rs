@@ -0,0 +1,1143 @@
+pub mod kernel;
+pub mod neighbor_grid;
+pub mod steps;
+pub mod marching_cubes;
+pub mod boundary;
+pub mod mesh_sdf;
+pub mod flip_grid;
+
+#[cfg(test)]
+mod scaling_benchmark;
+
+use glam::{IVec3, Vec3, Vec4};
+use crate::core::simulation::SimulationStats;
+use crate::entities::emitter::EmittedParticle;
+use crate::entities::obstacle::{Obstacle, ObstacleForce};
+use crate::entities::particle::{PressureSolve, PressureSolver, PressureSolverState, SimulationBackend, SimulationParams, ViscosityMode, ViscositySolverState};
+use boundary::BoundaryParticles;
+use flip_grid::{MacGrid, CELL_AIR};
+use neighbor_grid::NeighborGrid;
+
+/// Reference SPH solver on the CPU (rayon), running the same substep as
+/// `Simulation` with the same `SimulationParams`, pressure solver, kernel and
+/// hash grid.
+///
+/// Meant as ground truth for validating the compute shaders, as a fallback on
+/// machines without a usable Vulkan device and as the CPU side of the
+/// CPU-vs-GPU scaling comparison. Particles are never reordered, so indices are
+/// stable across steps — compare against the GPU by position, not by index.
+///
+/// Emission and sinks are applied when the grid is rebuilt at the start of a
+/// step, like on the GPU. Particles that survive keep their relative order.
+pub struct CpuSimulation {
+    params: SimulationParams,
+    grid: NeighborGrid,
+    boundary: BoundaryParticles,
+    mesh_boundary: Vec<Vec3>,
+    obstacles: Vec<Obstacle>,
+    sinks: Vec<Obstacle>,
+    capacity: usize,
+    pending_emission: Vec<EmittedParticle>,
+
+    positions: Vec<Vec3>,
+    phases: Vec<u32>,
+    velocities: Vec<Vec3>,
+    scratch_velocities: Vec<Vec3>,
+    densities: Vec<f32>,
+    factors: Vec<f32>,
+    source_terms: Vec<f32>,
+    pressures: Vec<f32>,
+    pressure_accelerations: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    vorticities: Vec<Vec3>,
+    predicted_positions: Vec<Vec3>,
+    /// IISPH's d_ii | a_ii and Σ_j d_ij p_j | p_i, packed like on the GPU.
+    iisph_diagonals: Vec<Vec4>,
+    iisph_pressure_sums: Vec<Vec4>,
+    /// Box-frame velocity gradients of the APIC transfer.
+    affine: Vec<[Vec3; 3]>,
+
+    /// The FLIP backend's MAC grid, sized to `flip_grid_dims` every substep.
+    grid_transfers: Vec<i32>,
+    grid_cells: Vec<u32>,
+    grid_velocities: Vec<Vec3>,
+    old_grid_velocities: Vec<Vec3>,
+    grid_divergence: Vec<f32>,
+    grid_pressures: Vec<f32>,
+
+    /// Scalars of the last implicit viscosity solve.
+    viscosity_solver: ViscositySolverState,
+    /// Progress of the last DFSPH density and divergence solves.
+    pressure_solver: PressureSolverState,
+    stats: SimulationStats,
+    /// Fixed-point obstacle impulses of the last step and the time they span.
+    obstacle_impulses: Vec<[IVec3; 2]>,
+    impulse_time: f32,
+    needs_init: bool,
+}
+
+impl CpuSimulation {
+    pub fn new(initial_positions: &[[f32; 3]], params: SimulationParams) -> Self {
+        Self::with_capacity(initial_positions, &[], params, initial_positions.len() as u32)
+    }
+
+    /// Mirrors `Simulation::with_capacity`: emitted particles beyond
+    /// `capacity` are dropped, and the hash table is sized for it. Particles
+    /// past the end of `initial_phases` are the base fluid.
+    pub fn with_capacity(initial_positions: &[[f32; 3]], initial_phases: &[u32], params: SimulationParams, capacity: u32) -> Self {
+        let n = initial_positions.len();
+        let capacity = (capacity as usize).max(n).max(1);
+        Self {
+            params,
+            grid: NeighborGrid::new(capacity),
+            boundary: BoundaryParticles::for_box(&params),
+            mesh_boundary: Vec::new(),
+            obstacles: Vec::new(),
+            sinks: Vec::new(),
+            capacity,
+            pending_emission: Vec::new(),
+            positions: initial_positions.iter().map(|p| Vec3::from_array(*p)).collect(),
+            phases: (0..n).map(|i| initial_phases.get(i).copied().unwrap_or(0)).collect(),
+            velocities: vec![Vec3::ZERO; n],
+            scratch_velocities: vec![Vec3::ZERO; n],
+            densities: vec![0.0; n],
+            factors: vec![0.0; n],
+            source_terms: vec![0.0; n],
+            pressures: vec![0.0; n],
+            pressure_accelerations: vec![Vec3::ZERO; n],
+            normals: vec![Vec3::ZERO; n],
+            vorticities: vec![Vec3::ZERO; n],
+            predicted_positions: vec![Vec3::ZERO; n],
+            iisph_diagonals: vec![Vec4::ZERO; n],
+            iisph_pressure_sums: vec![Vec4::ZERO; n],
+            affine: vec![[Vec3::ZERO; 3]; n],
+            grid_transfers: Vec::new(),
+            grid_cells: Vec::new(),
+            grid_velocities: Vec::new(),
+            old_grid_velocities: Vec::new(),
+            grid_divergence: Vec::new(),
+            grid_pressures: Vec::new(),
+            viscosity_solver: ViscositySolverState::default(),
+            pressure_solver: PressureSolverState::default(),
+            stats: SimulationStats::default(),
+            obstacle_impulses: Vec::new(),
+            impulse_time: 0.0,
+            needs_init: true,
+        }
+    }
+
+    pub fn particle_count(&self) -> u32 {
+        self.positions.len() as u32
+    }
+    pub fn capacity(&self) -> u32 {
+        self.capacity as u32
+    }
+    pub fn params(&self) -> &SimulationParams {
+        &self.params
+    }
+    pub fn set_params(&mut self, params: SimulationParams) {
+        if params.smoothing_radius != self.params.smoothing_radius {
+            self.needs_init = true;
+        }
+        if !self.boundary.matches(&params) {
+            self.boundary = BoundaryParticles::for_box_and_meshes(&params, &self.mesh_boundary);
+            self.needs_init = true;
+        }
+        if params.backend() != self.params.backend() {
+            self.affine.fill([Vec3::ZERO; 3]);
+        }
+        self.params = params;
+    }
+    pub fn set_mesh_boundary(&mut self, samples: &[Vec3]) {
+        self.mesh_boundary = samples.to_vec();
+        self.boundary = BoundaryParticles::for_box_and_meshes(&self.params, &self.mesh_boundary);
+        self.needs_init = true;
+    }
+    pub fn set_obstacles(&mut self, obstacles: &[Obstacle]) {
+        self.obstacles = obstacles.to_vec();
+    }
+    pub fn set_sinks(&mut self, sinks: &[Obstacle]) {
+        self.sinks = sinks.to_vec();
+    }
+    /// Queues particles to be appended at the start of the next step.
+    pub fn emit(&mut self, particles: &[EmittedParticle]) {
+        if !particles.is_empty() {
+            self.pending_emission.extend_from_slice(particles);
+            self.needs_init = true;
+        }
+    }
+
+    /// Advances by `frame_dt` seconds exactly like `Simulation::step`: rebuild
+    /// the grid, run substeps of `params.dt` while they fit, then compute stats.
+    /// Returns the number of substeps taken.
+    pub fn step(&mut self, frame_dt: f32) -> u32 {
+        self.init();
+        self.clear_impulses();
+
+        let mut substeps = 0;
+        let mut step = 0.0;
+        while step < frame_dt {
+            self.substep();
+            step += self.params.dt;
+            substeps += 1;
+        }
+
+        self.impulse_time = substeps as f32 * self.params.dt;
+        self.update_stats();
+        substeps
+    }
+
+    /// Runs exactly `n_substeps` substeps; mirrors `Simulation::run_substeps`.
+    pub fn run_substeps(&mut self, n_substeps: u32) {
+        if self.needs_init || !self.sinks.is_empty() {
+            self.init();
+        }
+        self.clear_impulses();
+        for _ in 0..n_substeps {
+            self.substep();
+        }
+        self.impulse_time = n_substeps as f32 * self.params.dt;
+        self.update_stats();
+    }
+
+    pub fn read_positions(&self) -> Vec<[f32; 3]> {
+        self.positions.iter().map(|p| p.to_array()).collect()
+    }
+    pub fn read_phases(&self) -> Vec<u32> {
+        self.phases.clone()
+    }
+    pub fn read_velocities(&self) -> Vec<[f32; 3]> {
+        self.velocities.iter().map(|v| v.to_array()).collect()
+    }
+    pub fn read_densities(&self) -> Vec<f32> {
+        self.densities.clone()
+    }
+    pub fn read_pressures(&self) -> Vec<f32> {
+        self.pressures.clone()
+    }
+
+    /// Stats of the last `step` / `run_substeps`, quantised like `stats.comp`.
+    pub fn stats(&self) -> SimulationStats {
+        self.stats
+    }
+
+    /// Force and torque the fluid exerted on each obstacle, averaged over the
+    /// last `step` / `run_substeps`; mirrors `Simulation::read_obstacle_forces`.
+    pub fn obstacle_forces(&self) -> Vec<ObstacleForce> {
+        self.obstacle_impulses
+            .iter()
+            .map(|&impulses| ObstacleForce::from_impulses(impulses, self.impulse_time))
+            .collect()
+    }
+
+    fn init(&mut self) {
+        let _s = tracy_client::span!("cpu_init");
+        self.apply_emission();
+        self.apply_sinks();
+        self.grid.build(&self.positions, self.params.smoothing_radius);
+        steps::density_alpha(&self.grid, &self.boundary, &self.params, &self.positions, &self.phases, &mut self.densities, &mut self.factors);
+        self.needs_init = false;
+    }
+
+    fn substep(&mut self) {
+        let _substep = tracy_client::span!("cpu_substep");
+        let params = &self.params;
+
+        if params.max_surface_tension() > 0.0 {
+            let _s = tracy_client::span!("cpu_surface_normals");
+            steps::surface_normals(&self.grid, params, &self.positions, &self.phases, &self.densities, &mut self.normals);
+        }
+        {
+            let _s = tracy_client::span!("cpu_viscosity");
+            steps::viscosity(&self.grid, params, &self.positions, &self.phases, &self.velocities, &self.densities, &self.normals, &mut self.scratch_velocities);
+            std::mem::swap(&mut self.velocities, &mut self.scratch_velocities);
+        }
+        if params.viscosity_mode() == ViscosityMode::Implicit {
+            let _s = tracy_client::span!("cpu_viscosity_solver");
+            self.viscosity_solver = steps::viscosity_solve(&self.grid, params, &self.positions, &self.phases, &self.densities, &mut self.velocities);
+        }
+        if params.vorticity_confinement > 0.0 {
+            let _s = tracy_client::span!("cpu_vorticity_confinement");
+            steps::vorticity(&self.grid, params, &self.velocities, &self.positions, &self.phases, &self.densities, &mut self.vorticities);
+            steps::vorticity_confinement(&self.grid, params, &self.positions, &self.phases, &self.densities, &self.vorticities, &mut self.velocities);
+        }
+        if params.backend() == SimulationBackend::Flip {
+            let _s = tracy_client::span!("cpu_flip");
+            self.flip_projection();
+        } else {
+            let _s = tracy_client::span!("cpu_density_solver");
+            match params.pressure_solver() {
+                PressureSolver::Dfsph => {
+                    steps::density_source_term(&self.grid, &self.boundary, params, &self.positions, &self.phases, &self.densities, &self.velocities, &mut self.pressures, &mut self.source_terms);
+                    self.pressure_solver.begin(PressureSolve::Density);
+                    for _ in 0..params.density_solver_iterations {
+                        steps::pressure_force(&self.grid, &self.boundary, params, &self.positions, &self.phases, &self.pressures, &self.densities, &mut self.pressure_accelerations);
+                        let residual = steps::pressure_update(&self.grid, &self.boundary, params, &self.positions, &self.phases, &self.pressure_accelerations, &self.factors, &self.source_terms, &self.densities, &mut self.pressures);
+                        if self.pressure_solver.check(params, residual, self.positions.len() as u32) {
+                            break;
+                        }
+                    }
+                }
+                PressureSolver::Wcsph => {
+                    steps::wcsph_pressure(params, &self.phases, &self.densities, &mut self.pressures);
+                    steps::pressure_force(&self.grid, &self.boundary, params, &self.positions, &self.phases, &self.pressures, &self.densities, &mut self.pressure_accelerations);
+                }
+                PressureSolver::Pcisph => {
+                    self.pressures.fill(0.0);
+                    self.pressure_accelerations.fill(Vec3::ZERO);
+                    let delta = params.pcisph_delta();
+                    for _ in 0..params.density_solver_iterations {
+                        steps::predict_positions(params, &self.positions, &self.velocities, &self.pressure_accelerations, &mut self.predicted_positions);
+                        steps::pcisph_pressure(&self.grid, &self.boundary, params, delta, &self.predicted_positions, &self.phases, &mut self.densities, &mut self.pressures);
+                        steps::pressure_force(&self.grid, &self.boundary, params, &self.positions, &self.phases, &self.pressures, &self.densities, &mut self.pressure_accelerations);
+                    }
+                }
+                PressureSolver::Iisph => {
+                    steps::iisph_diagonal(&self.grid, &self.boundary, params, &self.positions, &self.phases, &self.densities, &self.velocities, &mut self.pressures, &mut self.source_terms, &mut self.iisph_diagonals);
+                    for _ in 0..params.density_solver_iterations {
+                        steps::iisph_pressure_sum(&self.grid, params, &self.positions, &self.phases, &self.densities, &self.pressures, &mut self.iisph_pressure_sums);
+                        steps::iisph_pressure(&self.grid, &self.boundary, params, &self.positions, &self.phases, &self.densities, &self.source_terms, &self.iisph_diagonals, &self.iisph_pressure_sums, &mut self.pressures);
+                    }
+                    steps::pressure_force(&self.grid, &self.boundary, params, &self.positions, &self.phases, &self.pressures, &self.densities, &mut self.pressure_accelerations);
+                }
+                PressureSolver::Pbf => {
+                    self.pressure_accelerations.fill(Vec3::ZERO);
+                    for _ in 0..params.density_solver_iterations {
+                        steps::predict_positions(params, &self.positions, &self.velocities, &self.pressure_accelerations, &mut self.predicted_positions);
+                        steps::pbf_lambda(&self.grid, &self.boundary, params, &self.predicted_positions, &self.phases, &mut self.pressures);
+                        steps::pbf_correction(&self.grid, &self.boundary, params, &self.predicted_positions, &self.phases, &self.pressures, &mut self.pressure_accelerations);
+                    }
+                }
+            }
+        }
+        let params = &self.params;
+        {
+            let _s = tracy_client::span!("cpu_pressure_integration");
+            let impulses = steps::pressure_integration(params, &self.obstacles, &self.phases, &self.pressure_accelerations, &mut self.positions, &mut self.velocities);
+            for (sum, impulse) in self.obstacle_impulses.iter_mut().zip(impulses) {
+                sum[0] += impulse[0];
+                sum[1] += impulse[1];
+            }
+        }
+        {
+            let _s = tracy_client::span!("cpu_neighbor_search_post_integrate");
+            self.grid.build(&self.positions, params.smoothing_radius);
+            steps::density_alpha(&self.grid, &self.boundary, params, &self.positions, &self.phases, &mut self.densities, &mut self.factors);
+        }
+        {
+            let _s = tracy_client::span!("cpu_divergence_solver");
+            steps::divergence_source_term(&self.grid, &self.boundary, params, &self.positions, &self.phases, &self.velocities, &mut self.source_terms);
+            if params.backend() == SimulationBackend::Sph && params.pressure_solver() == PressureSolver::Dfsph {
+                self.pressure_solver.begin(PressureSolve::Divergence);
+                for _ in 0..params.divergence_solver_iterations {
+                    steps::pressure_force(&self.grid, &self.boundary, params, &self.positions, &self.phases, &self.pressures, &self.densities, &mut self.pressure_accelerations);
+                    let residual = steps::pressure_update(&self.grid, &self.boundary, params, &self.positions, &self.phases, &self.pressure_accelerations, &self.factors, &self.source_terms, &self.densities, &mut self.pressures);
+                    if self.pressure_solver.check(params, residual, self.positions.len() as u32) {
+                        break;
+                    }
+                }
+            } else {
+                self.pressure_accelerations.fill(Vec3::ZERO);
+            }
+            steps::divergence_integration(params, &self.pressure_accelerations, &mut self.velocities);
+        }
+    }
+
+    /// `FlipProjection`: transfers onto the grid, projects it and transfers
+    /// back into `velocities`, leaving no pressure acceleration.
+    fn flip_projection(&mut self) {
+        let params = &self.params;
+        let grid = MacGrid::new(params, params.flip_grid_dims());
+        let (cells, nodes) = (grid.cell_count(), grid.node_count());
+        self.grid_transfers.clear();
+        self.grid_transfers.resize(8 * nodes, 0);
+        self.grid_cells.clear();
+        self.grid_cells.resize(cells, CELL_AIR);
+        self.grid_velocities.resize(nodes, Vec3::ZERO);
+        self.old_grid_velocities.resize(nodes, Vec3::ZERO);
+        self.grid_divergence.resize(cells, 0.0);
+        self.grid_pressures.resize(2 * cells, 0.0);
+
+        steps::flip_p2g(params, &grid, &self.positions, &self.phases, &self.velocities, &self.affine, &mut self.grid_transfers, &mut self.grid_cells);
+        steps::flip_grid_normalize(params, &grid, &self.grid_transfers, &mut self.grid_velocities, &mut self.old_grid_velocities);
+        steps::flip_grid_divergence(&grid, &self.grid_cells, &self.grid_velocities, &mut self.grid_divergence, &mut self.grid_pressures);
+        for iteration in 0..params.grid_iterations {
+            steps::flip_grid_jacobi(&grid, &self.grid_cells, &self.grid_divergence, iteration, &mut self.grid_pressures);
+        }
+        steps::flip_grid_project(&grid, &self.grid_cells, params.grid_iterations, &mut self.grid_pressures, &mut self.grid_velocities);
+        steps::flip_g2p(params, &grid, &self.positions, &self.grid_velocities, &self.old_grid_velocities, &mut self.velocities, &mut self.affine);
+        self.pressure_accelerations.fill(Vec3::ZERO);
+    }
+
+    fn clear_impulses(&mut self) {
+        self.obstacle_impulses = vec![[IVec3::ZERO; 2]; self.obstacles.len()];
+    }
+
+    fn apply_emission(&mut self) {
+        let room = self.capacity - self.positions.len();
+        for particle in self.pending_emission.drain(..).take(room) {
+            self.positions.push(particle.position);
+            self.phases.push(particle.phase);
+            self.velocities.push(particle.velocity);
+            self.pressures.push(0.0);
+            self.affine.push([Vec3::ZERO; 3]);
+        }
+        self.resize_scratch();
+    }
+
+    fn apply_sinks(&mut self) {
+        if self.sinks.is_empty() {
+            return;
+        }
+        let keep: Vec<bool> = self.positions.iter().map(|p| self.sinks.iter().all(|s| s.distance(*p) >= 0.0)).collect();
+        if keep.contains(&false) {
+            retain_where(&mut self.positions, &keep);
+            retain_where(&mut self.phases, &keep);
+            retain_where(&mut self.velocities, &keep);
+            retain_where(&mut self.pressures, &keep);
+            retain_where(&mut self.affine, &keep);
+            self.resize_scratch();
+        }
+    }
+
+    /// Matches the per-particle buffers that are recomputed every substep to
+    /// the particle count.
+    fn resize_scratch(&mut self) {
+        let n = self.positions.len();
+        self.scratch_velocities.resize(n, Vec3::ZERO);
+        self.densities.resize(n, 0.0);
+        self.factors.resize(n, 0.0);
+        self.source_terms.resize(n, 0.0);
+        self.pressure_accelerations.resize(n, Vec3::ZERO);
+        self.normals.resize(n, Vec3::ZERO);
+        self.vorticities.resize(n, Vec3::ZERO);
+        self.predicted_positions.resize(n, Vec3::ZERO);
+        self.iisph_diagonals.resize(n, Vec4::ZERO);
+        self.iisph_pressure_sums.resize(n, Vec4::ZERO);
+    }
+
+    fn update_stats(&mut self) {
+        self.stats = steps::stats(&self.params, &self.phases, &self.velocities, &self.densities, &self.source_terms);
+        if self.params.viscosity_mode() == ViscosityMode::Implicit {
+            self.stats.viscosity_iterations = self.viscosity_solver.iterations;
+            self.stats.viscosity_residual = self.viscosity_solver.relative_residual();
+        }
+        (self.stats.density_iterations, self.stats.divergence_iterations) = self.pressure_solver.iterations(&self.params);
+    }
+}
+
+fn retain_where<T>(values: &mut Vec<T>, keep: &[bool]) {
+    let mut keep = keep.iter();
+    values.retain(|_| *keep.next().unwrap());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::{IVec3, Quat};
+    use crate::entities::diffuse::DiffuseParams;
+    use crate::entities::particle::{GridTransfer, ParticleGenerator, SphKernel};
+
+    fn block_params(radius: f32, mass: f32) -> SimulationParams {
+        SimulationParams::new(
+            radius, mass, 4.0 * radius, 1000.0, 0.15, 0.5, 0.005, 4, 4,
+            Vec3::new(0.0, -9.81, 0.0),
+            Vec3::new(-0.5, 0.0, -0.5),
+            Vec3::new(0.5, 1.0, 0.5),
+            IVec3::splat(128),
+        )
+    }
+
+    fn block() -> CpuSimulation {
+        let radius = 0.02;
+        let (positions, mass) = ParticleGenerator::generate_volume(
+            Vec3::new(-0.2, 0.05, -0.2), 0.4, 0.4, 0.4, radius, 1000.0, 2.0 * radius, 0.0,
+        );
+        CpuSimulation::new(&positions, block_params(radius, mass))
+    }
+
+    #[test]
+    fn grid_finds_same_neighbors_as_brute_force() {
+        let mut sim = block();
+        sim.run_substeps(0);
+        let h = sim.params.smoothing_radius;
+
+        for i in (0..sim.positions.len()).step_by(37) {
+            let mut found = Vec::new();
+            sim.grid.for_each_neighbor(&sim.positions, sim.positions[i], |j, _, _| found.push(j));
+            found.sort_unstable();
+            found.dedup();
+
+            let expected: Vec<usize> = (0..sim.positions.len())
+                .filter(|&j| sim.positions[i].distance_squared(sim.positions[j]) <= h * h)
+                .collect();
+            assert_eq!(found, expected, "particle {i}");
+        }
+    }
+
+    #[test]
+    fn particles_stay_inside_box() {
+        let mut sim = block();
+        sim.run_substeps(50);
+
+        let params = *sim.params();
+        for p in sim.read_positions() {
+            for axis in 0..3 {
+                assert!(p[axis] >= params.box_min[axis] && p[axis] <= params.box_max[axis], "{p:?} left the box");
+            }
+        }
+        assert!(sim.stats().max_speed.is_finite());
+    }
+
+    #[test]
+    fn particles_stay_inside_a_tilted_box() {
+        let mut sim = block();
+        let mut params = *sim.params();
+        params.box_rotation = Quat::from_rotation_z(0.3).to_array();
+        sim.set_params(params);
+        sim.run_substeps(50);
+
+        let min = Vec3::from_slice(&params.box_min[..3]);
+        let max = Vec3::from_slice(&params.box_max[..3]);
+        let centre = (min + max) * 0.5;
+        for p in sim.read_positions() {
+            let local = centre + params.box_rotation().inverse() * (Vec3::from_array(p) - centre);
+            assert!(local.cmpge(min).all() && local.cmple(max).all(), "{p:?} left the box");
+        }
+    }
+
+    #[test]
+    fn walls_carry_particles_along() {
+        let mut params = block_params(0.02, 0.064);
+        let rotation = Quat::from_rotation_z(30f32.to_radians());
+        params.box_rotation = rotation.to_array();
+        params.box_angular_velocity = [0.0, 0.0, 1.0, 0.0];
+        let centre = Vec3::new(0.0, 0.5, 0.0);
+
+        // At rest just below the tilted floor, which turns upwards there.
+        let mut positions = vec![centre + rotation * Vec3::new(0.2, -0.51, 0.0)];
+        let mut velocities = vec![Vec3::ZERO];
+        steps::pressure_integration(&params, &[], &[0], &[Vec3::ZERO], &mut positions, &mut velocities);
+
+        let local = rotation.inverse() * (positions[0] - centre);
+        assert!((local.y - (-0.5 + 0.021)).abs() < 1e-5, "{local}");
+        let normal = rotation * Vec3::Y;
+        let floor_velocity = Vec3::Z.cross(positions[0] - centre);
+        assert!((velocities[0].dot(normal) - floor_velocity.dot(normal)).abs() < 1e-5, "{}", velocities[0]);
+        assert!(velocities[0].dot(normal) > 0.1);
+    }
+
+    /// Mean distance of a weightless cube's particles from its centroid.
+    fn weightless_cube_spread(surface_tension: f32, substeps: u32) -> f32 {
+        let radius = 0.02;
+        let (positions, mass) = ParticleGenerator::generate_volume(
+            Vec3::splat(-0.16), 0.32, 0.32, 0.32, radius, 1000.0, 2.0 * radius, 0.0,
+        );
+        let mut params = block_params(radius, mass);
+        params.gravity = [0.0; 4];
+        params.box_min = [-1.0, -1.0, -1.0, 0.0];
+        params.box_max = [1.0, 1.0, 1.0, 0.0];
+        params.surface_tension = surface_tension;
+
+        let mut sim = CpuSimulation::new(&positions, params);
+        sim.run_substeps(substeps);
+        let centroid = sim.positions.iter().sum::<Vec3>() / sim.positions.len() as f32;
+        sim.positions.iter().map(|p| p.distance(centroid)).sum::<f32>() / sim.positions.len() as f32
+    }
+
+    #[test]
+    fn surface_tension_holds_the_fluid_together() {
+        let free = weightless_cube_spread(0.0, 40);
+        let held = weightless_cube_spread(1.0, 40);
+        assert!(held < 0.9 * free, "mean spread {held} with surface tension vs {free} without");
+    }
+
+    /// Lowest particle of a weightless slab resting just above the floor.
+    fn slab_bottom(adhesion: f32, substeps: u32) -> f32 {
+        let radius = 0.02;
+        let (positions, mass) = ParticleGenerator::generate_volume(
+            Vec3::new(-0.1, 0.045, -0.1), 0.2, 0.12, 0.2, radius, 1000.0, 2.0 * radius, 0.0,
+        );
+        let mut params = block_params(radius, mass);
+        params.gravity = [0.0; 4];
+        params.adhesion = adhesion;
+
+        let mut sim = CpuSimulation::new(&positions, params);
+        sim.run_substeps(substeps);
+        sim.positions.iter().map(|p| p.y).fold(f32::MAX, f32::min)
+    }
+
+    #[test]
+    fn adhesion_pulls_fluid_onto_walls() {
+        let free = slab_bottom(0.0, 10);
+        let clinging = slab_bottom(10.0, 10);
+        assert!(clinging < free, "bottom at {clinging} with adhesion vs {free} without");
+    }
+
+    #[test]
+    fn implicit_viscosity_solves_its_system_and_damps_noise() {
+        let mut sim = block();
+        sim.run_substeps(0);
+        let mut params = *sim.params();
+        params.set_viscosity_mode(ViscosityMode::Implicit);
+        params.viscosity = 5.0;
+        params.viscosity_iterations = 200;
+        params.viscosity_tolerance = 1e-4;
+
+        // Neighbors moving in opposite directions, far beyond what the
+        // explicit blend could take at this viscosity.
+        let initial: Vec<Vec3> = (0..sim.positions.len()).map(|i| Vec3::X * if i % 2 == 0 { 1.0 } else { -1.0 }).collect();
+        let mut velocities = initial.clone();
+        let state = steps::viscosity_solve(&sim.grid, &params, &sim.positions, &sim.phases, &sim.densities, &mut velocities);
+
+        assert_eq!(state.converged, 1, "{state:?}");
+        assert!(state.iterations > 0 && state.iterations < params.viscosity_iterations, "{state:?}");
+        assert!(state.relative_residual() <= params.viscosity_tolerance, "{state:?}");
+
+        // The recurrence's residual must be the true one of v - dt L(v) = v*.
+        for i in 0..velocities.len() {
+            let (acc, _) = steps::viscous_acceleration(&sim.grid, &params, &sim.positions, &sim.phases, &sim.densities, &velocities, i);
+            let error = velocities[i] - params.dt * acc - initial[i];
+            assert!(error.length() < 1e-2, "particle {i}: residual {error}");
+        }
+
+        let energy = |v: &[Vec3]| v.iter().map(|v| v.length_squared()).sum::<f32>();
+        assert!(energy(&velocities) < 0.1 * energy(&initial), "{} of {}", energy(&velocities), energy(&initial));
+    }
+
+    #[test]
+    fn implicit_viscosity_keeps_very_viscous_fluid_stable() {
+        let mut sim = block();
+        let mut params = *sim.params();
+        params.set_viscosity_mode(ViscosityMode::Implicit);
+        params.viscosity = 10.0;
+        sim.set_params(params);
+        sim.run_substeps(50);
+
+        let stats = sim.stats();
+        assert!(stats.max_speed.is_finite() && stats.max_speed < 2.0, "{stats:?}");
+        assert!(stats.viscosity_iterations > 0 && stats.viscosity_residual <= params.viscosity_tolerance, "{stats:?}");
+        for p in sim.read_positions() {
+            for axis in 0..3 {
+                assert!(p[axis] >= params.box_min[axis] && p[axis] <= params.box_max[axis], "{p:?} left the box");
+            }
+        }
+    }
+
+    #[test]
+    fn vorticity_of_rigid_rotation_is_twice_the_angular_velocity() {
+        let mut sim = block();
+        sim.run_substeps(0);
+        let omega = Vec3::new(0.0, 3.0, 0.0);
+        let velocities: Vec<Vec3> = sim.positions.iter().map(|p| omega.cross(*p)).collect();
+
+        let mut vorticities = vec![Vec3::ZERO; velocities.len()];
+        steps::vorticity(&sim.grid, &sim.params, &velocities, &sim.positions, &sim.phases, &sim.densities, &mut vorticities);
+
+        let center = Vec3::new(0.0, 0.25, 0.0);
+        let i = (0..sim.positions.len())
+            .min_by(|&a, &b| sim.positions[a].distance(center).total_cmp(&sim.positions[b].distance(center)))
+            .unwrap();
+        let error = (vorticities[i] - 2.0 * omega).length() / (2.0 * omega).length();
+        assert!(error < 0.1, "curl {:?}, expected {:?}", vorticities[i], 2.0 * omega);
+    }
+
+    #[test]
+    fn boundary_particles_fill_the_kernel_at_walls() {
+        // An 8³ lattice filling its box exactly: without the wall's share the
+        // outer layers would be missing up to half of their neighbors.
+        let radius = 0.02;
+        let spacing = 2.0 * radius;
+        let mut positions = Vec::new();
+        for x in 0..8 {
+            for y in 0..8 {
+                for z in 0..8 {
+                    positions.push(((Vec3::new(x as f32, y as f32, z as f32) + 0.5) * spacing).to_array());
+                }
+            }
+        }
+        let mut params = block_params(radius, 1000.0 * spacing.powi(3));
+        params.box_min = [0.0; 4];
+        params.box_max = [8.0 * spacing, 8.0 * spacing, 8.0 * spacing, 0.0];
+
+        let mut sim = CpuSimulation::new(&positions, params);
+        let at_wall = |p: Vec3| (0..3).any(|a| p[a] < spacing || p[a] > 7.0 * spacing);
+
+        // Switching the kernel has to resample the boundary volumes with it.
+        for kernel in SphKernel::ALL {
+            params.set_kernel(kernel);
+            sim.set_params(params);
+            sim.run_substeps(0);
+
+            let wall: Vec<f32> = (0..positions.len()).filter(|&i| at_wall(sim.positions[i])).map(|i| sim.densities[i]).collect();
+            let mean = wall.iter().sum::<f32>() / wall.len() as f32;
+            let min = wall.iter().copied().fold(f32::MAX, f32::min);
+            // Poly6's sharp peak over-weights the dense wall samples, so it
+            // over-fills rather than under-fills the kernel there.
+            let tolerance = if kernel == SphKernel::Poly6Spiky { 0.2 } else { 0.1 };
+            assert!(min > 0.97 * params.target_density, "{kernel:?}: lowest wall density {min}");
+            assert!((mean - params.target_density).abs() < tolerance * params.target_density, "{kernel:?}: mean wall density {mean}");
+        }
+    }
+
+    #[test]
+    fn fluid_does_not_stack_on_the_floor() {
+        let mut sim = block();
+        sim.run_substeps(200);
+
+        // The block spreads to about 1.6 layers over the floor; a single layer of
+        // the 1 m × 1 m floor holds 625 particles at rest spacing.
+        let spacing = 2.0 * sim.params.particle_radius;
+        let bottom = sim.positions.iter().filter(|p| p.y < 1.25 * spacing).count();
+        assert!(bottom < 750, "{bottom} particles packed into the bottom layer");
+    }
+
+    #[test]
+    fn particles_stay_out_of_obstacles() {
+        use std::f32::consts::PI;
+        use glam::Quat;
+        use crate::entities::obstacle::ObstacleShape;
+
+        let radius = 0.02;
+        let (positions, mass) = ParticleGenerator::generate_volume(
+            Vec3::new(-0.2, 0.35, -0.2), 0.4, 0.3, 0.4, radius, 1000.0, 2.0 * radius, 0.0,
+        );
+        let sphere = Obstacle::new(ObstacleShape::Sphere { radius: 0.12 }, Vec3::new(0.0, 0.2, 0.0), Quat::IDENTITY);
+        let mut paddle = Obstacle::new(
+            ObstacleShape::Box { half_extents: Vec3::new(0.25, 0.03, 0.1) },
+            Vec3::new(0.0, 0.3, 0.35),
+            Quat::IDENTITY,
+        );
+        paddle.angular_velocity = Vec3::new(0.0, 0.0, PI);
+        let mut obstacles = vec![sphere, paddle];
+
+        let mut sim = CpuSimulation::new(&positions, block_params(radius, mass));
+        for _ in 0..20 {
+            for obstacle in &mut obstacles {
+                obstacle.update(5.0 * sim.params.dt);
+            }
+            sim.set_obstacles(&obstacles);
+            sim.run_substeps(5);
+        }
+
+        for p in &sim.positions {
+            for obstacle in &obstacles {
+                let d = obstacle.distance(*p);
+                assert!(d > 0.5 * radius, "{p} is {d} from the {} surface", obstacle.shape.name());
+            }
+        }
+        let touching = sim.positions.iter().filter(|p| obstacles[0].distance(**p) < 2.0 * radius).count();
+        assert!(touching > 0, "no fluid reached the sphere");
+    }
+
+    #[test]
+    fn mesh_fluid_rests_on_a_mesh_boundary() {
+        use crate::cpu::boundary::sample_mesh;
+
+        let radius = 0.02;
+        let cube: Vec<Vec3> = (0..8)
+            .map(|i| Vec3::new((i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32) * 0.3 + Vec3::new(-0.15, 0.4, -0.15))
+            .collect();
+        let faces = [
+            [0, 2, 1], [1, 2, 3], [4, 5, 6], [5, 7, 6],
+            [0, 1, 4], [1, 5, 4], [2, 6, 3], [3, 6, 7],
+            [0, 4, 2], [2, 4, 6], [1, 3, 5], [3, 7, 5],
+        ];
+        let (positions, mass) = ParticleGenerator::generate_mesh_volume(&cube, &faces, radius, 1000.0, 2.0 * radius, 0.0);
+        let (block, block_mass) = ParticleGenerator::generate_volume(cube[0], 0.3, 0.3, 0.3, radius, 1000.0, 2.0 * radius, 0.0);
+        assert_eq!((positions.len(), mass), (block.len(), block_mass));
+
+        // A shelf across the whole box, held up only by its boundary particles.
+        let shelf = [Vec3::new(-0.5, 0.3, -0.5), Vec3::new(0.5, 0.3, -0.5), Vec3::new(-0.5, 0.3, 0.5), Vec3::new(0.5, 0.3, 0.5)];
+        let mut sim = CpuSimulation::new(&positions, block_params(radius, mass));
+        sim.set_mesh_boundary(&sample_mesh(&shelf, &[[0, 2, 1], [1, 2, 3]], radius));
+        sim.run_substeps(100);
+
+        let lowest = sim.positions.iter().map(|p| p.y).fold(f32::MAX, f32::min);
+        assert!(lowest > 0.3, "fluid leaked through the shelf down to y = {lowest}");
+    }
+
+    #[test]
+    fn step_is_deterministic() {
+        let mut a = block();
+        let mut b = block();
+        a.run_substeps(10);
+        b.run_substeps(10);
+        assert_eq!(a.read_positions(), b.read_positions());
+    }
+
+    #[test]
+    fn emitter_fills_up_to_capacity_and_drains_into_a_sink() {
+        use glam::Quat;
+        use crate::entities::emitter::{Emitter, EmitterShape};
+        use crate::entities::obstacle::ObstacleShape;
+
+        let radius = 0.02;
+        let spacing = 2.0 * radius;
+        let params = block_params(radius, 1000.0 * spacing.powi(3));
+        let mut faucet = Emitter::new(EmitterShape::Nozzle { radius: 0.06 }, Vec3::new(0.0, 0.8, 0.0), Vec3::NEG_Y, 2.0, spacing, None);
+
+        let mut sim = CpuSimulation::with_capacity(&[], &[], params, 400);
+        let frame_dt = 5.0 * params.dt;
+        for _ in 0..60 {
+            let emitted = faucet.update(frame_dt);
+            sim.emit(&emitted);
+            sim.step(frame_dt);
+        }
+        assert_eq!(sim.particle_count(), 400, "the emitter should have filled the capacity");
+        assert_eq!(sim.stats().particle_count, 400);
+
+        // A drain covering the floor swallows everything that reaches it.
+        sim.set_sinks(&[Obstacle::new(
+            ObstacleShape::Box { half_extents: Vec3::new(0.5, 0.1, 0.5) },
+            Vec3::ZERO,
+            Quat::IDENTITY,
+        )]);
+        for _ in 0..60 {
+            sim.step(frame_dt);
+        }
+        sim.run_substeps(0);
+        assert_eq!(sim.particle_count(), 0, "the fluid should have drained");
+    }
+
+    #[test]
+    fn light_bodies_float_and_heavy_ones_sink() {
+        use glam::Quat;
+        use crate::entities::rigid_body::RigidBody;
+
+        let radius = 0.02;
+        let (positions, mass) = ParticleGenerator::generate_volume(
+            Vec3::new(-0.2, 0.0, -0.2), 0.4, 0.32, 0.4, radius, 1000.0, 2.0 * radius, 0.0,
+        );
+        // A narrow tank, 0.32 m deep.
+        let params = SimulationParams::new(
+            radius, mass, 4.0 * radius, 1000.0, 0.15, 0.5, 0.005, 4, 4,
+            Vec3::new(0.0, -9.81, 0.0),
+            Vec3::new(-0.2, 0.0, -0.2),
+            Vec3::new(0.2, 0.6, 0.2),
+            IVec3::splat(128),
+        );
+
+        let mut bodies = [
+            RigidBody::sphere(0.06, 300.0, Vec3::new(-0.1, 0.12, 0.0), Quat::IDENTITY),
+            RigidBody::sphere(0.06, 3000.0, Vec3::new(0.1, 0.2, 0.0), Quat::IDENTITY),
+        ];
+        let mut sim = CpuSimulation::new(&positions, params);
+        let frame_dt = 5.0 * params.dt;
+        for _ in 0..80 {
+            sim.set_obstacles(&bodies.iter().map(RigidBody::obstacle).collect::<Vec<_>>());
+            sim.step(frame_dt);
+            for (body, force) in bodies.iter_mut().zip(sim.obstacle_forces()) {
+                body.fluid_force = force;
+                body.update(frame_dt, &params);
+            }
+        }
+
+        let [cork, stone] = &bodies;
+        assert!(cork.position.y > 0.25, "the light ball sits at y = {}", cork.position.y);
+        assert!(stone.position.y < 0.07, "the heavy ball sits at y = {}", stone.position.y);
+        assert!(sim.positions.iter().all(|p| bodies.iter().all(|b| b.obstacle().distance(*p) > 0.0)));
+    }
+
+    /// `block()` at rest with the given velocities, its outward surface
+    /// normals and the diffuse potentials over one 5 ms frame.
+    fn block_potentials(velocity: impl Fn(Vec3) -> Vec3) -> (CpuSimulation, Vec<Vec3>, Vec<[f32; 4]>) {
+        let mut sim = block();
+        sim.run_substeps(0);
+        let n = sim.positions.len();
+        let velocities: Vec<Vec3> = sim.positions.iter().map(|p| velocity(*p)).collect();
+
+        let mut normals = vec![Vec3::ZERO; n];
+        steps::surface_normals(&sim.grid, &sim.params, &sim.positions, &sim.phases, &sim.densities, &mut normals);
+        let mut potentials = vec![[0.0; 4]; n];
+        steps::diffuse_generate(
+            &sim.grid, &sim.params, &DiffuseParams::default(), 0.005, &sim.positions, &velocities, &normals, &mut potentials,
+        );
+        sim.velocities = velocities;
+        (sim, normals, potentials)
+    }
+
+    #[test]
+    fn diffuse_potentials_vanish_at_rest_and_fire_where_fluid_collides() {
+        let (_, _, potentials) = block_potentials(|_| Vec3::ZERO);
+        assert!(potentials.iter().all(|p| *p == [0.0; 4]), "potentials at rest");
+
+        // Two halves running into each other trap air along x = 0.
+        let (sim, _, potentials) = block_potentials(|p| Vec3::new(-3.0 * p.x.signum(), 0.0, 0.0));
+        let (near, far): (Vec<_>, Vec<_>) = (0..sim.positions.len()).partition(|&i| sim.positions[i].x.abs() < 0.05);
+        let trapped = |set: &[usize]| set.iter().map(|&i| potentials[i][0]).fold(0.0, f32::max);
+        assert!(trapped(&near) > DiffuseParams::default().trapped_air_min, "trapped air {} at the collision", trapped(&near));
+        assert!(trapped(&far) < trapped(&near) * 0.5, "trapped air {} away from it", trapped(&far));
+        assert!(near.iter().any(|&i| potentials[i][3] > 0.0), "no particles expected at the collision");
+    }
+
+    #[test]
+    fn wave_crests_are_the_convex_edges_of_rising_fluid() {
+        let (sim, normals, potentials) = block_potentials(|_| Vec3::new(0.0, 2.0, 0.0));
+        let top = sim.positions.iter().map(|p| p.y).fold(f32::MIN, f32::max);
+        let closest = |target: Vec3| {
+            (0..sim.positions.len())
+                .min_by(|&a, &b| sim.positions[a].distance(target).total_cmp(&sim.positions[b].distance(target)))
+                .unwrap()
+        };
+        let centre = closest(Vec3::new(0.0, top, 0.0));
+        let edge = closest(Vec3::new(0.2, top, 0.0));
+
+        // The Akinci normal points into the fluid.
+        assert!(-normals[centre].normalize().dot(Vec3::Y) > 0.9, "normal {}", normals[centre]);
+        // A rigid translation traps no air; the flat top is no crest, its rim is.
+        assert!(potentials.iter().all(|p| p[0] == 0.0));
+        assert!(potentials[edge][1] > 2.0 * potentials[centre][1], "edge {:?}, centre {:?}", potentials[edge], potentials[centre]);
+    }
+
+    #[test]
+    fn spray_falls_foam_drifts_and_bubbles_rise() {
+        use crate::entities::diffuse::{GpuDiffuseParticle, DIFFUSE_BUBBLE, DIFFUSE_FOAM, DIFFUSE_SPRAY};
+
+        let (sim, _, _) = block_potentials(|_| Vec3::new(1.0, 0.0, 0.0));
+        let top = sim.positions.iter().map(|p| p.y).fold(f32::MIN, f32::max);
+        let particle = |p: Vec3| GpuDiffuseParticle { position: [p.x, p.y, p.z, 1.0], velocity: [0.0; 4] };
+        let mut particles = [
+            particle(Vec3::new(0.0, 0.9, 0.0)),
+            particle(Vec3::new(0.0, top + 0.01, 0.0)),
+            particle(Vec3::new(0.0, 0.25, 0.0)),
+            particle(Vec3::new(0.0, 1.2, 0.0)),
+            GpuDiffuseParticle::default(),
+        ];
+        let params = DiffuseParams::default();
+        let dt = 0.01;
+        steps::diffuse_advect(&sim.grid, &sim.params, &params, dt, &sim.positions, &sim.velocities, &mut particles);
+
+        let [spray, foam, bubble, outside, free] = particles;
+        assert_eq!(spray.velocity[3], DIFFUSE_SPRAY);
+        assert!((spray.velocity[1] + 9.81 * dt).abs() < 1e-5 && spray.position[3] == 1.0, "{spray:?}");
+
+        assert_eq!(foam.velocity[3], DIFFUSE_FOAM);
+        assert!((Vec3::from_slice(&foam.velocity[..3]) - Vec3::X).length() < 1e-4, "{foam:?}");
+        assert!((foam.position[0] - dt).abs() < 1e-5 && foam.position[3] == 1.0 - dt, "{foam:?}");
+
+        assert_eq!(bubble.velocity[3], DIFFUSE_BUBBLE);
+        assert!(bubble.velocity[1] > 0.0 && bubble.velocity[0] > 0.0, "{bubble:?}");
+
+        assert_eq!(outside.position[3], 0.0, "particles leaving the box die");
+        assert_eq!(free, GpuDiffuseParticle::default(), "free slots stay untouched");
+    }
+
+    #[test]
+    fn light_phase_rises_through_heavy_one() {
+        use crate::entities::particle::{GpuPhase, DEFAULT_FLUID_COLOR};
+
+        let radius = 0.02;
+        let spacing = 2.0 * radius;
+        // Oil under a layer of water in a narrow tank.
+        let (oil, mass) = ParticleGenerator::generate_volume(
+            Vec3::new(-0.1, 0.0, -0.1), 0.2, 0.16, 0.2, radius, 1000.0, spacing, 0.0,
+        );
+        let (water, _) = ParticleGenerator::generate_volume(
+            Vec3::new(-0.1, 0.16, -0.1), 0.2, 0.16, 0.2, radius, 1000.0, spacing, 0.0,
+        );
+        let mut params = SimulationParams::new(
+            radius, mass, 4.0 * radius, 1000.0, 0.15, 0.5, 0.005, 4, 4,
+            Vec3::new(0.0, -9.81, 0.0),
+            Vec3::new(-0.1, 0.0, -0.1),
+            Vec3::new(0.1, 0.6, 0.1),
+            IVec3::splat(128),
+        );
+        params.set_phases(DEFAULT_FLUID_COLOR, &[GpuPhase::new([0.9, 0.7, 0.1], 600.0, 0.15, 0.0)]);
+        assert_eq!(params.phase_count(), 2);
+        assert_eq!(params.phase_mass(1), 0.6 * mass);
+
+        let positions = [oil.as_slice(), water.as_slice()].concat();
+        let phases: Vec<u32> = (0..positions.len()).map(|i| (i < oil.len()) as u32).collect();
+        let mut sim = CpuSimulation::with_capacity(&positions, &phases, params, positions.len() as u32);
+        let frame_dt = 5.0 * params.dt;
+        for _ in 0..120 {
+            sim.step(frame_dt);
+        }
+
+        let mean_height = |phase: u32| {
+            let heights: Vec<f32> = sim.positions.iter().zip(&sim.phases).filter(|(_, p)| **p == phase).map(|(x, _)| x.y).collect();
+            heights.iter().sum::<f32>() / heights.len() as f32
+        };
+        let (water_height, oil_height) = (mean_height(0), mean_height(1));
+        assert!(oil_height > water_height + 0.05, "oil at {oil_height}, water at {water_height}");
+        assert_eq!(sim.read_phases().iter().filter(|&&p| p == 1).count(), oil.len());
+    }
+
+    /// Stats density error of a weightless block squeezed to 95 % of its
+    /// rest spacing, before and after one substep of `solver`.
+    fn compressed_block_density_error(solver: PressureSolver, iterations: u32) -> (f32, f32) {
+        let radius = 0.02;
+        let (positions, mass) = ParticleGenerator::generate_volume(
+            Vec3::new(-0.2, 0.3, -0.2), 0.4, 0.4, 0.4, radius, 1000.0, 2.0 * radius, 0.0,
+        );
+        let centre = Vec3::new(0.0, 0.5, 0.0);
+        let positions: Vec<[f32; 3]> = positions.iter().map(|p| (centre + 0.95 * (Vec3::from_array(*p) - centre)).to_array()).collect();
+        let mut params = block_params(radius, mass);
+        params.gravity = [0.0; 4];
+        params.density_solver_iterations = iterations;
+        params.set_pressure_solver(solver);
+
+        let mut sim = CpuSimulation::new(&positions, params);
+        sim.run_substeps(0);
+        let before = sim.stats().avg_density_error;
+        sim.run_substeps(1);
+        (before, sim.stats().avg_density_error)
+    }
+
+    #[test]
+    fn every_pressure_solver_relieves_compression() {
+        for solver in PressureSolver::ALL {
+            let (before, after) = compressed_block_density_error(solver, 4);
+            assert!(after < 0.85 * before, "{solver:?}: density error {before} -> {after}");
+            if solver.is_iterative() {
+                let (_, unsolved) = compressed_block_density_error(solver, 0);
+                assert!(after < 0.7 * unsolved, "{solver:?}: density error {after} vs {unsolved} without iterations");
+            }
+        }
+    }
+
+    #[test]
+    fn every_pressure_solver_settles_a_block() {
+        for solver in PressureSolver::ALL {
+            let mut sim = block();
+            let mut params = *sim.params();
+            params.set_pressure_solver(solver);
+            sim.set_params(params);
+            sim.run_substeps(300);
+
+            let stats = sim.stats();
+            assert!(stats.max_speed.is_finite() && stats.max_speed < 4.0, "{solver:?}: {stats:?}");
+            assert!(stats.avg_density_error < 100.0, "{solver:?}: {stats:?}");
+            for p in sim.read_positions() {
+                for axis in 0..3 {
+                    assert!(p[axis] >= params.box_min[axis] && p[axis] <= params.box_max[axis], "{solver:?}: {p:?} left the box");
+                }
+            }
+        }
+    }
+
+    /// IISPH's iterations solve the same pressure equation `pressure_force`
+    /// feeds into the substep: with their pressures, the density predicted
+    /// from the corrected velocities is the rest density wherever a pressure
+    /// acts, and no higher anywhere else.
+    #[test]
+    fn iisph_drives_the_predicted_density_to_rest() {
+        let radius = 0.02;
+        let (positions, mass) = ParticleGenerator::generate_volume(
+            Vec3::new(-0.2, 0.3, -0.2), 0.4, 0.4, 0.4, radius, 1000.0, 2.0 * radius, 0.0,
+        );
+        let centre = Vec3::new(0.0, 0.5, 0.0);
+        let positions: Vec<[f32; 3]> = positions.iter().map(|p| (centre + 0.95 * (Vec3::from_array(*p) - centre)).to_array()).collect();
+        let mut sim = CpuSimulation::new(&positions, block_params(radius, mass));
+        sim.run_substeps(0);
+        // Still closing in, so ρ_adv has a velocity term as well.
+        sim.velocities = sim.positions.iter().map(|p| 0.3 * (centre - *p)).collect();
+
+        let s = &mut sim;
+        let params = s.params;
+        steps::iisph_diagonal(&s.grid, &s.boundary, &params, &s.positions, &s.phases, &s.densities, &s.velocities, &mut s.pressures, &mut s.source_terms, &mut s.iisph_diagonals);
+        for _ in 0..100 {
+            steps::iisph_pressure_sum(&s.grid, &params, &s.positions, &s.phases, &s.densities, &s.pressures, &mut s.iisph_pressure_sums);
+            steps::iisph_pressure(&s.grid, &s.boundary, &params, &s.positions, &s.phases, &s.densities, &s.source_terms, &s.iisph_diagonals, &s.iisph_pressure_sums, &mut s.pressures);
+        }
+        steps::pressure_force(&s.grid, &s.boundary, &params, &s.positions, &s.phases, &s.pressures, &s.densities, &mut s.pressure_accelerations);
+        let corrected: Vec<Vec3> = s.velocities.iter().zip(&s.pressure_accelerations).map(|(v, a)| *v + params.dt * *a).collect();
+        // -∇·v of the corrected velocities.
+        let mut source_terms = vec![0.0; corrected.len()];
+        steps::divergence_source_term(&s.grid, &s.boundary, &params, &s.positions, &s.phases, &corrected, &mut source_terms);
+
+        assert!(s.pressures.iter().any(|&p| p > 0.0), "no pressure");
+        for (i, (&rho, &p)) in s.densities.iter().zip(&s.pressures).enumerate() {
+            let predicted = rho - params.dt * source_terms[i];
+            if p > 0.0 {
+                assert!((predicted - params.target_density).abs() < 0.1, "particle {i}: ρ {predicted} at p {p}");
+            } else {
+                assert!(predicted < params.target_density + 0.1, "particle {i}: ρ {predicted} without pressure");
+            }
+        }
+    }
+
+    #[test]
+    fn dfsph_stops_iterating_once_the_error_is_below_eta() {
+        let mut sim = block();
+        let mut params = *sim.params();
+        params.density_solver_iterations = 50;
+        params.divergence_solver_iterations = 50;
+        sim.set_params(params);
+        sim.run_substeps(100);
+        let stats = sim.stats();
+        assert_eq!((stats.density_iterations, stats.divergence_iterations), (50, 50), "η = 0 runs every iteration");
+
+        // The paper's defaults: 0.1 % and 0.5 % of the rest density.
+        params.max_density_error = 0.001;
+        params.max_divergence_error = 0.005;
+        sim.set_params(params);
+        sim.run_substeps(1);
+        let stats = sim.stats();
+        let state = sim.pressure_solver;
+        assert!((2..50).contains(&stats.density_iterations), "{stats:?}");
+        assert!((1..50).contains(&stats.divergence_iterations), "{stats:?}");
+        assert!(state.density_error < PressureSolve::Density.max_error(&params), "{state:?}");
+        assert!(state.divergence_error < PressureSolve::Divergence.max_error(&params), "{state:?}");
+
+        // A loose η still runs the minimum.
+        params.max_density_error = 1.0;
+        params.max_divergence_error = 1.0;
+        sim.set_params(params);
+        sim.run_substeps(1);
+        let stats = sim.stats();
+        assert_eq!((stats.density_iterations, stats.divergence_iterations), (2, 1));
+    }
+
+    fn flip_block(transfer: GridTransfer) -> CpuSimulation {
+        let mut sim = block();
+        let mut params = *sim.params();
+        params.set_backend(SimulationBackend::Flip);
+        params.set_grid_transfer(transfer);
+        sim.set_params(params);
+        sim
+    }
+
+    #[test]
+    fn flip_projection_removes_grid_divergence() {
+        let mut sim = flip_block(GridTransfer::Flip);
+        let centre = Vec3::new(0.0, 0.25, 0.0);
+        sim.velocities = sim.positions.iter().map(|p| *p - centre).collect();
+        sim.run_substeps(0);
+        sim.flip_projection();
+
+        let grid = MacGrid::new(&sim.params, sim.params.flip_grid_dims());
+        let fluid_divergence = |divergence: &[f32]| divergence.iter().map(|d| d * d).sum::<f32>();
+        let before = fluid_divergence(&sim.grid_divergence);
+        let mut after = vec![0.0; grid.cell_count()];
+        let mut pressures = sim.grid_pressures.clone();
+        steps::flip_grid_divergence(&grid, &sim.grid_cells, &sim.grid_velocities, &mut after, &mut pressures);
+        let after = fluid_divergence(&after);
+
+        assert!(sim.grid_cells.contains(&flip_grid::CELL_FLUID));
+        assert!(before > 0.0 && after < 0.05 * before, "divergence² {before} -> {after}");
+    }
+
+    #[test]
+    fn every_grid_transfer_settles_a_block() {
+        for transfer in GridTransfer::ALL {
+            let mut sim = flip_block(transfer);
+            let params = *sim.params();
+            let start = sim.positions.iter().map(|p| p.y).sum::<f32>();
+            sim.run_substeps(300);
+
+            let stats = sim.stats();
+            assert!(stats.max_speed.is_finite() && stats.max_speed < 4.0, "{transfer:?}: {stats:?}");
+            assert!(sim.positions.iter().map(|p| p.y).sum::<f32>() < start, "{transfer:?}: the block did not fall");
+            for p in sim.read_positions() {
+                for axis in 0..3 {
+                    assert!(p[axis] >= params.box_min[axis] && p[axis] <= params.box_max[axis], "{transfer:?}: {p:?} left the box");
+                }
+            }
+            if transfer != GridTransfer::Apic {
+                assert!(sim.affine.iter().all(|a| *a == [Vec3::ZERO; 3]));
+            }
+        }
+    }
+}
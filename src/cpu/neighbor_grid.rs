@@ -0,0 +1,94 @@
+use glam::{IVec3, Vec3};
+use rayon::prelude::*;
+use crate::cpu::kernel::{cell_coords, cell_hash};
+use crate::entities::particle::Entry;
+
+const EMPTY_CELL: u32 = 0xFFFFFFFF;
+
+/// CPU counterpart of the `NeighborSearch` pass: the same hashed grid as
+/// spatial_hash.comp + sort + grid_offsets.comp, with the same table size
+/// (`count.next_power_of_two()`), so hash collisions and duplicate cells are
+/// visited exactly like on the GPU.
+///
+/// Unlike the GPU pass the particle arrays are not reordered — entries keep
+/// pointing at the original indices, so particle identity is stable on the CPU.
+pub struct NeighborGrid {
+    entries: Vec<Entry>,
+    grid_start: Vec<u32>,
+    table_size: u32,
+    h: f32,
+}
+
+impl NeighborGrid {
+    pub fn new(particle_count: usize) -> Self {
+        let table_size = (particle_count.max(1)).next_power_of_two() as u32;
+        Self {
+            entries: Vec::with_capacity(particle_count),
+            grid_start: vec![EMPTY_CELL; table_size as usize],
+            table_size,
+            h: 1.0,
+        }
+    }
+
+    pub fn build(&mut self, positions: &[Vec3], h: f32) {
+        let _span = tracy_client::span!("cpu_neighbor_search");
+        self.h = h;
+        let table_size = self.table_size;
+
+        self.entries.clear();
+        positions
+            .par_iter()
+            .enumerate()
+            .map(|(i, pos)| Entry {
+                hash: cell_hash(cell_coords(*pos, h), table_size),
+                index: i as u32,
+            })
+            .collect_into_vec(&mut self.entries);
+        self.entries.par_sort_unstable_by_key(|e| (e.hash, e.index));
+
+        self.grid_start.fill(EMPTY_CELL);
+        for (k, entry) in self.entries.iter().enumerate() {
+            if k == 0 || self.entries[k - 1].hash != entry.hash {
+                self.grid_start[entry.hash as usize] = k as u32;
+            }
+        }
+    }
+
+    /// Calls `f(j, r_vec, r)` for every particle within the smoothing radius of
+    /// `pos_i` (including the particle itself, `r == 0`), walking the 27
+    /// surrounding cells in the same order as the shaders.
+    pub fn for_each_neighbor<F>(&self, positions: &[Vec3], pos_i: Vec3, mut f: F)
+    where
+        F: FnMut(usize, Vec3, f32),
+    {
+        let h = self.h;
+        let cell = cell_coords(pos_i, h);
+
+        for z in -1..=1 {
+            for y in -1..=1 {
+                for x in -1..=1 {
+                    let hash = cell_hash(cell + IVec3::new(x, y, z), self.table_size);
+                    let start = self.grid_start[hash as usize];
+                    if start == EMPTY_CELL {
+                        continue;
+                    }
+
+                    for entry in &self.entries[start as usize..] {
+                        if entry.hash != hash {
+                            break;
+                        }
+
+                        let j = entry.index as usize;
+                        let r_vec = pos_i - positions[j];
+                        let r2 = r_vec.dot(r_vec);
+                        if r2 > h * h {
+                            continue;
+                        }
+
+                        f(j, r_vec, r2.sqrt());
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+#![cfg(test)]
+//
+// CPU-vs-GPU scaling benchmark.
+//
+// Times one full substep (viscosity, both solves with fixed iteration counts,
+// integration, neighbor search) of `CpuSimulation` and `Simulation` for growing
+// cubic blocks of particles. Both run the same scene, so the ratio is the
+// speed-up of the compute shaders over the rayon reference. Median wall time
+// per substep lands in `scripts/cpu_gpu_scaling.csv`.
+//
+// Marked `#[ignore]`; run manually with:
+//     cargo test --release -p fluid_engine -- --ignored scaling --nocapture
+
+use glam::{IVec3, Vec3};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::core::simulation::{create_headless_context, Simulation};
+use crate::cpu::CpuSimulation;
+use crate::entities::particle::{ParticleGenerator, SimulationParams};
+
+// ── Configuration ────────────────────────────────────────────────────────────
+
+// Particles per axis; the block holds edge³ particles (1k … 216k).
+const EDGE_VALUES: &[u32] = &[10, 16, 24, 32, 40, 48, 60];
+const WARMUP_SUBSTEPS: u32 = 3;
+const TIMED_SUBSTEPS: u32 = 10;
+const CSV_PATH: &str = "scripts/cpu_gpu_scaling.csv";
+
+const PARTICLE_RADIUS: f32 = 0.02;
+const TARGET_DENSITY: f32 = 1000.0;
+
+// ── Helpers ──────────────────────────────────────────────────────────────────
+
+fn make_block(edge: u32) -> (Vec<[f32; 3]>, SimulationParams) {
+    let spacing = PARTICLE_RADIUS * 2.0;
+    let size = edge as f32 * spacing + 1e-4;
+
+    let (positions, mass) = ParticleGenerator::generate_volume(
+        Vec3::new(-0.5 * size, 0.0, -0.5 * size),
+        size,
+        size,
+        size,
+        PARTICLE_RADIUS,
+        TARGET_DENSITY,
+        spacing,
+        0.0,
+    );
+
+    let params = SimulationParams::new(
+        PARTICLE_RADIUS,
+        mass,
+        PARTICLE_RADIUS * 4.0,
+        TARGET_DENSITY,
+        0.15,
+        0.5,
+        0.005,
+        4,
+        4,
+        Vec3::new(0.0, -9.81, 0.0),
+        Vec3::new(-size, -0.01, -size),
+        Vec3::new(size, 2.0 * size, size),
+        IVec3::splat(128),
+    );
+
+    (positions, params)
+}
+
+fn median(mut xs: Vec<f64>) -> f64 {
+    xs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    xs[xs.len() / 2]
+}
+
+fn time_substeps(mut run_one: impl FnMut()) -> f64 {
+    for _ in 0..WARMUP_SUBSTEPS {
+        run_one();
+    }
+    let samples = (0..TIMED_SUBSTEPS)
+        .map(|_| {
+            let start = Instant::now();
+            run_one();
+            start.elapsed().as_secs_f64() * 1000.0
+        })
+        .collect();
+    median(samples)
+}
+
+// ── Test ─────────────────────────────────────────────────────────────────────
+
+#[test]
+#[ignore]
+fn cpu_gpu_scaling_benchmark() {
+    let ctx = create_headless_context();
+
+    if let Some(parent) = Path::new(CSV_PATH).parent() {
+        fs::create_dir_all(parent).expect("failed to create CSV output dir");
+    }
+    let mut csv = fs::File::create(CSV_PATH).expect("failed to create CSV file");
+    writeln!(csv, "n,backend,time_ms").unwrap();
+
+    println!("\nCPU vs GPU scaling — output: {CSV_PATH}");
+    println!("rayon threads={}, warmup={WARMUP_SUBSTEPS}, timed={TIMED_SUBSTEPS} (median per substep)\n", rayon::current_num_threads());
+    println!("{:>10}  {:>12}  {:>12}  {:>8}", "N", "cpu_ms", "gpu_ms", "speedup");
+    println!("{:->10}  {:->12}  {:->12}  {:->8}", "", "", "", "");
+
+    for &edge in EDGE_VALUES {
+        let (positions, params) = make_block(edge);
+        let n = positions.len();
+
+        let mut cpu = CpuSimulation::new(&positions, params);
+        cpu.run_substeps(0);
+        let t_cpu = time_substeps(|| cpu.run_substeps(1));
+
+        // `run_substeps` waits on the fence, so wall time covers the whole dispatch.
+        let mut gpu = Simulation::new(ctx.clone(), &positions, params);
+        gpu.run_substeps(0);
+        let t_gpu = time_substeps(|| gpu.run_substeps(1));
+
+        writeln!(csv, "{n},CPU,{t_cpu:.4}").unwrap();
+        writeln!(csv, "{n},GPU,{t_gpu:.4}").unwrap();
+        println!("{n:>10}  {t_cpu:>12.3}  {t_gpu:>12.3}  {:>7.1}x", t_cpu / t_gpu);
+    }
+}
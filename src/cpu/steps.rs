@@ -0,0 +1,1370 @@
+use glam::{IVec3, Vec3, Vec4};
+use rayon::prelude::*;
+use crate::core::simulation::{SimulationStats, DENSITY_SCALE, DIVERGENCE_SCALE};
+use crate::cpu::boundary::BoundaryParticles;
+use crate::cpu::flip_grid::{stencil_corner, stencil_weight, stencil_weight_gradient, unit_axis, MacGrid, CELL_FLUID};
+use crate::cpu::kernel::{adhesion_kernel, cohesion_kernel, kernel_grad, kernel_w};
+use crate::cpu::neighbor_grid::NeighborGrid;
+use crate::entities::diffuse::{DiffuseParams, GpuDiffuseParticle, DIFFUSE_BUBBLE, DIFFUSE_FOAM, DIFFUSE_SPRAY};
+use crate::entities::obstacle::{Obstacle, OBSTACLE_IMPULSE_SCALE};
+use crate::entities::particle::{GridTransfer, SimulationParams, ViscosityMode, ViscositySolverState, FLIP_TRANSFER_SCALE};
+
+// One function per compute shader. Arguments follow the shader bindings:
+// read-only inputs first, outputs last. Each body is a line-by-line port of the
+// corresponding main(), so a divergence between the two is a bug on one side.
+// `phases` stands in for the phase the shaders read from `position.w`.
+
+fn vec3(v: [f32; 4]) -> Vec3 {
+    Vec3::new(v[0], v[1], v[2])
+}
+
+/// density_and_alpha.comp
+#[allow(clippy::too_many_arguments)]
+pub fn density_alpha(
+    grid: &NeighborGrid,
+    boundary: &BoundaryParticles,
+    params: &SimulationParams,
+    positions: &[Vec3],
+    phases: &[u32],
+    densities: &mut [f32],
+    factors: &mut [f32],
+) {
+    let h = params.smoothing_radius;
+    let kernel = params.kernel();
+
+    densities.par_iter_mut().zip(factors.par_iter_mut()).enumerate().for_each(|(i, (density_i, factor_i))| {
+        let psi_scale = psi_scale(params, phases[i]);
+        let mut density = 0.0;
+        let mut sum_grad_sq = 0.0;
+        let mut grad_sum = Vec3::ZERO;
+
+        grid.for_each_neighbor(positions, positions[i], |j, r_vec, r| {
+            let mass = params.phase_mass(phases[j]);
+            density += mass * kernel_w(kernel, r, h);
+
+            if r > 1e-6 {
+                let mass_grad = mass * kernel_grad(kernel, r_vec, r, h);
+                grad_sum += mass_grad;
+                sum_grad_sq += mass_grad.dot(mass_grad);
+            }
+        });
+        boundary.for_each_neighbor(params, positions[i], |psi, r_vec, r| {
+            let psi = psi_scale * psi;
+            density += psi * kernel_w(kernel, r, h);
+
+            if r > 1e-6 {
+                grad_sum += psi * kernel_grad(kernel, r_vec, r, h);
+            }
+        });
+
+        *density_i = density;
+        let alpha = sum_grad_sq + grad_sum.dot(grad_sum);
+        *factor_i = if alpha > 1e-6 { alpha } else { 0.0 };
+    });
+}
+
+/// surface_normals.comp — Akinci surface normals over same-phase neighbors.
+pub fn surface_normals(
+    grid: &NeighborGrid,
+    params: &SimulationParams,
+    positions: &[Vec3],
+    phases: &[u32],
+    densities: &[f32],
+    normals: &mut [Vec3],
+) {
+    let h = params.smoothing_radius;
+    let kernel = params.kernel();
+
+    normals.par_iter_mut().enumerate().for_each(|(i, normal_i)| {
+        let phase_i = phases[i];
+        let mass = params.phase_mass(phase_i);
+        let mut normal = Vec3::ZERO;
+
+        grid.for_each_neighbor(positions, positions[i], |j, r_vec, r| {
+            if phases[j] == phase_i && r > 1e-6 {
+                normal += mass / densities[j] * kernel_grad(kernel, r_vec, r, h);
+            }
+        });
+
+        *normal_i = h * normal;
+    });
+}
+
+/// viscosity.comp — XSPH-style smoothing (explicit mode only), Akinci surface
+/// tension between particles of the same phase and wall adhesion, plus gravity.
+#[allow(clippy::too_many_arguments)]
+pub fn viscosity(
+    grid: &NeighborGrid,
+    params: &SimulationParams,
+    positions: &[Vec3],
+    phases: &[u32],
+    velocities: &[Vec3],
+    densities: &[f32],
+    normals: &[Vec3],
+    new_velocities: &mut [Vec3],
+) {
+    let h = params.smoothing_radius;
+    let kernel = params.kernel();
+    let gravity = vec3(params.gravity);
+
+    new_velocities.par_iter_mut().enumerate().for_each(|(i, new_vel)| {
+        let phase_i = phases[i];
+        let rho_0 = params.phase_rest_density(phase_i);
+        let gamma = params.phase_surface_tension(phase_i);
+        let mut sum_viscosity = Vec3::ZERO;
+        let mut sum_surface_tension = Vec3::ZERO;
+
+        grid.for_each_neighbor(positions, positions[i], |j, r_vec, r| {
+            let w = kernel_w(kernel, r, h);
+            if r > 1e-6 {
+                let mass = params.phase_mass(phases[j]);
+                let vel_diff = velocities[j] - velocities[i];
+                sum_viscosity += mass / densities[j] * vel_diff * w;
+
+                if gamma > 0.0 && phases[j] == phase_i {
+                    let cohesion = -gamma * mass * cohesion_kernel(r, h) * r_vec / r;
+                    let curvature = -gamma * (normals[i] - normals[j]);
+                    let k_ij = 2.0 * rho_0 / (densities[i] + densities[j]);
+                    sum_surface_tension += k_ij * (cohesion + curvature);
+                }
+            }
+        });
+
+        let mut vel_visco = velocities[i];
+        if params.viscosity_mode() == ViscosityMode::Explicit {
+            vel_visco += params.phase_viscosity(phase_i) * sum_viscosity;
+        }
+        let mut accel = sum_surface_tension + gravity;
+        if params.adhesion > 0.0 {
+            accel += wall_adhesion(params, positions[i], h, 2.0 * params.particle_radius);
+        }
+        *new_vel = vel_visco + accel * params.dt;
+    });
+}
+
+/// `viscous_acceleration` in viscosity_cg.comp for the field `u`, and the
+/// coefficients of `u[i]` in it.
+pub(crate) fn viscous_acceleration(
+    grid: &NeighborGrid,
+    params: &SimulationParams,
+    positions: &[Vec3],
+    phases: &[u32],
+    densities: &[f32],
+    u: &[Vec3],
+    i: usize,
+) -> (Vec3, Vec3) {
+    let h = params.smoothing_radius;
+    let kernel = params.kernel();
+    let nu_i = params.phase_viscosity(phases[i]);
+    let mut acc = Vec3::ZERO;
+    let mut diagonal = Vec3::ZERO;
+
+    grid.for_each_neighbor(positions, positions[i], |j, r_vec, r| {
+        let r2 = r_vec.dot(r_vec);
+        if r2 < 1e-12 {
+            return;
+        }
+        let nu_ij = 0.5 * (nu_i + params.phase_viscosity(phases[j]));
+        let volume_j = params.phase_mass(phases[j]) / densities[j];
+        let grad = kernel_grad(kernel, r_vec, r, h);
+        let k = 10.0 * nu_ij * volume_j / (r2 + 0.01 * h * h);
+
+        acc += k * (u[i] - u[j]).dot(r_vec) * grad;
+        diagonal += k * r_vec * grad;
+    });
+
+    (acc, diagonal)
+}
+
+/// viscosity_cg.comp and viscosity_cg_reduce.comp — the implicit viscosity
+/// solve of Weiler et al. 2018 by Jacobi-preconditioned conjugate gradient,
+/// in place on the velocities left by `viscosity`. Stages run in the order
+/// `Simulation` records them; only the dot products are summed in a
+/// different order than the GPU's tree reduction.
+pub fn viscosity_solve(
+    grid: &NeighborGrid,
+    params: &SimulationParams,
+    positions: &[Vec3],
+    phases: &[u32],
+    densities: &[f32],
+    velocities: &mut [Vec3],
+) -> ViscositySolverState {
+    let n = velocities.len();
+    let dt = params.dt;
+    let tolerance2 = params.viscosity_tolerance * params.viscosity_tolerance;
+    let ratios: Vec<f32> = phases.iter().zip(densities).map(|(&phase, rho)| params.phase_rest_density(phase) / rho).collect();
+    let acceleration = |u: &[Vec3], i: usize| viscous_acceleration(grid, params, positions, phases, densities, u, i);
+
+    // CG_INIT, warm-started from x0 = v*.
+    let x0: &[Vec3] = velocities;
+    let (diagonals, mut residuals): (Vec<Vec3>, Vec<Vec3>) = (0..n)
+        .into_par_iter()
+        .map(|i| {
+            let (acc, diagonal) = acceleration(x0, i);
+            let b = ratios[i] * x0[i];
+            (ratios[i] * (Vec3::ONE - dt * diagonal), b - ratios[i] * (x0[i] - dt * acc))
+        })
+        .unzip();
+    let mut directions: Vec<Vec3> = residuals.iter().zip(&diagonals).map(|(r, d)| *r / *d).collect();
+
+    // REDUCE_INIT
+    let rhs_norm2: f32 = x0.par_iter().zip(&ratios).map(|(x, ratio)| (ratio * *x).length_squared()).sum();
+    let residual_norm2: f32 = residuals.par_iter().map(|r| r.length_squared()).sum();
+    let mut state = ViscositySolverState {
+        rz: residuals.par_iter().zip(&directions).map(|(r, z)| r.dot(*z)).sum(),
+        rhs_norm2,
+        residual_norm2,
+        converged: (residual_norm2 <= tolerance2 * rhs_norm2) as u32,
+        ..Default::default()
+    };
+
+    for _ in 0..params.viscosity_iterations {
+        if state.converged != 0 {
+            break;
+        }
+
+        // CG_APPLY, REDUCE_ALPHA
+        let products: Vec<Vec3> = (0..n)
+            .into_par_iter()
+            .map(|i| ratios[i] * (directions[i] - dt * acceleration(&directions, i).0))
+            .collect();
+        let p_ap: f32 = directions.par_iter().zip(&products).map(|(p, ap)| p.dot(*ap)).sum();
+        if p_ap <= 0.0 {
+            state.alpha = 0.0;
+            state.converged = 1;
+            break;
+        }
+        state.alpha = state.rz / p_ap;
+
+        // CG_UPDATE, REDUCE_BETA
+        let alpha = state.alpha;
+        velocities.par_iter_mut().zip(&directions).for_each(|(x, p)| *x += alpha * *p);
+        residuals.par_iter_mut().zip(&products).for_each(|(r, ap)| *r -= alpha * *ap);
+        let (rz, rr) = residuals
+            .par_iter()
+            .zip(&diagonals)
+            .map(|(r, d)| (r.dot(*r / *d), r.length_squared()))
+            .reduce(|| (0.0, 0.0), |a, b| (a.0 + b.0, a.1 + b.1));
+        state.beta = rz / state.rz;
+        state.rz = rz;
+        state.residual_norm2 = rr;
+        state.iterations += 1;
+        state.converged = (rr <= tolerance2 * state.rhs_norm2) as u32;
+
+        // CG_DIRECTION
+        if state.converged == 0 {
+            let beta = state.beta;
+            directions.par_iter_mut().zip(&residuals).zip(&diagonals).for_each(|((p, r), d)| *p = *r / *d + beta * *p);
+        }
+    }
+
+    state
+}
+
+fn wall_adhesion(params: &SimulationParams, pos_world: Vec3, h: f32, spacing: f32) -> Vec3 {
+    let n = (h / spacing).ceil() as i32;
+    let mut acc = Vec3::ZERO;
+    let pos_i = to_box_frame(params, pos_world);
+
+    for axis in 0..3 {
+        for side in 0..2 {
+            let d = if side == 0 { pos_i[axis] - params.box_min[axis] } else { params.box_max[axis] - pos_i[axis] };
+            if d <= 0.0 || d >= h {
+                continue;
+            }
+
+            let mut normal = Vec3::ZERO;
+            normal[axis] = if side == 0 { 1.0 } else { -1.0 };
+
+            for u in -n..=n {
+                for v in -n..=n {
+                    let mut offset = Vec3::ZERO;
+                    offset[(axis + 1) % 3] = u as f32 * spacing;
+                    offset[(axis + 2) % 3] = v as f32 * spacing;
+
+                    let r_vec = normal * d + offset;
+                    let r = r_vec.length();
+                    acc -= adhesion_kernel(r, h) * r_vec / r;
+                }
+            }
+        }
+    }
+
+    params.adhesion * params.particle_mass * (params.box_rotation() * acc)
+}
+
+/// vorticity.comp — velocity curl.
+pub fn vorticity(
+    grid: &NeighborGrid,
+    params: &SimulationParams,
+    velocities: &[Vec3],
+    positions: &[Vec3],
+    phases: &[u32],
+    densities: &[f32],
+    vorticities: &mut [Vec3],
+) {
+    let h = params.smoothing_radius;
+    let kernel = params.kernel();
+
+    vorticities.par_iter_mut().enumerate().for_each(|(i, vorticity_i)| {
+        let mut curl = Vec3::ZERO;
+
+        grid.for_each_neighbor(positions, positions[i], |j, r_vec, r| {
+            if r > 1e-6 {
+                let grad = kernel_grad(kernel, r_vec, r, h);
+                curl += params.phase_mass(phases[j]) / densities[j] * grad.cross(velocities[j] - velocities[i]);
+            }
+        });
+
+        *vorticity_i = curl;
+    });
+}
+
+/// vorticity_confinement.comp — updates the velocities in place.
+pub fn vorticity_confinement(
+    grid: &NeighborGrid,
+    params: &SimulationParams,
+    positions: &[Vec3],
+    phases: &[u32],
+    densities: &[f32],
+    vorticities: &[Vec3],
+    velocities: &mut [Vec3],
+) {
+    let h = params.smoothing_radius;
+    let kernel = params.kernel();
+
+    velocities.par_iter_mut().enumerate().for_each(|(i, vel_i)| {
+        let omega_i = vorticities[i];
+        let magnitude_i = omega_i.length();
+        let mut eta = Vec3::ZERO;
+
+        grid.for_each_neighbor(positions, positions[i], |j, r_vec, r| {
+            if r > 1e-6 {
+                let grad = kernel_grad(kernel, r_vec, r, h);
+                eta += params.phase_mass(phases[j]) / densities[j] * (vorticities[j].length() - magnitude_i) * grad;
+            }
+        });
+
+        let eta_length = eta.length();
+        if eta_length < 1e-6 {
+            return;
+        }
+
+        let n = eta / eta_length;
+        let accel = params.vorticity_confinement * h * n.cross(omega_i);
+        *vel_i += accel * params.dt;
+    });
+}
+
+/// density_source_term.comp — also resets the pressures for the density solve.
+#[allow(clippy::too_many_arguments)]
+pub fn density_source_term(
+    grid: &NeighborGrid,
+    boundary: &BoundaryParticles,
+    params: &SimulationParams,
+    positions: &[Vec3],
+    phases: &[u32],
+    densities: &[f32],
+    velocities: &[Vec3],
+    pressures: &mut [f32],
+    source_terms: &mut [f32],
+) {
+    let dt = params.dt;
+
+    source_terms.par_iter_mut().zip(pressures.par_iter_mut()).enumerate().for_each(|(i, (source_i, pressure_i))| {
+        let rho_0 = params.phase_rest_density(phases[i]);
+        let divergence_sum = divergence(grid, boundary, params, positions, phases, velocities, i);
+
+        *source_i = if dt > 1e-6 { (rho_0 - densities[i]) / dt - divergence_sum } else { 0.0 };
+        *pressure_i = 0.0;
+    });
+}
+
+/// divergence_source_term.comp — keeps the pressures of the density solve as warm start.
+pub fn divergence_source_term(
+    grid: &NeighborGrid,
+    boundary: &BoundaryParticles,
+    params: &SimulationParams,
+    positions: &[Vec3],
+    phases: &[u32],
+    velocities: &[Vec3],
+    source_terms: &mut [f32],
+) {
+    source_terms.par_iter_mut().enumerate().for_each(|(i, source_i)| {
+        *source_i = -divergence(grid, boundary, params, positions, phases, velocities, i);
+    });
+}
+
+/// Boundary particles are static, so their relative velocity is just `v_i`.
+fn divergence(
+    grid: &NeighborGrid,
+    boundary: &BoundaryParticles,
+    params: &SimulationParams,
+    positions: &[Vec3],
+    phases: &[u32],
+    velocities: &[Vec3],
+    i: usize,
+) -> f32 {
+    let h = params.smoothing_radius;
+    let kernel = params.kernel();
+    let psi_scale = psi_scale(params, phases[i]);
+    let mut divergence_sum = 0.0;
+    grid.for_each_neighbor(positions, positions[i], |j, r_vec, r| {
+        if r > 1e-6 {
+            let grad = kernel_grad(kernel, r_vec, r, h);
+            divergence_sum += params.phase_mass(phases[j]) * (velocities[i] - velocities[j]).dot(grad);
+        }
+    });
+    boundary.for_each_neighbor(params, positions[i], |psi, r_vec, r| {
+        if r > 1e-6 {
+            divergence_sum += psi_scale * psi * velocities[i].dot(kernel_grad(kernel, r_vec, r, h));
+        }
+    });
+    divergence_sum
+}
+
+/// Boundary particles carry Ψ = ρ0 V_b of the base fluid; this rescales it to
+/// the rest density of `phase`.
+fn psi_scale(params: &SimulationParams, phase: u32) -> f32 {
+    params.phase_rest_density(phase) / params.target_density
+}
+
+/// pressure_force.comp — boundary particles mirror the particle's own pressure.
+#[allow(clippy::too_many_arguments)]
+pub fn pressure_force(
+    grid: &NeighborGrid,
+    boundary: &BoundaryParticles,
+    params: &SimulationParams,
+    positions: &[Vec3],
+    phases: &[u32],
+    pressures: &[f32],
+    densities: &[f32],
+    pressure_accelerations: &mut [Vec3],
+) {
+    let h = params.smoothing_radius;
+    let kernel = params.kernel();
+
+    pressure_accelerations.par_iter_mut().enumerate().for_each(|(i, accel_i)| {
+        let psi_scale = psi_scale(params, phases[i]);
+        let rho_i = densities[i];
+        let p_rho_i = if rho_i > 1e-6 { pressures[i] / (rho_i * rho_i) } else { 0.0 };
+
+        let mut accel_sum = Vec3::ZERO;
+        grid.for_each_neighbor(positions, positions[i], |j, r_vec, r| {
+            if r > 1e-6 {
+                let rho_j = densities[j];
+                if rho_j > 1e-6 {
+                    let grad = kernel_grad(kernel, r_vec, r, h);
+                    let p_rho_j = pressures[j] / (rho_j * rho_j);
+                    accel_sum += params.phase_mass(phases[j]) * (p_rho_i + p_rho_j) * grad;
+                }
+            }
+        });
+        boundary.for_each_neighbor(params, positions[i], |psi, r_vec, r| {
+            if r > 1e-6 {
+                accel_sum += psi_scale * psi * p_rho_i * kernel_grad(kernel, r_vec, r, h);
+            }
+        });
+
+        *accel_i = -accel_sum;
+    });
+}
+
+/// pressure_update.comp — one relaxed Jacobi sweep. Only the particle's own
+/// pressure is read, so updating in place is equivalent to the GPU version.
+/// Returns the summed compression residual, the total of the shader's
+/// `pressure_partials`.
+#[allow(clippy::too_many_arguments)]
+pub fn pressure_update(
+    grid: &NeighborGrid,
+    boundary: &BoundaryParticles,
+    params: &SimulationParams,
+    positions: &[Vec3],
+    phases: &[u32],
+    pressure_accelerations: &[Vec3],
+    factors: &[f32],
+    source_terms: &[f32],
+    densities: &[f32],
+    pressures: &mut [f32],
+) -> f32 {
+    let h = params.smoothing_radius;
+    let kernel = params.kernel();
+    let relax_factor = params.relax_factor;
+    let dt = params.dt;
+
+    pressures.par_iter_mut().enumerate().map(|(i, p_i)| {
+        let p_acc_i = pressure_accelerations[i];
+        let psi_scale = psi_scale(params, phases[i]);
+
+        let mut sum_ap = 0.0;
+        grid.for_each_neighbor(positions, positions[i], |j, r_vec, r| {
+            if r > 1e-6 {
+                let grad = kernel_grad(kernel, r_vec, r, h);
+                sum_ap += params.phase_mass(phases[j]) * (p_acc_i - pressure_accelerations[j]).dot(grad);
+            }
+        });
+        boundary.for_each_neighbor(params, positions[i], |psi, r_vec, r| {
+            if r > 1e-6 {
+                sum_ap += psi_scale * psi * p_acc_i.dot(kernel_grad(kernel, r_vec, r, h));
+            }
+        });
+
+        let ap_i = dt * sum_ap;
+        let alpha_i = factors[i];
+        let rho_i = densities[i];
+        let mut residual = 0.0;
+
+        if alpha_i > 1e-6 && rho_i > 1e-6 {
+            let a_ii = -(dt / (rho_i * rho_i)) * alpha_i;
+            let error = source_terms[i] - ap_i;
+            residual = (-error).max(0.0);
+
+            if a_ii.abs() > 1e-20 {
+                let correction = (relax_factor * error) / a_ii;
+                *p_i = (*p_i + correction).max(0.0);
+            }
+        }
+        residual
+    }).sum()
+}
+
+/// wcsph_pressure.comp
+pub fn wcsph_pressure(params: &SimulationParams, phases: &[u32], densities: &[f32], pressures: &mut [f32]) {
+    pressures.par_iter_mut().enumerate().for_each(|(i, p_i)| {
+        let ratio = densities[i] / params.phase_rest_density(phases[i]);
+        let ratio3 = ratio * ratio * ratio;
+        *p_i = (params.stiffness * (ratio3 * ratio3 * ratio - 1.0)).max(0.0);
+    });
+}
+
+/// predict_positions.comp
+pub fn predict_positions(
+    params: &SimulationParams,
+    positions: &[Vec3],
+    velocities: &[Vec3],
+    pressure_accelerations: &[Vec3],
+    predicted_positions: &mut [Vec3],
+) {
+    let dt = params.dt;
+    predicted_positions.par_iter_mut().enumerate().for_each(|(i, predicted)| {
+        let new_vel = velocities[i] + pressure_accelerations[i] * dt;
+        *predicted = positions[i] + new_vel * dt;
+    });
+}
+
+/// pcisph_pressure.comp — the grid is the one of the substep's start, `delta`
+/// the push constant (`SimulationParams::pcisph_delta`).
+#[allow(clippy::too_many_arguments)]
+pub fn pcisph_pressure(
+    grid: &NeighborGrid,
+    boundary: &BoundaryParticles,
+    params: &SimulationParams,
+    delta: f32,
+    predicted_positions: &[Vec3],
+    phases: &[u32],
+    densities: &mut [f32],
+    pressures: &mut [f32],
+) {
+    let h = params.smoothing_radius;
+    let kernel = params.kernel();
+
+    densities.par_iter_mut().zip(pressures.par_iter_mut()).enumerate().for_each(|(i, (density_i, p_i))| {
+        let pos_i = predicted_positions[i];
+        let rho_0 = params.phase_rest_density(phases[i]);
+        let psi_scale = psi_scale(params, phases[i]);
+
+        let mut density = 0.0;
+        grid.for_each_neighbor(predicted_positions, pos_i, |j, _, r| {
+            density += params.phase_mass(phases[j]) * kernel_w(kernel, r, h);
+        });
+        boundary.for_each_neighbor(params, pos_i, |psi, _, r| {
+            density += psi_scale * psi * kernel_w(kernel, r, h);
+        });
+
+        *density_i = density;
+        *p_i = (*p_i + delta * (density - rho_0)).max(0.0);
+    });
+}
+
+/// iisph_diagonal.comp — d_ii and a_ii go to `diagonals` (xyz, w) like on the GPU.
+#[allow(clippy::too_many_arguments)]
+pub fn iisph_diagonal(
+    grid: &NeighborGrid,
+    boundary: &BoundaryParticles,
+    params: &SimulationParams,
+    positions: &[Vec3],
+    phases: &[u32],
+    densities: &[f32],
+    velocities: &[Vec3],
+    pressures: &mut [f32],
+    source_terms: &mut [f32],
+    diagonals: &mut [Vec4],
+) {
+    let h = params.smoothing_radius;
+    let kernel = params.kernel();
+    let dt = params.dt;
+
+    diagonals.par_iter_mut().zip(source_terms.par_iter_mut()).zip(pressures.par_iter_mut()).enumerate().for_each(|(i, ((diagonal_i, source_i), p_i))| {
+        let rho_i = densities[i];
+        let m_i = params.phase_mass(phases[i]);
+        let rho_0 = params.phase_rest_density(phases[i]);
+        let psi_scale = psi_scale(params, phases[i]);
+
+        let mut grad_sum = Vec3::ZERO;
+        let mut sum_grad_sq = 0.0;
+        let mut divergence_sum = 0.0;
+        grid.for_each_neighbor(positions, positions[i], |j, r_vec, r| {
+            if r > 1e-6 {
+                let grad = kernel_grad(kernel, r_vec, r, h);
+                let m_j = params.phase_mass(phases[j]);
+                grad_sum += m_j * grad;
+                sum_grad_sq += m_j * grad.length_squared();
+                divergence_sum += m_j * (velocities[i] - velocities[j]).dot(grad);
+            }
+        });
+        boundary.for_each_neighbor(params, positions[i], |psi, r_vec, r| {
+            if r > 1e-6 {
+                let grad = kernel_grad(kernel, r_vec, r, h);
+                let psi = psi_scale * psi;
+                grad_sum += psi * grad;
+                divergence_sum += psi * velocities[i].dot(grad);
+            }
+        });
+
+        let mut d_ii = Vec3::ZERO;
+        let mut a_ii = 0.0;
+        if rho_i > 1e-6 {
+            let dt2_rho2 = (dt * dt) / (rho_i * rho_i);
+            d_ii = -dt2_rho2 * grad_sum;
+            a_ii = d_ii.dot(grad_sum) - dt2_rho2 * m_i * sum_grad_sq;
+        }
+
+        *diagonal_i = d_ii.extend(a_ii);
+        *source_i = rho_0 - (rho_i + dt * divergence_sum);
+        *p_i = 0.0;
+    });
+}
+
+/// iisph_pressure_sum.comp — Σ_j d_ij p_j in xyz, p_i in w.
+pub fn iisph_pressure_sum(
+    grid: &NeighborGrid,
+    params: &SimulationParams,
+    positions: &[Vec3],
+    phases: &[u32],
+    densities: &[f32],
+    pressures: &[f32],
+    pressure_sums: &mut [Vec4],
+) {
+    let h = params.smoothing_radius;
+    let kernel = params.kernel();
+    let dt = params.dt;
+
+    pressure_sums.par_iter_mut().enumerate().for_each(|(i, sum_i)| {
+        let mut sum = Vec3::ZERO;
+        grid.for_each_neighbor(positions, positions[i], |j, r_vec, r| {
+            let rho_j = densities[j];
+            if r > 1e-6 && rho_j > 1e-6 {
+                let grad = kernel_grad(kernel, r_vec, r, h);
+                sum -= (params.phase_mass(phases[j]) * pressures[j] / (rho_j * rho_j)) * grad;
+            }
+        });
+        *sum_i = (dt * dt * sum).extend(pressures[i]);
+    });
+}
+
+/// iisph_pressure.comp — reads p^l from `pressure_sums`, so writing
+/// `pressures` is equivalent to the GPU version.
+#[allow(clippy::too_many_arguments)]
+pub fn iisph_pressure(
+    grid: &NeighborGrid,
+    boundary: &BoundaryParticles,
+    params: &SimulationParams,
+    positions: &[Vec3],
+    phases: &[u32],
+    densities: &[f32],
+    source_terms: &[f32],
+    diagonals: &[Vec4],
+    pressure_sums: &[Vec4],
+    pressures: &mut [f32],
+) {
+    let h = params.smoothing_radius;
+    let kernel = params.kernel();
+    let dt = params.dt;
+    let omega = params.relax_factor;
+
+    pressures.par_iter_mut().enumerate().for_each(|(i, p)| {
+        let rho_i = densities[i];
+        let m_i = params.phase_mass(phases[i]);
+        let sum_i = pressure_sums[i].truncate();
+        let p_i = pressure_sums[i].w;
+        let a_ii = diagonals[i].w;
+        let psi_scale = psi_scale(params, phases[i]);
+        let d_ji_scale = if rho_i > 1e-6 { (dt * dt * m_i) / (rho_i * rho_i) } else { 0.0 };
+
+        let mut sum = 0.0;
+        grid.for_each_neighbor(positions, positions[i], |j, r_vec, r| {
+            if r > 1e-6 {
+                let grad = kernel_grad(kernel, r_vec, r, h);
+                let sum_j = pressure_sums[j];
+                let d_jj_p_j = diagonals[j].truncate() * sum_j.w;
+                let d_ji_p_i = d_ji_scale * p_i * grad;
+                let displacement = sum_i - d_jj_p_j - (sum_j.truncate() - d_ji_p_i);
+                sum += params.phase_mass(phases[j]) * displacement.dot(grad);
+            }
+        });
+        boundary.for_each_neighbor(params, positions[i], |psi, r_vec, r| {
+            if r > 1e-6 {
+                sum += psi_scale * psi * sum_i.dot(kernel_grad(kernel, r_vec, r, h));
+            }
+        });
+
+        *p = if a_ii.abs() > 1e-20 {
+            ((1.0 - omega) * p_i + omega * (source_terms[i] - sum) / a_ii).max(0.0)
+        } else {
+            0.0
+        };
+    });
+}
+
+/// pbf_lambda.comp — the multipliers go to `lambdas`, the pressure buffer on the GPU.
+pub fn pbf_lambda(
+    grid: &NeighborGrid,
+    boundary: &BoundaryParticles,
+    params: &SimulationParams,
+    predicted_positions: &[Vec3],
+    phases: &[u32],
+    lambdas: &mut [f32],
+) {
+    let h = params.smoothing_radius;
+    let kernel = params.kernel();
+
+    lambdas.par_iter_mut().enumerate().for_each(|(i, lambda_i)| {
+        let pos_i = predicted_positions[i];
+        let rho_0 = params.phase_rest_density(phases[i]);
+        let mass_i = params.phase_mass(phases[i]);
+        let psi_scale = psi_scale(params, phases[i]);
+
+        let mut density = 0.0;
+        let mut grad_sum = Vec3::ZERO;
+        let mut sum_grad_sq = 0.0;
+        grid.for_each_neighbor(predicted_positions, pos_i, |j, r_vec, r| {
+            let mass = params.phase_mass(phases[j]);
+            density += mass * kernel_w(kernel, r, h);
+
+            if r > 1e-6 {
+                let grad = kernel_grad(kernel, r_vec, r, h);
+                grad_sum += mass * grad;
+                sum_grad_sq += mass * grad.dot(grad);
+            }
+        });
+        boundary.for_each_neighbor(params, pos_i, |psi, r_vec, r| {
+            let psi = psi_scale * psi;
+            density += psi * kernel_w(kernel, r, h);
+
+            if r > 1e-6 {
+                grad_sum += psi * kernel_grad(kernel, r_vec, r, h);
+            }
+        });
+
+        let constraint = (density / rho_0 - 1.0).max(0.0);
+        let denominator = (grad_sum.dot(grad_sum) / mass_i + sum_grad_sq) / (rho_0 * rho_0);
+        *lambda_i = if denominator > 1e-6 { -params.relax_factor * constraint / denominator } else { 0.0 };
+    });
+}
+
+/// pbf_correction.comp
+#[allow(clippy::too_many_arguments)]
+pub fn pbf_correction(
+    grid: &NeighborGrid,
+    boundary: &BoundaryParticles,
+    params: &SimulationParams,
+    predicted_positions: &[Vec3],
+    phases: &[u32],
+    lambdas: &[f32],
+    pressure_accelerations: &mut [Vec3],
+) {
+    let h = params.smoothing_radius;
+    let kernel = params.kernel();
+    let dt = params.dt;
+
+    pressure_accelerations.par_iter_mut().enumerate().for_each(|(i, accel_i)| {
+        let pos_i = predicted_positions[i];
+        let rho_0 = params.phase_rest_density(phases[i]);
+        let mass_i = params.phase_mass(phases[i]);
+        let psi_scale = psi_scale(params, phases[i]);
+
+        let mut grad_sum = Vec3::ZERO;
+        let mut neighbor_sum = Vec3::ZERO;
+        grid.for_each_neighbor(predicted_positions, pos_i, |j, r_vec, r| {
+            if r > 1e-6 {
+                let grad = kernel_grad(kernel, r_vec, r, h);
+                grad_sum += params.phase_mass(phases[j]) * grad;
+                neighbor_sum += lambdas[j] / params.phase_rest_density(phases[j]) * grad;
+            }
+        });
+        boundary.for_each_neighbor(params, pos_i, |psi, r_vec, r| {
+            if r > 1e-6 {
+                grad_sum += psi_scale * psi * kernel_grad(kernel, r_vec, r, h);
+            }
+        });
+
+        let correction = lambdas[i] / (mass_i * rho_0) * grad_sum + neighbor_sum;
+        if dt > 1e-6 {
+            *accel_i += correction / (dt * dt);
+        }
+    });
+}
+
+/// flip_p2g.comp. `transfers` and `cells` must be cleared. Runs serially in
+/// place of the shader's atomics; the fixed-point sums come out the same in
+/// any order.
+#[allow(clippy::too_many_arguments)]
+pub fn flip_p2g(
+    params: &SimulationParams,
+    grid: &MacGrid,
+    positions: &[Vec3],
+    phases: &[u32],
+    velocities: &[Vec3],
+    affine: &[[Vec3; 3]],
+    transfers: &mut [i32],
+    cells: &mut [u32],
+) {
+    let to_box_axes = params.box_rotation().inverse();
+    let apic = params.grid_transfer() == GridTransfer::Apic;
+    let fixed = |x: f32| (x * FLIP_TRANSFER_SCALE).round() as i32;
+
+    for i in 0..positions.len() {
+        let local = to_box_frame(params, positions[i]);
+        let vel = to_box_axes * velocities[i];
+        let mass = params.phase_mass(phases[i]) / params.particle_mass;
+
+        cells[grid.cell_index(grid.particle_cell(local))] = CELL_FLUID;
+
+        for axis in 0..3 {
+            let (base, frac) = grid.face_stencil(local, axis);
+            for corner in 0..8 {
+                let o = stencil_corner(corner);
+                let node = base + o;
+                if !grid.in_nodes(node) {
+                    continue;
+                }
+
+                let w = mass * stencil_weight(frac, o);
+                let mut v = vel[axis];
+                if apic {
+                    v += affine[i][axis].dot((o.as_vec3() - frac) * grid.cell);
+                }
+
+                let k = grid.node_index(node);
+                transfers[8 * k + axis] += fixed(w * v);
+                transfers[8 * k + 4 + axis] += fixed(w);
+            }
+        }
+    }
+}
+
+/// flip_grid.comp, `GRID_NORMALIZE`.
+pub fn flip_grid_normalize(
+    params: &SimulationParams,
+    grid: &MacGrid,
+    transfers: &[i32],
+    velocities: &mut [Vec3],
+    old_velocities: &mut [Vec3],
+) {
+    let centre = (vec3(params.box_min) + vec3(params.box_max)) * 0.5;
+    let rotation = params.box_rotation();
+    let box_velocity = vec3(params.box_velocity);
+    let box_angular_velocity = vec3(params.box_angular_velocity);
+    let growth = vec3(params.box_growth);
+
+    velocities.par_iter_mut().zip(old_velocities.par_iter_mut()).enumerate().for_each(|(k, (velocity, old))| {
+        let n = grid.node_coords(k);
+        let mut u = Vec3::ZERO;
+        for axis in 0..3 {
+            let weight = transfers[8 * k + 4 + axis];
+            if weight > 0 {
+                u[axis] = transfers[8 * k + axis] as f32 / weight as f32;
+            }
+        }
+        *old = u;
+
+        for axis in 0..3 {
+            if !grid.is_face(n, axis) || (n[axis] != 0 && n[axis] != grid.dims[axis]) {
+                continue;
+            }
+            let face = grid.origin + (n.as_vec3() + 0.5 - 0.5 * unit_axis(axis).as_vec3()) * grid.cell;
+            let world = centre + rotation * (face - centre);
+            let wall_vel = rotation.inverse() * (box_velocity + box_angular_velocity.cross(world - centre));
+            u[axis] = wall_vel[axis] + if n[axis] == 0 { -growth[axis] } else { growth[axis] };
+        }
+        *velocity = u;
+    });
+}
+
+/// flip_grid.comp, `GRID_DIVERGENCE`. Clears both pressure halves of the air
+/// cells.
+pub fn flip_grid_divergence(grid: &MacGrid, cells: &[u32], velocities: &[Vec3], divergence: &mut [f32], pressures: &mut [f32]) {
+    let (first, second) = pressures.split_at_mut(grid.cell_count());
+    divergence.par_iter_mut().zip(first.par_iter_mut().zip(second.par_iter_mut())).enumerate().for_each(|(k, (div, (p0, p1)))| {
+        *div = 0.0;
+        if cells[k] == CELL_FLUID {
+            let c = grid.cell_coords(k);
+            for axis in 0..3 {
+                let lo = velocities[grid.node_index(c)][axis];
+                let hi = velocities[grid.node_index(c + unit_axis(axis))][axis];
+                *div += (hi - lo) / grid.cell[axis];
+            }
+        } else {
+            *p0 = 0.0;
+            *p1 = 0.0;
+        }
+    });
+}
+
+/// flip_grid.comp, `GRID_JACOBI`: sweep number `iteration` of ∇²p = ∇·u.
+pub fn flip_grid_jacobi(grid: &MacGrid, cells: &[u32], divergence: &[f32], iteration: u32, pressures: &mut [f32]) {
+    let (first, second) = pressures.split_at_mut(grid.cell_count());
+    let (src, dst) = if iteration & 1 == 0 { (&*first, second) } else { (&*second, first) };
+
+    dst.par_iter_mut().enumerate().for_each(|(k, p)| {
+        if cells[k] != CELL_FLUID {
+            *p = 0.0;
+            return;
+        }
+        let c = grid.cell_coords(k);
+        let mut sum = 0.0;
+        let mut diag = 0.0;
+        for axis in 0..3 {
+            let inv_sq = 1.0 / (grid.cell[axis] * grid.cell[axis]);
+            for side in [-1, 1] {
+                let nb = c + side * unit_axis(axis);
+                if !grid.in_cells(nb) {
+                    continue;
+                }
+                diag += inv_sq;
+                if cells[grid.cell_index(nb)] == CELL_FLUID {
+                    sum += inv_sq * src[grid.cell_index(nb)];
+                }
+            }
+        }
+        *p = if diag > 0.0 { (sum - divergence[k]) / diag } else { 0.0 };
+    });
+}
+
+/// flip_grid.comp, `GRID_PROJECT`, with the pressure of `iterations` sweeps.
+pub fn flip_grid_project(grid: &MacGrid, cells: &[u32], iterations: u32, pressures: &mut [f32], velocities: &mut [Vec3]) {
+    let src = (iterations & 1) as usize * grid.cell_count();
+    let is_fluid = |c: IVec3| grid.in_cells(c) && cells[grid.cell_index(c)] == CELL_FLUID;
+
+    velocities.par_iter_mut().enumerate().for_each(|(k, u)| {
+        let n = grid.node_coords(k);
+        for axis in 0..3 {
+            if !grid.is_face(n, axis) || n[axis] == 0 || n[axis] == grid.dims[axis] {
+                continue;
+            }
+            let lo = n - unit_axis(axis);
+            let (fluid_lo, fluid_hi) = (is_fluid(lo), is_fluid(n));
+            if !fluid_lo && !fluid_hi {
+                continue;
+            }
+            let p_lo = if fluid_lo { pressures[src + grid.cell_index(lo)] } else { 0.0 };
+            let p_hi = if fluid_hi { pressures[src + grid.cell_index(n)] } else { 0.0 };
+            u[axis] -= (p_hi - p_lo) / grid.cell[axis];
+        }
+    });
+
+    if src != 0 {
+        pressures.copy_within(src..src + grid.cell_count(), 0);
+    }
+}
+
+/// flip_g2p.comp
+#[allow(clippy::too_many_arguments)]
+pub fn flip_g2p(
+    params: &SimulationParams,
+    grid: &MacGrid,
+    positions: &[Vec3],
+    grid_velocities: &[Vec3],
+    old_velocities: &[Vec3],
+    velocities: &mut [Vec3],
+    affine: &mut [[Vec3; 3]],
+) {
+    let rotation = params.box_rotation();
+    let transfer = params.grid_transfer();
+
+    velocities.par_iter_mut().zip(affine.par_iter_mut()).enumerate().for_each(|(i, (velocity, affine_i))| {
+        let local = to_box_frame(params, positions[i]);
+        let vel = rotation.inverse() * *velocity;
+
+        let mut pic = vel;
+        let mut change = Vec3::ZERO;
+        let mut gradients = [Vec3::ZERO; 3];
+        for axis in 0..3 {
+            let (base, frac) = grid.face_stencil(local, axis);
+            let mut sum_w = 0.0;
+            let mut u_new = 0.0;
+            let mut u_old = 0.0;
+            let mut gradient = Vec3::ZERO;
+            let mut sum_grad_w = Vec3::ZERO;
+            for corner in 0..8 {
+                let o = stencil_corner(corner);
+                let node = base + o;
+                if !grid.in_nodes(node) {
+                    continue;
+                }
+                let k = grid.node_index(node);
+                let w = stencil_weight(frac, o);
+                let u = grid_velocities[k][axis];
+                sum_w += w;
+                u_new += w * u;
+                u_old += w * old_velocities[k][axis];
+                let grad_w = stencil_weight_gradient(frac, o, grid.cell);
+                gradient += u * grad_w;
+                sum_grad_w += grad_w;
+            }
+            if sum_w > 0.0 {
+                pic[axis] = u_new / sum_w;
+                change[axis] = (u_new - u_old) / sum_w;
+                gradients[axis] = (gradient - pic[axis] * sum_grad_w) / sum_w;
+            }
+        }
+
+        let new_vel = match transfer {
+            GridTransfer::Flip => pic.lerp(vel + change, params.flip_ratio),
+            _ => pic,
+        };
+        *velocity = rotation * new_vel;
+        *affine_i = if transfer == GridTransfer::Apic { gradients } else { [Vec3::ZERO; 3] };
+    });
+}
+
+/// `to_box_frame` in common.glsl.
+pub(crate) fn to_box_frame(params: &SimulationParams, p: Vec3) -> Vec3 {
+    let centre = (vec3(params.box_min) + vec3(params.box_max)) * 0.5;
+    centre + params.box_rotation().inverse() * (p - centre)
+}
+
+/// pressure_integration.comp — symplectic Euler, the obstacle response and the
+/// box clamp that catches particles slipping past the boundary particles,
+/// which moves them along with the walls. Returns each obstacle's linear and
+/// angular impulse, summed in fixed point like `obstacle_impulses`.
+pub fn pressure_integration(
+    params: &SimulationParams,
+    obstacles: &[Obstacle],
+    phases: &[u32],
+    pressure_accelerations: &[Vec3],
+    positions: &mut [Vec3],
+    velocities: &mut [Vec3],
+) -> Vec<[IVec3; 2]> {
+    let dt = params.dt;
+    let min_b = vec3(params.box_min);
+    let max_b = vec3(params.box_max);
+    let centre = (min_b + max_b) * 0.5;
+    let rotation = params.box_rotation();
+    let box_velocity = vec3(params.box_velocity);
+    let box_angular_velocity = vec3(params.box_angular_velocity);
+    let growth = vec3(params.box_growth);
+    let r = params.particle_radius;
+
+    let eps = 0.001;
+
+    let no_impulses = || vec![[IVec3::ZERO; 2]; obstacles.len()];
+    let fixed = |v: Vec3| (v * OBSTACLE_IMPULSE_SCALE).round().as_ivec3();
+
+    positions.par_iter_mut().zip(velocities.par_iter_mut()).enumerate().fold(no_impulses, |mut impulses, (i, (pos, vel))| {
+        let mut new_vel = *vel + pressure_accelerations[i] * dt;
+        let mut new_pos = *pos + new_vel * dt;
+
+        for (k, obstacle) in obstacles.iter().enumerate() {
+            let d = obstacle.distance(new_pos);
+            if d < r {
+                let n = obstacle.normal(new_pos);
+                new_pos += (r - d) * n;
+
+                let vn = (new_vel - obstacle.velocity_at(new_pos)).dot(n);
+                if vn < 0.0 {
+                    new_vel -= vn * n;
+                    let impulse = params.phase_mass(phases[i]) * vn * n;
+                    impulses[k][0] += fixed(impulse);
+                    impulses[k][1] += fixed((new_pos - obstacle.position).cross(impulse));
+                }
+            }
+        }
+
+        let wall_vel = rotation.inverse() * (box_velocity + box_angular_velocity.cross(new_pos - centre));
+        let mut local_pos = to_box_frame(params, new_pos);
+        let mut local_vel = rotation.inverse() * new_vel;
+        let mut hit = false;
+        for axis in 0..3 {
+            if local_pos[axis] < min_b[axis] + r {
+                local_pos[axis] = min_b[axis] + r + eps;
+                local_vel[axis] = local_vel[axis].max(wall_vel[axis] - growth[axis]);
+                hit = true;
+            } else if local_pos[axis] > max_b[axis] - r {
+                local_pos[axis] = max_b[axis] - r - eps;
+                local_vel[axis] = local_vel[axis].min(wall_vel[axis] + growth[axis]);
+                hit = true;
+            }
+        }
+        if hit {
+            new_pos = centre + rotation * (local_pos - centre);
+            new_vel = rotation * local_vel;
+        }
+
+        *pos = new_pos;
+        *vel = new_vel;
+        impulses
+    }).reduce(no_impulses, |mut a, b| {
+        for (a, b) in a.iter_mut().zip(b) {
+            a[0] += b[0];
+            a[1] += b[1];
+        }
+        a
+    })
+}
+
+/// divergence_integration.comp, without the debug colouring.
+pub fn divergence_integration(params: &SimulationParams, pressure_accelerations: &[Vec3], velocities: &mut [Vec3]) {
+    let dt = params.dt;
+    velocities.par_iter_mut().zip(pressure_accelerations.par_iter()).for_each(|(vel, ap)| {
+        *vel += *ap * dt;
+    });
+}
+
+/// `SURFACE_NORMAL_MIN` in diffuse_generate.comp.
+const SURFACE_NORMAL_MIN: f32 = 0.5;
+
+/// `outward_normal` in diffuse_generate.comp.
+fn outward_normal(n: Vec3) -> Vec3 {
+    let len = n.length();
+    if len > SURFACE_NORMAL_MIN { -n / len } else { Vec3::ZERO }
+}
+
+/// diffuse_generate.comp — the trapped-air, wave-crest and kinetic-energy
+/// potentials and the expected particle count over `dt`. The spawning itself
+/// is random and not ported.
+#[allow(clippy::too_many_arguments)]
+pub fn diffuse_generate(
+    grid: &NeighborGrid,
+    params: &SimulationParams,
+    diffuse: &DiffuseParams,
+    dt: f32,
+    positions: &[Vec3],
+    velocities: &[Vec3],
+    normals: &[Vec3],
+    potentials: &mut [[f32; 4]],
+) {
+    let h = params.smoothing_radius;
+
+    potentials.par_iter_mut().enumerate().for_each(|(i, potential_i)| {
+        let vel_i = velocities[i];
+        let n_i = outward_normal(normals[i]);
+        let mut trapped_air = 0.0;
+        let mut curvature = 0.0;
+
+        grid.for_each_neighbor(positions, positions[i], |j, r_vec, r| {
+            if r < 1e-6 {
+                return;
+            }
+            let w = 1.0 - r / h;
+            let x_hat = r_vec / r;
+
+            let v_ij = vel_i - velocities[j];
+            let v_len = v_ij.length();
+            if v_len > 1e-6 {
+                trapped_air += v_len * (1.0 - (v_ij / v_len).dot(x_hat)) * w;
+            }
+
+            let n_j = outward_normal(normals[j]);
+            if n_j.dot(n_j) > 0.0 && (-x_hat).dot(n_i) < 0.0 {
+                curvature += (1.0 - n_i.dot(n_j)) * w;
+            }
+        });
+
+        let speed = vel_i.length();
+        let moving_out = speed > 1e-6 && (vel_i / speed).dot(n_i) >= 0.6;
+        let wave_crest = if moving_out { curvature } else { 0.0 };
+        let kinetic_energy = 0.5 * speed * speed;
+        let expected = diffuse.generation_count(trapped_air, wave_crest, kinetic_energy, dt);
+        *potential_i = [trapped_air, wave_crest, kinetic_energy, expected];
+    });
+}
+
+/// diffuse_advect.comp — classifies and moves the diffuse particles in place.
+pub fn diffuse_advect(
+    grid: &NeighborGrid,
+    params: &SimulationParams,
+    diffuse: &DiffuseParams,
+    dt: f32,
+    positions: &[Vec3],
+    velocities: &[Vec3],
+    particles: &mut [GpuDiffuseParticle],
+) {
+    let h = params.smoothing_radius;
+    let kernel = params.kernel();
+    let gravity = vec3(params.gravity);
+    let (min_b, max_b) = (vec3(params.box_min), vec3(params.box_max));
+
+    particles.par_iter_mut().for_each(|p| {
+        if p.position[3] <= 0.0 {
+            return;
+        }
+        let mut pos = vec3(p.position);
+        let mut vel = vec3(p.velocity);
+        let mut lifetime = p.position[3];
+
+        let mut neighbors = 0;
+        let mut weight_sum = 0.0;
+        let mut fluid_vel = Vec3::ZERO;
+        grid.for_each_neighbor(positions, pos, |j, _, r| {
+            let w = kernel_w(kernel, r, h);
+            neighbors += 1;
+            weight_sum += w;
+            fluid_vel += velocities[j] * w;
+        });
+        if weight_sum > 0.0 {
+            fluid_vel /= weight_sum;
+        }
+
+        let kind = if neighbors < diffuse.spray_neighbors {
+            vel += dt * gravity;
+            pos += dt * vel;
+            DIFFUSE_SPRAY
+        } else if neighbors > diffuse.bubble_neighbors {
+            vel += -dt * diffuse.buoyancy * gravity + diffuse.drag * (fluid_vel - vel);
+            pos += dt * vel;
+            DIFFUSE_BUBBLE
+        } else {
+            vel = fluid_vel;
+            pos += dt * fluid_vel;
+            lifetime -= dt;
+            DIFFUSE_FOAM
+        };
+
+        let local = to_box_frame(params, pos);
+        if local.cmplt(min_b).any() || local.cmpgt(max_b).any() {
+            lifetime = 0.0;
+        }
+
+        p.position = [pos.x, pos.y, pos.z, lifetime.max(0.0)];
+        p.velocity = [vel.x, vel.y, vel.z, kind];
+    });
+}
+
+/// splat_density.comp. `density` is the `grid_res` texture laid out x-fastest,
+/// as `copy_image_to_buffer` leaves it.
+#[cfg(test)]
+pub fn splat_density(params: &SimulationParams, positions: &[Vec3], density: &mut [u32]) {
+    let res = IVec3::from_slice(&params.grid_res[..3]);
+    let (domain_min, domain_max) = params.domain();
+    let radius = 8;
+    let r = radius as f32;
+
+    for &pos in positions {
+        let uvw = (pos - domain_min) / (domain_max - domain_min);
+        if uvw.cmplt(Vec3::ZERO).any() || uvw.cmpgt(Vec3::ONE).any() {
+            continue;
+        }
+        let center_voxel = (uvw * res.as_vec3()).as_ivec3();
+
+        for z in -radius..=radius {
+            for y in -radius..=radius {
+                for x in -radius..=radius {
+                    let voxel = center_voxel + IVec3::new(x, y, z);
+                    if voxel.cmplt(IVec3::ZERO).any() || voxel.cmpge(res).any() {
+                        continue;
+                    }
+
+                    let dist = Vec3::new(x as f32, y as f32, z as f32).length();
+                    if dist < r {
+                        let x_norm = dist / r;
+                        let weight = 1.0 - x_norm * x_norm;
+                        let weight = weight * weight * weight;
+                        let index = (voxel.x + res.x * (voxel.y + res.y * voxel.z)) as usize;
+                        density[index] += (weight * 200.0) as u32;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// ssfr_smooth.comp, one pass along `direction`. `src` and `dst` are
+/// `width`×`height` depth images laid out row by row; zero is background.
+#[cfg(test)]
+pub fn ssfr_smooth(
+    width: usize,
+    height: usize,
+    direction: [i32; 2],
+    particle_radius: f32,
+    pixels_per_unit: f32,
+    src: &[f32],
+    dst: &mut [f32],
+) {
+    const MAX_FILTER_RADIUS: i32 = 24;
+    const FILTER_RADII: f32 = 6.0;
+    const RANGE_RADII: f32 = 2.0;
+    let size = [width as i32, height as i32];
+
+    dst.par_iter_mut().enumerate().for_each(|(index, out)| {
+        let px = [(index % width) as i32, (index / width) as i32];
+        let depth = src[index];
+        if depth <= 0.0 {
+            *out = 0.0;
+            return;
+        }
+
+        let radius = ((FILTER_RADII * particle_radius * pixels_per_unit / depth) as i32).clamp(1, MAX_FILTER_RADIUS);
+        let sigma_spatial = (radius as f32 * 0.5).max(1.0);
+        let sigma_range = RANGE_RADII * particle_radius;
+
+        let mut sum = 0.0;
+        let mut wsum = 0.0;
+        for i in -radius..=radius {
+            let q = [px[0] + direction[0] * i, px[1] + direction[1] * i];
+            if q[0] < 0 || q[1] < 0 || q[0] >= size[0] || q[1] >= size[1] {
+                continue;
+            }
+
+            let s = src[q[1] as usize * width + q[0] as usize];
+            if s <= 0.0 {
+                continue;
+            }
+
+            let dr = (s - depth) / sigma_range;
+            let w = (-((i * i) as f32) / (2.0 * sigma_spatial * sigma_spatial)).exp() * (-dr * dr).exp();
+            sum += s * w;
+            wsum += w;
+        }
+        *out = sum / wsum;
+    });
+}
+
+/// stats.comp, including its fixed-point quantisation, so the numbers line up
+/// with `Simulation::read_stats`.
+pub fn stats(params: &SimulationParams, phases: &[u32], velocities: &[Vec3], densities: &[f32], source_terms: &[f32]) -> SimulationStats {
+    if velocities.is_empty() {
+        return SimulationStats::default();
+    }
+    let n = velocities.len() as f32;
+
+    let max_speed = velocities.par_iter().map(|v| v.length()).reduce(|| 0.0, f32::max);
+    let density_sum: u64 = densities
+        .par_iter()
+        .zip(phases)
+        .map(|(rho, &phase)| ((rho - params.phase_rest_density(phase)).abs() * DENSITY_SCALE).min(10000.0) as u64)
+        .sum();
+    let divergence_sum: u64 = source_terms
+        .par_iter()
+        .map(|s| (s.abs() * DIVERGENCE_SCALE).min(10000.0) as u64)
+        .sum();
+
+    SimulationStats {
+        max_speed,
+        avg_density_error: density_sum as f32 / (DENSITY_SCALE * n),
+        avg_divergence_error: divergence_sum as f32 / (DIVERGENCE_SCALE * n),
+        particle_count: velocities.len() as u32,
+        // Reported by the viscosity and pressure solves, not stats.comp.
+        ..SimulationStats::default()
+    }
+}
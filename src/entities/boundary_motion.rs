@@ -0,0 +1,153 @@
+use glam::{EulerRot, Quat, Vec3};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Piecewise linear; the velocity jumps at every key.
+    Linear,
+    /// Cubic Hermite through the keys with Catmull-Rom tangents, so the
+    /// velocity is continuous.
+    Smooth,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keyframe {
+    pub time: f32,
+    pub value: Vec3,
+}
+
+/// Scripted rigid motion of the collision box: a translation track and a
+/// rotation track, each a list of keyframes sorted by time.
+///
+/// Rotation keys are XYZ Euler angles in degrees, the same convention as
+/// obstacle rotations. They are interpolated per component, which is exact for
+/// rotations about a single axis and allows several turns between two keys
+/// (`[0, 0, 0]` to `[0, 0, 720]`). The box rotates about its centre.
+///
+/// Before the first key a track holds its first value, after the last key its
+/// last value; with `looped` it repeats over its own key span instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoundaryMotion {
+    pub translation: Vec<Keyframe>,
+    pub rotation: Vec<Keyframe>,
+    pub interpolation: Interpolation,
+    pub looped: bool,
+}
+
+impl BoundaryMotion {
+    /// Offset and orientation of the box at `time`.
+    pub fn pose(&self, time: f32) -> (Vec3, Quat) {
+        let translation = self.sample(&self.translation, time);
+        let [x, y, z] = self.sample(&self.rotation, time).to_array().map(f32::to_radians);
+        (translation, Quat::from_euler(EulerRot::XYZ, x, y, z))
+    }
+
+    fn sample(&self, keys: &[Keyframe], time: f32) -> Vec3 {
+        let (Some(first), Some(last)) = (keys.first(), keys.last()) else {
+            return Vec3::ZERO;
+        };
+        let span = last.time - first.time;
+        let time = if self.looped && span > 0.0 {
+            first.time + (time - first.time).rem_euclid(span)
+        } else {
+            time.clamp(first.time, last.time)
+        };
+        if keys.len() < 2 {
+            return first.value;
+        }
+
+        // Segment [k, k + 1] containing `time`.
+        let k = keys.partition_point(|key| key.time <= time).saturating_sub(1).min(keys.len() - 2);
+        let (a, b) = (keys[k], keys[k + 1]);
+        let dt = b.time - a.time;
+        let s = ((time - a.time) / dt).clamp(0.0, 1.0);
+
+        match self.interpolation {
+            Interpolation::Linear => a.value.lerp(b.value, s),
+            Interpolation::Smooth => {
+                let ta = self.tangent(keys, k) * dt;
+                let tb = self.tangent(keys, k + 1) * dt;
+                let (s2, s3) = (s * s, s * s * s);
+                a.value * (2.0 * s3 - 3.0 * s2 + 1.0)
+                    + ta * (s3 - 2.0 * s2 + s)
+                    + b.value * (-2.0 * s3 + 3.0 * s2)
+                    + tb * (s3 - s2)
+            }
+        }
+    }
+
+    /// Catmull-Rom slope at key `k`. The ends of a looped track take their
+    /// neighbours across the wrap, so the last key should repeat the first;
+    /// open tracks start and stop at rest.
+    fn tangent(&self, keys: &[Keyframe], k: usize) -> Vec3 {
+        let n = keys.len();
+        let (prev, next) = if k > 0 && k + 1 < n {
+            (keys[k - 1], keys[k + 1])
+        } else if self.looped && n > 2 {
+            let span = keys[n - 1].time - keys[0].time;
+            // keys[0] and keys[n - 1] are the same point of the cycle.
+            let prev = Keyframe { time: keys[n - 2].time - span, value: keys[n - 2].value };
+            let next = Keyframe { time: keys[1].time + span, value: keys[1].value };
+            if k == 0 {
+                (prev, keys[1])
+            } else {
+                (keys[n - 2], next)
+            }
+        } else {
+            return Vec3::ZERO;
+        };
+        (next.value - prev.value) / (next.time - prev.time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(values: &[(f32, f32)]) -> Vec<Keyframe> {
+        values.iter().map(|&(time, x)| Keyframe { time, value: Vec3::new(x, 0.0, 0.0) }).collect()
+    }
+
+    #[test]
+    fn linear_tracks_interpolate_and_hold_or_loop() {
+        let mut motion = BoundaryMotion {
+            translation: keys(&[(1.0, 0.0), (2.0, 1.0), (4.0, -1.0)]),
+            rotation: Vec::new(),
+            interpolation: Interpolation::Linear,
+            looped: false,
+        };
+        let x = |motion: &BoundaryMotion, t: f32| motion.pose(t).0.x;
+
+        assert_eq!(x(&motion, 0.0), 0.0, "holds the first key");
+        assert!((x(&motion, 1.5) - 0.5).abs() < 1e-6);
+        assert!((x(&motion, 3.0) - 0.0).abs() < 1e-6);
+        assert_eq!(x(&motion, 9.0), -1.0, "holds the last key");
+        assert_eq!(motion.pose(3.0).1, Quat::IDENTITY);
+
+        motion.looped = true;
+        assert!((x(&motion, 4.5) - 0.5).abs() < 1e-5, "repeats over [1, 4]");
+        assert!((x(&motion, 0.5) - x(&motion, 3.5)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn smooth_tracks_pass_through_keys_with_continuous_velocity() {
+        let motion = BoundaryMotion {
+            translation: Vec::new(),
+            rotation: keys(&[(0.0, 0.0), (1.0, 10.0), (3.0, -10.0), (4.0, 0.0)]),
+            interpolation: Interpolation::Smooth,
+            looped: true,
+        };
+        let angle = |t: f32| motion.pose(t).1.to_euler(EulerRot::XYZ).0.to_degrees();
+
+        for (t, expected) in [(0.0, 0.0), (1.0, 10.0), (3.0, -10.0), (4.0, 0.0)] {
+            assert!((angle(t) - expected).abs() < 1e-3, "{t}: {}", angle(t));
+        }
+
+        // One-sided slopes agree at an inner key and across the wrap.
+        let eps = 1e-3;
+        for t in [1.0, 4.0] {
+            let before = (angle(t) - angle(t - eps)) / eps;
+            let after = (angle(t + eps) - angle(t)) / eps;
+            assert!((before - after).abs() < 0.1, "{t}: {before} vs {after}");
+        }
+    }
+}
@@ -15,6 +15,7 @@ struct CameraGPU {
     _padding: f32,
 }
 
+#[derive(Clone)]
 pub struct Camera {
     position: Vec3,
     orientation: Quat,
@@ -95,6 +96,26 @@ impl Camera {
         self.far = far;
         self
     }
+    /// Position, orientation quaternion, fov, aspect ratio, near and far, as
+    /// stored in checkpoints.
+    pub(crate) fn checkpoint_state(&self) -> [f32; 11] {
+        let [x, y, z, w] = self.orientation.to_array();
+        [
+            self.position.x, self.position.y, self.position.z,
+            x, y, z, w,
+            self.fov, self.aspect_ratio, self.near, self.far,
+        ]
+    }
+    pub(crate) fn from_checkpoint_state(s: [f32; 11]) -> Self {
+        Self {
+            position: Vec3::new(s[0], s[1], s[2]),
+            orientation: Quat::from_xyzw(s[3], s[4], s[5], s[6]).normalize(),
+            fov: s[7],
+            aspect_ratio: s[8],
+            near: s[9],
+            far: s[10],
+        }
+    }
     pub fn forward(&self) -> Vec3 {
         (self.orientation * Vec3::Z).normalize()
     }
@@ -104,6 +125,10 @@ impl Camera {
     pub fn up(&self) -> Vec3 {
         (self.orientation * Vec3::Y).normalize()
     }
+    /// `proj[1][1]`: projected height at unit depth, relative to half the viewport.
+    pub fn projection_scale(&self) -> f32 {
+        1.0 / (self.fov.to_radians() * 0.5).tan()
+    }
     fn get_view_matrix(&self) -> Mat4 {
         let target = self.position + self.forward();
         Mat4::look_at_lh(self.position, target, Vec3::Y)
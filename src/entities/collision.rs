@@ -1,20 +1,33 @@
 use std::f32::consts::TAU;
 use std::sync::Arc;
-use glam::Vec3;
+use glam::{Quat, Vec3};
 use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer};
 use vulkano::command_buffer::AutoCommandBufferBuilder;
 use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
+use crate::entities::boundary_motion::BoundaryMotion;
+use crate::entities::particle::SimulationParams;
 use crate::entities::{Actor, ModelVertex};
 use crate::renderer::pipelines::Pipelines;
 use crate::utils::constants::MAX_FRAMES_IN_FLIGHT;
 
+/// The simulation box. `min` and `max` are its extents at rest; on top of
+/// that the min-x wall oscillates as `wave_amplitude * sin(2π f t)` and
+/// `motion` moves and rotates the whole box. `update` also tracks how fast
+/// the walls move, which the collision response hands on to the fluid.
+#[derive(Clone)]
 pub struct CollisionBox {
     pub min: Vec3,
     pub max: Vec3,
     pub wave_amplitude: f32,
     pub wave_frequency: f32,
+    motion: Option<BoundaryMotion>,
     time: f32,
     base_min_x: f32,
+    translation: Vec3,
+    rotation: Quat,
+    velocity: Vec3,
+    angular_velocity: Vec3,
+    growth: Vec3,
 }
 
 impl CollisionBox {
@@ -25,18 +38,116 @@ impl CollisionBox {
             max,
             wave_amplitude: 0.0,
             wave_frequency: 1.0,
+            motion: None,
             time: 0.0,
+            translation: Vec3::ZERO,
+            rotation: Quat::IDENTITY,
+            velocity: Vec3::ZERO,
+            angular_velocity: Vec3::ZERO,
+            growth: Vec3::ZERO,
         }
     }
+    pub fn motion(&self) -> Option<&BoundaryMotion> {
+        self.motion.as_ref()
+    }
+    /// Replaces the scripted motion and moves the box to its pose at the
+    /// current time, without imparting any velocity.
+    pub fn set_motion(&mut self, motion: Option<BoundaryMotion>) {
+        self.motion = motion;
+        self.evaluate();
+        self.velocity = Vec3::ZERO;
+        self.angular_velocity = Vec3::ZERO;
+        self.growth = Vec3::ZERO;
+    }
     pub fn update(&mut self, dt: f32) {
+        let (min_before, max_before, rotation_before) = self.frame();
+
         self.time += dt;
+        self.evaluate();
+
+        if dt > 0.0 {
+            let (min, max, rotation) = self.frame();
+            self.velocity = ((min + max) - (min_before + max_before)) * 0.5 / dt;
+            self.angular_velocity = (rotation * rotation_before.inverse()).to_scaled_axis() / dt;
+            self.growth = ((max - min) - (max_before - min_before)) * 0.5 / dt;
+        } else {
+            self.velocity = Vec3::ZERO;
+            self.angular_velocity = Vec3::ZERO;
+            self.growth = Vec3::ZERO;
+        }
+    }
+    /// Wall offset and pose at the current time.
+    fn evaluate(&mut self) {
         let offset = self.wave_amplitude * (self.time * self.wave_frequency * TAU).sin();
         self.min.x = self.base_min_x + offset;
+        (self.translation, self.rotation) = self.motion.as_ref().map_or((Vec3::ZERO, Quat::IDENTITY), |m| m.pose(self.time));
+    }
+    /// Extents in the box frame and the rotation about their centre, as the
+    /// shaders see them. The box turns about the centre of its rest extents,
+    /// so the extents are shifted to keep that pivot fixed while the wave
+    /// wall moves.
+    pub fn frame(&self) -> (Vec3, Vec3, Quat) {
+        let pivot = (Vec3::new(self.base_min_x, self.min.y, self.min.z) + self.max) * 0.5;
+        let centre = (self.min + self.max) * 0.5;
+        let shift = self.translation + (pivot - centre) - self.rotation * (pivot - centre);
+        (self.min + shift, self.max + shift, self.rotation)
+    }
+    /// World-space corners, x fastest.
+    pub fn corners(&self) -> [Vec3; 8] {
+        let (min, max, rotation) = self.frame();
+        let centre = (min + max) * 0.5;
+        std::array::from_fn(|i| {
+            let corner = Vec3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            );
+            centre + rotation * (corner - centre)
+        })
+    }
+    /// Writes the current frame and wall velocities into `params`.
+    pub fn apply_to(&self, params: &mut SimulationParams) {
+        let (min, max, rotation) = self.frame();
+        params.box_min = min.extend(0.0).to_array();
+        params.box_max = max.extend(0.0).to_array();
+        params.box_rotation = rotation.to_array();
+        params.box_velocity = self.velocity.extend(0.0).to_array();
+        params.box_angular_velocity = self.angular_velocity.extend(0.0).to_array();
+        params.box_growth = self.growth.extend(0.0).to_array();
+    }
+    /// `min`, `max`, wave amplitude and frequency, wave time and the wall's
+    /// rest position, as stored in checkpoints. The scripted motion is part
+    /// of the scene and follows from the time.
+    pub(crate) fn checkpoint_state(&self) -> [f32; 10] {
+        [
+            self.min.x, self.min.y, self.min.z,
+            self.max.x, self.max.y, self.max.z,
+            self.wave_amplitude, self.wave_frequency,
+            self.time, self.base_min_x,
+        ]
+    }
+    pub(crate) fn from_checkpoint_state(s: [f32; 10]) -> Self {
+        Self {
+            min: Vec3::new(s[0], s[1], s[2]),
+            max: Vec3::new(s[3], s[4], s[5]),
+            wave_amplitude: s[6],
+            wave_frequency: s[7],
+            time: s[8],
+            base_min_x: s[9],
+            ..Self::new(Vec3::ZERO, Vec3::ZERO)
+        }
+    }
+    /// Rewinds the wall motion to `t = 0`.
+    pub fn reset(&mut self) {
+        self.time = 0.0;
+        let motion = self.motion.take();
+        self.set_motion(motion);
     }
     pub fn contains(&self, actor: &impl Actor) -> bool {
-        actor.location().x >= self.min.x && actor.location().x <= self.max.x &&
-            actor.location().y >= self.min.y && actor.location().y <= self.max.y &&
-            actor.location().z >= self.min.z && actor.location().z <= self.max.z
+        let (min, max, rotation) = self.frame();
+        let centre = (min + max) * 0.5;
+        let location = centre + rotation.inverse() * (actor.location() - centre);
+        location.cmpge(min).all() && location.cmple(max).all()
     }
 }
 
@@ -93,19 +204,8 @@ impl CollisionBoxData {
         }
     }
     pub fn write_to_buffer(&self, collision_box: &CollisionBox, current_frame_idx: usize) {
-        let min = collision_box.min;
-        let max = collision_box.max;
-
-        let vertices = [
-            ModelVertex { position: [min.x, min.y, min.z] },
-            ModelVertex { position: [max.x, min.y, min.z] },
-            ModelVertex { position: [max.x, max.y, min.z] },
-            ModelVertex { position: [min.x, max.y, min.z] },
-            ModelVertex { position: [min.x, min.y, max.z] },
-            ModelVertex { position: [max.x, min.y, max.z] },
-            ModelVertex { position: [max.x, max.y, max.z] },
-            ModelVertex { position: [min.x, max.y, max.z] },
-        ];
+        let corners = collision_box.corners();
+        let vertices = [0, 1, 3, 2, 4, 5, 7, 6].map(|i| ModelVertex { position: corners[i].to_array() });
 
         self.vertex_buffer[current_frame_idx]
             .write()
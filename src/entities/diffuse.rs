@@ -0,0 +1,246 @@
+use std::sync::Arc;
+use vulkano::buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::device::Device;
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
+use vulkano::pipeline::graphics::vertex_input::Vertex;
+use vulkano::pipeline::Pipeline;
+use crate::core::simulation::Simulation;
+use crate::renderer::pipelines::ComputeStep;
+use crate::renderer::pipelines::diffuse::{DiffuseAdvectPipeline, DiffuseGeneratePipeline, DiffuseRenderPipeline};
+use crate::utils::constants::DEFAULT_DIFFUSE_PARTICLES;
+
+/// Kinds of diffuse particle, stored in `velocity.w`; `DIFFUSE_*` in
+/// diffuse.glsl.
+pub const DIFFUSE_SPRAY: f32 = 0.0;
+pub const DIFFUSE_FOAM: f32 = 1.0;
+pub const DIFFUSE_BUBBLE: f32 = 2.0;
+
+/// One spray, foam or bubble particle; `DiffuseParticle` in diffuse.glsl.
+/// `position.w` is the remaining lifetime in seconds, 0 for a free slot.
+#[repr(C)]
+#[derive(BufferContents, Vertex, Copy, Clone, Debug, Default, PartialEq)]
+pub struct GpuDiffuseParticle {
+    #[format(R32G32B32A32_SFLOAT)]
+    pub position: [f32; 4],
+    #[format(R32G32B32A32_SFLOAT)]
+    pub velocity: [f32; 4],
+}
+
+/// Generation and advection constants of the diffuse particles (Ihmsen et al.
+/// 2012); `DiffuseParams` in diffuse.glsl. Each potential is clamped to its
+/// `[min, max]` range and mapped to [0, 1] before it drives generation.
+#[repr(C, align(16))]
+#[derive(BufferContents, Copy, Clone, Debug, PartialEq)]
+pub struct DiffuseParams {
+    /// Trapped-air potential: relative velocity of converging neighbors.
+    pub trapped_air_min: f32,
+    pub trapped_air_max: f32,
+    /// Wave-crest potential: curvature of the surface where it moves outwards.
+    pub wave_crest_min: f32,
+    pub wave_crest_max: f32,
+    /// Kinetic energy per unit mass [J/kg] that scales both potentials.
+    pub kinetic_energy_min: f32,
+    pub kinetic_energy_max: f32,
+    /// Particles generated per second by a fully saturated potential.
+    pub trapped_air_rate: f32,
+    pub wave_crest_rate: f32,
+    /// Mean lifetime of a foam particle [s]; spray and bubbles only age once
+    /// they have turned into foam.
+    pub lifetime: f32,
+    /// Bubble buoyancy k_b, as a multiple of -gravity.
+    pub buoyancy: f32,
+    /// Fraction of the velocity difference to the fluid a bubble loses per step.
+    pub drag: f32,
+    /// Fluid neighbors below which a particle is spray, and above which a bubble.
+    pub spray_neighbors: u32,
+    pub bubble_neighbors: u32,
+}
+
+impl Default for DiffuseParams {
+    fn default() -> Self {
+        Self {
+            trapped_air_min: 5.0,
+            trapped_air_max: 20.0,
+            wave_crest_min: 2.0,
+            wave_crest_max: 8.0,
+            kinetic_energy_min: 0.5,
+            kinetic_energy_max: 5.0,
+            trapped_air_rate: 40.0,
+            wave_crest_rate: 40.0,
+            lifetime: 2.0,
+            buoyancy: 2.0,
+            drag: 0.5,
+            spray_neighbors: 6,
+            bubble_neighbors: 20,
+        }
+    }
+}
+
+impl DiffuseParams {
+    /// Potential `value` clamped to `[min, max]` and mapped to [0, 1]; `clamp_potential`
+    /// in diffuse_generate.comp.
+    pub fn clamp_potential(value: f32, min: f32, max: f32) -> f32 {
+        (value.min(max) - value.min(min)) / (max - min)
+    }
+
+    /// Expected number of diffuse particles a fluid particle generates over
+    /// `dt` from its trapped-air, wave-crest and kinetic-energy potentials.
+    pub fn generation_count(&self, trapped_air: f32, wave_crest: f32, kinetic_energy: f32, dt: f32) -> f32 {
+        let ta = Self::clamp_potential(trapped_air, self.trapped_air_min, self.trapped_air_max);
+        let wc = Self::clamp_potential(wave_crest, self.wave_crest_min, self.wave_crest_max);
+        let k = Self::clamp_potential(kinetic_energy, self.kinetic_energy_min, self.kinetic_energy_max);
+        k * (self.trapped_air_rate * ta + self.wave_crest_rate * wc) * dt
+    }
+}
+
+/// Scene-level switch and size of the diffuse particle system.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DiffuseSettings {
+    pub enabled: bool,
+    /// Slots in the ring buffer; once full, new particles replace the oldest.
+    pub capacity: u32,
+    pub params: DiffuseParams,
+}
+
+impl Default for DiffuseSettings {
+    fn default() -> Self {
+        Self { enabled: false, capacity: DEFAULT_DIFFUSE_PARTICLES, params: DiffuseParams::default() }
+    }
+}
+
+#[derive(BufferContents, Clone, Copy)]
+#[repr(C)]
+struct DiffuseDrawPushConstants {
+    camera_addr: u64,
+    particle_radius: f32,
+    viewport_height: f32,
+}
+
+/// GPU storage of the diffuse particles, bound by both compute passes.
+pub struct DiffuseBuffers {
+    pub particles: Subbuffer<[GpuDiffuseParticle]>,
+    /// Total particles generated so far; the ring-buffer slot of the next
+    /// one is this modulo the capacity.
+    pub next_slot: Subbuffer<[u32]>,
+    /// Per fluid particle (sorted order): trapped air, wave crest, kinetic
+    /// energy and the expected number generated this frame.
+    pub potentials: Subbuffer<[[f32; 4]]>,
+    pub params: Subbuffer<DiffuseParams>,
+}
+
+/// Secondary spray, foam and bubble particles generated from the fluid each
+/// frame and advected through its velocity field. They live in a ring buffer
+/// on the GPU, are updated in the render command buffer and drawn as
+/// billboards over the water.
+pub struct DiffuseParticles {
+    pub buffers: DiffuseBuffers,
+    params: DiffuseParams,
+    generate: DiffuseGeneratePipeline,
+    advect: DiffuseAdvectPipeline,
+    frame: u32,
+}
+
+impl DiffuseParticles {
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+        device: Arc<Device>,
+        simulation: &Simulation,
+        settings: &DiffuseSettings,
+    ) -> Self {
+        let capacity = settings.capacity.max(1) as u64;
+        let storage = |usage: BufferUsage, len: u64| {
+            Buffer::new_slice(
+                memory_allocator.clone(),
+                BufferCreateInfo { usage: BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_DST | usage, ..Default::default() },
+                AllocationCreateInfo { memory_type_filter: MemoryTypeFilter::PREFER_DEVICE, ..Default::default() },
+                len,
+            ).expect("Failed to create diffuse particle buffer")
+        };
+        let particles = storage(BufferUsage::VERTEX_BUFFER | BufferUsage::TRANSFER_SRC, capacity);
+        let next_slot = storage(BufferUsage::TRANSFER_SRC, 1);
+        let potentials = storage(BufferUsage::TRANSFER_SRC, simulation.capacity() as u64);
+
+        let params = Buffer::from_data(
+            memory_allocator,
+            BufferCreateInfo { usage: BufferUsage::UNIFORM_BUFFER, ..Default::default() },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            settings.params,
+        ).expect("Failed to create diffuse params buffer");
+
+        let mut diffuse = Self {
+            buffers: DiffuseBuffers { particles, next_slot, potentials, params },
+            params: settings.params,
+            generate: DiffuseGeneratePipeline::new(device.clone()),
+            advect: DiffuseAdvectPipeline::new(device),
+            frame: 0,
+        };
+        diffuse.prepare(descriptor_set_allocator, simulation);
+        diffuse
+    }
+
+    /// Re-binds the passes to the simulation's buffers.
+    pub fn prepare(&mut self, allocator: Arc<StandardDescriptorSetAllocator>, simulation: &Simulation) {
+        let (physics_data, sim_params) = (simulation.physics_data(), simulation.sim_params_buffer());
+        self.generate.prepare_with_diffuse(allocator.clone(), physics_data, sim_params, &self.buffers);
+        self.advect.prepare_with_diffuse(allocator, physics_data, sim_params, &self.buffers);
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.buffers.particles.len() as u32
+    }
+    pub fn params(&self) -> &DiffuseParams {
+        &self.params
+    }
+    /// Updates the constants; skipped while the GPU still reads the old ones.
+    pub fn set_params(&mut self, params: DiffuseParams) {
+        if params != self.params
+            && let Ok(mut gpu_params) = self.buffers.params.write()
+        {
+            *gpu_params = params;
+            self.params = params;
+        }
+    }
+
+    /// Records the generation from the fluid's current state, then the
+    /// advection of every diffuse particle over `dt`. `simulation` must have
+    /// just recorded a step, so its neighbor structure is up to date.
+    pub fn record_update<Cb>(&mut self, builder: &mut AutoCommandBufferBuilder<Cb>, simulation: &Simulation, dt: f32) {
+        self.frame = self.frame.wrapping_add(1);
+        self.generate.set_step(dt, self.frame);
+        self.advect.set_step(dt, self.frame);
+
+        simulation.record_surface_normals(builder);
+        self.generate.execute(builder);
+        self.advect.execute(builder);
+    }
+
+    /// Records the removal of every diffuse particle, for a reset or restore.
+    pub fn record_clear<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>) {
+        builder.fill_buffer(self.buffers.particles.clone().reinterpret::<[u32]>(), 0).unwrap();
+        builder.fill_buffer(self.buffers.next_slot.clone(), 0).unwrap();
+    }
+
+    /// Draws the live particles as billboards; call inside the main rendering
+    /// pass, after the water.
+    pub fn draw<Cb>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<Cb>,
+        pipeline: &DiffuseRenderPipeline,
+        camera_addr: u64,
+        particle_radius: f32,
+        viewport_height: f32,
+    ) {
+        let push = DiffuseDrawPushConstants { camera_addr, particle_radius, viewport_height };
+        builder
+            .bind_pipeline_graphics(pipeline.inner.clone()).unwrap()
+            .bind_vertex_buffers(0, self.buffers.particles.clone()).unwrap()
+            .push_constants(pipeline.inner.layout().clone(), 0, push).unwrap();
+        unsafe { builder.draw(self.capacity(), 1, 0, 0).unwrap(); }
+    }
+}
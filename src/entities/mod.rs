@@ -6,7 +6,14 @@ pub mod particle;
 pub mod camera;
 pub mod sky;
 pub mod collision;
+pub mod boundary_motion;
+pub mod obstacle;
+pub mod emitter;
+pub mod rigid_body;
 pub mod water;
+pub mod surface;
+pub mod screen_space_fluid;
+pub mod diffuse;
 
 #[derive(BufferContents, Vertex, Debug, Clone, Copy)]
 #[repr(C)]
@@ -0,0 +1,402 @@
+use std::f32::consts::TAU;
+use std::sync::Arc;
+use glam::{IVec3, Quat, Vec3};
+use vulkano::buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
+use crate::cpu::mesh_sdf::MeshSdf;
+use crate::entities::ModelVertex;
+use crate::renderer::pipelines::Pipelines;
+use crate::utils::constants::{MAX_FRAMES_IN_FLIGHT, OBSTACLE_MAX_LINE_VERTICES};
+
+// Shape ids shared with `obstacles.glsl`.
+pub const OBSTACLE_NONE: i32 = 0;
+pub const OBSTACLE_SPHERE: i32 = 1;
+pub const OBSTACLE_CAPSULE: i32 = 2;
+pub const OBSTACLE_BOX: i32 = 3;
+pub const OBSTACLE_MESH: i32 = 4;
+
+/// Fixed-point scale of the impulses `pressure_integration` hands to
+/// obstacles, shared with `obstacles.glsl`: 1 unit = 1 µN·s.
+pub const OBSTACLE_IMPULSE_SCALE: f32 = 1.0e6;
+
+/// Step of the central differences taken for obstacle normals, in metres.
+const NORMAL_EPS: f32 = 1e-3;
+const CIRCLE_SEGMENTS: usize = 32;
+
+/// Obstacle geometry in its local frame. Capsules run along local y.
+#[derive(Debug, Clone)]
+pub enum ObstacleShape {
+    Sphere { radius: f32 },
+    Capsule { radius: f32, half_height: f32 },
+    Box { half_extents: Vec3 },
+    Mesh(Arc<MeshSdf>),
+}
+
+impl ObstacleShape {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ObstacleShape::Sphere { .. } => "Sphere",
+            ObstacleShape::Capsule { .. } => "Capsule",
+            ObstacleShape::Box { .. } => "Box",
+            ObstacleShape::Mesh(_) => "Mesh",
+        }
+    }
+
+    /// Signed distance from a point in the local frame.
+    pub fn distance(&self, q: Vec3) -> f32 {
+        match self {
+            ObstacleShape::Sphere { radius } => q.length() - radius,
+            ObstacleShape::Capsule { radius, half_height } => {
+                q.distance(Vec3::new(0.0, q.y.clamp(-half_height, *half_height), 0.0)) - radius
+            }
+            ObstacleShape::Box { half_extents } => {
+                let d = q.abs() - *half_extents;
+                d.max(Vec3::ZERO).length() + d.max_element().min(0.0)
+            }
+            ObstacleShape::Mesh(sdf) => sdf.distance(q),
+        }
+    }
+}
+
+/// Solid inside the fluid domain, static or spinning around its position.
+///
+/// Particles are pushed out of it and lose the velocity component into it in
+/// `pressure_integration`; a moving obstacle hands its surface velocity to
+/// the particles it touches. The momentum the particles lose is summed per
+/// obstacle and comes back as an `ObstacleForce`.
+#[derive(Debug, Clone)]
+pub struct Obstacle {
+    pub shape: ObstacleShape,
+    pub position: Vec3,
+    /// Orientation at rest, as authored in the scene.
+    pub rotation: Quat,
+    /// World-space spin in rad/s; zero for static obstacles.
+    pub angular_velocity: Vec3,
+    /// Velocity of `position`; only rigid bodies move, `update` leaves it alone.
+    pub velocity: Vec3,
+    /// Rotation accumulated by `update` on top of `rotation`.
+    spin: Quat,
+}
+
+impl Obstacle {
+    pub fn new(shape: ObstacleShape, position: Vec3, rotation: Quat) -> Self {
+        Self {
+            shape,
+            position,
+            rotation,
+            angular_velocity: Vec3::ZERO,
+            velocity: Vec3::ZERO,
+            spin: Quat::IDENTITY,
+        }
+    }
+    pub fn update(&mut self, dt: f32) {
+        if self.angular_velocity != Vec3::ZERO {
+            self.spin = (Quat::from_scaled_axis(self.angular_velocity * dt) * self.spin).normalize();
+        }
+    }
+    /// Rewinds the spin to the rest orientation.
+    pub fn reset(&mut self) {
+        self.spin = Quat::IDENTITY;
+    }
+    pub fn orientation(&self) -> Quat {
+        self.spin * self.rotation
+    }
+    /// Position, rest rotation and spin, as stored in checkpoints.
+    pub(crate) fn checkpoint_state(&self) -> [f32; 11] {
+        let (p, r, s) = (self.position, self.rotation, self.spin);
+        [p.x, p.y, p.z, r.x, r.y, r.z, r.w, s.x, s.y, s.z, s.w]
+    }
+    pub(crate) fn restore_checkpoint_state(&mut self, s: [f32; 11]) {
+        self.position = Vec3::new(s[0], s[1], s[2]);
+        self.rotation = Quat::from_xyzw(s[3], s[4], s[5], s[6]);
+        self.spin = Quat::from_xyzw(s[7], s[8], s[9], s[10]);
+    }
+
+    fn to_local(&self, p: Vec3) -> Vec3 {
+        self.orientation().conjugate() * (p - self.position)
+    }
+    pub fn distance(&self, p: Vec3) -> f32 {
+        self.shape.distance(self.to_local(p))
+    }
+    /// Outward normal at `p`, from central differences of the distance.
+    pub fn normal(&self, p: Vec3) -> Vec3 {
+        let q = self.to_local(p);
+        let d = |e: Vec3| self.shape.distance(q + e) - self.shape.distance(q - e);
+        let gradient = Vec3::new(d(Vec3::X * NORMAL_EPS), d(Vec3::Y * NORMAL_EPS), d(Vec3::Z * NORMAL_EPS));
+        self.orientation() * gradient.normalize_or(Vec3::Y)
+    }
+    /// Velocity of the obstacle's material at `p`.
+    pub fn velocity_at(&self, p: Vec3) -> Vec3 {
+        self.velocity + self.angular_velocity.cross(p - self.position)
+    }
+
+    /// Appends the outline as line-list vertex pairs in world space.
+    pub fn wireframe(&self, lines: &mut Vec<Vec3>) {
+        let start = lines.len();
+        match &self.shape {
+            ObstacleShape::Sphere { radius } => {
+                for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+                    push_arc(lines, Vec3::ZERO, axis, *radius, 0.0, TAU);
+                }
+            }
+            ObstacleShape::Capsule { radius, half_height } => {
+                for y in [-half_height, *half_height] {
+                    push_arc(lines, Vec3::Y * y, Vec3::Y, *radius, 0.0, TAU);
+                }
+                for side in [Vec3::X, Vec3::Z, -Vec3::X, -Vec3::Z] {
+                    lines.extend([side * *radius - Vec3::Y * *half_height, side * *radius + Vec3::Y * *half_height]);
+                }
+                for normal in [Vec3::Z, Vec3::X] {
+                    push_arc(lines, Vec3::Y * *half_height, normal, *radius, 0.0, TAU / 2.0);
+                    push_arc(lines, -Vec3::Y * *half_height, normal, *radius, TAU / 2.0, TAU);
+                }
+            }
+            ObstacleShape::Box { half_extents } => {
+                let corner = |i: usize| {
+                    Vec3::new(
+                        if i & 1 == 0 { -half_extents.x } else { half_extents.x },
+                        if i & 2 == 0 { -half_extents.y } else { half_extents.y },
+                        if i & 4 == 0 { -half_extents.z } else { half_extents.z },
+                    )
+                };
+                for i in 0..8 {
+                    for bit in [1, 2, 4] {
+                        if i & bit == 0 {
+                            lines.extend([corner(i), corner(i | bit)]);
+                        }
+                    }
+                }
+            }
+            ObstacleShape::Mesh(sdf) => {
+                lines.extend(sdf.edges.iter().flatten());
+            }
+        }
+
+        let orientation = self.orientation();
+        for v in &mut lines[start..] {
+            *v = self.position + orientation * *v;
+        }
+    }
+
+    fn to_gpu(&self, sdf_offset: i32) -> GpuObstacle {
+        let (kind, extent, sdf_dims) = match &self.shape {
+            ObstacleShape::Sphere { radius } => (OBSTACLE_SPHERE, [*radius, 0.0, 0.0, 0.0], [0; 4]),
+            ObstacleShape::Capsule { radius, half_height } => (OBSTACLE_CAPSULE, [*radius, *half_height, 0.0, 0.0], [0; 4]),
+            ObstacleShape::Box { half_extents } => (OBSTACLE_BOX, half_extents.extend(0.0).to_array(), [0; 4]),
+            ObstacleShape::Mesh(sdf) => (OBSTACLE_MESH, sdf.origin.extend(sdf.cell_size).to_array(), sdf.dims.extend(0).to_array()),
+        };
+        GpuObstacle {
+            position: self.position.extend(0.0).to_array(),
+            rotation: self.orientation().to_array(),
+            extent,
+            angular_velocity: self.angular_velocity.extend(0.0).to_array(),
+            velocity: self.velocity.extend(0.0).to_array(),
+            shape: [kind, sdf_offset, 0, 0],
+            sdf_dims,
+        }
+    }
+
+    /// True when both lists have the same length and the same mesh SDFs in the
+    /// same slots, i.e. when `gpu_obstacles` lays out `b` like `a`.
+    pub fn same_layout(a: &[Obstacle], b: &[Obstacle]) -> bool {
+        a.len() == b.len()
+            && a.iter().zip(b).all(|(a, b)| match (&a.shape, &b.shape) {
+                (ObstacleShape::Mesh(a), ObstacleShape::Mesh(b)) => Arc::ptr_eq(a, b),
+                (ObstacleShape::Mesh(_), _) | (_, ObstacleShape::Mesh(_)) => false,
+                _ => true,
+            })
+    }
+}
+
+/// Packs obstacles for `obstacles.glsl`: one `GpuObstacle` each and all mesh
+/// SDF values back to back. Both lists hold at least one element, since
+/// Vulkan does not allow empty buffers; the padding obstacle has shape
+/// `OBSTACLE_NONE`.
+pub fn gpu_obstacles(obstacles: &[Obstacle]) -> (Vec<GpuObstacle>, Vec<f32>) {
+    let mut sdf_values = Vec::new();
+    let mut packed: Vec<GpuObstacle> = obstacles
+        .iter()
+        .map(|obstacle| {
+            let offset = sdf_values.len() as i32;
+            if let ObstacleShape::Mesh(sdf) = &obstacle.shape {
+                sdf_values.extend_from_slice(&sdf.values);
+            }
+            obstacle.to_gpu(offset)
+        })
+        .collect();
+
+    if packed.is_empty() {
+        packed.push(GpuObstacle { shape: [OBSTACLE_NONE, 0, 0, 0], ..GpuObstacle::default() });
+    }
+    if sdf_values.is_empty() {
+        sdf_values.push(0.0);
+    }
+    (packed, sdf_values)
+}
+
+/// Force and torque the fluid exerts on an obstacle, the torque about
+/// `Obstacle::position`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ObstacleForce {
+    pub force: Vec3,
+    pub torque: Vec3,
+}
+
+impl ObstacleForce {
+    /// Averages the fixed-point linear and angular impulses summed by
+    /// `pressure_integration` over the `duration` they were collected in.
+    pub fn from_impulses(impulses: [IVec3; 2], duration: f32) -> Self {
+        if duration <= 0.0 {
+            return Self::default();
+        }
+        let scale = 1.0 / (OBSTACLE_IMPULSE_SCALE * duration);
+        Self {
+            force: impulses[0].as_vec3() * scale,
+            torque: impulses[1].as_vec3() * scale,
+        }
+    }
+}
+
+fn push_arc(lines: &mut Vec<Vec3>, center: Vec3, normal: Vec3, radius: f32, from: f32, to: f32) {
+    let u = normal.any_orthonormal_vector();
+    let v = normal.cross(u);
+    let segments = (CIRCLE_SEGMENTS as f32 * (to - from) / TAU).ceil() as usize;
+    let point = |k: usize| {
+        let angle = from + (to - from) * k as f32 / segments as f32;
+        center + radius * (u * angle.cos() + v * angle.sin())
+    };
+    for k in 0..segments {
+        lines.extend([point(k), point(k + 1)]);
+    }
+}
+
+/// Mirrors `struct Obstacle` in `obstacles.glsl` (std430).
+#[derive(BufferContents, Debug, Clone, Copy, Default, PartialEq)]
+#[repr(C)]
+pub struct GpuObstacle {
+    pub position: [f32; 4],
+    /// Current orientation as a quaternion, `xyzw`.
+    pub rotation: [f32; 4],
+    /// Sphere: radius. Capsule: radius, half height. Box: half extents.
+    /// Mesh: SDF origin and cell size.
+    pub extent: [f32; 4],
+    pub angular_velocity: [f32; 4],
+    pub velocity: [f32; 4],
+    /// Shape id and the offset of a mesh's values in the SDF buffer.
+    pub shape: [i32; 4],
+    /// Node counts of a mesh SDF.
+    pub sdf_dims: [i32; 4],
+}
+
+pub struct ObstacleData {
+    vertex_buffer: Vec<Subbuffer<[ModelVertex]>>,
+    vertex_count: Vec<u32>,
+}
+
+impl ObstacleData {
+    pub fn new(memory_allocator: Arc<StandardMemoryAllocator>) -> Self {
+        let vertex_buffer = (0..MAX_FRAMES_IN_FLIGHT)
+            .map(|_| {
+                Buffer::new_slice(
+                    memory_allocator.clone(),
+                    BufferCreateInfo {
+                        usage: BufferUsage::VERTEX_BUFFER | BufferUsage::SHADER_DEVICE_ADDRESS,
+                        ..BufferCreateInfo::default()
+                    },
+                    AllocationCreateInfo {
+                        memory_type_filter: MemoryTypeFilter::PREFER_DEVICE | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                        ..AllocationCreateInfo::default()
+                    },
+                    OBSTACLE_MAX_LINE_VERTICES,
+                ).map_err(|e| panic!("[Obstacles] Failed to create vertex buffer:\n{:?}", e)).unwrap()
+            })
+            .collect();
+
+        Self {
+            vertex_buffer,
+            vertex_count: vec![0; MAX_FRAMES_IN_FLIGHT],
+        }
+    }
+
+    /// Writes the wireframes of all obstacles, dropping whatever does not fit
+    /// into `OBSTACLE_MAX_LINE_VERTICES`.
+    pub fn write_to_buffer(&mut self, obstacles: &[Obstacle], current_frame_idx: usize) {
+        let mut lines = Vec::new();
+        for obstacle in obstacles {
+            obstacle.wireframe(&mut lines);
+        }
+        let count = lines.len().min(OBSTACLE_MAX_LINE_VERTICES as usize) & !1;
+
+        let Ok(mut vertices) = self.vertex_buffer[current_frame_idx].write() else {
+            return;
+        };
+        for (vertex, line) in vertices.iter_mut().zip(&lines[..count]) {
+            vertex.position = line.to_array();
+        }
+        self.vertex_count[current_frame_idx] = count as u32;
+    }
+
+    pub fn vertex_buffer_addr(&self, current_frame_idx: usize) -> u64 {
+        self.vertex_buffer[current_frame_idx]
+            .device_address()
+            .map_err(|e| panic!("[Obstacles] Failed to get vertex buffer device address:\n{:?}", e))
+            .unwrap()
+            .get()
+    }
+
+    pub fn bind_to_command_buffer<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>, pipelines: &Pipelines, camera_addr: u64, current_frame_idx: usize) {
+        let count = self.vertex_count[current_frame_idx];
+        if count == 0 {
+            return;
+        }
+        unsafe {
+            builder
+                .bind_pipeline_graphics(pipelines.collision_pipeline.inner.clone()).unwrap()
+                .push_constants(
+                    pipelines.common_layout.clone(),
+                    0,
+                    [
+                        camera_addr,
+                        self.vertex_buffer_addr(current_frame_idx),
+                    ]
+                ).unwrap()
+                .draw(count, 1, 0, 0).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn primitive_distances_and_normals() {
+        let rotation = Quat::from_rotation_z(TAU / 4.0);
+        let capsule = Obstacle::new(ObstacleShape::Capsule { radius: 0.1, half_height: 0.5 }, Vec3::new(1.0, 0.0, 0.0), rotation);
+        // The capsule's axis now runs along world x.
+        assert!((capsule.distance(Vec3::new(1.4, 0.3, 0.0)) - 0.2).abs() < 1e-5);
+        assert!((capsule.distance(Vec3::new(2.0, 0.0, 0.0)) - 0.4).abs() < 1e-5);
+        assert!(capsule.normal(Vec3::new(1.4, 0.3, 0.0)).abs_diff_eq(Vec3::Y, 1e-3));
+
+        let cube = Obstacle::new(ObstacleShape::Box { half_extents: Vec3::splat(0.5) }, Vec3::ZERO, Quat::IDENTITY);
+        assert!((cube.distance(Vec3::ZERO) + 0.5).abs() < 1e-6);
+        assert!((cube.distance(Vec3::new(0.0, 0.0, 0.8)) - 0.3).abs() < 1e-6);
+        assert!(cube.normal(Vec3::new(0.1, 0.0, 0.45)).abs_diff_eq(Vec3::Z, 1e-3));
+    }
+
+    #[test]
+    fn spinning_obstacle_moves_its_surface() {
+        let mut paddle = Obstacle::new(ObstacleShape::Box { half_extents: Vec3::new(0.5, 0.05, 0.2) }, Vec3::ZERO, Quat::IDENTITY);
+        paddle.angular_velocity = Vec3::new(0.0, 0.0, TAU / 4.0);
+        assert!(paddle.velocity_at(Vec3::X).abs_diff_eq(Vec3::Y * TAU / 4.0, 1e-6));
+
+        paddle.update(1.0);
+        assert!(paddle.distance(Vec3::new(0.0, 0.45, 0.0)) < 0.0, "the paddle should stand upright after a quarter turn");
+        assert!(paddle.distance(Vec3::new(0.45, 0.0, 0.0)) > 0.0);
+        assert_eq!(paddle.rotation, Quat::IDENTITY);
+
+        paddle.reset();
+        assert!(paddle.distance(Vec3::new(0.45, 0.0, 0.0)) < 0.0);
+    }
+}
@@ -1,11 +1,15 @@
 use std::sync::Arc;
-use glam::{IVec3, Vec3};
+use glam::{IVec3, Mat3, Quat, Vec3};
 use rand::Rng;
 use vulkano::buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer};
-use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, CopyBufferInfo, DispatchIndirectCommand, DrawIndirectCommand};
 use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
 use vulkano::pipeline::graphics::vertex_input::Vertex;
 use vulkano::pipeline::Pipeline;
+use crate::cpu::boundary::BoundaryParticles;
+use crate::cpu::kernel::kernel_grad;
+use crate::cpu::mesh_sdf::MeshSdf;
+use crate::entities::obstacle::{gpu_obstacles, GpuObstacle, Obstacle};
 use crate::renderer::pipelines::Pipelines;
 use crate::utils::constants::MAX_FRAMES_IN_FLIGHT;
 
@@ -33,19 +37,26 @@ pub struct AttributeVertex {
 pub struct GpuRenderData {
     pub position_buffers: Vec<Subbuffer<[PositionVertex]>>,
     pub color_buffers: Vec<Subbuffer<[ColorVertex]>>,
+    /// Per-frame copies of `GpuPhysicsData::draw_command`, so each frame draws
+    /// the particle count its positions were copied with.
+    pub draw_commands: Vec<Subbuffer<[DrawIndirectCommand]>>,
     pub attribute_buffer: Subbuffer<[AttributeVertex]>,
 }
 
 impl GpuRenderData {
+    /// Sizes the buffers for `capacity` particles, of which the first
+    /// `initial_positions.len()` are drawn until the simulation says otherwise.
     pub fn new(
         allocator: Arc<StandardMemoryAllocator>,
         initial_positions: &[[f32; 3]],
+        capacity: u32,
         radius: f32,
     ) -> Self {
-        let particle_count = initial_positions.len();
+        let particle_count = capacity as usize;
 
         let mut position_buffers = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
         let mut color_buffers = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
+        let mut draw_commands = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
 
         for _ in 0..MAX_FRAMES_IN_FLIGHT {
             let buffer = Buffer::from_iter(
@@ -58,8 +69,8 @@ impl GpuRenderData {
                     memory_type_filter: MemoryTypeFilter::PREFER_DEVICE | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
                     ..Default::default()
                 },
-                initial_positions.iter().map(|p| PositionVertex {
-                    position: [p[0], p[1], p[2], 1.0]
+                (0..particle_count).map(|i| PositionVertex {
+                    position: initial_positions.get(i).map_or([0.0; 4], |p| [p[0], p[1], p[2], 1.0])
                 }),
             ).expect("Failed to create render position buffer");
             position_buffers.push(buffer);
@@ -79,6 +90,25 @@ impl GpuRenderData {
                 }),
             ).expect("Failed to create render color buffer");
             color_buffers.push(color_buffer);
+
+            let draw_command = Buffer::from_iter(
+                allocator.clone(),
+                BufferCreateInfo {
+                    usage: BufferUsage::INDIRECT_BUFFER | BufferUsage::TRANSFER_DST,
+                    ..Default::default()
+                },
+                AllocationCreateInfo {
+                    memory_type_filter: MemoryTypeFilter::PREFER_DEVICE | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                    ..Default::default()
+                },
+                [DrawIndirectCommand {
+                    vertex_count: initial_positions.len() as u32,
+                    instance_count: 1,
+                    first_vertex: 0,
+                    first_instance: 0,
+                }],
+            ).expect("Failed to create render draw command buffer");
+            draw_commands.push(draw_command);
         }
 
         let attribute_buffer = Buffer::from_iter(
@@ -96,7 +126,7 @@ impl GpuRenderData {
             }),
         ).expect("Failed to create attribute buffer");
 
-        Self { position_buffers, attribute_buffer, color_buffers }
+        Self { position_buffers, attribute_buffer, color_buffers, draw_commands }
     }
     pub fn bind_to_command_buffer<Cb>(
         &self,
@@ -104,7 +134,6 @@ impl GpuRenderData {
         pipelines: &Pipelines,
         camera_addr: u64,
         frame_idx: usize,
-        count: u32,
     ) {
 
         unsafe {
@@ -121,7 +150,7 @@ impl GpuRenderData {
                     0,
                     camera_addr,
                 ).unwrap()
-                .draw(count, 1, 0, 0)
+                .draw_indirect(self.draw_commands[frame_idx].clone())
                 .expect("Failed to bind particle vertex buffers");
         }
     }
@@ -134,8 +163,222 @@ pub struct Entry {
     pub index: u32,
 }
 
-pub struct GpuPhysicsData {
+/// One of the boundary particles' dense cell grids; mirrors `BoundaryGrid`
+/// in boundary.glsl. Built by `BoundaryParticles::grid_header`.
+#[derive(BufferContents, Copy, Clone, Debug, Default, PartialEq)]
+#[repr(C)]
+pub struct GpuBoundaryGrid {
+    /// First cell of the grid; `w` is where its cells start in
+    /// `boundary_cell_ranges`.
+    pub origin: [i32; 4],
+    /// Size in cells; an empty grid has none.
+    pub dims: [i32; 4],
+    /// The layer the walls were sampled as, in the box frame; `w` of `lo` is
+    /// the cell size.
+    pub lo: [f32; 4],
+    pub hi: [f32; 4],
+}
+
+/// Live particle count and the indirect arguments derived from it; mirrors
+/// `ParticleCounter` in common.glsl. `emit.comp` appends through `count`,
+/// `sink.comp` tallies `removed`, and `particle_count.comp` folds both in
+/// after the sort and rewrites `dispatch` / `draw`.
+#[derive(BufferContents, Copy, Clone, Debug)]
+#[repr(C)]
+pub struct ParticleCounter {
     pub count: u32,
+    pub removed: u32,
+    pub dispatch: [u32; 3],
+    pub draw: [u32; 4],
+}
+
+impl ParticleCounter {
+    pub fn new(count: u32) -> Self {
+        Self {
+            count,
+            removed: 0,
+            dispatch: [count.div_ceil(256), 1, 1],
+            draw: [count, 1, 0, 0],
+        }
+    }
+}
+
+/// Scalars of the implicit viscosity solve; mirrors `ViscositySolver` in
+/// viscosity_solver.glsl. Written by `viscosity_cg_reduce.comp` between the
+/// per-particle passes, which stop doing work once `converged` is set.
+#[derive(BufferContents, Copy, Clone, Debug, Default, PartialEq)]
+#[repr(C)]
+pub struct ViscositySolverState {
+    /// rᵀz of the current residual r and its preconditioned z.
+    pub rz: f32,
+    /// Step length along the search direction and its update factor.
+    pub alpha: f32,
+    pub beta: f32,
+    /// Squared norms of the right-hand side and of the current residual.
+    pub rhs_norm2: f32,
+    pub residual_norm2: f32,
+    pub iterations: u32,
+    pub converged: u32,
+    pub _pad: u32,
+}
+
+impl ViscositySolverState {
+    /// Residual norm relative to the right-hand side's, the quantity compared
+    /// against `viscosity_tolerance`.
+    pub fn relative_residual(&self) -> f32 {
+        if self.rhs_norm2 > 0.0 { (self.residual_norm2 / self.rhs_norm2).sqrt() } else { 0.0 }
+    }
+}
+
+/// Which DFSPH solve `pressure_convergence.comp` is checking; `SOLVE_*` in
+/// pressure_solver.glsl.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PressureSolve {
+    Density = 0,
+    Divergence = 1,
+}
+
+impl PressureSolve {
+    /// Iterations run before the error is looked at: the density solve needs
+    /// two to settle the pressure accelerations (Bender & Koschier 2015).
+    pub fn min_iterations(self) -> u32 {
+        match self {
+            PressureSolve::Density => 2,
+            PressureSolve::Divergence => 1,
+        }
+    }
+
+    /// η or η_v in kg/m³ (per second for η_v).
+    pub fn max_error(self, params: &SimulationParams) -> f32 {
+        let fraction = match self {
+            PressureSolve::Density => params.max_density_error,
+            PressureSolve::Divergence => params.max_divergence_error,
+        };
+        fraction * params.target_density
+    }
+}
+
+/// Progress of the DFSPH density and divergence solves; mirrors
+/// `PressureSolver` in pressure_solver.glsl. `pressure_convergence.comp`
+/// checks the average error after every `pressure_update.comp` and zeroes
+/// `dispatch` once it is below η, which skips the remaining iterations.
+#[derive(BufferContents, Copy, Clone, Debug, Default, PartialEq)]
+#[repr(C)]
+pub struct PressureSolverState {
+    /// Indirect arguments of the iterations' pressure_force and
+    /// pressure_update passes.
+    pub dispatch: [u32; 3],
+    /// `PressureSolve` as u32 of the solve in progress.
+    pub solve: u32,
+    pub converged: u32,
+    /// Iterations the last solve of each kind ran and the average error it
+    /// stopped at, in kg/m³ (per second for the divergence).
+    pub density_iterations: u32,
+    pub divergence_iterations: u32,
+    pub density_error: f32,
+    pub divergence_error: f32,
+}
+
+impl PressureSolverState {
+    /// Starts a solve of the kind `solve`, as `CHECK_BEGIN` does.
+    pub fn begin(&mut self, solve: PressureSolve) {
+        self.solve = solve as u32;
+        self.converged = 0;
+        match solve {
+            PressureSolve::Density => self.density_iterations = 0,
+            PressureSolve::Divergence => self.divergence_iterations = 0,
+        }
+    }
+
+    /// Counts an iteration whose pressure_update.comp summed `residual` over
+    /// `count` particles, as `CHECK_ITERATION` does. Returns whether the
+    /// solve has converged.
+    pub fn check(&mut self, params: &SimulationParams, residual: f32, count: u32) -> bool {
+        if self.converged != 0 {
+            return true;
+        }
+        let solve = if self.solve == PressureSolve::Density as u32 { PressureSolve::Density } else { PressureSolve::Divergence };
+        let mut error = residual / count.max(1) as f32;
+        let iterations = match solve {
+            PressureSolve::Density => {
+                // The density residual is a rate; dt turns it into kg/m³.
+                error *= params.dt;
+                self.density_error = error;
+                self.density_iterations += 1;
+                self.density_iterations
+            }
+            PressureSolve::Divergence => {
+                self.divergence_error = error;
+                self.divergence_iterations += 1;
+                self.divergence_iterations
+            }
+        };
+        self.converged = u32::from(iterations >= solve.min_iterations() && error < solve.max_error(params));
+        self.converged != 0
+    }
+
+    /// Density and divergence iterations of the last substep, for the stats:
+    /// as counted for the solves that check their error, the fixed counts of
+    /// the other iterative solvers, 0 where a solver has no such solve.
+    pub fn iterations(&self, params: &SimulationParams) -> (u32, u32) {
+        match params.pressure_solver() {
+            PressureSolver::Dfsph => (self.density_iterations, self.divergence_iterations),
+            PressureSolver::Pcisph | PressureSolver::Iisph | PressureSolver::Pbf => (params.density_solver_iterations, 0),
+            PressureSolver::Wcsph => (0, 0),
+        }
+    }
+}
+
+/// MAC grid buffers of the FLIP backend, sized for a grid of `dims` cells or
+/// a smaller one; see flip.glsl for the layout.
+pub struct FlipGrid {
+    pub dims: IVec3,
+    /// Per node: fixed-point momentum xyz, pad, weight xyz, pad, summed by
+    /// `flip_p2g.comp`.
+    pub transfers: Subbuffer<[i32]>,
+    /// Per node: the face velocities, after the projection and as transferred.
+    pub velocities: Subbuffer<[[f32; 4]]>,
+    pub old_velocities: Subbuffer<[[f32; 4]]>,
+    /// Per cell: `CELL_*`, the velocity divergence and two halves of the
+    /// pressure for the Jacobi sweeps to alternate between.
+    pub cells: Subbuffer<[u32]>,
+    pub divergence: Subbuffer<[f32]>,
+    pub pressures: Subbuffer<[f32]>,
+}
+
+impl FlipGrid {
+    pub fn cell_count(dims: IVec3) -> u64 {
+        (dims.x * dims.y * dims.z) as u64
+    }
+    pub fn node_count(dims: IVec3) -> u64 {
+        Self::cell_count(dims + IVec3::ONE)
+    }
+
+    fn new(allocator: Arc<StandardMemoryAllocator>, dims: IVec3) -> Self {
+        let usage = BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_SRC | BufferUsage::TRANSFER_DST;
+        let nodes = Self::node_count(dims);
+        let cells = Self::cell_count(dims);
+        Self {
+            dims,
+            transfers: GpuPhysicsData::create_buffer(usage, allocator.clone(), 8 * nodes),
+            velocities: GpuPhysicsData::create_buffer(usage, allocator.clone(), nodes),
+            old_velocities: GpuPhysicsData::create_buffer(usage, allocator.clone(), nodes),
+            cells: GpuPhysicsData::create_buffer(usage, allocator.clone(), cells),
+            divergence: GpuPhysicsData::create_buffer(usage, allocator.clone(), cells),
+            pressures: GpuPhysicsData::create_buffer(usage, allocator, 2 * cells),
+        }
+    }
+
+    fn fits(&self, dims: IVec3) -> bool {
+        Self::node_count(dims) <= self.velocities.len() && Self::cell_count(dims) <= self.cells.len()
+    }
+}
+
+pub struct GpuPhysicsData {
+    /// Number of particles every per-particle buffer has room for. The live
+    /// count is only known on the GPU, in `particle_counter`.
+    pub capacity: u32,
+    pub particle_counter: Subbuffer<[ParticleCounter]>,
 
     pub position_a: Subbuffer<[[f32; 4]]>,
     pub position_b: Subbuffer<[[f32; 4]]>,
@@ -152,24 +395,85 @@ pub struct GpuPhysicsData {
     pub pressures: Subbuffer<[f32]>,
     pub pressure_accelerations: Subbuffer<[[f32; 4]]>,
 
+    /// Akinci surface normals, written by `surface_normals.comp`.
+    pub normals: Subbuffer<[[f32; 4]]>,
+    /// Velocity curl, written by `vorticity.comp`.
+    pub vorticities: Subbuffer<[[f32; 4]]>,
+    /// Positions after the substep under the current pressure accelerations,
+    /// written by `predict_positions.comp` for PCISPH and PBF.
+    pub predicted_positions: Subbuffer<[[f32; 4]]>,
+    /// IISPH's d_ii in xyz and a_ii in w, written by `iisph_diagonal.comp`.
+    pub iisph_diagonals: Subbuffer<[[f32; 4]]>,
+    /// Σ_j d_ij p_j in xyz and the pressure p_i it was taken with in w,
+    /// written by `iisph_pressure_sum.comp` every iteration.
+    pub iisph_pressure_sums: Subbuffer<[[f32; 4]]>,
+    /// APIC velocity gradients of the FLIP backend, three box-frame rows per
+    /// particle. `flip_g2p.comp` writes the a side, the reorder permutes it
+    /// into b for the next `flip_p2g.comp`.
+    pub affine_a: Subbuffer<[[f32; 4]]>,
+    pub affine_b: Subbuffer<[[f32; 4]]>,
+    /// Replaced by `reserve_flip_grid`.
+    pub flip_grid: FlipGrid,
+
+    /// Conjugate gradient vectors of the implicit viscosity solve: residual,
+    /// search direction, the operator applied to it and the Jacobi
+    /// preconditioner. The solution is `velocity_a` itself.
+    pub viscosity_residuals: Subbuffer<[[f32; 4]]>,
+    pub viscosity_directions: Subbuffer<[[f32; 4]]>,
+    pub viscosity_products: Subbuffer<[[f32; 4]]>,
+    pub viscosity_diagonals: Subbuffer<[[f32; 4]]>,
+    /// One vec4 of dot-product partial sums per 256-wide workgroup.
+    pub viscosity_partials: Subbuffer<[[f32; 4]]>,
+    /// Host-visible so the UI can show the iterations the last solve took.
+    pub viscosity_solver: Subbuffer<[ViscositySolverState]>,
+    /// One partial sum of the compression residual per 256-wide workgroup of
+    /// `pressure_update.comp`.
+    pub pressure_partials: Subbuffer<[f32]>,
+    /// Indirect arguments of the DFSPH iterations and the iterations the last
+    /// solves took; host-visible for the UI.
+    pub pressure_solver: Subbuffer<[PressureSolverState]>,
 
     pub grid_entries: Subbuffer<[Entry]>,
     pub grid_start: Subbuffer<[u32]>,
 
+    /// Akinci boundary particles (position + ψ), their per-cell ranges and the
+    /// two grids' headers, as built by `BoundaryParticles`. Replaced by
+    /// `set_boundary`.
+    pub boundary_particles: Subbuffer<[[f32; 4]]>,
+    pub boundary_cell_ranges: Subbuffer<[[u32; 2]]>,
+    pub boundary_grid: Subbuffer<[GpuBoundaryGrid]>,
+
+    /// Obstacles and the concatenated values of their mesh SDFs, packed by
+    /// `gpu_obstacles`. Poses are rewritten in place by `write_obstacles`; the
+    /// buffers are replaced by `set_obstacles`.
+    pub obstacles: Subbuffer<[GpuObstacle]>,
+    pub obstacle_sdf: Subbuffer<[f32]>,
+    /// Eight fixed-point sums per obstacle, the linear and angular impulse
+    /// the fluid handed it during the last step. Host-visible so the CPU can
+    /// read it next frame; replaced by `set_obstacles`.
+    pub obstacle_impulses: Subbuffer<[i32]>,
+    /// Sinks, packed like the obstacles. Replaced by `set_sinks`.
+    pub sinks: Subbuffer<[GpuObstacle]>,
+    pub sink_sdf: Subbuffer<[f32]>,
+
     // Single u32: max_speed_bits (IEEE 754 trick). Host-visible so CPU can read it next frame.
     pub stats_buffer: Subbuffer<[u32]>,
 }
 
 impl GpuPhysicsData {
+    /// Allocates every per-particle buffer for `capacity` particles and
+    /// uploads `initial_positions` into the front of them, tagged with
+    /// `initial_phases` (missing entries are phase 0).
     pub fn new(
         allocator: Arc<StandardMemoryAllocator>,
         initial_positions: Vec<[f32; 3]>,
+        initial_phases: &[u32],
+        capacity: u32,
+        boundary: &BoundaryParticles,
     ) -> Self {
-        let count = initial_positions.len() as u32;
+        assert!(initial_positions.len() as u32 <= capacity, "more initial particles than capacity");
 
-        let positions_vec4: Vec<[f32; 4]> = initial_positions.iter()
-            .map(|p| [p[0], p[1], p[2], 1.0])
-            .collect();
+        let positions_vec4: Vec<[f32; 4]> = Self::tagged_positions(&initial_positions, initial_phases, capacity).collect();
 
         let position_a = Buffer::from_iter(
             allocator.clone(),
@@ -187,7 +491,7 @@ impl GpuPhysicsData {
         let position_b = Self::create_buffer(
             BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_SRC | BufferUsage::TRANSFER_DST,
             allocator.clone(),
-            count as u64
+            capacity as u64
         );
 
         let velocity_a = Buffer::from_iter(
@@ -200,68 +504,162 @@ impl GpuPhysicsData {
                 memory_type_filter: MemoryTypeFilter::PREFER_DEVICE | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
                 ..Default::default()
             },
-            (0..count).map(|_| [0.0, 0.0, 0.0, 0.0]),
+            (0..capacity).map(|_| [0.0, 0.0, 0.0, 0.0]),
         ).expect("Failed to create velocity buffer");
 
         let velocity_b = Self::create_buffer(
             BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_SRC | BufferUsage::TRANSFER_DST,
             allocator.clone(),
-            count as u64
+            capacity as u64
         );
 
         let pressures = Buffer::from_iter(
             allocator.clone(),
             BufferCreateInfo {
-                usage: BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_SRC,
+                usage: BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_SRC | BufferUsage::TRANSFER_DST,
                 ..Default::default()
             },
             AllocationCreateInfo {
                 memory_type_filter: MemoryTypeFilter::PREFER_DEVICE | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
                 ..Default::default()
             },
-            (0..count).map(|_| 0.0f32),
+            (0..capacity).map(|_| 0.0f32),
         ).expect("Failed to create pressure buffer");
 
+        let particle_counter = Buffer::from_iter(
+            allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER | BufferUsage::INDIRECT_BUFFER
+                    | BufferUsage::TRANSFER_SRC | BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            [ParticleCounter::new(initial_positions.len() as u32)],
+        ).expect("Failed to create particle counter buffer");
+
         let pressure_accelerations = Self::create_buffer::<[f32; 4]>(
-            BufferUsage::STORAGE_BUFFER,
+            BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_SRC | BufferUsage::TRANSFER_DST,
+            allocator.clone(),
+            capacity as u64
+        );
+
+        let normals = Self::create_buffer::<[f32; 4]>(
+            BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_SRC | BufferUsage::TRANSFER_DST,
+            allocator.clone(),
+            capacity as u64
+        );
+
+        let vorticities = Self::create_buffer::<[f32; 4]>(
+            BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_SRC | BufferUsage::TRANSFER_DST,
+            allocator.clone(),
+            capacity as u64
+        );
+
+        let predicted_positions = Self::create_buffer::<[f32; 4]>(
+            BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_SRC | BufferUsage::TRANSFER_DST,
+            allocator.clone(),
+            capacity as u64
+        );
+
+        let [iisph_diagonals, iisph_pressure_sums] = std::array::from_fn(|_| {
+            Self::create_buffer::<[f32; 4]>(
+                BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_SRC | BufferUsage::TRANSFER_DST,
+                allocator.clone(),
+                capacity as u64
+            )
+        });
+
+        let [affine_a, affine_b] = std::array::from_fn(|_| {
+            Self::create_buffer::<[f32; 4]>(
+                BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_SRC | BufferUsage::TRANSFER_DST,
+                allocator.clone(),
+                3 * capacity as u64
+            )
+        });
+
+        let [viscosity_residuals, viscosity_directions, viscosity_products, viscosity_diagonals] = std::array::from_fn(|_| {
+            Self::create_buffer::<[f32; 4]>(
+                BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_SRC | BufferUsage::TRANSFER_DST,
+                allocator.clone(),
+                capacity as u64
+            )
+        });
+
+        let viscosity_partials = Self::create_buffer::<[f32; 4]>(
+            BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_SRC | BufferUsage::TRANSFER_DST,
+            allocator.clone(),
+            capacity.div_ceil(256) as u64
+        );
+
+        let viscosity_solver = Buffer::from_iter(
+            allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..Default::default()
+            },
+            [ViscositySolverState::default()],
+        ).expect("Failed to create viscosity solver buffer");
+
+        let pressure_partials = Self::create_buffer::<f32>(
+            BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_SRC | BufferUsage::TRANSFER_DST,
             allocator.clone(),
-            count as u64
+            capacity.div_ceil(256) as u64
         );
 
+        let pressure_solver = Buffer::from_iter(
+            allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER | BufferUsage::INDIRECT_BUFFER | BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..Default::default()
+            },
+            [PressureSolverState::default()],
+        ).expect("Failed to create pressure solver buffer");
+
         let colors = Self::create_buffer(
             BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_SRC | BufferUsage::TRANSFER_DST,
             allocator.clone(),
-            count as u64
+            capacity as u64
         );
 
         let densities = Self::create_buffer::<f32>(
-            BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_SRC,
+            BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_SRC | BufferUsage::TRANSFER_DST,
             allocator.clone(),
-            count as u64
+            capacity as u64
         );
 
         let factors = Self::create_buffer::<f32>(
-            BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_SRC,
+            BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_SRC | BufferUsage::TRANSFER_DST,
             allocator.clone(),
-            count as u64
+            capacity as u64
         );
 
         let source_terms = Self::create_buffer::<f32>(
-            BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_SRC,
+            BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_SRC | BufferUsage::TRANSFER_DST,
             allocator.clone(),
-            count as u64
+            capacity as u64
         );
 
-        let sort_buffer_size = count.next_power_of_two();
+        let sort_buffer_size = capacity.next_power_of_two();
 
         let grid_entries = Self::create_buffer::<Entry>(
-            BufferUsage::STORAGE_BUFFER,
+            BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_SRC | BufferUsage::TRANSFER_DST,
             allocator.clone(),
             sort_buffer_size as u64
         );
 
         let grid_start = Self::create_buffer::<u32>(
-            BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_DST,
+            BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_SRC | BufferUsage::TRANSFER_DST,
             allocator.clone(),
             sort_buffer_size as u64
         );
@@ -276,12 +674,16 @@ impl GpuPhysicsData {
                 memory_type_filter: MemoryTypeFilter::PREFER_HOST | MemoryTypeFilter::HOST_RANDOM_ACCESS,
                 ..Default::default()
             },
-            // [0]: max_speed_bits, [1]: density_error_fp, [2]: divergence_error_fp
-            [0u32, 0u32, 0u32].into_iter(),
+            // [0]: max_speed_bits, [1]: density_error_fp, [2]: divergence_error_fp, [3]: particle count
+            [0u32, 0u32, 0u32, 0u32].into_iter(),
         ).expect("Failed to create stats buffer");
 
+        let (obstacles, obstacle_sdf) = gpu_obstacles(&[]);
+        let (sinks, sink_sdf) = gpu_obstacles(&[]);
+
         Self {
-            count,
+            capacity,
+            particle_counter,
             position_a,
             position_b,
             velocity_a,
@@ -292,11 +694,203 @@ impl GpuPhysicsData {
             source_terms,
             pressures,
             pressure_accelerations,
+            normals,
+            vorticities,
+            predicted_positions,
+            iisph_diagonals,
+            iisph_pressure_sums,
+            affine_a,
+            affine_b,
+            flip_grid: FlipGrid::new(allocator.clone(), IVec3::ONE),
+            viscosity_residuals,
+            viscosity_directions,
+            viscosity_products,
+            viscosity_diagonals,
+            viscosity_partials,
+            viscosity_solver,
+            pressure_partials,
+            pressure_solver,
             grid_entries,
             grid_start,
+            boundary_particles: Self::upload_buffer(allocator.clone(), boundary.particles.iter().copied()),
+            boundary_cell_ranges: Self::upload_buffer(allocator.clone(), boundary.cell_ranges.iter().copied()),
+            boundary_grid: Self::upload_buffer(allocator.clone(), boundary.grid_header()),
+            obstacles: Self::upload_buffer(allocator.clone(), obstacles),
+            obstacle_sdf: Self::upload_buffer(allocator.clone(), obstacle_sdf),
+            obstacle_impulses: Self::impulse_buffer(allocator.clone(), 0),
+            sinks: Self::upload_buffer(allocator.clone(), sinks),
+            sink_sdf: Self::upload_buffer(allocator, sink_sdf),
             stats_buffer,
         }
     }
+    /// Swaps in freshly uploaded boundary buffers. Frames still in flight keep
+    /// the old ones alive; descriptor sets have to be prepared again.
+    pub fn set_boundary(&mut self, allocator: Arc<StandardMemoryAllocator>, boundary: &BoundaryParticles) {
+        self.boundary_particles = Self::upload_buffer(allocator.clone(), boundary.particles.iter().copied());
+        self.boundary_cell_ranges = Self::upload_buffer(allocator.clone(), boundary.cell_ranges.iter().copied());
+        self.boundary_grid = Self::upload_buffer(allocator, boundary.grid_header());
+    }
+    /// Makes room for a FLIP grid of `dims` cells. Returns whether the grid
+    /// buffers were replaced, in which case descriptor sets have to be
+    /// prepared again; smaller grids reuse the buffers.
+    pub fn reserve_flip_grid(&mut self, allocator: Arc<StandardMemoryAllocator>, dims: IVec3) -> bool {
+        if self.flip_grid.fits(dims) {
+            self.flip_grid.dims = dims;
+            return false;
+        }
+        self.flip_grid = FlipGrid::new(allocator, dims);
+        true
+    }
+    /// Swaps in freshly uploaded obstacle buffers; descriptor sets have to be
+    /// prepared again.
+    pub fn set_obstacles(&mut self, allocator: Arc<StandardMemoryAllocator>, obstacles: &[Obstacle]) {
+        let (packed, sdf_values) = gpu_obstacles(obstacles);
+        self.obstacles = Self::upload_buffer(allocator.clone(), packed);
+        self.obstacle_sdf = Self::upload_buffer(allocator.clone(), sdf_values);
+        self.obstacle_impulses = Self::impulse_buffer(allocator, obstacles.len());
+    }
+    /// Swaps in freshly uploaded sink buffers; descriptor sets have to be
+    /// prepared again.
+    pub fn set_sinks(&mut self, allocator: Arc<StandardMemoryAllocator>, sinks: &[Obstacle]) {
+        let (packed, sdf_values) = gpu_obstacles(sinks);
+        self.sinks = Self::upload_buffer(allocator.clone(), packed);
+        self.sink_sdf = Self::upload_buffer(allocator, sdf_values);
+    }
+    /// The `dispatch` arguments of `particle_counter`: one 256-wide workgroup
+    /// per live particle, for `dispatch_indirect`.
+    pub fn dispatch_command(&self) -> Subbuffer<[DispatchIndirectCommand]> {
+        Self::counter_field(&self.particle_counter, std::mem::offset_of!(ParticleCounter, dispatch), 12)
+    }
+    /// The `draw` arguments of `particle_counter`: one point per live
+    /// particle, for `draw_indirect`.
+    pub fn draw_command(&self) -> Subbuffer<[DrawIndirectCommand]> {
+        Self::counter_field(&self.particle_counter, std::mem::offset_of!(ParticleCounter, draw), 16)
+    }
+    /// The `dispatch` arguments of `pressure_solver`: the particle workgroups
+    /// until the solve in progress converges, then none.
+    pub fn pressure_solver_dispatch_command(&self) -> Subbuffer<[DispatchIndirectCommand]> {
+        let offset = std::mem::offset_of!(PressureSolverState, dispatch) as u64;
+        self.pressure_solver.as_bytes().clone().slice(offset..offset + 12).reinterpret()
+    }
+    fn counter_field<T: BufferContents + ?Sized>(counter: &Subbuffer<[ParticleCounter]>, offset: usize, size: usize) -> Subbuffer<T> {
+        counter.as_bytes().clone().slice(offset as u64..(offset + size) as u64).reinterpret()
+    }
+    /// Rewrites the obstacle poses in place. Only valid while `obstacles` has
+    /// the layout the buffers were created with (`Obstacle::same_layout`).
+    pub fn write_obstacles(&self, obstacles: &[Obstacle]) {
+        let (packed, _) = gpu_obstacles(obstacles);
+        if let Ok(mut gpu_obstacles) = self.obstacles.write() {
+            gpu_obstacles.copy_from_slice(&packed);
+        }
+    }
+    /// Records a reset to `initial_positions`, tagged with `initial_phases`:
+    /// both position buffers are re-uploaded, the live count set back to their
+    /// number and velocities, pressures, pressure accelerations and APIC
+    /// gradients zeroed.
+    /// Densities and factors are left stale for the next neighbor search /
+    /// `density_alpha` pass to recompute.
+    pub fn record_reset<Cb>(
+        &self,
+        allocator: Arc<StandardMemoryAllocator>,
+        builder: &mut AutoCommandBufferBuilder<Cb>,
+        initial_positions: &[[f32; 3]],
+        initial_phases: &[u32],
+    ) {
+        assert!(initial_positions.len() as u32 <= self.capacity, "reset needs more particles than the capacity");
+
+        let staging = Buffer::from_iter(
+            allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            Self::tagged_positions(initial_positions, initial_phases, self.capacity),
+        ).expect("Failed to create reset staging buffer");
+
+        builder.copy_buffer(CopyBufferInfo::buffers(staging.clone(), self.position_a.clone())).unwrap();
+        builder.copy_buffer(CopyBufferInfo::buffers(staging, self.position_b.clone())).unwrap();
+        self.record_set_count(allocator, builder, initial_positions.len() as u32);
+
+        builder.fill_buffer(self.velocity_a.clone().reinterpret::<[u32]>(), 0).unwrap();
+        builder.fill_buffer(self.velocity_b.clone().reinterpret::<[u32]>(), 0).unwrap();
+        builder.fill_buffer(self.pressures.clone().reinterpret::<[u32]>(), 0).unwrap();
+        builder.fill_buffer(self.pressure_accelerations.clone().reinterpret::<[u32]>(), 0).unwrap();
+        self.record_clear_affine(builder);
+    }
+    /// Records a reset of the APIC velocity gradients, which go stale while
+    /// another backend moves the particles.
+    pub fn record_clear_affine<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>) {
+        builder.fill_buffer(self.affine_a.clone().reinterpret::<[u32]>(), 0).unwrap();
+        builder.fill_buffer(self.affine_b.clone().reinterpret::<[u32]>(), 0).unwrap();
+    }
+    /// Records an overwrite of the live count (and the indirect arguments
+    /// derived from it) with `count`.
+    pub fn record_set_count<Cb>(
+        &self,
+        allocator: Arc<StandardMemoryAllocator>,
+        builder: &mut AutoCommandBufferBuilder<Cb>,
+        count: u32,
+    ) {
+        let staging = Buffer::from_iter(
+            allocator,
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            [ParticleCounter::new(count)],
+        ).expect("Failed to create counter staging buffer");
+        builder.copy_buffer(CopyBufferInfo::buffers(staging, self.particle_counter.clone())).unwrap();
+    }
+    pub(crate) fn upload_buffer<T, I>(allocator: Arc<StandardMemoryAllocator>, data: I) -> Subbuffer<[T]>
+    where
+        T: BufferContents,
+        I: IntoIterator<Item = T>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        Buffer::from_iter(
+            allocator,
+            BufferCreateInfo { usage: BufferUsage::STORAGE_BUFFER, ..Default::default() },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            data,
+        ).expect("Failed to create storage buffer")
+    }
+    fn impulse_buffer(allocator: Arc<StandardMemoryAllocator>, obstacle_count: usize) -> Subbuffer<[i32]> {
+        Buffer::from_iter(
+            allocator,
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..Default::default()
+            },
+            std::iter::repeat_n(0, 8 * obstacle_count.max(1)),
+        ).expect("Failed to create obstacle impulse buffer")
+    }
+    /// `initial_positions` as `position_a` holds them, padded to `capacity`.
+    fn tagged_positions<'a>(
+        initial_positions: &'a [[f32; 3]],
+        initial_phases: &'a [u32],
+        capacity: u32,
+    ) -> impl ExactSizeIterator<Item = [f32; 4]> + 'a {
+        (0..capacity as usize).map(|i| {
+            initial_positions.get(i).map_or([0.0; 4], |p| {
+                [p[0], p[1], p[2], phase_tag(initial_phases.get(i).copied().unwrap_or(0))]
+            })
+        })
+    }
     fn create_buffer<T>(usage: BufferUsage, allocator: Arc<StandardMemoryAllocator>, count: u64) -> Subbuffer<[T]> where T: BufferContents {
         Buffer::new_slice::<T>(
             allocator.clone(),
@@ -307,6 +901,239 @@ impl GpuPhysicsData {
     }
 }
 
+/// `position.w` of a live particle of `phase`. Sinks mark dead particles with
+/// `w = 0`, so the phase is stored off by one; `particle_phase` in
+/// common.glsl reads it back.
+pub fn phase_tag(phase: u32) -> f32 {
+    phase as f32 + 1.0
+}
+
+/// Inverse of `phase_tag`.
+pub fn particle_phase(w: f32) -> u32 {
+    (w - 1.0).max(0.0).round() as u32
+}
+
+/// Number of rows in the phase table, the base fluid included; `MAX_PHASES`
+/// in common.glsl.
+pub const MAX_PHASES: usize = 8;
+
+/// Colour the base fluid is drawn with unless the scene picks another.
+pub const DEFAULT_FLUID_COLOR: [f32; 3] = [0.2, 0.5, 1.0];
+
+/// One row of the phase table; `Phase` in common.glsl. Row 0 is the base
+/// fluid, whose rest density, viscosity and surface tension are the global
+/// ones in `SimulationParams` so the solver sliders keep applying to it; only
+/// its colour is read from the table. Unused rows have a rest density of 0.
+#[repr(C)]
+#[derive(BufferContents, Copy, Clone, Debug, Default, PartialEq)]
+pub struct GpuPhase {
+    /// RGB the particles are drawn with; alpha unused.
+    pub color: [f32; 4],
+    pub rest_density: f32,
+    pub viscosity: f32,
+    pub surface_tension: f32,
+    pub _pad: f32,
+}
+
+impl GpuPhase {
+    pub fn new(color: [f32; 3], rest_density: f32, viscosity: f32, surface_tension: f32) -> Self {
+        Self {
+            color: [color[0], color[1], color[2], 1.0],
+            rest_density,
+            viscosity,
+            surface_tension,
+            _pad: 0.0,
+        }
+    }
+}
+
+/// Smoothing kernel pair used by every solver pass, on the GPU and the CPU;
+/// `KERNEL_*` in common.glsl. All of them have compact support `h`
+/// (`smoothing_radius`).
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SphKernel {
+    /// Cubic B-spline (Monaghan 1992), for both W and ∇W.
+    #[default]
+    CubicSpline = 0,
+    /// Wendland C2 (Dehnen & Aly 2012): immune to the pairing instability.
+    WendlandC2 = 1,
+    /// Wendland C4: smoother and wider than C2 for the same `h`.
+    WendlandC4 = 2,
+    /// Poly6 for W and the Spiky gradient for ∇W (Müller et al. 2003).
+    Poly6Spiky = 3,
+}
+
+impl SphKernel {
+    pub const ALL: [SphKernel; 4] = [Self::CubicSpline, Self::WendlandC2, Self::WendlandC4, Self::Poly6Spiky];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::CubicSpline => "Cubic Spline",
+            Self::WendlandC2 => "Wendland C2",
+            Self::WendlandC4 => "Wendland C4",
+            Self::Poly6Spiky => "Poly6 / Spiky",
+        }
+    }
+
+    /// Inverse of `as u32`; unknown values fall back to the cubic spline.
+    pub fn from_index(index: u32) -> Self {
+        Self::ALL.get(index as usize).copied().unwrap_or_default()
+    }
+}
+
+/// How `viscosity` and the phases' viscosities are applied; `VISCOSITY_*` in
+/// common.glsl.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ViscosityMode {
+    /// XSPH-style velocity blend in viscosity.comp. The viscosity is a
+    /// dimensionless blend factor and goes unstable above about 0.5.
+    #[default]
+    Explicit = 0,
+    /// Implicit solve of Weiler et al. 2018 by matrix-free conjugate
+    /// gradient. The viscosity is kinematic [m²/s] and may be arbitrarily
+    /// high (honey, lava).
+    Implicit = 1,
+}
+
+impl ViscosityMode {
+    pub const ALL: [ViscosityMode; 2] = [Self::Explicit, Self::Implicit];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Explicit => "Explicit (XSPH)",
+            Self::Implicit => "Implicit (Weiler 2018)",
+        }
+    }
+
+    /// Inverse of `as u32`; unknown values fall back to explicit.
+    pub fn from_index(index: u32) -> Self {
+        Self::ALL.get(index as usize).copied().unwrap_or_default()
+    }
+}
+
+/// Pressure solver of the substep, which picks the passes `Simulation`
+/// records. Every solver leaves its pressure accelerations for
+/// `pressure_integration`, and the density and divergence errors in the stats
+/// are measured the same way for all of them.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PressureSolver {
+    /// Divergence-free SPH (Bender & Koschier 2015): a density solve before
+    /// and a divergence solve after the position update.
+    #[default]
+    Dfsph = 0,
+    /// Weakly compressible SPH with the Tait equation (Becker & Teschner
+    /// 2007). No iterations; `stiffness` trades compression against the time
+    /// step it needs.
+    Wcsph = 1,
+    /// Predictive-corrective incompressible SPH (Solenthaler & Pajarola 2009).
+    /// Its correction factor is that of a neighborhood at rest density, so
+    /// strongly compressed fluid makes it overshoot.
+    Pcisph = 2,
+    /// Implicit incompressible SPH (Ihmsen et al. 2014): relaxed Jacobi on the
+    /// pressure Poisson equation, without a divergence solve.
+    Iisph = 3,
+    /// Position based fluids (Macklin & Müller 2013): density constraints on
+    /// the predicted positions.
+    Pbf = 4,
+}
+
+impl PressureSolver {
+    pub const ALL: [PressureSolver; 5] = [Self::Dfsph, Self::Wcsph, Self::Pcisph, Self::Iisph, Self::Pbf];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Dfsph => "DFSPH",
+            Self::Wcsph => "WCSPH",
+            Self::Pcisph => "PCISPH",
+            Self::Iisph => "IISPH",
+            Self::Pbf => "PBF",
+        }
+    }
+
+    /// Inverse of `as u32`; unknown values fall back to DFSPH.
+    pub fn from_index(index: u32) -> Self {
+        Self::ALL.get(index as usize).copied().unwrap_or_default()
+    }
+
+    /// Whether `density_solver_iterations` applies; WCSPH evaluates its
+    /// equation of state once per substep.
+    pub fn is_iterative(self) -> bool {
+        self != Self::Wcsph
+    }
+}
+
+/// What advances the particles past the non-pressure forces; picks the passes
+/// `Simulation` records. Both keep the particles in `GpuPhysicsData`, so the
+/// renderers do not care which one ran.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SimulationBackend {
+    /// The SPH `PressureSolver` on the particles themselves.
+    #[default]
+    Sph = 0,
+    /// Hybrid particle-grid solver (Zhu & Bridson 2005): the velocities are
+    /// transferred to a MAC grid, made divergence-free there by Jacobi
+    /// iterations and transferred back as `grid_transfer` says. Obstacles
+    /// only act on the particles, not on the projection.
+    Flip = 1,
+}
+
+impl SimulationBackend {
+    pub const ALL: [SimulationBackend; 2] = [Self::Sph, Self::Flip];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Sph => "SPH",
+            Self::Flip => "FLIP / APIC grid",
+        }
+    }
+
+    /// Inverse of `as u32`; unknown values fall back to SPH.
+    pub fn from_index(index: u32) -> Self {
+        Self::ALL.get(index as usize).copied().unwrap_or_default()
+    }
+}
+
+/// Grid-to-particle transfer of the FLIP backend; `TRANSFER_*` in flip.glsl.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GridTransfer {
+    /// Particles take the grid velocity: stable, but smears out detail.
+    Pic = 0,
+    /// Particles add the grid's velocity change, blended with PIC by
+    /// `flip_ratio`: lively, but noisy near 1.
+    #[default]
+    Flip = 1,
+    /// Affine particle-in-cell (Jiang et al. 2015): PIC plus a per-particle
+    /// velocity gradient, which keeps the rotation PIC loses without FLIP's
+    /// noise.
+    Apic = 2,
+}
+
+impl GridTransfer {
+    pub const ALL: [GridTransfer; 3] = [Self::Pic, Self::Flip, Self::Apic];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Pic => "PIC",
+            Self::Flip => "FLIP",
+            Self::Apic => "APIC",
+        }
+    }
+
+    /// Inverse of `as u32`; unknown values fall back to FLIP.
+    pub fn from_index(index: u32) -> Self {
+        Self::ALL.get(index as usize).copied().unwrap_or_default()
+    }
+}
+
+/// Fixed-point scale of the momentum and weight sums of the particle-to-grid
+/// transfer; `FLIP_TRANSFER_SCALE` in flip.glsl.
+pub const FLIP_TRANSFER_SCALE: f32 = 65536.0;
+
 #[repr(C, align(16))]
 #[derive(BufferContents, Copy, Clone)]
 pub struct SimulationParams {
@@ -320,14 +1147,61 @@ pub struct SimulationParams {
     pub dt: f32,
     pub density_solver_iterations: u32,
     pub divergence_solver_iterations: u32,
-    
-    _padding: [f32; 3],
+
+    /// Akinci et al. 2013 surface tension coefficient γ (cohesion + curvature).
+    pub surface_tension: f32,
+    /// Akinci et al. 2013 fluid-boundary adhesion coefficient β.
+    pub adhesion: f32,
+    /// Vorticity confinement strength ε (dimensionless); 0 skips the passes.
+    pub vorticity_confinement: f32,
+    /// `SphKernel` as u32. Set through `set_kernel`.
+    pub kernel: u32,
+    /// `ViscosityMode` as u32. Set through `set_viscosity_mode`.
+    pub viscosity_mode: u32,
+    /// Cap on the conjugate gradient iterations of the implicit viscosity
+    /// solve, and the residual relative to the right-hand side it stops at.
+    pub viscosity_iterations: u32,
+    pub viscosity_tolerance: f32,
+    /// `PressureSolver` as u32. Set through `set_pressure_solver`.
+    pub pressure_solver: u32,
+    /// Tait stiffness B [Pa] of WCSPH, p = B((ρ/ρ0)^7 - 1).
+    pub stiffness: f32,
+    /// `SimulationBackend` as u32. Set through `set_backend`.
+    pub backend: u32,
+    /// `GridTransfer` as u32. Set through `set_grid_transfer`.
+    pub grid_transfer: u32,
+
+    /// Share of the FLIP update in the FLIP/PIC blend; 1 is pure FLIP.
+    pub flip_ratio: f32,
+    /// Jacobi iterations of the FLIP backend's pressure projection.
+    pub grid_iterations: u32,
+    /// Average density error η and divergence error η_v, as fractions of the
+    /// rest density (per second for η_v), below which the DFSPH iterations
+    /// stop early. 0 runs every iteration.
+    pub max_density_error: f32,
+    pub max_divergence_error: f32,
 
     pub gravity: [f32; 4],
+    /// Extents of the collision box in its own frame, which is rotated by
+    /// `box_rotation` about the box centre.
     pub box_min: [f32; 4],
     pub box_max: [f32; 4],
 
     pub grid_res: [i32; 4],
+
+    /// Quaternion (xyzw) of the collision box about its centre.
+    pub box_rotation: [f32; 4],
+    /// Velocity of the box centre and angular velocity about it [rad/s],
+    /// imparted to the fluid by the wall collisions.
+    pub box_velocity: [f32; 4],
+    pub box_angular_velocity: [f32; 4],
+    /// Half the rate at which the box grows along each of its axes: the max
+    /// wall moves out at this speed relative to the centre, the min wall in
+    /// the opposite direction (the wave wall).
+    pub box_growth: [f32; 4],
+
+    /// Phase table, indexed by `particle_phase`. Set through `set_phases`.
+    pub phases: [GpuPhase; MAX_PHASES],
 }
 
 impl SimulationParams {
@@ -356,12 +1230,156 @@ impl SimulationParams {
             dt,
             density_solver_iterations,
             divergence_solver_iterations,
-            _padding: [0.0; 3],
+            surface_tension: 0.0,
+            adhesion: 0.0,
+            vorticity_confinement: 0.0,
+            kernel: SphKernel::CubicSpline as u32,
+            viscosity_mode: ViscosityMode::Explicit as u32,
+            viscosity_iterations: 100,
+            viscosity_tolerance: 1e-3,
+            pressure_solver: PressureSolver::Dfsph as u32,
+            stiffness: 5000.0,
+            backend: SimulationBackend::Sph as u32,
+            grid_transfer: GridTransfer::Flip as u32,
+            flip_ratio: 0.95,
+            grid_iterations: 50,
+            max_density_error: 0.0,
+            max_divergence_error: 0.0,
             gravity: [gravity.x, gravity.y, gravity.z, 0.0],
             box_min: [box_min.x, box_min.y, box_min.z, 0.0],
             box_max: [box_max.x, box_max.y, box_max.z, 0.0],
             grid_res: [grid_res.x, grid_res.y, grid_res.z, 0],
+            box_rotation: Quat::IDENTITY.to_array(),
+            box_velocity: [0.0; 4],
+            box_angular_velocity: [0.0; 4],
+            box_growth: [0.0; 4],
+            phases: std::array::from_fn(|i| {
+                if i == 0 { GpuPhase::new(DEFAULT_FLUID_COLOR, 0.0, 0.0, 0.0) } else { GpuPhase::default() }
+            }),
+        }
+    }
+
+    pub fn box_rotation(&self) -> Quat {
+        Quat::from_array(self.box_rotation)
+    }
+
+    pub fn kernel(&self) -> SphKernel {
+        SphKernel::from_index(self.kernel)
+    }
+    pub fn set_kernel(&mut self, kernel: SphKernel) {
+        self.kernel = kernel as u32;
+    }
+
+    pub fn viscosity_mode(&self) -> ViscosityMode {
+        ViscosityMode::from_index(self.viscosity_mode)
+    }
+    pub fn set_viscosity_mode(&mut self, mode: ViscosityMode) {
+        self.viscosity_mode = mode as u32;
+    }
+
+    pub fn pressure_solver(&self) -> PressureSolver {
+        PressureSolver::from_index(self.pressure_solver)
+    }
+    pub fn set_pressure_solver(&mut self, solver: PressureSolver) {
+        self.pressure_solver = solver as u32;
+    }
+
+    pub fn backend(&self) -> SimulationBackend {
+        SimulationBackend::from_index(self.backend)
+    }
+    pub fn set_backend(&mut self, backend: SimulationBackend) {
+        self.backend = backend as u32;
+    }
+
+    pub fn grid_transfer(&self) -> GridTransfer {
+        GridTransfer::from_index(self.grid_transfer)
+    }
+    pub fn set_grid_transfer(&mut self, transfer: GridTransfer) {
+        self.grid_transfer = transfer as u32;
+    }
+
+    /// Cells per axis of the FLIP backend's MAC grid, which spans the box in
+    /// its own frame: cubes of about the smoothing radius, stretched to fit
+    /// the box exactly.
+    pub fn flip_grid_dims(&self) -> IVec3 {
+        let extent = Vec3::from_slice(&self.box_max[..3]) - Vec3::from_slice(&self.box_min[..3]);
+        (extent / self.smoothing_radius).round().as_ivec3().max(IVec3::ONE)
+    }
+    /// Edge lengths of a cell of the grid with `dims` cells; `cell_size` in
+    /// flip.glsl.
+    pub fn flip_cell_size(&self, dims: IVec3) -> Vec3 {
+        (Vec3::from_slice(&self.box_max[..3]) - Vec3::from_slice(&self.box_min[..3])) / dims.as_vec3()
+    }
+
+    /// Fills the phase table: the base fluid's colour, then one row per
+    /// extra phase. Rows past `extra.len()` are cleared.
+    pub fn set_phases(&mut self, base_color: [f32; 3], extra: &[GpuPhase]) {
+        assert!(extra.len() < MAX_PHASES, "at most {} extra phases", MAX_PHASES - 1);
+        self.phases = [GpuPhase::default(); MAX_PHASES];
+        self.phases[0] = GpuPhase::new(base_color, 0.0, 0.0, 0.0);
+        self.phases[1..=extra.len()].copy_from_slice(extra);
+    }
+
+    /// Number of phases in use, the base fluid included.
+    pub fn phase_count(&self) -> usize {
+        1 + self.phases[1..].iter().take_while(|p| p.rest_density > 0.0).count()
+    }
+
+    /// The accessors below mirror the `phase_*` helpers in common.glsl.
+    pub fn phase_rest_density(&self, phase: u32) -> f32 {
+        if phase == 0 { self.target_density } else { self.phases[phase as usize].rest_density }
+    }
+    /// Every particle fills the same volume, so the mass scales with the
+    /// phase's rest density.
+    pub fn phase_mass(&self, phase: u32) -> f32 {
+        self.particle_mass * self.phase_rest_density(phase) / self.target_density
+    }
+    pub fn phase_viscosity(&self, phase: u32) -> f32 {
+        if phase == 0 { self.viscosity } else { self.phases[phase as usize].viscosity }
+    }
+    pub fn phase_surface_tension(&self, phase: u32) -> f32 {
+        if phase == 0 { self.surface_tension } else { self.phases[phase as usize].surface_tension }
+    }
+    /// Strongest surface tension of any phase; the normals pass is skipped
+    /// while it is 0.
+    pub fn max_surface_tension(&self) -> f32 {
+        (1..self.phase_count() as u32).map(|k| self.phase_surface_tension(k)).fold(self.surface_tension, f32::max)
+    }
+
+    /// PCISPH's δ = ρ0² / (2 Δt² m² Σ|∇W|²) (Solenthaler & Pajarola 2009): the
+    /// pressure per unit density error of a particle with a full
+    /// neighborhood. The sum runs over the lattice fluid blocks are spawned
+    /// on, with spacing (m / ρ0)^(1/3), where Σ∇W vanishes by symmetry. The
+    /// mass scales with the rest density, so δ is the same for every phase.
+    /// 0 when the time step or the mass is 0.
+    pub fn pcisph_delta(&self) -> f32 {
+        let h = self.smoothing_radius;
+        let m = self.particle_mass;
+        let spacing = (m / self.target_density).powf(1.0 / 3.0);
+        let n = (h / spacing).ceil() as i32;
+
+        let mut grad_sq_sum = 0.0;
+        for z in -n..=n {
+            for y in -n..=n {
+                for x in -n..=n {
+                    let r_vec = Vec3::new(x as f32, y as f32, z as f32) * spacing;
+                    let grad = kernel_grad(self.kernel(), r_vec, r_vec.length(), h);
+                    grad_sq_sum += grad.dot(grad);
+                }
+            }
         }
+        let denominator = 2.0 * self.dt * self.dt * m * m * grad_sq_sum;
+        if denominator > 1e-20 { self.target_density * self.target_density / denominator } else { 0.0 }
+    }
+
+    /// World-space bounds of the rotated collision box, spanned by the
+    /// density volume; `domain_bounds` in common.glsl.
+    pub fn domain(&self) -> (Vec3, Vec3) {
+        let min = Vec3::from_slice(&self.box_min[..3]);
+        let max = Vec3::from_slice(&self.box_max[..3]);
+        let centre = (min + max) * 0.5;
+        let half = Mat3::from_quat(self.box_rotation()).abs() * ((max - min) * 0.5);
+        (centre - half, centre + half)
     }
 }
 
@@ -405,6 +1423,36 @@ impl ParticleGenerator {
 
         (positions, mass)
     }
+    /// `generate_volume` for the inside of a closed triangle mesh: the same
+    /// lattice over the mesh's bounds, keeping the nodes that lie at least half
+    /// a particle radius inside. The margin is below the radius so faces that
+    /// line up with the lattice keep their outer layer despite the SDF's
+    /// interpolation error.
+    pub fn generate_mesh_volume(
+        vertices: &[Vec3],
+        triangles: &[[u32; 3]],
+        radius: f32,
+        density: f32,
+        spacing: f32,
+        jitter: f32,
+    ) -> (Vec<[f32; 3]>, f32) {
+        let sdf = MeshSdf::bake(vertices, triangles, radius, 0.0);
+        let (lo, hi) = triangles
+            .iter()
+            .flatten()
+            .map(|&i| vertices[i as usize])
+            .fold((Vec3::MAX, Vec3::MIN), |(lo, hi), v| (lo.min(v), hi.max(v)));
+        let size = hi - lo;
+
+        let (lattice, mass) = Self::generate_volume(lo, size.x, size.y, size.z, radius, density, spacing, 0.0);
+        let positions = lattice
+            .into_iter()
+            .filter(|p| sdf.distance(Vec3::from_array(*p)) <= -0.5 * radius)
+            .map(|p| p.map(|c| c + (rand::random::<f32>() - 0.5) * jitter))
+            .collect();
+
+        (positions, mass)
+    }
     pub fn generate_cube(
         num_per_axis: usize,
         centre: Vec3,
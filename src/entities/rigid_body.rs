@@ -0,0 +1,358 @@
+use std::f32::consts::PI;
+use glam::{Mat3, Quat, Vec3};
+use crate::entities::obstacle::{Obstacle, ObstacleForce, ObstacleShape};
+use crate::entities::particle::SimulationParams;
+
+/// Coulomb friction coefficient between bodies and the box walls.
+const WALL_FRICTION: f32 = 0.4;
+/// Sequential-impulse sweeps over the wall contacts per update.
+const CONTACT_ITERATIONS: usize = 8;
+/// Points this close to a wall count as touching it and feel friction, in
+/// metres.
+const CONTACT_SLOP: f32 = 1e-3;
+
+/// Free solid of uniform density, coupled both ways with the fluid.
+///
+/// The solver sees the body as an obstacle moving with its velocities (see
+/// `obstacle`); the momentum the fluid loses against it comes back through
+/// `Simulation::read_obstacle_forces` into `fluid_force`, which acts on the
+/// body during the next `update`. Buoyancy is the net of those impulses, so a
+/// body lighter than the fluid floats and a heavier one sinks.
+///
+/// Bodies are integrated on the CPU once per frame and collide with the walls
+/// of the collision box, inelastically and with friction. They pass through
+/// each other and through static obstacles.
+#[derive(Debug, Clone)]
+pub struct RigidBody {
+    pub shape: ObstacleShape,
+    pub mass: f32,
+    /// Principal moments of inertia about the body's local axes.
+    pub inertia: Vec3,
+    /// Centre of mass.
+    pub position: Vec3,
+    pub orientation: Quat,
+    pub velocity: Vec3,
+    /// World-space, in rad/s.
+    pub angular_velocity: Vec3,
+    /// The fluid's push during the last step, torque about `position`.
+    pub fluid_force: ObstacleForce,
+    /// State restored by `reset`, as in `checkpoint_state`.
+    initial: [f32; 13],
+}
+
+impl RigidBody {
+    pub fn sphere(radius: f32, density: f32, position: Vec3, orientation: Quat) -> Self {
+        let mass = density * 4.0 / 3.0 * PI * radius.powi(3);
+        let inertia = Vec3::splat(0.4 * mass * radius * radius);
+        Self::with_mass(ObstacleShape::Sphere { radius }, mass, inertia, position, orientation)
+    }
+
+    pub fn cuboid(half_extents: Vec3, density: f32, position: Vec3, orientation: Quat) -> Self {
+        let mass = density * 8.0 * half_extents.x * half_extents.y * half_extents.z;
+        let h2 = half_extents * half_extents;
+        let inertia = Vec3::new(h2.y + h2.z, h2.x + h2.z, h2.x + h2.y) * (mass / 3.0);
+        Self::with_mass(ObstacleShape::Box { half_extents }, mass, inertia, position, orientation)
+    }
+
+    fn with_mass(shape: ObstacleShape, mass: f32, inertia: Vec3, position: Vec3, orientation: Quat) -> Self {
+        let mut body = Self {
+            shape,
+            mass,
+            inertia,
+            position,
+            orientation,
+            velocity: Vec3::ZERO,
+            angular_velocity: Vec3::ZERO,
+            fluid_force: ObstacleForce::default(),
+            initial: [0.0; 13],
+        };
+        body.initial = body.checkpoint_state();
+        body
+    }
+
+    /// Sets the velocities the body starts with, also after `reset`.
+    pub fn set_initial_velocity(&mut self, velocity: Vec3, angular_velocity: Vec3) {
+        self.velocity = velocity;
+        self.angular_velocity = angular_velocity;
+        self.initial = self.checkpoint_state();
+    }
+
+    /// Back to the initial pose and velocities, without fluid force.
+    pub fn reset(&mut self) {
+        self.restore_checkpoint_state(self.initial);
+        self.fluid_force = ObstacleForce::default();
+    }
+
+    /// Position, orientation, velocity and angular velocity.
+    pub(crate) fn checkpoint_state(&self) -> [f32; 13] {
+        let (p, q, v, w) = (self.position, self.orientation, self.velocity, self.angular_velocity);
+        [p.x, p.y, p.z, q.x, q.y, q.z, q.w, v.x, v.y, v.z, w.x, w.y, w.z]
+    }
+    pub(crate) fn restore_checkpoint_state(&mut self, s: [f32; 13]) {
+        self.position = Vec3::new(s[0], s[1], s[2]);
+        self.orientation = Quat::from_xyzw(s[3], s[4], s[5], s[6]);
+        self.velocity = Vec3::new(s[7], s[8], s[9]);
+        self.angular_velocity = Vec3::new(s[10], s[11], s[12]);
+    }
+
+    /// The body as the solver sees it, at its current pose.
+    pub fn obstacle(&self) -> Obstacle {
+        let mut obstacle = Obstacle::new(self.shape.clone(), self.position, self.orientation);
+        obstacle.velocity = self.velocity;
+        obstacle.angular_velocity = self.angular_velocity;
+        obstacle
+    }
+
+    /// Advances by `dt` under gravity and `fluid_force` (semi-implicit
+    /// Euler) and keeps the body inside the box in `params`.
+    pub fn update(&mut self, dt: f32, params: &SimulationParams) {
+        if dt <= 0.0 {
+            return;
+        }
+        let gravity = Vec3::from_slice(&params.gravity);
+        self.velocity += (gravity + self.fluid_force.force / self.mass) * dt;
+        // Without the gyroscopic term, which explicit steps make unstable;
+        // it only matters for fast tumbling.
+        self.angular_velocity += self.inverse_inertia() * self.fluid_force.torque * dt;
+
+        let walls = Walls::new(params);
+        let mut contacts = self.wall_contacts(&walls, dt);
+        for _ in 0..CONTACT_ITERATIONS {
+            for contact in &mut contacts {
+                self.solve_contact(contact);
+            }
+        }
+
+        self.position += self.velocity * dt;
+        self.orientation = (Quat::from_scaled_axis(self.angular_velocity * dt) * self.orientation).normalize();
+        self.push_inside(&walls);
+    }
+
+    fn inverse_inertia(&self) -> Mat3 {
+        let rotation = Mat3::from_quat(self.orientation);
+        rotation * Mat3::from_diagonal(self.inertia.recip()) * rotation.transpose()
+    }
+
+    /// Surface points that can touch a wall lying in direction `towards`.
+    fn contact_candidates(&self, towards: Vec3) -> Vec<Vec3> {
+        match &self.shape {
+            ObstacleShape::Sphere { radius } => vec![self.position + towards * *radius],
+            ObstacleShape::Box { half_extents } => (0..8)
+                .map(|i| {
+                    let corner = Vec3::new(
+                        if i & 1 == 0 { -half_extents.x } else { half_extents.x },
+                        if i & 2 == 0 { -half_extents.y } else { half_extents.y },
+                        if i & 4 == 0 { -half_extents.z } else { half_extents.z },
+                    );
+                    self.position + self.orientation * corner
+                })
+                .collect(),
+            ObstacleShape::Capsule { .. } | ObstacleShape::Mesh(_) => Vec::new(),
+        }
+    }
+
+    /// Speculative contacts: every surface point may approach a wall by at
+    /// most its gap within `dt`, relative to the wall's motion.
+    fn wall_contacts(&self, walls: &Walls, dt: f32) -> Vec<Contact> {
+        let mut contacts = Vec::new();
+        for wall in walls.iter() {
+            for point in self.contact_candidates(-wall.normal) {
+                let gap = -walls.depth(&wall, point);
+                contacts.push(Contact {
+                    arm: point - self.position,
+                    normal: wall.normal,
+                    wall_velocity: walls.velocity(&wall, point),
+                    allowed: gap.max(0.0) / dt,
+                    touching: gap < CONTACT_SLOP,
+                    normal_impulse: 0.0,
+                    friction_impulse: Vec3::ZERO,
+                });
+            }
+        }
+        contacts
+    }
+
+    /// Moves the body out of the walls it ended up in.
+    fn push_inside(&mut self, walls: &Walls) {
+        for wall in walls.iter() {
+            let deepest = self.contact_candidates(-wall.normal)
+                .into_iter()
+                .map(|point| walls.depth(&wall, point))
+                .fold(0.0, f32::max);
+            self.position += wall.normal * deepest;
+        }
+    }
+
+    /// One sequential-impulse pass over `contact`: the accumulated normal
+    /// impulse slows the approach to `allowed` and never pulls, the friction
+    /// impulse of touching contacts stays within the Coulomb cone.
+    fn solve_contact(&mut self, contact: &mut Contact) {
+        let inverse_inertia = self.inverse_inertia();
+        let (arm, mass) = (contact.arm, self.mass);
+        // Velocity change at the contact per unit impulse along `d`.
+        let response = |d: Vec3| 1.0 / mass + d.dot((inverse_inertia * arm.cross(d)).cross(arm));
+        let relative = |body: &Self| body.velocity + body.angular_velocity.cross(arm) - contact.wall_velocity;
+
+        let vn = relative(self).dot(contact.normal);
+        let total = (contact.normal_impulse + (-contact.allowed - vn) / response(contact.normal)).max(0.0);
+        self.apply_impulse(arm, contact.normal * (total - contact.normal_impulse), inverse_inertia);
+        contact.normal_impulse = total;
+
+        if !contact.touching {
+            return;
+        }
+        let velocity = relative(self);
+        let sliding = velocity - velocity.dot(contact.normal) * contact.normal;
+        let Some(tangent) = sliding.try_normalize() else {
+            return;
+        };
+        let total = (contact.friction_impulse - sliding / response(tangent))
+            .clamp_length_max(WALL_FRICTION * contact.normal_impulse);
+        self.apply_impulse(arm, total - contact.friction_impulse, inverse_inertia);
+        contact.friction_impulse = total;
+    }
+
+    fn apply_impulse(&mut self, arm: Vec3, impulse: Vec3, inverse_inertia: Mat3) {
+        self.velocity += impulse / self.mass;
+        self.angular_velocity += inverse_inertia * arm.cross(impulse);
+    }
+}
+
+/// A surface point of a body near a wall, with the impulses it has taken so
+/// far during one `update`.
+struct Contact {
+    /// From the centre of mass.
+    arm: Vec3,
+    normal: Vec3,
+    wall_velocity: Vec3,
+    /// Largest approach speed along `normal`.
+    allowed: f32,
+    touching: bool,
+    normal_impulse: f32,
+    friction_impulse: Vec3,
+}
+
+/// One face of the collision box; `normal` points into the box.
+struct Wall {
+    axis: usize,
+    side: f32,
+    normal: Vec3,
+}
+
+/// The collision box of a `SimulationParams`, with its motion.
+struct Walls {
+    min: Vec3,
+    max: Vec3,
+    rotation: Quat,
+    velocity: Vec3,
+    angular_velocity: Vec3,
+    growth: Vec3,
+}
+
+impl Walls {
+    fn new(params: &SimulationParams) -> Self {
+        Self {
+            min: Vec3::from_slice(&params.box_min),
+            max: Vec3::from_slice(&params.box_max),
+            rotation: params.box_rotation(),
+            velocity: Vec3::from_slice(&params.box_velocity),
+            angular_velocity: Vec3::from_slice(&params.box_angular_velocity),
+            growth: Vec3::from_slice(&params.box_growth),
+        }
+    }
+
+    fn centre(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    fn iter(&self) -> impl Iterator<Item = Wall> + '_ {
+        (0..3).flat_map(move |axis| {
+            [-1.0, 1.0].map(|side| Wall { axis, side, normal: self.rotation * (Vec3::AXES[axis] * -side) })
+        })
+    }
+
+    /// How far `point` lies beyond `wall`; negative inside.
+    fn depth(&self, wall: &Wall, point: Vec3) -> f32 {
+        let local = self.centre() + self.rotation.inverse() * (point - self.centre());
+        let plane = if wall.side < 0.0 { self.min[wall.axis] } else { self.max[wall.axis] };
+        wall.side * (local[wall.axis] - plane)
+    }
+
+    /// Velocity of the wall's material at `point`. Both walls of an axis move
+    /// apart at `growth`.
+    fn velocity(&self, wall: &Wall, point: Vec3) -> Vec3 {
+        self.velocity + self.angular_velocity.cross(point - self.centre()) - wall.normal * self.growth[wall.axis]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::IVec3;
+
+    fn tank() -> SimulationParams {
+        SimulationParams::new(
+            0.02, 0.064, 0.08, 1000.0, 0.15, 0.5, 0.005, 4, 4,
+            Vec3::new(0.0, -9.81, 0.0),
+            Vec3::new(-1.0, 0.0, -1.0),
+            Vec3::new(1.0, 2.0, 1.0),
+            IVec3::splat(64),
+        )
+    }
+
+    #[test]
+    fn mass_properties_follow_the_shape() {
+        let cube = RigidBody::cuboid(Vec3::new(0.1, 0.2, 0.3), 500.0, Vec3::ZERO, Quat::IDENTITY);
+        assert!((cube.mass - 500.0 * 0.2 * 0.4 * 0.6).abs() < 1e-4);
+        // m (b² + c²) / 12 with full edge lengths b and c.
+        assert!((cube.inertia.x - cube.mass * (0.16 + 0.36) / 12.0).abs() < 1e-5);
+        assert!((cube.inertia.z - cube.mass * (0.04 + 0.16) / 12.0).abs() < 1e-5);
+
+        let ball = RigidBody::sphere(0.1, 1000.0, Vec3::ZERO, Quat::IDENTITY);
+        assert!((ball.mass - 4.18879).abs() < 1e-4);
+        assert!((ball.inertia.y - 0.4 * ball.mass * 0.01).abs() < 1e-6);
+    }
+
+    #[test]
+    fn tilted_box_falls_and_settles_flat_on_the_floor() {
+        let params = tank();
+        let mut body = RigidBody::cuboid(Vec3::new(0.2, 0.1, 0.15), 800.0, Vec3::new(0.0, 1.0, 0.0), Quat::from_rotation_z(0.3));
+        for _ in 0..600 {
+            body.update(1.0 / 60.0, &params);
+        }
+
+        assert!(body.velocity.length() < 1e-2 && body.angular_velocity.length() < 1e-2, "{body:?}");
+        assert!((body.position.y - 0.1).abs() < 5e-3, "rests on its largest face: {}", body.position);
+        let up = body.orientation * Vec3::Y;
+        assert!(up.y.abs() > 0.999, "{up}");
+
+        body.reset();
+        assert_eq!(body.position, Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(body.velocity, Vec3::ZERO);
+    }
+
+    #[test]
+    fn fluid_force_and_walls_act_on_the_body() {
+        let params = tank();
+        let mut ball = RigidBody::sphere(0.1, 500.0, Vec3::new(0.0, 1.0, 0.0), Quat::IDENTITY);
+        ball.set_initial_velocity(Vec3::new(3.0, 0.0, 0.0), Vec3::ZERO);
+
+        // A push that cancels gravity keeps it at height while it slides
+        // into the +x wall and stops there.
+        ball.fluid_force.force = Vec3::new(0.0, 9.81 * ball.mass, 0.0);
+        for _ in 0..120 {
+            ball.update(1.0 / 60.0, &params);
+        }
+        assert!((ball.position.y - 1.0).abs() < 1e-4, "{}", ball.position);
+        assert!((ball.position.x - 0.9).abs() < 1e-4, "{}", ball.position);
+        assert!(ball.velocity.length() < 1e-4);
+
+        let obstacle = ball.obstacle();
+        assert_eq!(obstacle.position, ball.position);
+        assert!(obstacle.distance(Vec3::new(1.0, 1.0, 0.0)).abs() < 1e-5);
+
+        ball.reset();
+        assert_eq!(ball.velocity, Vec3::new(3.0, 0.0, 0.0));
+        assert_eq!(ball.fluid_force, ObstacleForce::default());
+    }
+}
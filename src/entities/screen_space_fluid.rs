@@ -0,0 +1,220 @@
+use std::sync::Arc;
+use vulkano::buffer::{BufferContents, Subbuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DrawIndirectCommand, RenderingAttachmentInfo, RenderingInfo};
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::image::sampler::{Filter, Sampler, SamplerCreateInfo};
+use vulkano::image::view::ImageView;
+use vulkano::image::{Image, ImageCreateInfo, ImageType, ImageUsage};
+use vulkano::memory::allocator::{AllocationCreateInfo, StandardMemoryAllocator};
+use vulkano::pipeline::graphics::viewport::{Scissor, Viewport};
+use vulkano::pipeline::{Pipeline, PipelineBindPoint};
+use vulkano::render_pass::{AttachmentLoadOp, AttachmentStoreOp};
+use crate::entities::particle::PositionVertex;
+use crate::renderer::pipelines::ComputeStep;
+use crate::renderer::pipelines::ssfr_pipeline::{ScreenSpaceFluidPipelines, FLUID_DEPTH_FORMAT, FLUID_THICKNESS_FORMAT};
+use crate::renderer::pipelines::ssfr_smooth::DepthSmoothPipeline;
+
+#[derive(BufferContents, Clone, Copy)]
+#[repr(C)]
+struct SpherePushConstants {
+    camera_addr: u64,
+    particle_radius: f32,
+    viewport_height: f32,
+}
+
+/// Window-sized targets of the screen-space fluid render mode.
+struct Targets {
+    extent: [u32; 2],
+    fluid_depth: Arc<ImageView>,
+    thickness: Arc<ImageView>,
+    sphere_depth: Arc<ImageView>,
+    composite_set: Arc<DescriptorSet>,
+}
+
+/// Screen-space fluid rendering: particles are splatted as spheres into an
+/// eye-depth and a thickness target, the depth is bilaterally smoothed, and
+/// a fullscreen pass reconstructs normals and shades the water.
+pub struct ScreenSpaceFluid {
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    skybox_view: Arc<ImageView>,
+    skybox_sampler: Arc<Sampler>,
+    target_sampler: Arc<Sampler>,
+    smooth: DepthSmoothPipeline,
+    targets: Option<Targets>,
+}
+
+impl ScreenSpaceFluid {
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+        device: Arc<Device>,
+        skybox_view: Arc<ImageView>,
+    ) -> Self {
+        let skybox_sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                ..SamplerCreateInfo::default()
+            }
+        ).unwrap();
+
+        // Targets are read with texelFetch only.
+        let target_sampler = Sampler::new(device.clone(), SamplerCreateInfo::default()).unwrap();
+
+        Self {
+            memory_allocator,
+            descriptor_set_allocator,
+            skybox_view,
+            skybox_sampler,
+            target_sampler,
+            smooth: DepthSmoothPipeline::new(device),
+            targets: None,
+        }
+    }
+
+    fn create_target(&self, format: Format, usage: ImageUsage, extent: [u32; 2]) -> Arc<ImageView> {
+        let image = Image::new(
+            self.memory_allocator.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format,
+                extent: [extent[0], extent[1], 1],
+                usage,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default()
+        ).expect("[SSFR] Failed to create render target");
+        ImageView::new_default(image).unwrap()
+    }
+
+    /// (Re)creates the targets when the window size changed.
+    fn ensure_targets(&mut self, pipelines: &ScreenSpaceFluidPipelines, extent: [u32; 2]) {
+        if self.targets.as_ref().is_some_and(|t| t.extent == extent) {
+            return;
+        }
+
+        let fluid_depth = self.create_target(
+            FLUID_DEPTH_FORMAT,
+            ImageUsage::COLOR_ATTACHMENT | ImageUsage::STORAGE | ImageUsage::SAMPLED,
+            extent,
+        );
+        let smoothing_scratch = self.create_target(FLUID_DEPTH_FORMAT, ImageUsage::STORAGE, extent);
+        let thickness = self.create_target(
+            FLUID_THICKNESS_FORMAT,
+            ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED,
+            extent,
+        );
+        let sphere_depth = self.create_target(Format::D32_SFLOAT, ImageUsage::DEPTH_STENCIL_ATTACHMENT, extent);
+
+        self.smooth.prepare_with_images(
+            self.descriptor_set_allocator.clone(),
+            fluid_depth.clone(),
+            smoothing_scratch,
+        );
+
+        let set_layout = pipelines.composite.layout().set_layouts().first().unwrap();
+        let composite_set = DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            set_layout.clone(),
+            [
+                WriteDescriptorSet::image_view_sampler(0, fluid_depth.clone(), self.target_sampler.clone()),
+                WriteDescriptorSet::image_view_sampler(1, self.skybox_view.clone(), self.skybox_sampler.clone()),
+                WriteDescriptorSet::image_view_sampler(2, thickness.clone(), self.target_sampler.clone()),
+            ],
+            []
+        ).unwrap();
+
+        self.targets = Some(Targets { extent, fluid_depth, thickness, sphere_depth, composite_set });
+    }
+
+    /// Records the sphere, thickness and smoothing passes. Must run outside
+    /// the main rendering pass; `draw` then composites the result.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record_offscreen<Cb>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<Cb>,
+        pipelines: &ScreenSpaceFluidPipelines,
+        camera_addr: u64,
+        projection_scale: f32,
+        positions: Subbuffer<[PositionVertex]>,
+        draw_command: Subbuffer<[DrawIndirectCommand]>,
+        particle_radius: f32,
+        extent: [u32; 2],
+    ) {
+        self.ensure_targets(pipelines, extent);
+        let targets = self.targets.as_ref().unwrap();
+
+        let [w, h] = extent.map(|n| n as f32);
+        // Same flipped viewport as the main pass, so pixels line up.
+        let viewport = Viewport {
+            offset: [0.0, h],
+            extent: [w, -h],
+            depth_range: 0.0..=1.0,
+        };
+        let scissor = Scissor { offset: [0, 0], extent };
+        let push = SpherePushConstants { camera_addr, particle_radius, viewport_height: h };
+
+        let passes = [
+            (&pipelines.sphere_depth, &targets.fluid_depth, Some(&targets.sphere_depth)),
+            (&pipelines.thickness, &targets.thickness, None),
+        ];
+        for (pipeline, color, depth) in passes {
+            builder
+                .begin_rendering(RenderingInfo {
+                    color_attachments: vec![Some(RenderingAttachmentInfo {
+                        load_op: AttachmentLoadOp::Clear,
+                        store_op: AttachmentStoreOp::Store,
+                        clear_value: Some([0.0, 0.0, 0.0, 0.0].into()),
+                        ..RenderingAttachmentInfo::image_view(color.clone())
+                    })],
+                    depth_attachment: depth.map(|view| RenderingAttachmentInfo {
+                        load_op: AttachmentLoadOp::Clear,
+                        store_op: AttachmentStoreOp::DontCare,
+                        clear_value: Some(1f32.into()),
+                        ..RenderingAttachmentInfo::image_view(view.clone())
+                    }),
+                    ..RenderingInfo::default()
+                }).map_err(|e| panic!("[SSFR] Failed to begin rendering: {:?}", e)).unwrap()
+                .set_viewport(0, [viewport.clone()].into_iter().collect()).unwrap()
+                .set_scissor(0, [scissor.clone()].into_iter().collect()).unwrap()
+                .bind_pipeline_graphics(pipeline.clone()).unwrap()
+                .bind_vertex_buffers(0, positions.clone()).unwrap()
+                .push_constants(pipeline.layout().clone(), 0, push)
+                .unwrap();
+            unsafe { builder.draw_indirect(draw_command.clone()).unwrap(); }
+            builder.end_rendering().unwrap();
+        }
+
+        self.smooth.set_filter_scale(particle_radius, projection_scale * h * 0.5);
+        self.smooth.execute(builder);
+    }
+
+    /// Shades the smoothed surface; call inside the main rendering pass.
+    pub fn draw<Cb>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<Cb>,
+        pipelines: &ScreenSpaceFluidPipelines,
+        camera_addr: u64,
+    ) {
+        let Some(targets) = &self.targets else { return };
+        let pipeline = &pipelines.composite;
+
+        unsafe {
+            builder
+                .bind_pipeline_graphics(pipeline.clone()).unwrap()
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Graphics,
+                    pipeline.layout().clone(),
+                    0,
+                    targets.composite_set.clone(),
+                ).unwrap()
+                .push_constants(pipeline.layout().clone(), 0, camera_addr).unwrap()
+                .draw(3, 1, 0, 0).unwrap();
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::sync::Arc;
+use vulkano::buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DrawIndirectCommand};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
+use vulkano::pipeline::graphics::vertex_input::Vertex;
+use crate::cpu::marching_cubes::triangle_table;
+
+#[repr(C)]
+#[derive(BufferContents, Vertex, Copy, Clone, Debug, Default)]
+pub struct SurfaceVertex {
+    #[format(R32G32B32A32_SFLOAT)]
+    pub position: [f32; 4],
+    #[format(R32G32B32A32_SFLOAT)]
+    pub normal: [f32; 4],
+}
+
+/// Triangle soup written by `marching_cubes.comp` and drawn indirectly, so the
+/// vertex count never has to come back to the CPU.
+pub struct GpuSurfaceMesh {
+    pub vertices: Subbuffer<[SurfaceVertex]>,
+    pub draw_command: Subbuffer<[DrawIndirectCommand]>,
+    /// Vertices handed out by the extraction, including those dropped because
+    /// `vertices` was full.
+    pub reserved: Subbuffer<[u32]>,
+    pub triangle_table: Subbuffer<[i32]>,
+}
+
+impl GpuSurfaceMesh {
+    pub fn new(allocator: Arc<StandardMemoryAllocator>, max_vertices: u64) -> Self {
+        let vertices = Buffer::new_slice::<SurfaceVertex>(
+            allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER | BufferUsage::VERTEX_BUFFER | BufferUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            AllocationCreateInfo { memory_type_filter: MemoryTypeFilter::PREFER_DEVICE, ..Default::default() },
+            max_vertices,
+        ).expect("Failed to create surface vertex buffer");
+
+        let draw_command = Buffer::new_slice::<DrawIndirectCommand>(
+            allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER | BufferUsage::INDIRECT_BUFFER | BufferUsage::TRANSFER_SRC | BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo { memory_type_filter: MemoryTypeFilter::PREFER_DEVICE, ..Default::default() },
+            1,
+        ).expect("Failed to create surface draw command buffer");
+
+        let reserved = Buffer::new_slice::<u32>(
+            allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_SRC | BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo { memory_type_filter: MemoryTypeFilter::PREFER_DEVICE, ..Default::default() },
+            1,
+        ).expect("Failed to create surface counter buffer");
+
+        let triangle_table = Buffer::from_iter(
+            allocator,
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            triangle_table().into_iter().flatten(),
+        ).expect("Failed to create triangle table buffer");
+
+        Self { vertices, draw_command, reserved, triangle_table }
+    }
+
+    /// Zeroes the counters; must precede every extraction.
+    pub fn record_clear<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>) {
+        builder.fill_buffer(self.draw_command.clone().reinterpret::<[u32]>(), 0).unwrap();
+        builder.fill_buffer(self.reserved.clone(), 0).unwrap();
+    }
+}
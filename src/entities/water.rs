@@ -11,6 +11,7 @@ use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, Standar
 use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout};
 use crate::entities::ModelVertex;
 use crate::entities::particle::SimulationParams;
+use crate::entities::surface::GpuSurfaceMesh;
 
 #[derive(BufferContents)]
 #[repr(C)]
@@ -132,11 +133,9 @@ impl WaterRenderer {
         builder: &mut AutoCommandBufferBuilder<Cb>,
         pipeline: Arc<GraphicsPipeline>,
         camera_addr: u64,
-        box_min: [f32; 4],
-        box_max: [f32; 4],
+        min: Vec3,
+        max: Vec3,
     ) {
-        let min = Vec3::from_slice(&box_min[0..3]);
-        let max = Vec3::from_slice(&box_max[0..3]);
         let size = max - min;
         let center = min + size * 0.5;
 
@@ -167,4 +166,34 @@ impl WaterRenderer {
                 .draw_indexed(self.index_count, 1, 0, 0, 0).unwrap();
         }
     }
-}
\ No newline at end of file
+
+    /// Draws the marching-cubes mesh with the same shading and descriptor set
+    /// as the raymarched volume. `pipeline` must share the water layout.
+    pub fn draw_surface<Cb>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<Cb>,
+        pipeline: Arc<GraphicsPipeline>,
+        camera_addr: u64,
+        mesh: &GpuSurfaceMesh,
+    ) {
+        let push_data = WaterPushConstants {
+            camera_addr,
+            _pad: 0,
+            model: Mat4::IDENTITY.to_cols_array_2d(),
+        };
+
+        unsafe {
+            builder
+                .bind_pipeline_graphics(pipeline.clone()).unwrap()
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Graphics,
+                    pipeline.layout().clone(),
+                    0,
+                    self.descriptor_set.clone(),
+                ).unwrap()
+                .bind_vertex_buffers(0, mesh.vertices.clone()).unwrap()
+                .push_constants(pipeline.layout().clone(), 0, push_data).unwrap()
+                .draw_indirect(mesh.draw_command.clone()).unwrap();
+        }
+    }
+}
@@ -11,6 +11,9 @@ pub enum ApplicationError {
 
     #[error("Failed to load resource: {0}")]
     ResourceLoadError(String),
+
+    #[error("Invalid scene: {0}")]
+    InvalidScene(String),
     
     #[error("An unexpected application error occurred: {0}")]
     Other(String),
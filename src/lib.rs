@@ -5,4 +5,5 @@ mod utils;
 mod renderer;
 mod errors;
 mod entities;
-mod commands;
\ No newline at end of file
+mod commands;
+mod cpu;
\ No newline at end of file
@@ -1,4 +1,7 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
 use std::sync::Arc;
+use log::{error, info, warn};
 use egui_winit_vulkano::{Gui, GuiConfig};
 use vulkano::command_buffer::{AutoCommandBufferBuilder, ClearColorImageInfo, CommandBufferUsage, CopyBufferInfo, RenderingAttachmentInfo, RenderingInfo};
 use vulkano::command_buffer::allocator::{StandardCommandBufferAllocator, StandardCommandBufferAllocatorCreateInfo};
@@ -18,10 +21,23 @@ use vulkano_util::renderer::VulkanoWindowRenderer;
 use vulkano_util::window::WindowDescriptor;
 use winit::event_loop::ActiveEventLoop;
 use winit::window::Window;
+use crate::core::checkpoint::{Checkpoint, ParticleState, PendingSnapshot};
+use crate::core::export::{ParticleExporter, PendingSurfaceMesh};
 use crate::core::scene::Scene;
+use crate::entities::camera::Camera;
+use crate::entities::collision::CollisionBox;
+use crate::entities::diffuse::DiffuseParticles;
+use crate::entities::emitter::Emitter;
+use crate::entities::obstacle::Obstacle;
+use crate::entities::particle::SimulationParams;
+use crate::entities::rigid_body::RigidBody;
+use crate::entities::screen_space_fluid::ScreenSpaceFluid;
 use crate::entities::sky::SkyData;
 use crate::entities::water::WaterRenderer;
-use crate::renderer::pipelines::{ComputePipelines, ComputeStep, Pipelines};
+use crate::core::simulation::Simulation;
+use crate::renderer::pipelines::{ComputeStep, Pipelines};
+use crate::renderer::pipelines::density_texture::DensityTexturePipeline;
+use crate::renderer::pipelines::marching_cubes::MarchingCubesPipeline;
 use crate::renderer::resources::GpuSceneResources;
 use crate::renderer::ui::{AppUI, RenderMode};
 use crate::utils::constants::{MAX_FRAMES_IN_FLIGHT, WINDOW_TITLE};
@@ -35,14 +51,47 @@ pub struct Renderer {
     context: Arc<VulkanoContext>,
     command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
     pipelines: Pipelines,
-    physics_steps: ComputePipelines,
+    simulation: Simulation,
+    density_texture: DensityTexturePipeline,
+    marching_cubes: MarchingCubesPipeline,
     sky_data: SkyData,
     water_renderer: WaterRenderer,
+    screen_space_fluid: ScreenSpaceFluid,
+    diffuse: DiffuseParticles,
+    /// `scene.diffuse.enabled` as of the last step.
+    diffuse_enabled: bool,
 
     resources: GpuSceneResources,
 
     pub gui: Gui,
     pub app_ui: AppUI,
+
+    pending_checkpoint: Option<PendingCheckpoint>,
+    exporter: Option<ParticleExporter>,
+    /// Set by `step` when this frame is exported; the readbacks are recorded
+    /// in `update_and_render`, after the density splat.
+    export_due: bool,
+    pending_exports: VecDeque<PendingExport>,
+    /// Simulated time since start or the last reset.
+    sim_time: f32,
+}
+
+/// Exported frame whose readbacks are still in flight.
+struct PendingExport {
+    time: f32,
+    particles: Option<PendingSnapshot>,
+    surface: Option<PendingSurfaceMesh>,
+}
+
+/// Checkpoint save whose particle buffers are still being copied back.
+struct PendingCheckpoint {
+    path: PathBuf,
+    snapshot: PendingSnapshot,
+    params: SimulationParams,
+    boundary: CollisionBox,
+    obstacle_states: Vec<[f32; 11]>,
+    body_states: Vec<[f32; 13]>,
+    camera: Camera,
 }
 
 impl Renderer {
@@ -124,71 +173,35 @@ impl Renderer {
             command_buffer_allocator.clone(),
             pipelines.sky_layout.clone(),
             context.graphics_queue().clone(),
-            concat!(env!("CARGO_MANIFEST_DIR"), "/assets/hdri/citrus_orchard_road_puresky_4k.exr")
+            &scene.sky_hdri.to_string_lossy()
         );
 
-        let sort_buffer_size = resources.physics_data.grid_entries.len() as u32;
-        let mut gpu_physics = ComputePipelines::new(
-            context.device().clone(),
-            context.memory_allocator().clone(),
-            sort_buffer_size,
+        let mut simulation = Simulation::with_capacity(
+            context.clone(),
+            &scene.initial_positions,
+            &scene.initial_phases,
+            scene.sim_params,
+            scene.particle_capacity,
         );
+        if !scene.mesh_boundary.is_empty() {
+            simulation.set_mesh_boundary(&scene.mesh_boundary);
+        }
+        simulation.set_sinks(&scene.sinks);
 
-        gpu_physics.neighbor_search.prepare(
-            descriptor_set_allocator.clone(),
-            &resources.physics_data,
-            &resources.sim_params_buffer,
-        );
-        gpu_physics.density_alpha.prepare(
-            descriptor_set_allocator.clone(),
-            &resources.physics_data,
-            &resources.sim_params_buffer,
-        );
-        gpu_physics.viscosity.prepare(
-            descriptor_set_allocator.clone(),
-            &resources.physics_data,
-            &resources.sim_params_buffer,
-        );
-        gpu_physics.density_source_term.prepare(
-            descriptor_set_allocator.clone(),
-            &resources.physics_data,
-            &resources.sim_params_buffer,
-        );
-        gpu_physics.pressure_force.prepare(
+        let mut density_texture = DensityTexturePipeline::new(context.device().clone());
+        density_texture.prepare_with_image(
             descriptor_set_allocator.clone(),
-            &resources.physics_data,
-            &resources.sim_params_buffer,
-        );
-        gpu_physics.pressure_update.prepare(
-            descriptor_set_allocator.clone(),
-            &resources.physics_data,
-            &resources.sim_params_buffer,
-        );
-        gpu_physics.pressure_integration.prepare(
-            descriptor_set_allocator.clone(),
-            &resources.physics_data,
-            &resources.sim_params_buffer,
-        );
-        gpu_physics.divergence_source_term.prepare(
-            descriptor_set_allocator.clone(),
-            &resources.physics_data,
-            &resources.sim_params_buffer,
-        );
-        gpu_physics.divergence_integration.prepare(
-            descriptor_set_allocator.clone(),
-            &resources.physics_data,
-            &resources.sim_params_buffer,
-        );
-        gpu_physics.density_texture.prepare_with_image(
-            descriptor_set_allocator.clone(),
-            &resources.physics_data,
+            simulation.physics_data(),
             resources.density_view.clone(),
-            &resources.sim_params_buffer,
+            simulation.sim_params_buffer(),
         );
-        gpu_physics.stats.prepare(
+
+        let mut marching_cubes = MarchingCubesPipeline::new(context.device().clone());
+        marching_cubes.prepare_with_mesh(
             descriptor_set_allocator.clone(),
-            &resources.physics_data,
-            &resources.sim_params_buffer,
+            resources.density_view.clone(),
+            &resources.surface_mesh,
+            simulation.sim_params_buffer(),
         );
 
         let gui = Gui::new(
@@ -208,9 +221,28 @@ impl Renderer {
             pipelines.water_renderer_pipeline.inner.layout().clone(),
             resources.density_view.clone(),
             sky_data.texture_view.clone(),
-            &resources.sim_params_buffer,
+            simulation.sim_params_buffer(),
         );
 
+        let screen_space_fluid = ScreenSpaceFluid::new(
+            context.memory_allocator().clone(),
+            descriptor_set_allocator.clone(),
+            context.device().clone(),
+            sky_data.texture_view.clone(),
+        );
+
+        let diffuse = DiffuseParticles::new(
+            context.memory_allocator().clone(),
+            descriptor_set_allocator.clone(),
+            context.device().clone(),
+            &simulation,
+            &scene.diffuse,
+        );
+
+        let mut app_ui = AppUI::new();
+        app_ui.use_vorticity_confinement = scene.sim_params.vorticity_confinement > 0.0;
+        app_ui.display_particle_count = scene.initial_positions.len() as u32;
+
         Self {
             context,
             window_renderer,
@@ -219,55 +251,83 @@ impl Renderer {
             resources,
             sky_data,
             water_renderer,
-            physics_steps: gpu_physics,
+            screen_space_fluid,
+            diffuse,
+            diffuse_enabled: false,
+            simulation,
+            density_texture,
+            marching_cubes,
             gui,
-            app_ui: AppUI::new(),
+            app_ui,
+            pending_checkpoint: None,
+            exporter: None,
+            export_due: false,
+            pending_exports: VecDeque::new(),
+            sim_time: 0.0,
         }
     }
     pub fn step(&mut self, scene: &mut Scene, max_dt: f32, previous_future: Box<dyn GpuFuture>) -> Box<dyn GpuFuture> {
-        const DENSITY_SCALE: f32 = 1.0;
-        const DIVERGENCE_SCALE: f32 = 10.0;
-
-        if let Ok(stats) = self.resources.physics_data.stats_buffer.read() {
-            let n = scene.initial_positions.len() as f32;
-            let max_speed = f32::from_bits(stats[0]);
-            self.app_ui.display_max_speed = max_speed;
-            self.app_ui.display_avg_density_error = stats[1] as f32 / (DENSITY_SCALE * n);
-            self.app_ui.display_avg_divergence_error = stats[2] as f32 / (DIVERGENCE_SCALE * n);
-
-            if self.app_ui.use_cfl && max_speed > 0.01 {
+        if let Some(stats) = self.simulation.read_stats() {
+            self.app_ui.display_max_speed = stats.max_speed;
+            self.app_ui.display_avg_density_error = stats.avg_density_error;
+            self.app_ui.display_avg_divergence_error = stats.avg_divergence_error;
+            self.app_ui.display_particle_count = stats.particle_count;
+            self.app_ui.display_viscosity_iters_used = stats.viscosity_iterations;
+            self.app_ui.display_viscosity_residual = stats.viscosity_residual;
+            self.app_ui.display_density_iters_used = stats.density_iterations;
+            self.app_ui.display_divergence_iters_used = stats.divergence_iterations;
+
+            if self.app_ui.use_cfl && stats.max_speed > 0.01 {
                 let h = scene.sim_params.smoothing_radius;
-                let cfl_dt = (0.4 * h / max_speed).clamp(0.001, 0.05);
+                let cfl_dt = (0.4 * h / stats.max_speed).clamp(0.001, 0.05);
   
                 let smooth_dt = cfl_dt.min(scene.sim_params.dt * 1.1);
                 scene.sim_params.dt = smooth_dt;
                 self.app_ui.display_cfl_dt = cfl_dt;
             }
         }
-        let rho0 = scene.sim_params.target_density;
-        let density_threshold_abs    = self.app_ui.density_error_pct    / 100.0 * rho0;
-        let divergence_threshold_abs = self.app_ui.divergence_error_pct / 100.0 * rho0;
-
-
-        let density_iters = if self.app_ui.use_solver_error_threshold
-            && self.app_ui.display_avg_density_error < density_threshold_abs
+        self.finish_pending_checkpoint();
+        self.finish_pending_exports();
+        // Forces of the last finished step, one per obstacle and then one per
+        // rigid body; skipped when the obstacle list changed since.
+        if let Some(forces) = self.simulation.read_obstacle_forces()
+            && forces.len() == scene.obstacles.len() + scene.rigid_bodies.len()
         {
-            2u32
-        } else {
-            scene.sim_params.density_solver_iterations
-        };
-        let divergence_iters = if self.app_ui.use_solver_error_threshold
-            && self.app_ui.display_avg_divergence_error < divergence_threshold_abs
-        {
-            1u32
-        } else {
-            scene.sim_params.divergence_solver_iterations
-        };
-        self.app_ui.display_density_iters_used = density_iters;
-        self.app_ui.display_divergence_iters_used = divergence_iters;
+            for (body, force) in scene.rigid_bodies.iter_mut().zip(&forces[scene.obstacles.len()..]) {
+                body.fluid_force = *force;
+            }
+        }
+        let restore = if scene.playback.take_load() { self.load_checkpoint(scene) } else { None };
+
+        let reset = scene.playback.take_reset();
+        if reset {
+            scene.boundary.reset();
+            scene.obstacles.iter_mut().for_each(Obstacle::reset);
+            scene.rigid_bodies.iter_mut().for_each(RigidBody::reset);
+            scene.emitters.iter_mut().for_each(Emitter::reset);
+        }
+        // While paused only explicitly requested substeps run; the copies below
+        // still refresh the next frame's render buffers.
+        let pending_substeps = scene.playback.take_pending_substeps();
+        let motion_dt = if scene.playback.paused { pending_substeps as f32 * scene.sim_params.dt } else { max_dt };
+        // The box moves before the parameters go out, so this frame's
+        // substeps see its new pose and wall velocities.
+        scene.boundary.update(motion_dt);
+        scene.boundary.apply_to(&mut scene.sim_params);
 
         self.resources.sync_with_scene(scene);
-        scene.boundary.update(max_dt);
+        let mut sim_params = scene.sim_params;
+        if !self.app_ui.use_vorticity_confinement {
+            sim_params.vorticity_confinement = 0.0;
+        }
+        // The GPU stops the DFSPH iterations at these; 0 runs them all.
+        if self.app_ui.use_solver_error_threshold {
+            sim_params.max_density_error = self.app_ui.density_error_pct / 100.0;
+            sim_params.max_divergence_error = self.app_ui.divergence_error_pct / 100.0;
+        }
+        self.simulation.set_params(sim_params);
+        self.simulation.set_sort_algorithm(self.app_ui.sort_algorithm);
+        self.diffuse.set_params(scene.diffuse.params);
 
         let mut builder = AutoCommandBufferBuilder::primary(
             self.command_buffer_allocator.clone(),
@@ -275,85 +335,95 @@ impl Renderer {
             CommandBufferUsage::OneTimeSubmit,
         ).unwrap();
 
-        {
-            let _s = tracy_client::span!("neighbor_search_init");
-            self.physics_steps.neighbor_search.sort_algorithm = self.app_ui.sort_algorithm;
-            self.physics_steps.neighbor_search.execute(&mut builder);
+        if reset {
+            self.simulation.record_reset(&mut builder, &scene.initial_positions, &scene.initial_phases);
+            self.sim_time = 0.0;
         }
-        {
-            let _s = tracy_client::span!("density_alpha_init");
-            self.physics_steps.density_alpha.execute(&mut builder);
+        if let Some(state) = &restore {
+            self.simulation.record_restore(&mut builder, state);
         }
-
-        let mut step = 0.0;
-        while step < max_dt {
-            let _substep = tracy_client::span!("substep");
-
-            {
-                let _s = tracy_client::span!("viscosity");
-                self.physics_steps.viscosity.execute(&mut builder);
-            }
-            {
-                let _s = tracy_client::span!("density_source_term");
-                self.physics_steps.density_source_term.execute(&mut builder);
-            }
-            {
-                let _s = tracy_client::span!("density_solver");
-                for _ in 0..density_iters {
-                    self.physics_steps.pressure_force.execute(&mut builder);
-                    self.physics_steps.pressure_update.execute(&mut builder);
-                }
-            }
-            {
-                let _s = tracy_client::span!("pressure_integration");
-                self.physics_steps.pressure_integration.execute(&mut builder);
-            }
-
-            step += scene.sim_params.dt;
-
-            {
-                let _s = tracy_client::span!("neighbor_search_post_integrate");
-                self.physics_steps.neighbor_search.execute(&mut builder);
-            }
-            {
-                let _s = tracy_client::span!("density_alpha_post_integrate");
-                self.physics_steps.density_alpha.execute(&mut builder);
-            }
-            {
-                let _s = tracy_client::span!("divergence_source_term");
-                self.physics_steps.divergence_source_term.execute(&mut builder);
-            }
-            {
-                let _s = tracy_client::span!("divergence_solver");
-                for _ in 0..divergence_iters {
-                    self.physics_steps.pressure_force.execute(&mut builder);
-                    self.physics_steps.pressure_update.execute(&mut builder);
-                }
-            }
-            {
-                let _s = tracy_client::span!("divergence_integration");
-                self.physics_steps.divergence_integration.execute(&mut builder);
-            }
+        // Diffuse particles from before a reset, a restore or the last time
+        // they were switched off must not reappear; the buffer also starts
+        // out uninitialized.
+        let diffuse_switched_on = scene.diffuse.enabled && !self.diffuse_enabled;
+        self.diffuse_enabled = scene.diffuse.enabled;
+        if reset || restore.is_some() || diffuse_switched_on {
+            self.diffuse.record_clear(&mut builder);
+        }
+        for obstacle in &mut scene.obstacles {
+            obstacle.update(motion_dt);
+        }
+        for body in &mut scene.rigid_bodies {
+            body.update(motion_dt, &scene.sim_params);
+        }
+        self.simulation.set_obstacles(&scene.solid_obstacles());
+        for emitter in &mut scene.emitters {
+            self.simulation.emit(&emitter.update(motion_dt));
         }
 
-        {
-            let _s = tracy_client::span!("stats");
-            self.physics_steps.stats.execute(&mut builder);
+        let substeps = if !scene.playback.paused {
+            self.simulation.record_step(&mut builder, max_dt)
+        } else if pending_substeps > 0 {
+            self.simulation.record_substeps(&mut builder, pending_substeps);
+            pending_substeps
+        } else {
+            0
+        };
+        self.sim_time += substeps as f32 * scene.sim_params.dt;
+        // Generated from the state the substeps just left, while its
+        // neighbor structure is still current.
+        if scene.diffuse.enabled && substeps > 0 {
+            self.diffuse.record_update(&mut builder, &self.simulation, substeps as f32 * scene.sim_params.dt);
         }
 
         let next_frame = (self.resources.current_frame_idx + 1) % MAX_FRAMES_IN_FLIGHT;
 
 
         builder.copy_buffer(CopyBufferInfo::buffers(
-            self.resources.physics_data.position_a.clone(),
+            self.simulation.physics_data().position_a.clone(),
             self.resources.render_data.position_buffers[next_frame].clone()
         )).unwrap();
 
         builder.copy_buffer(CopyBufferInfo::buffers(
-            self.resources.physics_data.colors.clone(),
+            self.simulation.physics_data().colors.clone(),
             self.resources.render_data.color_buffers[next_frame].clone()
         )).unwrap();
 
+        builder.copy_buffer(CopyBufferInfo::buffers(
+            self.simulation.physics_data().draw_command(),
+            self.resources.render_data.draw_commands[next_frame].clone()
+        )).unwrap();
+
+        if self.app_ui.export_enabled {
+            if self.exporter.as_ref().is_none_or(|e| *e.settings() != self.app_ui.export_settings) {
+                self.exporter = Some(ParticleExporter::new(self.app_ui.export_settings.clone()));
+                self.pending_exports.clear();
+                self.app_ui.display_exported_frames = 0;
+            }
+            // Only frames that advanced the solver are exported.
+            self.export_due = substeps > 0 && self.exporter.as_mut().unwrap().tick();
+        } else {
+            self.exporter = None;
+            self.pending_exports.clear();
+        }
+
+        if scene.playback.take_save() {
+            self.pending_checkpoint = Some(PendingCheckpoint {
+                path: scene.playback.checkpoint_path.clone(),
+                snapshot: self.simulation.record_snapshot(&mut builder),
+                // The thresholds in effect come from the UI, not the scene.
+                params: SimulationParams {
+                    max_density_error: sim_params.max_density_error,
+                    max_divergence_error: sim_params.max_divergence_error,
+                    ..scene.sim_params
+                },
+                boundary: scene.boundary.clone(),
+                obstacle_states: scene.obstacles.iter().map(Obstacle::checkpoint_state).collect(),
+                body_states: scene.rigid_bodies.iter().map(RigidBody::checkpoint_state).collect(),
+                camera: scene.camera.clone(),
+            });
+        }
+
         let command_buffer = builder.build().unwrap();
 
         previous_future
@@ -362,6 +432,139 @@ impl Renderer {
             .then_signal_semaphore()
             .boxed()
     }
+    /// Reads a checkpoint and applies its parameters, boundary, obstacle poses,
+    /// rigid bodies and camera to the scene, and its solver error thresholds
+    /// to the UI. Returns the particle state to upload, or `None` (logged) if
+    /// the file is unusable.
+    fn load_checkpoint(&mut self, scene: &mut Scene) -> Option<ParticleState> {
+        let path = &scene.playback.checkpoint_path;
+        let checkpoint = match Checkpoint::load(path) {
+            Ok(checkpoint) => checkpoint,
+            Err(e) => {
+                error!("[Renderer] {}", e);
+                return None;
+            }
+        };
+        if checkpoint.particles.len() as u32 > self.simulation.capacity() {
+            error!(
+                "[Renderer] Checkpoint {} holds {} particles, the scene has room for {}.",
+                path.display(),
+                checkpoint.particles.len(),
+                self.simulation.capacity()
+            );
+            return None;
+        }
+
+        info!("[Renderer] Loaded checkpoint {}.", path.display());
+        scene.sim_params = checkpoint.params;
+        let params = &checkpoint.params;
+        self.app_ui.use_solver_error_threshold = params.max_density_error > 0.0 || params.max_divergence_error > 0.0;
+        if self.app_ui.use_solver_error_threshold {
+            self.app_ui.density_error_pct = params.max_density_error * 100.0;
+            self.app_ui.divergence_error_pct = params.max_divergence_error * 100.0;
+        }
+        let motion = scene.boundary.motion().cloned();
+        scene.boundary = checkpoint.boundary;
+        scene.boundary.set_motion(motion);
+        if checkpoint.obstacle_states.len() == scene.obstacles.len() {
+            for (obstacle, state) in scene.obstacles.iter_mut().zip(checkpoint.obstacle_states) {
+                obstacle.restore_checkpoint_state(state);
+            }
+        } else {
+            warn!(
+                "[Renderer] Checkpoint {} holds {} obstacles, the scene has {}; obstacle poses not restored.",
+                path.display(),
+                checkpoint.obstacle_states.len(),
+                scene.obstacles.len()
+            );
+        }
+        if checkpoint.body_states.len() == scene.rigid_bodies.len() {
+            for (body, state) in scene.rigid_bodies.iter_mut().zip(checkpoint.body_states) {
+                body.restore_checkpoint_state(state);
+                body.fluid_force = Default::default();
+            }
+        } else {
+            warn!(
+                "[Renderer] Checkpoint {} holds {} rigid bodies, the scene has {}; body states not restored.",
+                path.display(),
+                checkpoint.body_states.len(),
+                scene.rigid_bodies.len()
+            );
+        }
+        scene.camera = checkpoint.camera;
+        Some(checkpoint.particles)
+    }
+
+    /// Writes the pending checkpoint once its readback copies have finished.
+    fn finish_pending_checkpoint(&mut self) {
+        let Some(particles) = self.pending_checkpoint.as_ref().and_then(|p| p.snapshot.try_read()) else {
+            return;
+        };
+        let pending = self.pending_checkpoint.take().unwrap();
+        let checkpoint = Checkpoint {
+            particles,
+            params: pending.params,
+            boundary: pending.boundary,
+            obstacle_states: pending.obstacle_states,
+            body_states: pending.body_states,
+            camera: pending.camera,
+        };
+        match checkpoint.save(&pending.path) {
+            Ok(()) => info!("[Renderer] Saved checkpoint {}.", pending.path.display()),
+            Err(e) => error!("[Renderer] {}", e),
+        }
+    }
+
+    /// Records the readbacks of an export due this frame. Runs after the
+    /// surface extraction so the mesh is this frame's.
+    fn record_export<Cb>(&mut self, builder: &mut AutoCommandBufferBuilder<Cb>) {
+        let Some(settings) = self.exporter.as_ref().map(|e| e.settings()) else {
+            return;
+        };
+        let particles = settings.particles.then(|| self.simulation.record_snapshot(builder));
+        let surface = settings.surface_format.is_some().then(|| {
+            self.resources.record_surface_readback(self.context.memory_allocator().clone(), builder)
+        });
+        self.pending_exports.push_back(PendingExport { time: self.sim_time, particles, surface });
+    }
+
+    /// Writes every exported frame whose readbacks have finished, in order.
+    fn finish_pending_exports(&mut self) {
+        let Some(exporter) = self.exporter.as_mut() else {
+            return;
+        };
+        while let Some(front) = self.pending_exports.front() {
+            let particles = match &front.particles {
+                Some(snapshot) => match snapshot.try_read() {
+                    Some(state) => Some(state),
+                    None => return,
+                },
+                None => None,
+            };
+            let surface = match &front.surface {
+                Some(pending) => match pending.try_read() {
+                    Some(mesh) => Some(mesh),
+                    None => return,
+                },
+                None => None,
+            };
+            let time = self.pending_exports.pop_front().unwrap().time;
+
+            let particles_written = particles.map_or(Ok(()), |state| exporter.write_frame(&state, time).map(|_| ()));
+            let written = particles_written.and_then(|_| {
+                surface.map_or(Ok(()), |mesh| exporter.write_surface(&mesh, time).map(|_| ()))
+            });
+            match written {
+                Ok(()) => self.app_ui.display_exported_frames += 1,
+                Err(e) => {
+                    error!("[Renderer] {}", e);
+                    self.app_ui.export_enabled = false;
+                    self.pending_exports.clear();
+                }
+            }
+        }
+    }
+
     pub fn update_and_render(&mut self, scene: &mut Scene, safe_dt: f32, fps: u32) {
 
         self.gui.immediate_ui(|gui| {
@@ -383,11 +586,33 @@ impl Renderer {
         ).map_err(|e| panic!("[Renderer] Failed to create command buffer builder: {:?}", e))
             .unwrap();
 
-        if self.app_ui.render_mode == RenderMode::Raymarching {
+        let export_surface = self.export_due
+            && self.exporter.as_ref().is_some_and(|e| e.settings().surface_format.is_some());
+        let needs_density = matches!(self.app_ui.render_mode, RenderMode::Raymarching | RenderMode::Surface);
+        if needs_density || export_surface {
             let mut clear_info = ClearColorImageInfo::image(self.resources.density_texture.clone());
             clear_info.clear_value = ClearColorValue::Uint([0; 4]);
             builder.clear_color_image(clear_info).unwrap();
-            self.physics_steps.density_texture.execute(&mut builder);
+            self.density_texture.execute(&mut builder);
+        }
+        if self.app_ui.render_mode == RenderMode::Surface || export_surface {
+            self.resources.surface_mesh.record_clear(&mut builder);
+            self.marching_cubes.execute(&mut builder);
+        }
+        if self.app_ui.render_mode == RenderMode::ScreenSpace {
+            self.screen_space_fluid.record_offscreen(
+                &mut builder,
+                &self.pipelines.screen_space_fluid,
+                self.resources.camera_addr(),
+                scene.camera.projection_scale(),
+                self.resources.render_data.position_buffers[self.resources.current_frame_idx].clone(),
+                self.resources.render_data.draw_commands[self.resources.current_frame_idx].clone(),
+                scene.sim_params.particle_radius,
+                self.window_renderer.swapchain_image_size(),
+            );
+        }
+        if std::mem::take(&mut self.export_due) {
+            self.record_export(&mut builder);
         }
 
         let extent = self.window_renderer.window_size();
@@ -425,12 +650,28 @@ impl Renderer {
         self.sky_data.bind_to_command_buffer(&mut builder, &self.pipelines, self.resources.camera_addr());
         match self.app_ui.render_mode {
             RenderMode::Raymarching => {
+                let (domain_min, domain_max) = scene.sim_params.domain();
                 self.water_renderer.bind_to_command_buffer(
                     &mut builder,
                     self.pipelines.water_renderer_pipeline.inner.clone(),
                     self.resources.camera_addr(),
-                    scene.sim_params.box_min,
-                    scene.sim_params.box_max,
+                    domain_min,
+                    domain_max,
+                );
+            }
+            RenderMode::Surface => {
+                self.water_renderer.draw_surface(
+                    &mut builder,
+                    self.pipelines.surface_pipeline.inner.clone(),
+                    self.resources.camera_addr(),
+                    &self.resources.surface_mesh,
+                );
+            }
+            RenderMode::ScreenSpace => {
+                self.screen_space_fluid.draw(
+                    &mut builder,
+                    &self.pipelines.screen_space_fluid,
+                    self.resources.camera_addr(),
                 );
             }
             RenderMode::Particles => {
@@ -439,10 +680,18 @@ impl Renderer {
                     &self.pipelines,
                     self.resources.camera_addr(),
                     self.resources.current_frame_idx,
-                    self.resources.physics_data.count,
                 );
             }
         }
+        if scene.diffuse.enabled {
+            self.diffuse.draw(
+                &mut builder,
+                &self.pipelines.diffuse,
+                self.resources.camera_addr(),
+                scene.sim_params.particle_radius,
+                extent[1],
+            );
+        }
         self.resources.bind_to_command_buffer(&mut builder, &self.pipelines);
 
         builder.end_rendering().map_err(|e| panic!("[Renderer] Failed to end rendering: {:?}", e)).unwrap();
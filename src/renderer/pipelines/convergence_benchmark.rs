@@ -3,11 +3,12 @@
 // DFSPH solver convergence benchmark.
 //
 // Measures steady-state density and divergence error as a function of the
-// pressure-solver iteration count. Replicates the production substep loop
-// exactly (see `Renderer::step`) but in a headless `VulkanoContext`. For each
-// iteration count: fresh particle state, `WARMUP_SUBSTEPS` settle, then
-// `MEASUREMENT_SUBSTEPS` are submitted one at a time and stats are read after
-// each. The averaged errors land in `scripts/convergence.csv`.
+// pressure-solver iteration count. Runs the production substep loop through
+// `Simulation` (the same code path `Renderer::step` records) on a headless
+// `VulkanoContext`. The block settles once for `WARMUP_SUBSTEPS` at the
+// default iteration count; every iteration count then starts from that
+// snapshot, and `MEASUREMENT_SUBSTEPS` are submitted one at a time with stats
+// read after each. The averaged errors land in `scripts/convergence.csv`.
 //
 // Marked `#[ignore]`; run manually with:
 //     cargo test --release -p fluid_engine -- --ignored convergence --nocapture
@@ -16,25 +17,9 @@ use glam::{IVec3, Vec3};
 use std::fs;
 use std::io::Write;
 use std::path::Path;
-use std::sync::Arc;
-use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer};
-use vulkano::command_buffer::allocator::{
-    StandardCommandBufferAllocator, StandardCommandBufferAllocatorCreateInfo,
-};
-use vulkano::command_buffer::{
-    AutoCommandBufferBuilder, CommandBufferUsage, CopyBufferInfo, PrimaryCommandBufferAbstract,
-};
-use vulkano::descriptor_set::allocator::{
-    StandardDescriptorSetAllocator, StandardDescriptorSetAllocatorCreateInfo,
-};
-use vulkano::device::{DeviceFeatures, Queue};
-use vulkano::instance::{InstanceCreateInfo, InstanceExtensions};
-use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter};
-use vulkano::sync::GpuFuture;
-use vulkano_util::context::{VulkanoConfig, VulkanoContext};
-
-use crate::entities::particle::{GpuPhysicsData, ParticleGenerator, SimulationParams};
-use crate::renderer::pipelines::{ComputePipelines, ComputeStep};
+
+use crate::core::simulation::{create_headless_context, Simulation};
+use crate::entities::particle::{ParticleGenerator, SimulationParams};
 
 // ── Configuration ────────────────────────────────────────────────────────────
 
@@ -54,120 +39,11 @@ const CFL_MIN_DT: f32 = 0.001;
 const CFL_MAX_DT: f32 = 0.05;
 const STATIC_DT: f32 = 0.005;
 
-// Fixed-point scales — must match the constants in stats.comp / mod.rs.
-const DENSITY_SCALE: f32 = 1.0;
-const DIVERGENCE_SCALE: f32 = 10.0;
-
-// ── Vulkan setup ─────────────────────────────────────────────────────────────
-
-fn make_context() -> Arc<VulkanoContext> {
-    let config = VulkanoConfig {
-        instance_create_info: InstanceCreateInfo {
-            enabled_extensions: InstanceExtensions {
-                ext_debug_utils: true,
-                ..InstanceExtensions::default()
-            },
-            ..Default::default()
-        },
-        device_features: DeviceFeatures {
-            scalar_block_layout: true,
-            buffer_device_address: true,
-            shader_int64: true,
-            ..DeviceFeatures::empty()
-        },
-        ..VulkanoConfig::default()
-    };
-    Arc::new(VulkanoContext::new(config))
-}
-
 // ── Helpers ──────────────────────────────────────────────────────────────────
 
-fn prepare_all_pipelines(
-    pipelines: &mut ComputePipelines,
-    ds_alloc: Arc<StandardDescriptorSetAllocator>,
-    physics_data: &GpuPhysicsData,
-    sim_params: &Subbuffer<SimulationParams>,
-) {
-    pipelines.neighbor_search.prepare(ds_alloc.clone(), physics_data, sim_params);
-    pipelines.density_alpha.prepare(ds_alloc.clone(), physics_data, sim_params);
-    pipelines.viscosity.prepare(ds_alloc.clone(), physics_data, sim_params);
-    pipelines.density_source_term.prepare(ds_alloc.clone(), physics_data, sim_params);
-    pipelines.pressure_force.prepare(ds_alloc.clone(), physics_data, sim_params);
-    pipelines.pressure_update.prepare(ds_alloc.clone(), physics_data, sim_params);
-    pipelines.pressure_integration.prepare(ds_alloc.clone(), physics_data, sim_params);
-    pipelines.divergence_source_term.prepare(ds_alloc.clone(), physics_data, sim_params);
-    pipelines.divergence_integration.prepare(ds_alloc.clone(), physics_data, sim_params);
-    pipelines.stats.prepare(ds_alloc, physics_data, sim_params);
-}
-
-// Records and submits `n_substeps` substeps in a single command buffer. The
-// substep body mirrors `Renderer::step()` exactly: viscosity, density source,
-// density solver loop, pressure integration, neighbor search rebuild,
-// density_alpha, divergence source, divergence solver loop, divergence
-// integration. Stats is recorded once at the end of the batch.
-fn submit_substeps(
-    cb_alloc: &Arc<StandardCommandBufferAllocator>,
-    queue: &Arc<Queue>,
-    pipelines: &ComputePipelines,
-    iter_count: u32,
-    n_substeps: u32,
-    is_first_call: bool,
-) {
-    let mut builder = AutoCommandBufferBuilder::primary(
-        cb_alloc.clone(),
-        queue.queue_family_index(),
-        CommandBufferUsage::OneTimeSubmit,
-    )
-    .unwrap();
-
-    // Init pass: populate spatial hash and densities/factors for the initial
-    // state. Only required for the very first command buffer of a given
-    // physics_data, since subsequent substeps' internal neighbor_search keeps
-    // the structure synchronized.
-    if is_first_call {
-        pipelines.neighbor_search.execute(&mut builder);
-        pipelines.density_alpha.execute(&mut builder);
-    }
-
-    for _ in 0..n_substeps {
-        // Phase 1 — density correction.
-        pipelines.viscosity.execute(&mut builder);
-        pipelines.density_source_term.execute(&mut builder);
-        for _ in 0..iter_count {
-            pipelines.pressure_force.execute(&mut builder);
-            pipelines.pressure_update.execute(&mut builder);
-        }
-        pipelines.pressure_integration.execute(&mut builder);
-
-        // Phase 2 — divergence correction (rebuild neighbours first, since
-        // pressure_integration just moved every particle).
-        pipelines.neighbor_search.execute(&mut builder);
-        pipelines.density_alpha.execute(&mut builder);
-        pipelines.divergence_source_term.execute(&mut builder);
-        for _ in 0..iter_count {
-            pipelines.pressure_force.execute(&mut builder);
-            pipelines.pressure_update.execute(&mut builder);
-        }
-        pipelines.divergence_integration.execute(&mut builder);
-    }
-
-    pipelines.stats.execute(&mut builder);
-
-    let cb = builder.build().unwrap();
-    cb.execute(queue.clone())
-        .unwrap()
-        .then_signal_fence_and_flush()
-        .unwrap()
-        .wait(None)
-        .unwrap();
-}
-
-fn read_stats(physics_data: &GpuPhysicsData, n_particles: u32) -> (f32, f32) {
-    let stats = physics_data.stats_buffer.read().unwrap();
-    let n = n_particles as f32;
-    let density_err = stats[1] as f32 / (DENSITY_SCALE * n);
-    let div_err = stats[2] as f32 / (DIVERGENCE_SCALE * n);
-    (density_err, div_err)
+fn read_stats(simulation: &Simulation) -> (f32, f32) {
+    let stats = simulation.read_stats().unwrap();
+    (stats.avg_density_error, stats.avg_divergence_error)
 }
 
 fn mean(xs: &[f32]) -> f32 {
@@ -195,19 +71,7 @@ fn write_csv(path: &str, rows: &[(u32, f32, f32)]) {
 #[test]
 #[ignore]
 fn solver_convergence_benchmark() {
-    let ctx = make_context();
-    let device = ctx.device().clone();
-    let memory_allocator = ctx.memory_allocator().clone();
-    let queue = ctx.graphics_queue().clone();
-
-    let cb_allocator = Arc::new(StandardCommandBufferAllocator::new(
-        device.clone(),
-        StandardCommandBufferAllocatorCreateInfo::default(),
-    ));
-    let ds_allocator = Arc::new(StandardDescriptorSetAllocator::new(
-        device.clone(),
-        StandardDescriptorSetAllocatorCreateInfo::default(),
-    ));
+    let ctx = create_headless_context();
 
     // Simulation parameters — match `Scene::new()` defaults but smaller block
     // to keep the benchmark in the seconds-not-minutes range.
@@ -243,72 +107,43 @@ fn solver_convergence_benchmark() {
         MEASUREMENT_SUBSTEPS
     );
 
-    let mut results: Vec<(u32, f32, f32)> = Vec::with_capacity(ITER_VALUES.len());
+    let params_for = |iter_count: u32| SimulationParams::new(
+        particle_radius,
+        particle_mass,
+        smoothing_radius,
+        target_density,
+        viscosity_coeff,
+        relax_factor,
+        dt,
+        iter_count, // density iters
+        iter_count, // divergence iters
+        Vec3::new(0.0, -9.81, 0.0),
+        box_min,
+        box_max,
+        IVec3::new(128, 128, 128),
+    );
 
-    for &iter_count in ITER_VALUES {
-        let sim_params = SimulationParams::new(
-            particle_radius,
-            particle_mass,
-            smoothing_radius,
-            target_density,
-            viscosity_coeff,
-            relax_factor,
-            dt,
-            iter_count, // density iters
-            iter_count, // divergence iters
-            Vec3::new(0.0, -9.81, 0.0),
-            box_min,
-            box_max,
-            IVec3::new(128, 128, 128),
-        );
+    // Warmup batch — one big command buffer to amortize submission overhead —
+    // run once; every iteration count resumes from the settled snapshot.
+    let settled = {
+        let mut warmup = Simulation::new(ctx.clone(), &initial_positions, params_for(4));
+        warmup.run_substeps(WARMUP_SUBSTEPS);
+        warmup.snapshot()
+    };
 
-        // Fresh particle state per iteration-count test.
-        let physics_data =
-            GpuPhysicsData::new(memory_allocator.clone(), initial_positions.clone());
-        let sim_params_buffer = Buffer::from_data(
-            memory_allocator.clone(),
-            BufferCreateInfo {
-                usage: BufferUsage::UNIFORM_BUFFER,
-                ..Default::default()
-            },
-            AllocationCreateInfo {
-                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
-                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-                ..Default::default()
-            },
-            sim_params,
-        )
-        .unwrap();
-
-        let mut pipelines = ComputePipelines::new(
-            device.clone(),
-            memory_allocator.clone(),
-            physics_data.grid_entries.len() as u32,
-        );
-        prepare_all_pipelines(
-            &mut pipelines,
-            ds_allocator.clone(),
-            &physics_data,
-            &sim_params_buffer,
-        );
+    let mut results: Vec<(u32, f32, f32)> = Vec::with_capacity(ITER_VALUES.len());
 
-        // Warmup batch — one big command buffer to amortize submission overhead.
-        submit_substeps(
-            &cb_allocator,
-            &queue,
-            &pipelines,
-            iter_count,
-            WARMUP_SUBSTEPS,
-            true,
-        );
+    for &iter_count in ITER_VALUES {
+        let mut simulation = Simulation::new(ctx.clone(), &initial_positions, params_for(iter_count));
+        simulation.restore(&settled);
 
         // Measurement: one substep per submit so we can read stats after each.
         let mut density_errs = Vec::with_capacity(MEASUREMENT_SUBSTEPS as usize);
         let mut div_errs = Vec::with_capacity(MEASUREMENT_SUBSTEPS as usize);
 
         for _ in 0..MEASUREMENT_SUBSTEPS {
-            submit_substeps(&cb_allocator, &queue, &pipelines, iter_count, 1, false);
-            let (de, ve) = read_stats(&physics_data, n_particles);
+            simulation.run_substeps(1);
+            let (de, ve) = read_stats(&simulation);
             density_errs.push(de);
             div_errs.push(ve);
         }
@@ -329,25 +164,6 @@ fn solver_convergence_benchmark() {
 
 // ── Helpers shared by diagnostics ────────────────────────────────────────────
 
-fn make_density_staging(
-    memory_allocator: &Arc<vulkano::memory::allocator::StandardMemoryAllocator>,
-    n_particles: u32,
-) -> Subbuffer<[f32]> {
-    Buffer::from_iter(
-        memory_allocator.clone(),
-        BufferCreateInfo {
-            usage: BufferUsage::TRANSFER_DST,
-            ..Default::default()
-        },
-        AllocationCreateInfo {
-            memory_type_filter: MemoryTypeFilter::PREFER_HOST | MemoryTypeFilter::HOST_RANDOM_ACCESS,
-            ..Default::default()
-        },
-        (0..n_particles).map(|_| 0.0f32),
-    )
-    .unwrap()
-}
-
 fn print_density_stats(label: &str, densities: &[f32], target: f32) {
     let n = densities.len() as f32;
     let avg = densities.iter().sum::<f32>() / n;
@@ -420,20 +236,6 @@ fn print_density_stats(label: &str, densities: &[f32], target: f32) {
 #[test]
 #[ignore]
 fn rest_density_diagnostic() {
-    let ctx = make_context();
-    let device = ctx.device().clone();
-    let memory_allocator = ctx.memory_allocator().clone();
-    let queue = ctx.graphics_queue().clone();
-
-    let cb_allocator = Arc::new(StandardCommandBufferAllocator::new(
-        device.clone(),
-        StandardCommandBufferAllocatorCreateInfo::default(),
-    ));
-    let ds_allocator = Arc::new(StandardDescriptorSetAllocator::new(
-        device.clone(),
-        StandardDescriptorSetAllocatorCreateInfo::default(),
-    ));
-
     // Same particle setup as the convergence benchmark.
     let particle_radius = 0.020f32;
     let target_density = 1000.0f32;
@@ -476,60 +278,11 @@ fn rest_density_diagnostic() {
         IVec3::new(128, 128, 128),
     );
 
-    let physics_data = GpuPhysicsData::new(memory_allocator.clone(), initial_positions);
-    let sim_params_buffer = Buffer::from_data(
-        memory_allocator.clone(),
-        BufferCreateInfo {
-            usage: BufferUsage::UNIFORM_BUFFER,
-            ..Default::default()
-        },
-        AllocationCreateInfo {
-            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
-                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-            ..Default::default()
-        },
-        sim_params,
-    )
-    .unwrap();
-
-    let mut pipelines = ComputePipelines::new(
-        device.clone(),
-        memory_allocator.clone(),
-        physics_data.grid_entries.len() as u32,
-    );
-    prepare_all_pipelines(
-        &mut pipelines,
-        ds_allocator.clone(),
-        &physics_data,
-        &sim_params_buffer,
-    );
+    let mut simulation = Simulation::headless(&initial_positions, sim_params);
 
-    let staging = make_density_staging(&memory_allocator, n_particles);
-
-    // Single pass: build spatial hash, compute densities, copy out.
-    let mut builder = AutoCommandBufferBuilder::primary(
-        cb_allocator.clone(),
-        queue.queue_family_index(),
-        CommandBufferUsage::OneTimeSubmit,
-    )
-    .unwrap();
-    pipelines.neighbor_search.execute(&mut builder);
-    pipelines.density_alpha.execute(&mut builder);
-    builder
-        .copy_buffer(CopyBufferInfo::buffers(
-            physics_data.densities.clone(),
-            staging.clone(),
-        ))
-        .unwrap();
-    let cb = builder.build().unwrap();
-    cb.execute(queue)
-        .unwrap()
-        .then_signal_fence_and_flush()
-        .unwrap()
-        .wait(None)
-        .unwrap();
-
-    let densities: Vec<f32> = staging.read().unwrap().to_vec();
+    // Zero substeps: build spatial hash and compute densities only.
+    simulation.run_substeps(0);
+    let densities = simulation.read_densities();
     print_density_stats("at rest", &densities, target_density);
 
     // Write CSV for plot_rest_density.py.
@@ -569,20 +322,6 @@ fn rest_density_diagnostic() {
 #[test]
 #[ignore]
 fn density_after_warmup_diagnostic() {
-    let ctx = make_context();
-    let device = ctx.device().clone();
-    let memory_allocator = ctx.memory_allocator().clone();
-    let queue = ctx.graphics_queue().clone();
-
-    let cb_allocator = Arc::new(StandardCommandBufferAllocator::new(
-        device.clone(),
-        StandardCommandBufferAllocatorCreateInfo::default(),
-    ));
-    let ds_allocator = Arc::new(StandardDescriptorSetAllocator::new(
-        device.clone(),
-        StandardDescriptorSetAllocatorCreateInfo::default(),
-    ));
-
     // Same setup as rest_density_diagnostic — so results are directly comparable.
     let particle_radius = 0.020f32;
     let target_density = 1000.0f32;
@@ -627,97 +366,19 @@ fn density_after_warmup_diagnostic() {
         IVec3::new(128, 128, 128),
     );
 
-    let physics_data = GpuPhysicsData::new(memory_allocator.clone(), initial_positions);
-    let sim_params_buffer = Buffer::from_data(
-        memory_allocator.clone(),
-        BufferCreateInfo {
-            usage: BufferUsage::UNIFORM_BUFFER,
-            ..Default::default()
-        },
-        AllocationCreateInfo {
-            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
-                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-            ..Default::default()
-        },
-        sim_params,
-    )
-    .unwrap();
-
-    let mut pipelines = ComputePipelines::new(
-        device.clone(),
-        memory_allocator.clone(),
-        physics_data.grid_entries.len() as u32,
-    );
-    prepare_all_pipelines(
-        &mut pipelines,
-        ds_allocator.clone(),
-        &physics_data,
-        &sim_params_buffer,
-    );
+    let mut simulation = Simulation::headless(&initial_positions, sim_params);
 
-    let staging = make_density_staging(&memory_allocator, n_particles);
-
-    // Phase 1: read rest density (init pipeline, copy, wait).
-    {
-        let mut builder = AutoCommandBufferBuilder::primary(
-            cb_allocator.clone(),
-            queue.queue_family_index(),
-            CommandBufferUsage::OneTimeSubmit,
-        )
-        .unwrap();
-        pipelines.neighbor_search.execute(&mut builder);
-        pipelines.density_alpha.execute(&mut builder);
-        builder
-            .copy_buffer(CopyBufferInfo::buffers(
-                physics_data.densities.clone(),
-                staging.clone(),
-            ))
-            .unwrap();
-        let cb = builder.build().unwrap();
-        cb.execute(queue.clone())
-            .unwrap()
-            .then_signal_fence_and_flush()
-            .unwrap()
-            .wait(None)
-            .unwrap();
-    }
-    let rest: Vec<f32> = staging.read().unwrap().to_vec();
+    // Phase 1: read rest density (init pass only).
+    simulation.run_substeps(0);
+    let rest = simulation.read_densities();
     print_density_stats("at rest (before any substep)", &rest, target_density);
 
-    // Phase 2: warmup. We've already done the init pass above (neighbor_search
-    // + density_alpha), so the next command buffer can skip init.
-    submit_substeps(
-        &cb_allocator,
-        &queue,
-        &pipelines,
-        iter_count,
-        warmup,
-        false,
-    );
+    // Phase 2: warmup. The init pass above already built the neighbor
+    // structure, so this batch skips it.
+    simulation.run_substeps(warmup);
 
     // Phase 3: read post-warmup densities.
-    {
-        let mut builder = AutoCommandBufferBuilder::primary(
-            cb_allocator.clone(),
-            queue.queue_family_index(),
-            CommandBufferUsage::OneTimeSubmit,
-        )
-        .unwrap();
-        builder
-            .copy_buffer(CopyBufferInfo::buffers(
-                physics_data.densities.clone(),
-                staging.clone(),
-            ))
-            .unwrap();
-        let cb = builder.build().unwrap();
-        cb.execute(queue.clone())
-            .unwrap()
-            .then_signal_fence_and_flush()
-            .unwrap()
-            .wait(None)
-            .unwrap();
-    }
-    let post: Vec<f32> = staging.read().unwrap().to_vec();
+    let post = simulation.read_densities();
     print_density_stats(
         &format!("after {} substeps with K={}", warmup, iter_count),
         &post,
@@ -748,18 +409,14 @@ fn write_cfl_csv(path: &str, rows: &[(u32, &str, f32, f32, u32)]) {
     }
 }
 
-fn read_max_speed(physics_data: &GpuPhysicsData) -> f32 {
-    let stats = physics_data.stats_buffer.read().unwrap();
-    f32::from_bits(stats[0])
+fn read_max_speed(simulation: &Simulation) -> f32 {
+    simulation.read_stats().unwrap().max_speed
 }
 
 #[test]
 #[ignore]
 fn cfl_comparison() {
-    let ctx = make_context();
-    let device = ctx.device().clone();
-    let memory_allocator = ctx.memory_allocator().clone();
-    let queue = ctx.graphics_queue().clone();
+    let ctx = create_headless_context();
 
     let particle_radius = 0.020f32;
     let target_density = 1000.0f32;
@@ -786,67 +443,38 @@ fn cfl_comparison() {
 
         println!("--- Tryb: {} (iters={}) ---", mode_label, iter_count);
 
-        let cb_allocator = Arc::new(StandardCommandBufferAllocator::new(
-            device.clone(),
-            StandardCommandBufferAllocatorCreateInfo::default(),
-        ));
-        let ds_allocator = Arc::new(StandardDescriptorSetAllocator::new(
-            device.clone(),
-            StandardDescriptorSetAllocatorCreateInfo::default(),
-        ));
-
         let mut dt = STATIC_DT;
 
-        let physics_data =
-            GpuPhysicsData::new(memory_allocator.clone(), initial_positions.clone());
-
-        // Host-accessible so we can update dt each frame.
-        let sim_params_buffer = Buffer::from_data(
-            memory_allocator.clone(),
-            BufferCreateInfo { usage: BufferUsage::UNIFORM_BUFFER, ..Default::default() },
-            AllocationCreateInfo {
-                memory_type_filter: MemoryTypeFilter::PREFER_HOST
-                    | MemoryTypeFilter::HOST_RANDOM_ACCESS,
-                ..Default::default()
-            },
+        let mut simulation = Simulation::new(
+            ctx.clone(),
+            &initial_positions,
             SimulationParams::new(
                 particle_radius, particle_mass, smoothing_radius, target_density,
                 0.15, 0.5, dt, iter_count, iter_count,
                 Vec3::new(0.0, -9.81, 0.0), box_min, box_max,
                 IVec3::new(128, 128, 128),
             ),
-        )
-        .unwrap();
-
-        let mut pipelines = ComputePipelines::new(
-            device.clone(),
-            memory_allocator.clone(),
-            physics_data.grid_entries.len() as u32,
-        );
-        prepare_all_pipelines(
-            &mut pipelines,
-            ds_allocator.clone(),
-            &physics_data,
-            &sim_params_buffer,
         );
 
         for frame in 0..CFL_FRAMES {
             // Update Δt via CFL formula before each submission (skip frame 0 —
             // stats buffer is zeroed and v_max=0 would give dt=CFL_MAX_DT).
             if use_cfl && frame > 0 {
-                let v_max = read_max_speed(&physics_data);
+                let v_max = read_max_speed(&simulation);
                 if v_max > 0.01 {
                     let cfl_dt =
                         (CFL_LAMBDA * smoothing_radius / v_max).clamp(CFL_MIN_DT, CFL_MAX_DT);
                     // Limit growth to 10 % per frame to avoid abrupt jumps.
                     dt = cfl_dt.min(dt * 1.1_f32);
-                    sim_params_buffer.write().unwrap().dt = dt;
+                    let mut params = *simulation.params();
+                    params.dt = dt;
+                    simulation.set_params(params);
                 }
             }
 
-            submit_substeps(&cb_allocator, &queue, &pipelines, iter_count, 1, frame == 0);
+            simulation.run_substeps(1);
 
-            let v_max = read_max_speed(&physics_data);
+            let v_max = read_max_speed(&simulation);
 
             if frame % 50 == 0 {
                 println!(
@@ -0,0 +1,1333 @@
+#![cfg(test)]
+//
+// GPU-vs-CPU cross-validation of every solver compute step.
+//
+// Each test builds the same small deterministic block (jittered lattice, seeded
+// random velocities, two phases), lets `Simulation` run its init pass (neighbor search +
+// density/α), uploads fresh inputs for one step, dispatches that step once and
+// compares the output buffers with the matching function in `crate::cpu::steps`
+// evaluated on the same inputs. Everything is compared in the GPU's sorted
+// order (`position_b` / `velocity_b`), where CPU and GPU walk neighbors in the
+// same order, so the tolerance only absorbs FMA and rounding differences.
+//
+// Needs a Vulkan device; lavapipe is enough:
+//     cargo test -p fluid_engine cross_validation
+
+use std::sync::Arc;
+use glam::{IVec3, Quat, Vec3, Vec4};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::{ClearColorImageInfo, CopyBufferToImageInfo, CopyImageToBufferInfo};
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::format::{ClearColorValue, Format};
+use vulkano::image::view::ImageView;
+use vulkano::image::{Image, ImageCreateInfo, ImageType, ImageUsage};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter};
+
+use crate::core::simulation::{create_headless_context, Simulation};
+use crate::cpu::boundary::BoundaryParticles;
+use crate::cpu::flip_grid::MacGrid;
+use crate::cpu::kernel::{cell_coords, cell_hash};
+use crate::cpu::marching_cubes::{DensityGrid, SurfaceMesh, ISO_LEVEL};
+use crate::cpu::mesh_sdf::MeshSdf;
+use crate::cpu::neighbor_grid::NeighborGrid;
+use crate::cpu::steps;
+use crate::entities::diffuse::{DiffuseParams, DiffuseParticles, DiffuseSettings, GpuDiffuseParticle};
+use crate::entities::emitter::{EmittedParticle, GpuEmittedParticle};
+use crate::entities::obstacle::{Obstacle, ObstacleShape};
+use crate::entities::particle::{
+    particle_phase, GpuPhase, GpuPhysicsData, GridTransfer, PressureSolve, PressureSolverState, SimulationBackend, SimulationParams, SphKernel,
+    ViscosityMode, DEFAULT_FLUID_COLOR,
+};
+use crate::entities::surface::GpuSurfaceMesh;
+use crate::renderer::pipelines::density_texture::DensityTexturePipeline;
+use crate::renderer::pipelines::emit::EmitPipeline;
+use crate::renderer::pipelines::marching_cubes::MarchingCubesPipeline;
+use crate::renderer::pipelines::ssfr_smooth::DepthSmoothPipeline;
+use crate::renderer::pipelines::ComputeStep;
+
+// ── Configuration ────────────────────────────────────────────────────────────
+
+const PARTICLE_RADIUS: f32 = 0.02;
+const TARGET_DENSITY: f32 = 1000.0;
+// 8³ = 512 particles in a 512-entry hash table, so collisions are exercised too.
+const EDGE: usize = 8;
+const SEED: u64 = 0x5eed;
+const REL_TOL: f32 = 1e-3;
+
+// ── Fixture ──────────────────────────────────────────────────────────────────
+
+struct Fixture {
+    sim: Simulation,
+    params: SimulationParams,
+    rng: StdRng,
+    grid: NeighborGrid,
+    boundary: BoundaryParticles,
+    obstacles: Vec<Obstacle>,
+
+    initial_velocities: Vec<Vec3>,
+
+    // GPU state after the init pass, in sorted order.
+    positions: Vec<Vec3>,
+    phases: Vec<u32>,
+    velocities: Vec<Vec3>,
+    densities: Vec<f32>,
+    factors: Vec<f32>,
+}
+
+impl Fixture {
+    fn new() -> Self {
+        Self::with_kernel(SphKernel::CubicSpline)
+    }
+
+    fn with_kernel(kernel: SphKernel) -> Self {
+        let mut rng = StdRng::seed_from_u64(SEED);
+        let spacing = 2.0 * PARTICLE_RADIUS;
+
+        let mut initial_positions = Vec::with_capacity(EDGE * EDGE * EDGE);
+        for x in 0..EDGE {
+            for y in 0..EDGE {
+                for z in 0..EDGE {
+                    let lattice = Vec3::new(x as f32, y as f32, z as f32) * spacing + Vec3::splat(PARTICLE_RADIUS);
+                    let jitter = random_vec3(&mut rng, 0.1 * PARTICLE_RADIUS);
+                    initial_positions.push((lattice + jitter).to_array());
+                }
+            }
+        }
+
+        // The box hugs the block, so random velocities push the outer layer into
+        // the walls and pressure_integration's collision branches get covered.
+        let extent = EDGE as f32 * spacing;
+        let mut params = SimulationParams::new(
+            PARTICLE_RADIUS,
+            TARGET_DENSITY * spacing.powi(3),
+            4.0 * PARTICLE_RADIUS,
+            TARGET_DENSITY,
+            0.15,
+            0.5,
+            0.005,
+            4,
+            4,
+            Vec3::new(0.0, -9.81, 0.0),
+            Vec3::ZERO,
+            Vec3::splat(extent),
+            IVec3::splat(128),
+        );
+        // Non-zero so the surface tension, wall adhesion and vorticity
+        // confinement branches are covered.
+        params.surface_tension = 0.5;
+        params.adhesion = 2.0;
+        params.vorticity_confinement = 0.5;
+        params.set_kernel(kernel);
+        // The upper half is a lighter, thicker phase, so every per-phase term
+        // and the interface between the two are covered.
+        params.set_phases(DEFAULT_FLUID_COLOR, &[GpuPhase::new([0.9, 0.7, 0.1], 700.0, 0.3, 0.8)]);
+        let initial_phases: Vec<u32> = (0..initial_positions.len()).map(|i| ((i / EDGE) % EDGE >= EDGE / 2) as u32).collect();
+
+        let n = initial_positions.len() as u32;
+        let mut sim = Simulation::with_capacity(create_headless_context(), &initial_positions, &initial_phases, params, n);
+        let obstacles = obstacles(extent);
+        sim.set_obstacles(&obstacles);
+        let initial_velocities: Vec<Vec3> = (0..initial_positions.len()).map(|_| random_vec3(&mut rng, 0.5)).collect();
+        sim.write_buffer(&sim.physics_data().velocity_a, &to_vec4(&initial_velocities));
+        sim.run_substeps(0);
+
+        let data = sim.physics_data();
+        let sorted_positions = sim.read_buffer(&data.position_b);
+        let phases = sorted_positions.iter().map(|p| particle_phase(p[3])).collect();
+        let positions = to_vec3(sorted_positions);
+        let velocities = to_vec3(sim.read_buffer(&data.velocity_b));
+        let densities = sim.read_buffer(&data.densities);
+        let factors = sim.read_buffer(&data.factors);
+
+        let mut grid = NeighborGrid::new(positions.len());
+        grid.build(&positions, params.smoothing_radius);
+        let boundary = BoundaryParticles::for_box(&params);
+
+        Self { sim, params, rng, grid, boundary, obstacles, initial_velocities, positions, phases, velocities, densities, factors }
+    }
+
+    fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    fn random_vectors(&mut self, scale: f32) -> Vec<Vec3> {
+        (0..self.len()).map(|_| random_vec3(&mut self.rng, scale)).collect()
+    }
+
+    fn random_scalars(&mut self, min: f32, max: f32) -> Vec<f32> {
+        (0..self.len()).map(|_| self.rng.random_range(min..max)).collect()
+    }
+
+    fn upload_vec3(&self, destination: &Subbuffer<[[f32; 4]]>, data: &[Vec3]) {
+        self.sim.write_buffer(destination, &to_vec4(data));
+    }
+
+    /// Uploads `predicted` with the phase tags of `position_b`, which the
+    /// predicted-position passes read from `.w`.
+    fn upload_predicted(&self, predicted: &[Vec3]) {
+        let data = self.sim.physics_data();
+        let tagged: Vec<[f32; 4]> = self.sim.read_buffer(&data.position_b)
+            .iter()
+            .zip(predicted)
+            .map(|(p, v)| [v.x, v.y, v.z, p[3]])
+            .collect();
+        self.sim.write_buffer(&data.predicted_positions, &tagged);
+    }
+
+    fn read_vec3(&self, source: &Subbuffer<[[f32; 4]]>) -> Vec<Vec3> {
+        to_vec3(self.sim.read_buffer(source))
+    }
+
+    /// Switches to the FLIP backend with `transfer` and uploads random
+    /// velocities and APIC gradients for `flip_p2g.comp` to read; returns the
+    /// grid and the uploads.
+    fn flip(&mut self, transfer: GridTransfer) -> (MacGrid, Vec<Vec3>, Vec<[Vec3; 3]>) {
+        self.params.set_backend(SimulationBackend::Flip);
+        self.params.set_grid_transfer(transfer);
+        self.sim.set_params(self.params);
+        let velocities = self.random_vectors(0.5);
+        let affine: Vec<[Vec3; 3]> = (0..self.len()).map(|_| std::array::from_fn(|_| random_vec3(&mut self.rng, 2.0))).collect();
+        let data = self.sim.physics_data();
+        self.upload_vec3(&data.velocity_a, &velocities);
+        self.upload_vec3(&data.affine_b, affine.as_flattened());
+        (MacGrid::new(&self.params, self.params.flip_grid_dims()), velocities, affine)
+    }
+
+    fn dispatch(&self, step: &impl ComputeStep) {
+        self.sim.submit(|builder| step.execute(builder));
+    }
+
+    /// For the passes that are prepared with their own images or buffers.
+    fn descriptor_set_allocator(&self) -> Arc<StandardDescriptorSetAllocator> {
+        Arc::new(StandardDescriptorSetAllocator::new(self.sim.context().device().clone(), Default::default()))
+    }
+
+    /// Diffuse particles over this fixture's fluid, with room for `capacity`.
+    fn diffuse(&self, params: DiffuseParams, capacity: u32) -> DiffuseParticles {
+        let device = self.sim.context().device().clone();
+        let allocator = Arc::new(StandardDescriptorSetAllocator::new(device.clone(), Default::default()));
+        let settings = DiffuseSettings { enabled: true, capacity, params };
+        let diffuse = DiffuseParticles::new(self.sim.context().memory_allocator().clone(), allocator, device, &self.sim, &settings);
+        self.sim.submit(|builder| diffuse.record_clear(builder));
+        diffuse
+    }
+}
+
+// ── Helpers ──────────────────────────────────────────────────────────────────
+
+/// One obstacle per shape, each cutting into the block so every branch of the
+/// obstacle response runs. The sphere spins and the box slides to cover the
+/// moving-surface terms.
+fn obstacles(extent: f32) -> Vec<Obstacle> {
+    let tetrahedron = [Vec3::ZERO, Vec3::X, Vec3::Y, Vec3::Z].map(|v| v * 0.1);
+    let mesh = MeshSdf::bake(&tetrahedron, &[[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]], PARTICLE_RADIUS, 0.0);
+
+    let mut sphere = Obstacle::new(ObstacleShape::Sphere { radius: 0.05 }, Vec3::splat(0.5 * extent), Quat::IDENTITY);
+    sphere.angular_velocity = Vec3::new(0.0, 2.0, 0.0);
+    let mut slab = Obstacle::new(ObstacleShape::Box { half_extents: Vec3::new(0.06, 0.02, 0.04) }, Vec3::new(extent, 0.0, 0.0), Quat::from_rotation_y(0.5));
+    slab.velocity = Vec3::new(-0.3, 0.2, 0.0);
+    vec![
+        sphere,
+        Obstacle::new(
+            ObstacleShape::Capsule { radius: 0.03, half_height: 0.05 },
+            Vec3::new(0.0, 0.5 * extent, extent),
+            Quat::from_rotation_x(1.0),
+        ),
+        slab,
+        Obstacle::new(ObstacleShape::Mesh(Arc::new(mesh)), Vec3::new(0.0, extent - 0.1, 0.0), Quat::IDENTITY),
+    ]
+}
+
+fn random_vec3(rng: &mut StdRng, scale: f32) -> Vec3 {
+    Vec3::new(
+        rng.random_range(-1.0f32..1.0),
+        rng.random_range(-1.0f32..1.0),
+        rng.random_range(-1.0f32..1.0),
+    ) * scale
+}
+
+fn to_vec3(data: Vec<[f32; 4]>) -> Vec<Vec3> {
+    data.into_iter().map(|v| Vec3::new(v[0], v[1], v[2])).collect()
+}
+
+fn to_vec4(data: &[Vec3]) -> Vec<[f32; 4]> {
+    data.iter().map(|v| [v.x, v.y, v.z, 0.0]).collect()
+}
+
+/// Element-wise comparison with a tolerance relative to the largest CPU value,
+/// so near-zero entries of a buffer are not held to a relative bound.
+fn assert_close(name: &str, gpu: &[f32], cpu: &[f32]) {
+    assert_eq!(gpu.len(), cpu.len(), "{name}: length mismatch");
+    let scale = cpu.iter().fold(0.0f32, |m, v| m.max(v.abs())).max(1e-6);
+    for (i, (g, c)) in gpu.iter().zip(cpu).enumerate() {
+        assert!(
+            (g - c).abs() <= REL_TOL * scale,
+            "{name}[{i}]: gpu {g} vs cpu {c} (scale {scale})"
+        );
+    }
+}
+
+fn assert_close_vec3(name: &str, gpu: &[Vec3], cpu: &[Vec3]) {
+    let flatten = |v: &[Vec3]| v.iter().flat_map(|p| p.to_array()).collect::<Vec<f32>>();
+    assert_close(name, &flatten(gpu), &flatten(cpu));
+}
+
+/// xyz and w hold different quantities (d_ii and a_ii, Σ d_ij p_j and p_i),
+/// so each gets its own scale.
+fn assert_close_vec4(name: &str, gpu: &[[f32; 4]], cpu: &[Vec4]) {
+    let gpu_xyz: Vec<Vec3> = gpu.iter().map(|v| Vec3::new(v[0], v[1], v[2])).collect();
+    let cpu_xyz: Vec<Vec3> = cpu.iter().map(|v| v.truncate()).collect();
+    assert_close_vec3(&format!("{name}.xyz"), &gpu_xyz, &cpu_xyz);
+    let gpu_w: Vec<f32> = gpu.iter().map(|v| v[3]).collect();
+    let cpu_w: Vec<f32> = cpu.iter().map(|v| v.w).collect();
+    assert_close(&format!("{name}.w"), &gpu_w, &cpu_w);
+}
+
+// ── Tests ────────────────────────────────────────────────────────────────────
+
+#[test]
+fn neighbor_search_matches_cpu() {
+    let fx = Fixture::new();
+    let data = fx.sim.physics_data();
+    let entries = fx.sim.read_buffer(&data.grid_entries);
+    let grid_start = fx.sim.read_buffer(&data.grid_start);
+    let unsorted_positions = to_vec3(fx.sim.read_buffer(&data.position_a));
+    let table_size = grid_start.len() as u32;
+    let h = fx.params.smoothing_radius;
+
+    for (j, &entry) in entries.iter().take(fx.len()).enumerate() {
+        assert_eq!(entry.hash, cell_hash(cell_coords(fx.positions[j], h), table_size), "hash of entry {j}");
+        assert_eq!(fx.positions[j], unsorted_positions[entry.index as usize], "position_b[{j}] not reordered");
+        assert_eq!(fx.velocities[j], fx.initial_velocities[entry.index as usize], "velocity_b[{j}] not reordered");
+
+        if j > 0 {
+            assert!(entries[j - 1].hash <= entry.hash, "entries not sorted at {j}");
+        }
+        if j == 0 || entries[j - 1].hash != entry.hash {
+            assert_eq!(grid_start[entry.hash as usize], j as u32, "grid_start of hash {}", entry.hash);
+        }
+    }
+
+    let occupied = grid_start.iter().filter(|&&s| s != 0xFFFFFFFF).count();
+    let mut hashes: Vec<u32> = entries[..fx.len()].iter().map(|e| e.hash).collect();
+    hashes.dedup();
+    assert_eq!(occupied, hashes.len(), "stale grid_start entries");
+}
+
+#[test]
+fn density_alpha_matches_cpu() {
+    let fx = Fixture::new();
+    let mut densities = vec![0.0; fx.len()];
+    let mut factors = vec![0.0; fx.len()];
+    steps::density_alpha(&fx.grid, &fx.boundary, &fx.params, &fx.positions, &fx.phases, &mut densities, &mut factors);
+
+    assert_close("densities", &fx.densities, &densities);
+    assert_close("factors", &fx.factors, &factors);
+}
+
+#[test]
+fn density_alpha_matches_cpu_for_every_kernel() {
+    for kernel in SphKernel::ALL {
+        let fx = Fixture::with_kernel(kernel);
+        let mut densities = vec![0.0; fx.len()];
+        let mut factors = vec![0.0; fx.len()];
+        steps::density_alpha(&fx.grid, &fx.boundary, &fx.params, &fx.positions, &fx.phases, &mut densities, &mut factors);
+
+        assert_close(&format!("{kernel:?} densities"), &fx.densities, &densities);
+        assert_close(&format!("{kernel:?} factors"), &fx.factors, &factors);
+    }
+}
+
+#[test]
+fn density_alpha_matches_cpu_with_the_box_moved() {
+    let mut fx = Fixture::new();
+    // Turned, shifted and with the min-x wall pulled in, all within what the
+    // wall samples follow without being resampled.
+    let extent = EDGE as f32 * 2.0 * PARTICLE_RADIUS;
+    fx.params.box_min = [0.02, -0.01, 0.0, 0.0];
+    fx.params.box_max = [extent, extent - 0.01, extent, 0.0];
+    fx.params.box_rotation = Quat::from_rotation_z(0.1).to_array();
+    assert!(fx.boundary.matches(&fx.params));
+    fx.sim.set_params(fx.params);
+
+    fx.dispatch(&fx.sim.pipelines().density_alpha);
+    let data = fx.sim.physics_data();
+    let gpu_densities = fx.sim.read_buffer(&data.densities);
+    let gpu_factors = fx.sim.read_buffer(&data.factors);
+
+    let mut densities = vec![0.0; fx.len()];
+    let mut factors = vec![0.0; fx.len()];
+    steps::density_alpha(&fx.grid, &fx.boundary, &fx.params, &fx.positions, &fx.phases, &mut densities, &mut factors);
+
+    assert_close("densities", &gpu_densities, &densities);
+    assert_close("factors", &gpu_factors, &factors);
+}
+
+#[test]
+fn surface_normals_match_cpu() {
+    let fx = Fixture::new();
+    fx.dispatch(&fx.sim.pipelines().surface_normals);
+    let gpu = fx.read_vec3(&fx.sim.physics_data().normals);
+
+    let mut cpu = vec![Vec3::ZERO; fx.len()];
+    steps::surface_normals(&fx.grid, &fx.params, &fx.positions, &fx.phases, &fx.densities, &mut cpu);
+
+    assert_close_vec3("normals", &gpu, &cpu);
+}
+
+#[test]
+fn viscosity_matches_cpu() {
+    let mut fx = Fixture::new();
+    let normals = fx.random_vectors(1.0);
+    fx.upload_vec3(&fx.sim.physics_data().normals, &normals);
+
+    fx.dispatch(&fx.sim.pipelines().viscosity);
+    let gpu = fx.read_vec3(&fx.sim.physics_data().velocity_a);
+
+    let mut cpu = vec![Vec3::ZERO; fx.len()];
+    steps::viscosity(&fx.grid, &fx.params, &fx.positions, &fx.phases, &fx.velocities, &fx.densities, &normals, &mut cpu);
+
+    assert_close_vec3("velocity_a", &gpu, &cpu);
+}
+
+#[test]
+fn viscosity_solver_matches_cpu() {
+    let mut fx = Fixture::new();
+    fx.params.set_viscosity_mode(ViscosityMode::Implicit);
+    fx.params.viscosity = 2.0;
+    // Tight enough that both solves end up next to the exact solution, whatever
+    // their rounding does to the iteration count.
+    fx.params.viscosity_tolerance = 1e-5;
+    fx.sim.set_params(fx.params);
+    let velocities = fx.random_vectors(1.0);
+    let data = fx.sim.physics_data();
+    fx.upload_vec3(&data.velocity_a, &velocities);
+
+    let solver = &fx.sim.pipelines().viscosity_solver;
+    fx.sim.submit(|builder| {
+        solver.execute(builder);
+        for _ in 0..fx.params.viscosity_iterations {
+            solver.execute_iteration(builder);
+        }
+    });
+    let gpu = fx.read_vec3(&data.velocity_a);
+    let gpu_state = data.viscosity_solver.read().expect("solver buffer still in use")[0];
+
+    let mut cpu = velocities.clone();
+    let cpu_state = steps::viscosity_solve(&fx.grid, &fx.params, &fx.positions, &fx.phases, &fx.densities, &mut cpu);
+
+    assert_close_vec3("velocity_a", &gpu, &cpu);
+    assert_eq!(gpu_state.converged, 1, "gpu {gpu_state:?}");
+    assert_eq!(cpu_state.converged, 1, "cpu {cpu_state:?}");
+    assert!(gpu_state.iterations.abs_diff(cpu_state.iterations) <= 2, "gpu {gpu_state:?} vs cpu {cpu_state:?}");
+}
+
+#[test]
+fn vorticity_matches_cpu() {
+    let mut fx = Fixture::new();
+    let velocities = fx.random_vectors(0.5);
+    fx.upload_vec3(&fx.sim.physics_data().velocity_a, &velocities);
+
+    fx.dispatch(&fx.sim.pipelines().vorticity);
+    let gpu = fx.read_vec3(&fx.sim.physics_data().vorticities);
+
+    let mut cpu = vec![Vec3::ZERO; fx.len()];
+    steps::vorticity(&fx.grid, &fx.params, &velocities, &fx.positions, &fx.phases, &fx.densities, &mut cpu);
+
+    assert_close_vec3("vorticities", &gpu, &cpu);
+}
+
+#[test]
+fn vorticity_confinement_matches_cpu() {
+    let mut fx = Fixture::new();
+    let velocities = fx.random_vectors(0.5);
+    let vorticities = fx.random_vectors(10.0);
+    let data = fx.sim.physics_data();
+    fx.upload_vec3(&data.velocity_a, &velocities);
+    fx.upload_vec3(&data.vorticities, &vorticities);
+
+    fx.dispatch(&fx.sim.pipelines().vorticity_confinement);
+    let gpu = fx.read_vec3(&data.velocity_a);
+
+    let mut cpu = velocities.clone();
+    steps::vorticity_confinement(&fx.grid, &fx.params, &fx.positions, &fx.phases, &fx.densities, &vorticities, &mut cpu);
+
+    assert_close_vec3("velocity_a", &gpu, &cpu);
+}
+
+#[test]
+fn density_source_term_matches_cpu() {
+    let mut fx = Fixture::new();
+    let velocities = fx.random_vectors(0.5);
+    let stale_pressures = fx.random_scalars(0.0, 2000.0);
+    let data = fx.sim.physics_data();
+    fx.upload_vec3(&data.velocity_a, &velocities);
+    fx.sim.write_buffer(&data.pressures, &stale_pressures);
+
+    fx.dispatch(&fx.sim.pipelines().density_source_term);
+    let gpu_source = fx.sim.read_buffer(&data.source_terms);
+    let gpu_pressures = fx.sim.read_buffer(&data.pressures);
+
+    let mut cpu_source = vec![0.0; fx.len()];
+    let mut cpu_pressures = stale_pressures.clone();
+    steps::density_source_term(&fx.grid, &fx.boundary, &fx.params, &fx.positions, &fx.phases, &fx.densities, &velocities, &mut cpu_pressures, &mut cpu_source);
+
+    assert_close("source_terms", &gpu_source, &cpu_source);
+    assert!(gpu_pressures.iter().all(|&p| p == 0.0), "pressures not reset");
+}
+
+#[test]
+fn pressure_force_matches_cpu() {
+    let mut fx = Fixture::new();
+    let pressures = fx.random_scalars(0.0, 2000.0);
+    let data = fx.sim.physics_data();
+    fx.sim.write_buffer(&data.pressures, &pressures);
+
+    fx.dispatch(&fx.sim.pipelines().pressure_force);
+    let gpu = fx.read_vec3(&data.pressure_accelerations);
+
+    let mut cpu = vec![Vec3::ZERO; fx.len()];
+    steps::pressure_force(&fx.grid, &fx.boundary, &fx.params, &fx.positions, &fx.phases, &pressures, &fx.densities, &mut cpu);
+
+    assert_close_vec3("pressure_accelerations", &gpu, &cpu);
+}
+
+#[test]
+fn pressure_update_matches_cpu() {
+    let mut fx = Fixture::new();
+    let pressures = fx.random_scalars(0.0, 2000.0);
+    let accelerations = fx.random_vectors(5.0);
+    let source_terms = fx.random_scalars(-500.0, 500.0);
+    let data = fx.sim.physics_data();
+    fx.sim.write_buffer(&data.pressures, &pressures);
+    fx.upload_vec3(&data.pressure_accelerations, &accelerations);
+    fx.sim.write_buffer(&data.source_terms, &source_terms);
+
+    fx.dispatch(&fx.sim.pipelines().pressure_update);
+    let gpu = fx.sim.read_buffer(&data.pressures);
+    let gpu_residual: f32 = fx.sim.read_buffer(&data.pressure_partials)[..fx.len().div_ceil(256)].iter().sum();
+
+    let mut cpu = pressures.clone();
+    let cpu_residual = steps::pressure_update(&fx.grid, &fx.boundary, &fx.params, &fx.positions, &fx.phases, &accelerations, &fx.factors, &source_terms, &fx.densities, &mut cpu);
+
+    assert_close("pressures", &gpu, &cpu);
+    assert_close("residual", &[gpu_residual], &[cpu_residual]);
+}
+
+#[test]
+fn pressure_convergence_matches_cpu() {
+    let mut fx = Fixture::new();
+    let source_terms = fx.random_scalars(-500.0, 0.0);
+    let n = fx.len();
+    let iterations = 50;
+
+    // η at half the error the minimum iterations leave, so the solve stops
+    // somewhere past them.
+    let solve = |params: &SimulationParams| {
+        let mut pressures = vec![0.0; n];
+        let mut accelerations = vec![Vec3::ZERO; n];
+        let mut state = PressureSolverState::default();
+        state.begin(PressureSolve::Density);
+        for _ in 0..iterations {
+            steps::pressure_force(&fx.grid, &fx.boundary, params, &fx.positions, &fx.phases, &pressures, &fx.densities, &mut accelerations);
+            let residual = steps::pressure_update(&fx.grid, &fx.boundary, params, &fx.positions, &fx.phases, &accelerations, &fx.factors, &source_terms, &fx.densities, &mut pressures);
+            if state.check(params, residual, n as u32) {
+                break;
+            }
+        }
+        state
+    };
+    let mut first = fx.params;
+    first.max_density_error = f32::MAX;
+    fx.params.max_density_error = 0.5 * solve(&first).density_error / fx.params.target_density;
+    let cpu_state = solve(&fx.params);
+
+    fx.sim.set_params(fx.params);
+    let data = fx.sim.physics_data();
+    fx.sim.write_buffer(&data.pressures, &vec![0.0; n]);
+    fx.sim.write_buffer(&data.source_terms, &source_terms);
+    let pipelines = fx.sim.pipelines();
+    fx.sim.submit(|builder| {
+        pipelines.pressure_convergence.execute_begin(builder, PressureSolve::Density);
+        for _ in 0..iterations {
+            pipelines.pressure_force.execute_iteration(builder);
+            pipelines.pressure_update.execute_iteration(builder);
+            pipelines.pressure_convergence.execute(builder);
+        }
+    });
+    let gpu_state = data.pressure_solver.read().expect("solver buffer still in use")[0];
+
+    assert_eq!(cpu_state.converged, 1, "cpu {cpu_state:?}");
+    assert!(cpu_state.density_iterations > PressureSolve::Density.min_iterations(), "cpu {cpu_state:?}");
+    assert_eq!(gpu_state.converged, 1, "gpu {gpu_state:?}");
+    assert_eq!(gpu_state.dispatch, [0, 1, 1], "gpu {gpu_state:?}");
+    assert!(gpu_state.density_iterations.abs_diff(cpu_state.density_iterations) <= 1, "gpu {gpu_state:?} vs cpu {cpu_state:?}");
+    assert!(gpu_state.density_error < PressureSolve::Density.max_error(&fx.params), "gpu {gpu_state:?}");
+}
+
+#[test]
+fn pressure_integration_matches_cpu() {
+    let mut fx = Fixture::new();
+    let velocities = fx.random_vectors(0.5);
+    let accelerations = fx.random_vectors(5.0);
+    let data = fx.sim.physics_data();
+    fx.upload_vec3(&data.velocity_a, &velocities);
+    fx.upload_vec3(&data.pressure_accelerations, &accelerations);
+    data.obstacle_impulses.write().unwrap().fill(0);
+
+    fx.dispatch(&fx.sim.pipelines().pressure_integration);
+    let gpu_tagged = fx.sim.read_buffer(&data.position_a);
+    let gpu_phases: Vec<u32> = gpu_tagged.iter().map(|p| particle_phase(p[3])).collect();
+    let gpu_positions = to_vec3(gpu_tagged);
+    let gpu_velocities = fx.read_vec3(&data.velocity_a);
+    let gpu_impulses = data.obstacle_impulses.read().unwrap().to_vec();
+
+    let mut cpu_positions = fx.positions.clone();
+    let mut cpu_velocities = velocities;
+    let cpu_impulses = steps::pressure_integration(&fx.params, &fx.obstacles, &fx.phases, &accelerations, &mut cpu_positions, &mut cpu_velocities);
+
+    assert_close_vec3("position_a", &gpu_positions, &cpu_positions);
+    assert_close_vec3("velocity_a", &gpu_velocities, &cpu_velocities);
+    assert_eq!(gpu_phases, fx.phases, "phases lost in integration");
+
+    // Fixed point on both sides; rounding per contact adds up to one unit each.
+    let cpu_impulses: Vec<f32> = cpu_impulses
+        .iter()
+        .flat_map(|[linear, angular]| [linear.extend(0), angular.extend(0)])
+        .flat_map(|v| v.to_array())
+        .map(|v| v as f32)
+        .collect();
+    let gpu_impulses: Vec<f32> = gpu_impulses.iter().map(|&v| v as f32).collect();
+    assert!(cpu_impulses.iter().any(|&v| v != 0.0), "no particle hit an obstacle");
+    assert_close("obstacle_impulses", &gpu_impulses, &cpu_impulses);
+}
+
+#[test]
+fn divergence_source_term_matches_cpu() {
+    let mut fx = Fixture::new();
+    let velocities = fx.random_vectors(0.5);
+    let data = fx.sim.physics_data();
+    fx.upload_vec3(&data.velocity_b, &velocities);
+
+    fx.dispatch(&fx.sim.pipelines().divergence_source_term);
+    let gpu = fx.sim.read_buffer(&data.source_terms);
+
+    let mut cpu = vec![0.0; fx.len()];
+    steps::divergence_source_term(&fx.grid, &fx.boundary, &fx.params, &fx.positions, &fx.phases, &velocities, &mut cpu);
+
+    assert_close("source_terms", &gpu, &cpu);
+}
+
+/// The substep's divergence solve runs right after the post-integrate sort.
+/// The particles go in shuffled, and the GPU result is mapped back through
+/// the grid entries onto a CPU solve in that shuffled order. A pass that
+/// pairs sorted positions with unsorted velocities fails this.
+#[test]
+fn divergence_source_term_matches_cpu_after_the_reorder() {
+    let mut fx = Fixture::new();
+    let mut order: Vec<usize> = (0..fx.len()).collect();
+    order.shuffle(&mut fx.rng);
+    let sorted = fx.sim.read_buffer(&fx.sim.physics_data().position_b);
+    let tagged: Vec<[f32; 4]> = order.iter().map(|&i| sorted[i]).collect();
+    let positions: Vec<Vec3> = order.iter().map(|&i| fx.positions[i]).collect();
+    let phases: Vec<u32> = order.iter().map(|&i| fx.phases[i]).collect();
+    let velocities = fx.random_vectors(0.5);
+    let data = fx.sim.physics_data();
+    fx.sim.write_buffer(&data.position_a, &tagged);
+    fx.upload_vec3(&data.velocity_a, &velocities);
+
+    fx.dispatch(&fx.sim.pipelines().neighbor_search);
+    fx.dispatch(&fx.sim.pipelines().divergence_source_term);
+    let entries = fx.sim.read_buffer(&data.grid_entries);
+    let sorted_source = fx.sim.read_buffer(&data.source_terms);
+    let mut gpu = vec![0.0; fx.len()];
+    for (entry, source) in entries.iter().zip(&sorted_source).take(fx.len()) {
+        gpu[entry.index as usize] = *source;
+    }
+
+    let mut grid = NeighborGrid::new(positions.len());
+    grid.build(&positions, fx.params.smoothing_radius);
+    let mut cpu = vec![0.0; fx.len()];
+    steps::divergence_source_term(&grid, &fx.boundary, &fx.params, &positions, &phases, &velocities, &mut cpu);
+
+    assert_close("source_terms", &gpu, &cpu);
+}
+
+#[test]
+fn divergence_integration_matches_cpu() {
+    let mut fx = Fixture::new();
+    let velocities = fx.random_vectors(0.5);
+    let accelerations = fx.random_vectors(5.0);
+    let data = fx.sim.physics_data();
+    fx.upload_vec3(&data.velocity_b, &velocities);
+    fx.upload_vec3(&data.pressure_accelerations, &accelerations);
+    fx.sim.submit(|builder| {
+        builder.fill_buffer(data.position_a.clone().reinterpret::<[u32]>(), 0).unwrap();
+    });
+
+    fx.dispatch(&fx.sim.pipelines().divergence_integration);
+    let gpu = fx.read_vec3(&data.velocity_b);
+
+    let mut cpu = velocities;
+    steps::divergence_integration(&fx.params, &accelerations, &mut cpu);
+
+    assert_close_vec3("velocity_b", &gpu, &cpu);
+    // The sorted state is handed back to the a buffers.
+    assert_close_vec3("velocity_a", &fx.read_vec3(&data.velocity_a), &cpu);
+    assert_close_vec3("position_a", &fx.read_vec3(&data.position_a), &fx.positions);
+}
+
+#[test]
+fn wcsph_pressure_matches_cpu() {
+    let mut fx = Fixture::new();
+    let densities = fx.random_scalars(900.0, 1100.0);
+    let data = fx.sim.physics_data();
+    fx.sim.write_buffer(&data.densities, &densities);
+
+    fx.dispatch(&fx.sim.pipelines().wcsph_pressure);
+    let gpu = fx.sim.read_buffer(&data.pressures);
+
+    let mut cpu = vec![0.0; fx.len()];
+    steps::wcsph_pressure(&fx.params, &fx.phases, &densities, &mut cpu);
+
+    assert!(cpu.iter().any(|&p| p > 0.0), "no particle is compressed");
+    assert_close("pressures", &gpu, &cpu);
+}
+
+#[test]
+fn predict_positions_matches_cpu() {
+    let mut fx = Fixture::new();
+    let velocities = fx.random_vectors(0.5);
+    let accelerations = fx.random_vectors(5.0);
+    let data = fx.sim.physics_data();
+    fx.upload_vec3(&data.velocity_a, &velocities);
+    fx.upload_vec3(&data.pressure_accelerations, &accelerations);
+
+    fx.dispatch(&fx.sim.pipelines().predict_positions);
+    let gpu_tagged = fx.sim.read_buffer(&data.predicted_positions);
+    let gpu_phases: Vec<u32> = gpu_tagged.iter().map(|p| particle_phase(p[3])).collect();
+    let gpu = to_vec3(gpu_tagged);
+
+    let mut cpu = vec![Vec3::ZERO; fx.len()];
+    steps::predict_positions(&fx.params, &fx.positions, &velocities, &accelerations, &mut cpu);
+
+    assert_close_vec3("predicted_positions", &gpu, &cpu);
+    assert_eq!(gpu_phases, fx.phases, "phases lost in the prediction");
+}
+
+/// The fixture's positions moved by up to a fifth of the particle radius.
+fn predicted_positions(fx: &mut Fixture) -> Vec<Vec3> {
+    let offsets = fx.random_vectors(0.2 * PARTICLE_RADIUS);
+    fx.positions.iter().zip(offsets).map(|(p, o)| *p + o).collect()
+}
+
+#[test]
+fn pcisph_pressure_matches_cpu() {
+    let mut fx = Fixture::new();
+    let predicted = predicted_positions(&mut fx);
+    let pressures = fx.random_scalars(0.0, 2000.0);
+    fx.upload_predicted(&predicted);
+    let data = fx.sim.physics_data();
+    fx.sim.write_buffer(&data.pressures, &pressures);
+
+    fx.dispatch(&fx.sim.pipelines().pcisph_pressure);
+    let gpu_densities = fx.sim.read_buffer(&data.densities);
+    let gpu_pressures = fx.sim.read_buffer(&data.pressures);
+
+    let mut cpu_densities = vec![0.0; fx.len()];
+    let mut cpu_pressures = pressures;
+    steps::pcisph_pressure(&fx.grid, &fx.boundary, &fx.params, fx.params.pcisph_delta(), &predicted, &fx.phases, &mut cpu_densities, &mut cpu_pressures);
+
+    assert_close("densities", &gpu_densities, &cpu_densities);
+    assert_close("pressures", &gpu_pressures, &cpu_pressures);
+}
+
+fn iisph_diagonals(fx: &mut Fixture) -> (Vec<f32>, Vec<Vec4>) {
+    let velocities = fx.random_vectors(0.5);
+    let mut pressures = vec![0.0; fx.len()];
+    let mut source_terms = vec![0.0; fx.len()];
+    let mut diagonals = vec![Vec4::ZERO; fx.len()];
+    steps::iisph_diagonal(&fx.grid, &fx.boundary, &fx.params, &fx.positions, &fx.phases, &fx.densities, &velocities, &mut pressures, &mut source_terms, &mut diagonals);
+    (source_terms, diagonals)
+}
+
+#[test]
+fn iisph_diagonal_matches_cpu() {
+    let mut fx = Fixture::new();
+    let velocities = fx.random_vectors(0.5);
+    let stale_pressures = fx.random_scalars(0.0, 2000.0);
+    let data = fx.sim.physics_data();
+    fx.upload_vec3(&data.velocity_a, &velocities);
+    fx.sim.write_buffer(&data.pressures, &stale_pressures);
+
+    fx.dispatch(&fx.sim.pipelines().iisph_diagonal);
+    let gpu_source = fx.sim.read_buffer(&data.source_terms);
+    let gpu_diagonals = fx.sim.read_buffer(&data.iisph_diagonals);
+    let gpu_pressures = fx.sim.read_buffer(&data.pressures);
+
+    let mut cpu_source = vec![0.0; fx.len()];
+    let mut cpu_diagonals = vec![Vec4::ZERO; fx.len()];
+    let mut cpu_pressures = stale_pressures;
+    steps::iisph_diagonal(&fx.grid, &fx.boundary, &fx.params, &fx.positions, &fx.phases, &fx.densities, &velocities, &mut cpu_pressures, &mut cpu_source, &mut cpu_diagonals);
+
+    assert!(cpu_diagonals.iter().all(|d| d.w < 0.0), "a_ii not negative");
+    assert_close("source_terms", &gpu_source, &cpu_source);
+    assert_close_vec4("iisph_diagonals", &gpu_diagonals, &cpu_diagonals);
+    assert!(gpu_pressures.iter().all(|&p| p == 0.0), "pressures not reset");
+}
+
+#[test]
+fn iisph_pressure_sum_matches_cpu() {
+    let mut fx = Fixture::new();
+    let pressures = fx.random_scalars(0.0, 2000.0);
+    let data = fx.sim.physics_data();
+    fx.sim.write_buffer(&data.pressures, &pressures);
+
+    fx.dispatch(&fx.sim.pipelines().iisph_pressure_sum);
+    let gpu = fx.sim.read_buffer(&data.iisph_pressure_sums);
+
+    let mut cpu = vec![Vec4::ZERO; fx.len()];
+    steps::iisph_pressure_sum(&fx.grid, &fx.params, &fx.positions, &fx.phases, &fx.densities, &pressures, &mut cpu);
+
+    assert_close_vec4("iisph_pressure_sums", &gpu, &cpu);
+}
+
+#[test]
+fn iisph_pressure_matches_cpu() {
+    let mut fx = Fixture::new();
+    let (source_terms, diagonals) = iisph_diagonals(&mut fx);
+    let pressures = fx.random_scalars(0.0, 2000.0);
+    let mut sums = vec![Vec4::ZERO; fx.len()];
+    steps::iisph_pressure_sum(&fx.grid, &fx.params, &fx.positions, &fx.phases, &fx.densities, &pressures, &mut sums);
+    let data = fx.sim.physics_data();
+    fx.sim.write_buffer(&data.source_terms, &source_terms);
+    fx.sim.write_buffer(&data.iisph_diagonals, &diagonals.iter().map(|d| d.to_array()).collect::<Vec<_>>());
+    fx.sim.write_buffer(&data.iisph_pressure_sums, &sums.iter().map(|s| s.to_array()).collect::<Vec<_>>());
+
+    fx.dispatch(&fx.sim.pipelines().iisph_pressure);
+    let gpu = fx.sim.read_buffer(&data.pressures);
+
+    let mut cpu = vec![0.0; fx.len()];
+    steps::iisph_pressure(&fx.grid, &fx.boundary, &fx.params, &fx.positions, &fx.phases, &fx.densities, &source_terms, &diagonals, &sums, &mut cpu);
+
+    assert!(cpu.iter().any(|&p| p > 0.0), "no particle is compressed");
+    assert_close("pressures", &gpu, &cpu);
+}
+
+#[test]
+fn pbf_lambda_matches_cpu() {
+    let mut fx = Fixture::new();
+    let predicted = predicted_positions(&mut fx);
+    fx.upload_predicted(&predicted);
+
+    fx.dispatch(&fx.sim.pipelines().pbf_lambda);
+    let gpu = fx.sim.read_buffer(&fx.sim.physics_data().pressures);
+
+    let mut cpu = vec![0.0; fx.len()];
+    steps::pbf_lambda(&fx.grid, &fx.boundary, &fx.params, &predicted, &fx.phases, &mut cpu);
+
+    assert!(cpu.iter().any(|&l| l < 0.0), "no constraint is violated");
+    assert_close("lambdas", &gpu, &cpu);
+}
+
+#[test]
+fn pbf_correction_matches_cpu() {
+    let mut fx = Fixture::new();
+    let predicted = predicted_positions(&mut fx);
+    let accelerations = fx.random_vectors(5.0);
+    let mut lambdas = vec![0.0; fx.len()];
+    steps::pbf_lambda(&fx.grid, &fx.boundary, &fx.params, &predicted, &fx.phases, &mut lambdas);
+    fx.upload_predicted(&predicted);
+    let data = fx.sim.physics_data();
+    fx.sim.write_buffer(&data.pressures, &lambdas);
+    fx.upload_vec3(&data.pressure_accelerations, &accelerations);
+
+    fx.dispatch(&fx.sim.pipelines().pbf_correction);
+    let gpu = fx.read_vec3(&data.pressure_accelerations);
+
+    let mut cpu = accelerations;
+    steps::pbf_correction(&fx.grid, &fx.boundary, &fx.params, &predicted, &fx.phases, &lambdas, &mut cpu);
+
+    assert_close_vec3("pressure_accelerations", &gpu, &cpu);
+}
+
+#[test]
+fn stats_matches_cpu() {
+    let mut fx = Fixture::new();
+    let velocities = fx.random_vectors(2.0);
+    let source_terms = fx.random_scalars(-50.0, 50.0);
+    let data = fx.sim.physics_data();
+    fx.upload_vec3(&data.velocity_a, &velocities);
+    fx.sim.write_buffer(&data.source_terms, &source_terms);
+
+    fx.dispatch(&fx.sim.pipelines().stats);
+    let gpu = fx.sim.read_stats().expect("stats buffer still in use");
+    let cpu = steps::stats(&fx.params, &fx.phases, &velocities, &fx.densities, &source_terms);
+
+    assert!((gpu.max_speed - cpu.max_speed).abs() <= 1e-5 * cpu.max_speed, "max_speed: gpu {} vs cpu {}", gpu.max_speed, cpu.max_speed);
+    // A rounding difference may flip one fixed-point truncation per particle.
+    assert!((gpu.avg_density_error - cpu.avg_density_error).abs() <= 1.0, "density error: gpu {} vs cpu {}", gpu.avg_density_error, cpu.avg_density_error);
+    assert!((gpu.avg_divergence_error - cpu.avg_divergence_error).abs() <= 0.1, "divergence error: gpu {} vs cpu {}", gpu.avg_divergence_error, cpu.avg_divergence_error);
+}
+
+/// Splats `position_a` into a fresh `grid_res` density texture; returns the
+/// texture and a readback of it.
+fn splat_density(fx: &Fixture) -> (Arc<Image>, Vec<u32>) {
+    let allocator = fx.sim.context().memory_allocator().clone();
+    let extent = [0, 1, 2].map(|k| fx.params.grid_res[k] as u32);
+    let image = Image::new(
+        allocator.clone(),
+        ImageCreateInfo {
+            image_type: ImageType::Dim3d,
+            format: Format::R32_UINT,
+            extent,
+            usage: ImageUsage::STORAGE | ImageUsage::TRANSFER_SRC | ImageUsage::TRANSFER_DST,
+            ..Default::default()
+        },
+        AllocationCreateInfo::default(),
+    ).unwrap();
+    let readback = Buffer::new_slice::<u32>(
+        allocator,
+        BufferCreateInfo { usage: BufferUsage::TRANSFER_DST, ..Default::default() },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_HOST | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+            ..Default::default()
+        },
+        extent.iter().map(|&e| e as u64).product(),
+    ).unwrap();
+
+    let mut splat = DensityTexturePipeline::new(fx.sim.context().device().clone());
+    splat.prepare_with_image(fx.descriptor_set_allocator(), fx.sim.physics_data(), ImageView::new_default(image.clone()).unwrap(), fx.sim.sim_params_buffer());
+    fx.sim.submit(|builder| {
+        let mut clear_info = ClearColorImageInfo::image(image.clone());
+        clear_info.clear_value = ClearColorValue::Uint([0; 4]);
+        builder.clear_color_image(clear_info).unwrap();
+        splat.execute(builder);
+        builder.copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(image.clone(), readback.clone())).unwrap();
+    });
+    let values = readback.read().unwrap().to_vec();
+    (image, values)
+}
+
+#[test]
+fn splat_density_matches_cpu() {
+    let mut fx = Fixture::new();
+    // Coarse enough that a particle covers a few voxels and the 17³ stencil
+    // runs off the texture at the walls.
+    let res = 32;
+    fx.params.grid_res = [res, res, res, 0];
+    fx.sim.set_params(fx.params);
+
+    // Snapped to voxel centres, so GPU and CPU cannot disagree on which voxel
+    // a particle lands in.
+    let (domain_min, domain_max) = fx.params.domain();
+    let voxel = (domain_max - domain_min) / res as f32;
+    let positions: Vec<Vec3> = fx.positions
+        .iter()
+        .map(|&p| domain_min + (((p - domain_min) / voxel).floor() + 0.5) * voxel)
+        .collect();
+    let data = fx.sim.physics_data();
+    fx.upload_vec3(&data.position_a, &positions);
+
+    let (_, splatted) = splat_density(&fx);
+    let gpu: Vec<f32> = splatted.iter().map(|&v| v as f32).collect();
+
+    let mut cpu = vec![0; gpu.len()];
+    steps::splat_density(&fx.params, &positions, &mut cpu);
+    let cpu: Vec<f32> = cpu.into_iter().map(|v| v as f32).collect();
+
+    assert!(cpu.iter().any(|&v| v > 0.0), "nothing was splatted");
+    // Rounding may flip one integer truncation per contribution.
+    assert_close("density_texture", &gpu, &cpu);
+}
+
+/// Position and normal of each corner, in emission order.
+type SurfaceTriangle = [(Vec3, Vec3); 3];
+
+#[test]
+fn marching_cubes_matches_cpu() {
+    let mut fx = Fixture::new();
+    let res = 32;
+    fx.params.grid_res = [res, res, res, 0];
+    fx.sim.set_params(fx.params);
+    let (image, splatted) = splat_density(&fx);
+
+    let mesh = GpuSurfaceMesh::new(fx.sim.context().memory_allocator().clone(), 1 << 16);
+    let mut marching_cubes = MarchingCubesPipeline::new(fx.sim.context().device().clone());
+    marching_cubes.prepare_with_mesh(fx.descriptor_set_allocator(), ImageView::new_default(image).unwrap(), &mesh, fx.sim.sim_params_buffer());
+    fx.sim.submit(|builder| {
+        mesh.record_clear(builder);
+        marching_cubes.execute(builder);
+    });
+    let vertex_count = fx.sim.read_buffer(&mesh.draw_command)[0].vertex_count as usize;
+    assert_eq!(fx.sim.read_buffer(&mesh.reserved)[0] as usize, vertex_count, "vertex buffer overflowed");
+    let vertices = fx.sim.read_buffer(&mesh.vertices);
+    let gpu: Vec<SurfaceTriangle> = vertices[..vertex_count]
+        .chunks_exact(3)
+        .map(|t| std::array::from_fn(|k| (Vec4::from(t[k].position).truncate(), Vec4::from(t[k].normal).truncate())))
+        .collect();
+
+    let (box_min, box_max) = fx.params.domain();
+    let grid = DensityGrid {
+        values: splatted.iter().map(|&v| v as f32).collect(),
+        res: [res as usize; 3],
+        box_min,
+        box_max,
+    };
+    let cpu_mesh = grid.polygonize(ISO_LEVEL);
+    let cpu: Vec<SurfaceTriangle> = cpu_mesh.indices
+        .chunks_exact(3)
+        .map(|t| std::array::from_fn(|k| (cpu_mesh.positions[t[k] as usize], cpu_mesh.normals[t[k] as usize])))
+        .collect();
+
+    assert!(cpu.len() > 100, "only {} triangles", cpu.len());
+    assert_same_triangles(&gpu, &cpu, 1e-3 * grid.cell_size().min_element());
+
+    // The export welds the GPU soup by position. A corner that lands exactly
+    // on a grid node is shared by several edges and merges further than the
+    // CPU's per-edge welding, never less.
+    let exported = SurfaceMesh::weld(gpu.iter().flatten().copied());
+    assert_eq!(exported.triangle_count(), cpu_mesh.triangle_count());
+    assert!(
+        exported.positions.len() <= cpu_mesh.positions.len() && 2 * exported.positions.len() < vertex_count,
+        "{} welded vertices for {} corners, cpu has {}", exported.positions.len(), vertex_count, cpu_mesh.positions.len()
+    );
+}
+
+/// Every CPU triangle has a GPU twin with the same corners in the same order.
+/// Cells write their triangles in whatever order the atomics hand out slots,
+/// so the GPU triangles are looked up by the x of their first corner.
+fn assert_same_triangles(gpu: &[SurfaceTriangle], cpu: &[SurfaceTriangle], tolerance: f32) {
+    assert_eq!(gpu.len(), cpu.len(), "triangle count");
+    let mut sorted: Vec<(f32, usize)> = gpu.iter().enumerate().map(|(i, t)| (t[0].0.x, i)).collect();
+    sorted.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let mut matched = vec![false; gpu.len()];
+
+    for (i, triangle) in cpu.iter().enumerate() {
+        let x = triangle[0].0.x;
+        let start = sorted.partition_point(|&(key, _)| key < x - tolerance);
+        let twin = sorted[start..]
+            .iter()
+            .take_while(|&&(key, _)| key <= x + tolerance)
+            .map(|&(_, j)| j)
+            .find(|&j| {
+                !matched[j] && gpu[j].iter().zip(triangle).all(|((gp, gn), (cp, cn))| {
+                    gp.distance(*cp) <= tolerance && gn.distance(*cn) <= 1e-3
+                })
+            });
+        match twin {
+            Some(j) => matched[j] = true,
+            None => panic!("cpu triangle {i} {triangle:?} has no gpu twin"),
+        }
+    }
+}
+
+#[test]
+fn depth_smooth_matches_cpu() {
+    let mut fx = Fixture::new();
+    let (width, height) = (96, 64);
+    let particle_radius = PARTICLE_RADIUS;
+    // About ten pixels of filter radius at depth 1.
+    let pixels_per_unit = 80.0;
+
+    // An ellipse of bumpy fluid on a zero background, with a depth step
+    // across its middle for the range weight to keep.
+    let depth: Vec<f32> = (0..width * height)
+        .map(|i| {
+            let (x, y) = ((i % width) as f32, (i / width) as f32);
+            let inside = ((x - 48.0) / 40.0).powi(2) + ((y - 32.0) / 26.0).powi(2) < 1.0;
+            if !inside {
+                return 0.0;
+            }
+            let step = if x > 60.0 { 0.3 } else { 0.0 };
+            1.0 + step + 0.01 * (0.7 * x).sin() * (0.9 * y).cos() + fx.rng.random_range(-0.003f32..0.003)
+        })
+        .collect();
+
+    let allocator = fx.sim.context().memory_allocator().clone();
+    let image = || {
+        let image = Image::new(
+            allocator.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: Format::R32_SFLOAT,
+                extent: [width as u32, height as u32, 1],
+                usage: ImageUsage::STORAGE | ImageUsage::TRANSFER_SRC | ImageUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        ).unwrap();
+        ImageView::new_default(image).unwrap()
+    };
+    let (depth_image, scratch_image) = (image(), image());
+    let upload = Buffer::from_iter(
+        allocator.clone(),
+        BufferCreateInfo { usage: BufferUsage::TRANSFER_SRC, ..Default::default() },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_HOST | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        },
+        depth.iter().copied(),
+    ).unwrap();
+    let readback = Buffer::new_slice::<f32>(
+        allocator,
+        BufferCreateInfo { usage: BufferUsage::TRANSFER_DST, ..Default::default() },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_HOST | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+            ..Default::default()
+        },
+        (width * height) as u64,
+    ).unwrap();
+
+    let mut smooth = DepthSmoothPipeline::new(fx.sim.context().device().clone());
+    smooth.prepare_with_images(fx.descriptor_set_allocator(), depth_image.clone(), scratch_image);
+    smooth.set_filter_scale(particle_radius, pixels_per_unit);
+    fx.sim.submit(|builder| {
+        builder.copy_buffer_to_image(CopyBufferToImageInfo::buffer_image(upload.clone(), depth_image.image().clone())).unwrap();
+        smooth.execute(builder);
+        builder.copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(depth_image.image().clone(), readback.clone())).unwrap();
+    });
+    let gpu = readback.read().unwrap().to_vec();
+
+    let mut scratch = vec![0.0; depth.len()];
+    let mut cpu = vec![0.0; depth.len()];
+    steps::ssfr_smooth(width, height, [1, 0], particle_radius, pixels_per_unit, &depth, &mut scratch);
+    steps::ssfr_smooth(width, height, [0, 1], particle_radius, pixels_per_unit, &scratch, &mut cpu);
+
+    assert!(cpu.iter().zip(&depth).any(|(c, d)| (c - d).abs() > 1e-3), "nothing was smoothed");
+    assert_close("depth", &gpu, &cpu);
+}
+
+#[test]
+fn emit_fills_the_slots_behind_the_live_particles() {
+    let mut fx = Fixture::new();
+    let n = fx.len();
+    let capacity = n + 64;
+    let initial: Vec<[f32; 3]> = fx.positions.iter().map(|p| p.to_array()).collect();
+    let sim = Simulation::with_capacity(fx.sim.context().clone(), &initial, &fx.phases, fx.params, capacity as u32);
+    let data = sim.physics_data();
+    // Stale values the emission has to overwrite.
+    sim.write_buffer(&data.pressures, &vec![1.0; capacity]);
+    sim.write_buffer(&data.affine_a, &vec![[1.0; 4]; 3 * capacity]);
+    let live = sim.read_buffer(&data.position_a)[..n].to_vec();
+
+    // 100 particles for 64 free slots; the rest are dropped.
+    let emitted: Vec<GpuEmittedParticle> = (0..100)
+        .map(|i| EmittedParticle {
+            position: random_vec3(&mut fx.rng, 0.1) + Vec3::splat(0.15),
+            velocity: random_vec3(&mut fx.rng, 1.0),
+            phase: i % 2,
+        }.to_gpu())
+        .collect();
+    let mut emit = EmitPipeline::new(sim.context().device().clone());
+    let batch = GpuPhysicsData::upload_buffer(sim.context().memory_allocator().clone(), emitted.iter().copied());
+    emit.prepare_with_particles(fx.descriptor_set_allocator(), data, batch);
+    sim.submit(|builder| emit.execute(builder));
+
+    // particle_count.comp clamps the overshoot later.
+    assert_eq!(sim.read_buffer(&data.particle_counter)[0].count, n as u32 + 100);
+    let positions = sim.read_buffer(&data.position_a);
+    let velocities = sim.read_buffer(&data.velocity_a);
+    let pressures = sim.read_buffer(&data.pressures);
+    let affine = sim.read_buffer(&data.affine_a);
+    assert_eq!(positions[..n], live[..], "live particles were overwritten");
+
+    let mut taken = vec![false; emitted.len()];
+    for slot in n..capacity {
+        let source = emitted.iter().position(|e| e.position == positions[slot])
+            .unwrap_or_else(|| panic!("slot {slot} holds {:?}, which was not emitted", positions[slot]));
+        assert!(!std::mem::replace(&mut taken[source], true), "particle {source} emitted twice");
+        let v = emitted[source].velocity;
+        assert_eq!(velocities[slot], [v[0], v[1], v[2], 0.0], "velocity of slot {slot}");
+        assert_eq!(pressures[slot], 0.0, "pressure of slot {slot}");
+        assert!(affine[3 * slot..3 * slot + 3].iter().all(|row| *row == [0.0; 4]), "affine of slot {slot}");
+    }
+}
+
+#[test]
+fn sink_removal_compacts_the_survivors() {
+    let fx = Fixture::new();
+    let n = fx.len();
+    // Cuts the block between two lattice layers, well clear of the jitter.
+    let extent = EDGE as f32 * 2.0 * PARTICLE_RADIUS;
+    let mut sim = fx.sim;
+    sim.set_sinks(&[Obstacle::new(
+        ObstacleShape::Box { half_extents: Vec3::new(extent, extent, 0.5 * extent) },
+        Vec3::new(0.5 * extent, 0.5 * extent, 0.0),
+        Quat::IDENTITY,
+    )]);
+    let data = sim.physics_data();
+    let unsorted = sim.read_buffer(&data.position_a);
+    let unsorted_velocities = sim.read_buffer(&data.velocity_a);
+    let survivors: Vec<usize> = (0..n).filter(|&i| unsorted[i][2] > 0.5 * extent).collect();
+    assert_eq!(survivors.len(), n / 2);
+
+    sim.submit(|builder| {
+        sim.pipelines().sink.execute(builder);
+        sim.pipelines().neighbor_search.execute(builder);
+    });
+
+    let counter = sim.read_buffer(&data.particle_counter)[0];
+    let live = survivors.len();
+    assert_eq!(counter.count, live as u32);
+    assert_eq!(counter.dispatch, [(live as u32).div_ceil(256), 1, 1]);
+    assert_eq!(counter.draw[0], live as u32);
+
+    // The survivors come first, each carried over whole; the removed ones
+    // are sorted behind them.
+    let entries = sim.read_buffer(&data.grid_entries);
+    let positions = sim.read_buffer(&data.position_b);
+    let velocities = sim.read_buffer(&data.velocity_b);
+    let mut kept: Vec<usize> = entries[..live].iter().map(|e| e.index as usize).collect();
+    for (j, &i) in kept.iter().enumerate() {
+        assert_eq!(positions[j], unsorted[i], "position_b[{j}]");
+        assert_eq!(velocities[j], unsorted_velocities[i], "velocity_b[{j}]");
+    }
+    kept.sort();
+    assert_eq!(kept, survivors);
+    assert!(positions[live..n].iter().all(|p| p[3] == 0.0), "removed particles among the live ones");
+}
+
+#[test]
+fn diffuse_generate_matches_cpu() {
+    let mut fx = Fixture::new();
+    let velocities = fx.random_vectors(3.0);
+    fx.upload_vec3(&fx.sim.physics_data().velocity_a, &velocities);
+    let params = DiffuseParams::default();
+    let dt = 0.02;
+
+    let mut diffuse = fx.diffuse(params, 1 << 14);
+    fx.sim.submit(|builder| diffuse.record_update(builder, &fx.sim, dt));
+    let gpu: Vec<f32> = fx.sim.read_buffer(&diffuse.buffers.potentials)[..fx.len()].iter().flatten().copied().collect();
+    let spawned = fx.sim.read_buffer(&diffuse.buffers.next_slot)[0];
+
+    let mut normals = vec![Vec3::ZERO; fx.len()];
+    steps::surface_normals(&fx.grid, &fx.params, &fx.positions, &fx.phases, &fx.densities, &mut normals);
+    let mut cpu = vec![[0.0; 4]; fx.len()];
+    steps::diffuse_generate(&fx.grid, &fx.params, &params, dt, &fx.positions, &velocities, &normals, &mut cpu);
+
+    for (k, name) in ["trapped_air", "wave_crest", "kinetic_energy", "expected"].into_iter().enumerate() {
+        let column = |data: &[f32]| data.iter().skip(k).step_by(4).copied().collect::<Vec<_>>();
+        assert_close(name, &column(&gpu), &column(cpu.as_flattened()));
+    }
+    // Each particle rounds its expected count up or down at random.
+    let floor: u32 = cpu.iter().map(|p| (p[3].floor() as u32).min(16)).sum();
+    let ceil: u32 = cpu.iter().map(|p| (p[3].ceil() as u32).min(16)).sum();
+    assert!(floor > 0 && (floor..=ceil).contains(&spawned), "spawned {spawned}, expected {floor}..={ceil}");
+}
+
+#[test]
+fn diffuse_advect_matches_cpu() {
+    let mut fx = Fixture::new();
+    let velocities = fx.random_vectors(1.0);
+    fx.upload_vec3(&fx.sim.physics_data().velocity_a, &velocities);
+    // Nothing is generated, so only the uploaded particles move.
+    let params = DiffuseParams { trapped_air_rate: 0.0, wave_crest_rate: 0.0, ..DiffuseParams::default() };
+    let dt = 0.01;
+
+    // Spread over and a little beyond the box, so every kind and the kill
+    // at the walls are covered; every fourth slot is free.
+    let extent = EDGE as f32 * 2.0 * PARTICLE_RADIUS;
+    let particles: Vec<GpuDiffuseParticle> = (0..256)
+        .map(|k| {
+            let p = Vec3::new(fx.rng.random_range(-0.05..extent + 0.05), fx.rng.random_range(0.0..extent + 0.1), fx.rng.random_range(0.0..extent));
+            let v = random_vec3(&mut fx.rng, 1.0);
+            let lifetime = if k % 4 == 0 { 0.0 } else { fx.rng.random_range(0.005..2.0) };
+            GpuDiffuseParticle { position: [p.x, p.y, p.z, lifetime], velocity: [v.x, v.y, v.z, 0.0] }
+        })
+        .collect();
+
+    let mut diffuse = fx.diffuse(params, particles.len() as u32);
+    fx.sim.write_buffer(&diffuse.buffers.particles, &particles);
+    fx.sim.submit(|builder| diffuse.record_update(builder, &fx.sim, dt));
+    let gpu = fx.sim.read_buffer(&diffuse.buffers.particles);
+
+    let mut cpu = particles;
+    steps::diffuse_advect(&fx.grid, &fx.params, &params, dt, &fx.positions, &velocities, &mut cpu);
+
+    let kinds = |data: &[GpuDiffuseParticle]| data.iter().map(|p| p.velocity[3]).collect::<Vec<_>>();
+    assert_eq!(kinds(&gpu), kinds(&cpu), "kinds");
+    for field in 0..2 {
+        let flatten = |data: &[GpuDiffuseParticle]| {
+            data.iter().flat_map(|p| if field == 0 { p.position } else { p.velocity }).collect::<Vec<f32>>()
+        };
+        assert_close(["position", "velocity"][field], &flatten(&gpu), &flatten(&cpu));
+    }
+}
+
+#[test]
+fn flip_transfer_to_grid_matches_cpu() {
+    let mut fx = Fixture::new();
+    let (grid, velocities, affine) = fx.flip(GridTransfer::Apic);
+    let (nodes, cells) = (grid.node_count(), grid.cell_count());
+
+    fx.dispatch(&fx.sim.pipelines().flip);
+    let flip_grid = &fx.sim.physics_data().flip_grid;
+    let gpu_transfers = fx.sim.read_buffer(&flip_grid.transfers)[..8 * nodes].to_vec();
+    let gpu_cells = fx.sim.read_buffer(&flip_grid.cells)[..cells].to_vec();
+    let gpu_velocities = fx.read_vec3(&flip_grid.velocities)[..nodes].to_vec();
+    let gpu_old = fx.read_vec3(&flip_grid.old_velocities)[..nodes].to_vec();
+    let gpu_divergence = fx.sim.read_buffer(&flip_grid.divergence)[..cells].to_vec();
+
+    let mut transfers = vec![0; 8 * nodes];
+    let mut cpu_cells = vec![0; cells];
+    steps::flip_p2g(&fx.params, &grid, &fx.positions, &fx.phases, &velocities, &affine, &mut transfers, &mut cpu_cells);
+    let (mut cpu_velocities, mut cpu_old) = (vec![Vec3::ZERO; nodes], vec![Vec3::ZERO; nodes]);
+    steps::flip_grid_normalize(&fx.params, &grid, &transfers, &mut cpu_velocities, &mut cpu_old);
+    let (mut cpu_divergence, mut pressures) = (vec![0.0; cells], vec![0.0; 2 * cells]);
+    steps::flip_grid_divergence(&grid, &cpu_cells, &cpu_velocities, &mut cpu_divergence, &mut pressures);
+
+    assert_eq!(gpu_cells, cpu_cells, "cells");
+    // Fixed point on both sides; rounding per particle adds up to one unit each.
+    let as_f32 = |v: &[i32]| v.iter().map(|&v| v as f32).collect::<Vec<f32>>();
+    assert_close("transfers", &as_f32(&gpu_transfers), &as_f32(&transfers));
+    assert_close_vec3("old_velocities", &gpu_old, &cpu_old);
+    assert_close_vec3("grid_velocities", &gpu_velocities, &cpu_velocities);
+    assert_close("divergence", &gpu_divergence, &cpu_divergence);
+}
+
+#[test]
+fn flip_projection_matches_cpu() {
+    for transfer in GridTransfer::ALL {
+        let mut fx = Fixture::new();
+        let (grid, velocities, affine) = fx.flip(transfer);
+        let iterations = fx.params.grid_iterations;
+
+        let flip = &fx.sim.pipelines().flip;
+        fx.sim.submit(|builder| {
+            flip.execute(builder);
+            for iteration in 0..iterations {
+                flip.execute_iteration(builder, iteration);
+            }
+            flip.execute_projection(builder, iterations);
+        });
+        let data = fx.sim.physics_data();
+        let gpu_grid = fx.read_vec3(&data.flip_grid.velocities)[..grid.node_count()].to_vec();
+        let gpu_velocities = fx.read_vec3(&data.velocity_a);
+        let gpu_affine = fx.read_vec3(&data.affine_a);
+
+        let mut transfers = vec![0; 8 * grid.node_count()];
+        let mut cells = vec![0; grid.cell_count()];
+        let mut grid_velocities = vec![Vec3::ZERO; grid.node_count()];
+        let mut old = vec![Vec3::ZERO; grid.node_count()];
+        let mut divergence = vec![0.0; grid.cell_count()];
+        let mut pressures = vec![0.0; 2 * grid.cell_count()];
+        steps::flip_p2g(&fx.params, &grid, &fx.positions, &fx.phases, &velocities, &affine, &mut transfers, &mut cells);
+        steps::flip_grid_normalize(&fx.params, &grid, &transfers, &mut grid_velocities, &mut old);
+        steps::flip_grid_divergence(&grid, &cells, &grid_velocities, &mut divergence, &mut pressures);
+        for iteration in 0..iterations {
+            steps::flip_grid_jacobi(&grid, &cells, &divergence, iteration, &mut pressures);
+        }
+        steps::flip_grid_project(&grid, &cells, iterations, &mut pressures, &mut grid_velocities);
+        let mut cpu_velocities = velocities;
+        let mut cpu_affine = vec![[Vec3::ZERO; 3]; fx.len()];
+        steps::flip_g2p(&fx.params, &grid, &fx.positions, &grid_velocities, &old, &mut cpu_velocities, &mut cpu_affine);
+
+        assert_close_vec3(&format!("{transfer:?} grid_velocities"), &gpu_grid, &grid_velocities);
+        assert_close_vec3(&format!("{transfer:?} velocity_a"), &gpu_velocities, &cpu_velocities);
+        assert_close_vec3(&format!("{transfer:?} affine_a"), &gpu_affine[..3 * fx.len()], cpu_affine.as_flattened());
+    }
+}
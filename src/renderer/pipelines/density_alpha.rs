@@ -1,6 +1,6 @@
 use std::sync::Arc;
 use vulkano::buffer::Subbuffer;
-use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DispatchIndirectCommand};
 use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
 use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
 use vulkano::device::Device;
@@ -12,13 +12,13 @@ use crate::utils::shader_loader::load_shader_entry_point;
 
 mod cs {
     use vulkano_shaders::shader;
-    shader!(ty: "compute", path: "shaders\\compute\\density_and_alpha.comp");
+    shader!(ty: "compute", path: "shaders/compute/density_and_alpha.comp");
 }
 
 pub struct DensityAlphaPipeline {
     pipeline: Arc<ComputePipeline>,
     descriptor_set: Option<Arc<DescriptorSet>>,
-    dispatch_count: u32,
+    dispatch: Option<Subbuffer<[DispatchIndirectCommand]>>,
 }
 
 impl ComputeStep for DensityAlphaPipeline {
@@ -26,7 +26,7 @@ impl ComputeStep for DensityAlphaPipeline {
         load_shader_entry_point(device, cs::load, "main")
     }
     fn from_pipeline(pipeline: Arc<ComputePipeline>) -> Self {
-        Self { pipeline, descriptor_set: None, dispatch_count: 0 }
+        Self { pipeline, descriptor_set: None, dispatch: None }
     }
     fn prepare(
         &mut self,
@@ -34,9 +34,7 @@ impl ComputeStep for DensityAlphaPipeline {
         physics_data: &GpuPhysicsData,
         sim_params: &Subbuffer<SimulationParams>,
     ) {
-        let num_particles = physics_data.count;
-        let group_size = 256;
-        self.dispatch_count = (num_particles + group_size - 1) / group_size;
+        self.dispatch = Some(physics_data.dispatch_command());
 
         let layout = self.pipeline.layout().set_layouts().get(0).unwrap();
         self.descriptor_set = Some(DescriptorSet::new(
@@ -49,6 +47,10 @@ impl ComputeStep for DensityAlphaPipeline {
                 WriteDescriptorSet::buffer(3, physics_data.position_b.clone()),
                 WriteDescriptorSet::buffer(4, physics_data.densities.clone()),
                 WriteDescriptorSet::buffer(5, physics_data.factors.clone()),
+                WriteDescriptorSet::buffer(9, physics_data.boundary_particles.clone()),
+                WriteDescriptorSet::buffer(10, physics_data.boundary_cell_ranges.clone()),
+                WriteDescriptorSet::buffer(11, physics_data.boundary_grid.clone()),
+                WriteDescriptorSet::buffer(12, physics_data.particle_counter.clone()),
             ],
             [],
         ).unwrap());
@@ -59,6 +61,6 @@ impl ComputeStep for DensityAlphaPipeline {
             .bind_pipeline_compute(self.pipeline.clone()).unwrap()
             .bind_descriptor_sets(PipelineBindPoint::Compute, self.pipeline.layout().clone(), 0, set.clone())
             .unwrap();
-        unsafe { builder.dispatch([self.dispatch_count, 1, 1]).unwrap(); }
+        unsafe { builder.dispatch_indirect(self.dispatch.clone().unwrap()).unwrap(); }
     }
 }
\ No newline at end of file
@@ -1,6 +1,6 @@
 use std::sync::Arc;
 use vulkano::buffer::Subbuffer;
-use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DispatchIndirectCommand};
 use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
 use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
 use vulkano::pipeline::{ComputePipeline, Pipeline, PipelineBindPoint};
@@ -9,13 +9,13 @@ use crate::renderer::pipelines::ComputeStep;
 
 mod cs {
     use vulkano_shaders::shader;
-    shader!(ty: "compute", path: "shaders\\compute\\density_source_term.comp");
+    shader!(ty: "compute", path: "shaders/compute/density_source_term.comp");
 }
 
 pub struct DensitySourceTermPipeline {
     pipeline: Arc<ComputePipeline>,
     descriptor_set: Option<Arc<DescriptorSet>>,
-    dispatch_count: u32,
+    dispatch: Option<Subbuffer<[DispatchIndirectCommand]>>,
 }
 
 impl ComputeStep for DensitySourceTermPipeline {
@@ -23,7 +23,7 @@ impl ComputeStep for DensitySourceTermPipeline {
         crate::utils::shader_loader::load_shader_entry_point(device, cs::load, "main")
     }
     fn from_pipeline(pipeline: Arc<ComputePipeline>) -> Self {
-        Self { pipeline, descriptor_set: None, dispatch_count: 0 }
+        Self { pipeline, descriptor_set: None, dispatch: None }
     }
     fn prepare(
         &mut self,
@@ -31,9 +31,7 @@ impl ComputeStep for DensitySourceTermPipeline {
         physics_data: &GpuPhysicsData,
         sim_params: &Subbuffer<SimulationParams>,
     ) {
-        let num_particles = physics_data.count;
-        let group_size = 256;
-        self.dispatch_count = (num_particles + group_size - 1) / group_size;
+        self.dispatch = Some(physics_data.dispatch_command());
 
         let layout = self.pipeline.layout().set_layouts().get(0).unwrap();
         self.descriptor_set = Some(DescriptorSet::new(
@@ -48,6 +46,10 @@ impl ComputeStep for DensitySourceTermPipeline {
                 WriteDescriptorSet::buffer(5, physics_data.velocity_a.clone()),
                 WriteDescriptorSet::buffer(6, physics_data.pressures.clone()),
                 WriteDescriptorSet::buffer(7, physics_data.source_terms.clone()),
+                WriteDescriptorSet::buffer(9, physics_data.boundary_particles.clone()),
+                WriteDescriptorSet::buffer(10, physics_data.boundary_cell_ranges.clone()),
+                WriteDescriptorSet::buffer(11, physics_data.boundary_grid.clone()),
+                WriteDescriptorSet::buffer(12, physics_data.particle_counter.clone()),
             ],
             []
         ).unwrap());
@@ -58,6 +60,6 @@ impl ComputeStep for DensitySourceTermPipeline {
             .bind_pipeline_compute(self.pipeline.clone()).unwrap()
             .bind_descriptor_sets(PipelineBindPoint::Compute, self.pipeline.layout().clone(), 0, set.clone())
             .unwrap();
-        unsafe { builder.dispatch([self.dispatch_count, 1, 1]).unwrap(); }
+        unsafe { builder.dispatch_indirect(self.dispatch.clone().unwrap()).unwrap(); }
     }
 }
\ No newline at end of file
@@ -1,6 +1,6 @@
 use std::sync::Arc;
 use vulkano::buffer::Subbuffer;
-use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DispatchIndirectCommand};
 use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
 use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
 use vulkano::device::Device;
@@ -15,15 +15,15 @@ mod splat_cs {
     use vulkano_shaders::shader;
     shader! {
         ty: "compute",
-        path: "shaders\\compute\\splat_density.comp",
-        include: ["shaders\\include"],
+        path: "shaders/compute/splat_density.comp",
+        include: ["shaders/include"],
     }
 }
 
 pub struct DensityTexturePipeline {
     pub pipeline: Arc<ComputePipeline>,
     descriptor_set: Option<Arc<DescriptorSet>>,
-    dispatch_count: u32,
+    dispatch: Option<Subbuffer<[DispatchIndirectCommand]>>,
 }
 
 impl DensityTexturePipeline {
@@ -34,9 +34,7 @@ impl DensityTexturePipeline {
         image: Arc<ImageView>,
         sim_params: &Subbuffer<SimulationParams>,
     ) {
-        let num_particles = physics_data.count;
-        let group_size = 256;
-        self.dispatch_count = (num_particles + group_size - 1) / group_size;
+        self.dispatch = Some(physics_data.dispatch_command());
 
         let layout = self.pipeline.layout().set_layouts().get(0).unwrap();
         self.descriptor_set = Some(DescriptorSet::new(
@@ -46,6 +44,7 @@ impl DensityTexturePipeline {
                 WriteDescriptorSet::buffer(0, physics_data.position_a.clone()),
                 WriteDescriptorSet::image_view(1, image),
                 WriteDescriptorSet::buffer(2, sim_params.clone()),
+                WriteDescriptorSet::buffer(12, physics_data.particle_counter.clone()),
             ],
             []
         ).unwrap());
@@ -57,7 +56,7 @@ impl ComputeStep for DensityTexturePipeline {
         load_shader_entry_point(device, splat_cs::load, "main")
     }
     fn from_pipeline(pipeline: Arc<ComputePipeline>) -> Self {
-        Self { pipeline, descriptor_set: None, dispatch_count: 0 }
+        Self { pipeline, descriptor_set: None, dispatch: None }
     }
     fn prepare(
         &mut self,
@@ -73,6 +72,6 @@ impl ComputeStep for DensityTexturePipeline {
             .bind_pipeline_compute(self.pipeline.clone()).unwrap()
             .bind_descriptor_sets(PipelineBindPoint::Compute, self.pipeline.layout().clone(), 0, set.clone())
             .unwrap();
-        unsafe { builder.dispatch([self.dispatch_count, 1, 1]).unwrap(); }
+        unsafe { builder.dispatch_indirect(self.dispatch.clone().unwrap()).unwrap(); }
     }
 }
\ No newline at end of file
@@ -0,0 +1,268 @@
+use std::sync::Arc;
+use vulkano::buffer::{BufferContents, Subbuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DispatchIndirectCommand};
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::pipeline::graphics::color_blend::{AttachmentBlend, ColorBlendAttachmentState, ColorBlendState};
+use vulkano::pipeline::graphics::depth_stencil::{CompareOp, DepthState, DepthStencilState};
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::graphics::input_assembly::{InputAssemblyState, PrimitiveTopology};
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::subpass::{PipelineRenderingCreateInfo, PipelineSubpassType};
+use vulkano::pipeline::graphics::vertex_input::{Vertex, VertexDefinition};
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::{ComputePipeline, DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout, PipelineShaderStageCreateInfo};
+use vulkano::shader::EntryPoint;
+use crate::entities::diffuse::{DiffuseBuffers, GpuDiffuseParticle};
+use crate::entities::particle::{GpuPhysicsData, SimulationParams};
+use crate::renderer::pipelines::ComputeStep;
+use crate::utils::shader_loader::load_shader_entry_point;
+
+mod generate_cs {
+    use vulkano_shaders::shader;
+    shader! {
+        ty: "compute",
+        path: "shaders/compute/diffuse_generate.comp",
+        include: ["shaders/include"],
+    }
+}
+
+mod advect_cs {
+    use vulkano_shaders::shader;
+    shader! {
+        ty: "compute",
+        path: "shaders/compute/diffuse_advect.comp",
+        include: ["shaders/include"],
+    }
+}
+
+mod vs {
+    use vulkano_shaders::shader;
+    shader! {
+        ty: "vertex",
+        path: "shaders/diffuse.vert",
+    }
+}
+
+mod fs {
+    use vulkano_shaders::shader;
+    shader! {
+        ty: "fragment",
+        path: "shaders/diffuse.frag",
+    }
+}
+
+/// `DiffusePushConstants` in diffuse.glsl.
+#[derive(BufferContents, Clone, Copy)]
+#[repr(C)]
+struct DiffusePushConstants {
+    dt: f32,
+    seed: u32,
+}
+
+/// Scores every fluid particle's trapped-air, wave-crest and kinetic-energy
+/// potentials and spawns diffuse particles from them. Needs the surface
+/// normals of the current state.
+pub struct DiffuseGeneratePipeline {
+    pub pipeline: Arc<ComputePipeline>,
+    descriptor_set: Option<Arc<DescriptorSet>>,
+    dispatch: Option<Subbuffer<[DispatchIndirectCommand]>>,
+    push: DiffusePushConstants,
+}
+
+impl DiffuseGeneratePipeline {
+    pub fn prepare_with_diffuse(
+        &mut self,
+        allocator: Arc<StandardDescriptorSetAllocator>,
+        physics_data: &GpuPhysicsData,
+        sim_params: &Subbuffer<SimulationParams>,
+        diffuse: &DiffuseBuffers,
+    ) {
+        self.dispatch = Some(physics_data.dispatch_command());
+
+        let layout = self.pipeline.layout().set_layouts().get(0).unwrap();
+        self.descriptor_set = Some(DescriptorSet::new(
+            allocator,
+            layout.clone(),
+            [
+                WriteDescriptorSet::buffer(0, physics_data.grid_entries.clone()),
+                WriteDescriptorSet::buffer(1, physics_data.grid_start.clone()),
+                WriteDescriptorSet::buffer(2, sim_params.clone()),
+                WriteDescriptorSet::buffer(3, physics_data.position_b.clone()),
+                WriteDescriptorSet::buffer(4, physics_data.velocity_a.clone()),
+                WriteDescriptorSet::buffer(5, physics_data.normals.clone()),
+                WriteDescriptorSet::buffer(6, diffuse.potentials.clone()),
+                WriteDescriptorSet::buffer(7, diffuse.particles.clone()),
+                WriteDescriptorSet::buffer(8, diffuse.next_slot.clone()),
+                WriteDescriptorSet::buffer(9, diffuse.params.clone()),
+                WriteDescriptorSet::buffer(12, physics_data.particle_counter.clone()),
+            ],
+            []
+        ).unwrap());
+    }
+
+    /// `dt` is the simulated time since the last update; `seed` must change
+    /// every update so the spawn positions do.
+    pub fn set_step(&mut self, dt: f32, seed: u32) {
+        self.push = DiffusePushConstants { dt, seed };
+    }
+}
+
+impl ComputeStep for DiffuseGeneratePipeline {
+    fn load_shader_module(device: Arc<Device>) -> EntryPoint {
+        load_shader_entry_point(device, generate_cs::load, "main")
+    }
+    fn from_pipeline(pipeline: Arc<ComputePipeline>) -> Self {
+        Self { pipeline, descriptor_set: None, dispatch: None, push: DiffusePushConstants { dt: 0.0, seed: 0 } }
+    }
+    fn prepare(
+        &mut self,
+        _allocator: Arc<StandardDescriptorSetAllocator>,
+        _physics_data: &GpuPhysicsData,
+        _sim_params: &Subbuffer<SimulationParams>,
+    ) {
+        // Use prepare_with_diffuse() instead, as the diffuse buffers are required.
+    }
+    fn execute<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>) {
+        let set = self.descriptor_set.as_ref().expect("DiffuseGeneratePipeline: call prepare_with_diffuse() before execute()");
+        builder
+            .bind_pipeline_compute(self.pipeline.clone()).unwrap()
+            .bind_descriptor_sets(PipelineBindPoint::Compute, self.pipeline.layout().clone(), 0, set.clone()).unwrap()
+            .push_constants(self.pipeline.layout().clone(), 0, self.push)
+            .unwrap();
+        unsafe { builder.dispatch_indirect(self.dispatch.clone().unwrap()).unwrap(); }
+    }
+}
+
+/// Moves every live diffuse particle through the fluid velocity field as
+/// spray, foam or bubble, depending on its fluid neighbor count.
+pub struct DiffuseAdvectPipeline {
+    pub pipeline: Arc<ComputePipeline>,
+    descriptor_set: Option<Arc<DescriptorSet>>,
+    dispatch_count: [u32; 3],
+    push: DiffusePushConstants,
+}
+
+impl DiffuseAdvectPipeline {
+    pub fn prepare_with_diffuse(
+        &mut self,
+        allocator: Arc<StandardDescriptorSetAllocator>,
+        physics_data: &GpuPhysicsData,
+        sim_params: &Subbuffer<SimulationParams>,
+        diffuse: &DiffuseBuffers,
+    ) {
+        self.dispatch_count = [(diffuse.particles.len() as u32).div_ceil(256), 1, 1];
+
+        let layout = self.pipeline.layout().set_layouts().get(0).unwrap();
+        self.descriptor_set = Some(DescriptorSet::new(
+            allocator,
+            layout.clone(),
+            [
+                WriteDescriptorSet::buffer(0, physics_data.grid_entries.clone()),
+                WriteDescriptorSet::buffer(1, physics_data.grid_start.clone()),
+                WriteDescriptorSet::buffer(2, sim_params.clone()),
+                WriteDescriptorSet::buffer(3, physics_data.position_b.clone()),
+                WriteDescriptorSet::buffer(4, physics_data.velocity_a.clone()),
+                WriteDescriptorSet::buffer(7, diffuse.particles.clone()),
+                WriteDescriptorSet::buffer(9, diffuse.params.clone()),
+                WriteDescriptorSet::buffer(12, physics_data.particle_counter.clone()),
+            ],
+            []
+        ).unwrap());
+    }
+
+    /// `dt` is the simulated time since the last update.
+    pub fn set_step(&mut self, dt: f32, seed: u32) {
+        self.push = DiffusePushConstants { dt, seed };
+    }
+}
+
+impl ComputeStep for DiffuseAdvectPipeline {
+    fn load_shader_module(device: Arc<Device>) -> EntryPoint {
+        load_shader_entry_point(device, advect_cs::load, "main")
+    }
+    fn from_pipeline(pipeline: Arc<ComputePipeline>) -> Self {
+        Self { pipeline, descriptor_set: None, dispatch_count: [0; 3], push: DiffusePushConstants { dt: 0.0, seed: 0 } }
+    }
+    fn prepare(
+        &mut self,
+        _allocator: Arc<StandardDescriptorSetAllocator>,
+        _physics_data: &GpuPhysicsData,
+        _sim_params: &Subbuffer<SimulationParams>,
+    ) {
+        // Use prepare_with_diffuse() instead, as the diffuse buffers are required.
+    }
+    fn execute<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>) {
+        let set = self.descriptor_set.as_ref().expect("DiffuseAdvectPipeline: call prepare_with_diffuse() before execute()");
+        builder
+            .bind_pipeline_compute(self.pipeline.clone()).unwrap()
+            .bind_descriptor_sets(PipelineBindPoint::Compute, self.pipeline.layout().clone(), 0, set.clone()).unwrap()
+            .push_constants(self.pipeline.layout().clone(), 0, self.push)
+            .unwrap();
+        unsafe { builder.dispatch(self.dispatch_count).unwrap(); }
+    }
+}
+
+/// Soft point sprites for the diffuse particles, alpha-blended over the
+/// water. They are depth tested against the scene but do not write depth, so
+/// they never hide each other.
+pub struct DiffuseRenderPipeline {
+    pub inner: Arc<GraphicsPipeline>,
+}
+
+impl DiffuseRenderPipeline {
+    pub fn new(device: Arc<Device>, color_format: Format, depth_format: Format) -> Self {
+        let vs = load_shader_entry_point(device.clone(), vs::load, "diffuse vertex");
+        let fs = load_shader_entry_point(device.clone(), fs::load, "diffuse fragment");
+
+        let vertex_input_state = GpuDiffuseParticle::per_vertex().definition(&vs).unwrap();
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vs),
+            PipelineShaderStageCreateInfo::new(fs),
+        ];
+
+        let layout = PipelineLayout::new(
+            device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(device.clone())
+                .expect("[Diffuse Pipeline] Failed to create layout info from shaders")
+        ).expect("[Diffuse Pipeline] Failed to create PipelineLayout");
+
+        let pipeline = GraphicsPipeline::new(
+            device,
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(vertex_input_state),
+                input_assembly_state: Some(InputAssemblyState {
+                    topology: PrimitiveTopology::PointList,
+                    ..InputAssemblyState::default()
+                }),
+                dynamic_state: [DynamicState::Viewport, DynamicState::Scissor].into_iter().collect(),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                multisample_state: Some(MultisampleState::default()),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState { write_enable: false, compare_op: CompareOp::Less }),
+                    ..DepthStencilState::default()
+                }),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    1,
+                    ColorBlendAttachmentState { blend: Some(AttachmentBlend::alpha()), ..ColorBlendAttachmentState::default() },
+                )),
+                subpass: Some(PipelineSubpassType::BeginRendering(PipelineRenderingCreateInfo {
+                    color_attachment_formats: vec![Some(color_format)],
+                    depth_attachment_format: Some(depth_format),
+                    ..PipelineRenderingCreateInfo::default()
+                })),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            }
+        ).map_err(|e| panic!("[Diffuse Pipeline] Validation Error:\n{:?}", e)).unwrap();
+
+        Self { inner: pipeline }
+    }
+}
@@ -1,6 +1,6 @@
 use std::sync::Arc;
 use vulkano::buffer::Subbuffer;
-use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DispatchIndirectCommand};
 use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
 use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
 use vulkano::device::Device;
@@ -14,14 +14,14 @@ mod cs {
     use vulkano_shaders::shader;
     shader! {
         ty: "compute",
-        path: "shaders\\compute\\divergence_integration.comp"
+        path: "shaders/compute/divergence_integration.comp"
     }
 }
 
 pub struct DivergenceIntegrationPipeline {
     pub pipeline: Arc<ComputePipeline>,
     descriptor_set: Option<Arc<DescriptorSet>>,
-    dispatch_count: u32,
+    dispatch: Option<Subbuffer<[DispatchIndirectCommand]>>,
 }
 
 impl ComputeStep for DivergenceIntegrationPipeline {
@@ -29,7 +29,7 @@ impl ComputeStep for DivergenceIntegrationPipeline {
         load_shader_entry_point(device, cs::load, "main")
     }
     fn from_pipeline(pipeline: Arc<ComputePipeline>) -> Self {
-        Self { pipeline, descriptor_set: None, dispatch_count: 0 }
+        Self { pipeline, descriptor_set: None, dispatch: None }
     }
     fn prepare(
         &mut self,
@@ -37,19 +37,23 @@ impl ComputeStep for DivergenceIntegrationPipeline {
         physics_data: &GpuPhysicsData,
         sim_params: &Subbuffer<SimulationParams>,
     ) {
-        let num_particles = physics_data.count;
-        let group_size = 256;
-        self.dispatch_count = (num_particles + group_size - 1) / group_size;
+        self.dispatch = Some(physics_data.dispatch_command());
 
         let layout = self.pipeline.layout().set_layouts().get(0).unwrap();
         self.descriptor_set = Some(DescriptorSet::new(
             allocator,
             layout.clone(),
             [
-                WriteDescriptorSet::buffer(0, physics_data.velocity_a.clone()),
+                WriteDescriptorSet::buffer(0, physics_data.velocity_b.clone()),
                 WriteDescriptorSet::buffer(1, physics_data.pressure_accelerations.clone()),
                 WriteDescriptorSet::buffer(2, sim_params.clone()),
+                WriteDescriptorSet::buffer(3, physics_data.position_b.clone()),
                 WriteDescriptorSet::buffer(4, physics_data.colors.clone()),
+                WriteDescriptorSet::buffer(5, physics_data.velocity_a.clone()),
+                WriteDescriptorSet::buffer(6, physics_data.position_a.clone()),
+                WriteDescriptorSet::buffer(7, physics_data.affine_b.clone()),
+                WriteDescriptorSet::buffer(8, physics_data.affine_a.clone()),
+                WriteDescriptorSet::buffer(12, physics_data.particle_counter.clone()),
             ],
             []
         ).unwrap());
@@ -60,6 +64,6 @@ impl ComputeStep for DivergenceIntegrationPipeline {
             .bind_pipeline_compute(self.pipeline.clone()).unwrap()
             .bind_descriptor_sets(PipelineBindPoint::Compute, self.pipeline.layout().clone(), 0, set.clone())
             .unwrap();
-        unsafe { builder.dispatch([self.dispatch_count, 1, 1]).unwrap(); }
+        unsafe { builder.dispatch_indirect(self.dispatch.clone().unwrap()).unwrap(); }
     }
 }
\ No newline at end of file
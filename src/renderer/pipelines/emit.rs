@@ -0,0 +1,77 @@
+use std::sync::Arc;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::pipeline::{ComputePipeline, Pipeline, PipelineBindPoint};
+use vulkano::shader::EntryPoint;
+use crate::entities::emitter::GpuEmittedParticle;
+use crate::entities::particle::{GpuPhysicsData, SimulationParams};
+use crate::renderer::pipelines::ComputeStep;
+use crate::utils::shader_loader::load_shader_entry_point;
+
+mod cs {
+    use vulkano_shaders::shader;
+    shader!(ty: "compute", path: "shaders/compute/emit.comp");
+}
+
+/// Appends a batch of emitted particles behind the live ones.
+pub struct EmitPipeline {
+    pipeline: Arc<ComputePipeline>,
+    descriptor_set: Option<Arc<DescriptorSet>>,
+    dispatch_count: u32,
+}
+
+impl EmitPipeline {
+    /// Binds a freshly uploaded batch; it is consumed by the next `execute`.
+    pub fn prepare_with_particles(
+        &mut self,
+        allocator: Arc<StandardDescriptorSetAllocator>,
+        physics_data: &GpuPhysicsData,
+        particles: Subbuffer<[GpuEmittedParticle]>,
+    ) {
+        let group_size = 256;
+        self.dispatch_count = (particles.len() as u32).div_ceil(group_size);
+
+        let layout = self.pipeline.layout().set_layouts().get(0).unwrap();
+        self.descriptor_set = Some(DescriptorSet::new(
+            allocator,
+            layout.clone(),
+            [
+                WriteDescriptorSet::buffer(0, particles),
+                WriteDescriptorSet::buffer(3, physics_data.position_a.clone()),
+                WriteDescriptorSet::buffer(4, physics_data.velocity_a.clone()),
+                WriteDescriptorSet::buffer(5, physics_data.pressures.clone()),
+                WriteDescriptorSet::buffer(6, physics_data.affine_a.clone()),
+                WriteDescriptorSet::buffer(12, physics_data.particle_counter.clone()),
+            ],
+            [],
+        ).unwrap());
+    }
+}
+
+impl ComputeStep for EmitPipeline {
+    fn load_shader_module(device: Arc<Device>) -> EntryPoint {
+        load_shader_entry_point(device, cs::load, "main")
+    }
+    fn from_pipeline(pipeline: Arc<ComputePipeline>) -> Self {
+        Self { pipeline, descriptor_set: None, dispatch_count: 0 }
+    }
+    fn prepare(
+        &mut self,
+        _allocator: Arc<StandardDescriptorSetAllocator>,
+        _physics_data: &GpuPhysicsData,
+        _sim_params: &Subbuffer<SimulationParams>,
+    ) {
+        // Use prepare_with_particles() instead, as each batch is its own buffer.
+    }
+    fn execute<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>) {
+        let set = self.descriptor_set.as_ref().expect("EmitPipeline: call prepare_with_particles() before execute()");
+        builder
+            .bind_pipeline_compute(self.pipeline.clone()).unwrap()
+            .bind_descriptor_sets(PipelineBindPoint::Compute, self.pipeline.layout().clone(), 0, set.clone())
+            .unwrap();
+        unsafe { builder.dispatch([self.dispatch_count, 1, 1]).unwrap(); }
+    }
+}
@@ -0,0 +1,218 @@
+use std::sync::Arc;
+use glam::IVec3;
+use vulkano::buffer::{BufferContents, Subbuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DispatchIndirectCommand};
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::pipeline::{ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout, PipelineShaderStageCreateInfo};
+use vulkano::pipeline::compute::ComputePipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::shader::EntryPoint;
+use crate::entities::particle::{FlipGrid, GpuPhysicsData, SimulationParams};
+use crate::renderer::pipelines::ComputeStep;
+use crate::utils::shader_loader::load_shader_entry_point;
+
+mod cs_p2g {
+    use vulkano_shaders::shader;
+    shader!(ty: "compute", path: "shaders/compute/flip_p2g.comp");
+}
+mod cs_grid {
+    use vulkano_shaders::shader;
+    shader!(ty: "compute", path: "shaders/compute/flip_grid.comp");
+}
+mod cs_g2p {
+    use vulkano_shaders::shader;
+    shader!(ty: "compute", path: "shaders/compute/flip_g2p.comp");
+}
+
+// GRID_* in flip.glsl.
+const GRID_NORMALIZE: u32 = 0;
+const GRID_DIVERGENCE: u32 = 1;
+const GRID_JACOBI: u32 = 2;
+const GRID_PROJECT: u32 = 3;
+
+/// `FlipPushConstants` in flip.glsl.
+#[derive(BufferContents, Clone, Copy)]
+#[repr(C)]
+struct FlipPushConstants {
+    dims: [i32; 4],
+    stage: u32,
+    iteration: u32,
+}
+
+/// The FLIP backend's grid passes (Zhu & Bridson 2005, Jiang et al. 2015):
+/// particle-to-grid transfer, pressure projection on the MAC grid and
+/// grid-to-particle transfer. It reads the particles where the SPH density
+/// solve would, `position_b` and `velocity_a` after the non-pressure forces,
+/// and leaves the new velocities in `velocity_a` for `pressure_integration`.
+///
+/// `execute` transfers onto the grid; every `execute_iteration` is one
+/// Jacobi sweep, and `execute_projection` projects and transfers back.
+pub struct FlipPipeline {
+    p2g_pipeline: Arc<ComputePipeline>,
+    grid_pipeline: Arc<ComputePipeline>,
+    g2p_pipeline: Arc<ComputePipeline>,
+    p2g_set: Option<Arc<DescriptorSet>>,
+    grid_set: Option<Arc<DescriptorSet>>,
+    g2p_set: Option<Arc<DescriptorSet>>,
+    transfers: Option<Subbuffer<[i32]>>,
+    cells: Option<Subbuffer<[u32]>>,
+    dispatch: Option<Subbuffer<[DispatchIndirectCommand]>>,
+    dims: IVec3,
+}
+
+impl FlipPipeline {
+    /// The grid size changes with the box; the buffers bound by `prepare`
+    /// must fit `dims` (`GpuPhysicsData::reserve_flip_grid`).
+    pub fn set_dims(&mut self, dims: IVec3) {
+        self.dims = dims;
+    }
+
+    /// Jacobi sweep number `iteration`, counting from 0.
+    pub fn execute_iteration<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>, iteration: u32) {
+        self.dispatch_grid(builder, GRID_JACOBI, iteration);
+    }
+
+    /// Subtracts the gradient of the pressure `iterations` sweeps left and
+    /// transfers the result back onto the particles.
+    pub fn execute_projection<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>, iterations: u32) {
+        self.dispatch_grid(builder, GRID_PROJECT, iterations);
+        self.dispatch_particles(builder, &self.g2p_pipeline, self.g2p_set.as_ref());
+    }
+
+    fn dispatch_particles<Cb>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<Cb>,
+        pipeline: &Arc<ComputePipeline>,
+        set: Option<&Arc<DescriptorSet>>,
+    ) {
+        let set = set.expect("FlipPipeline: call prepare() before execute()");
+        builder
+            .bind_pipeline_compute(pipeline.clone()).unwrap()
+            .bind_descriptor_sets(PipelineBindPoint::Compute, pipeline.layout().clone(), 0, set.clone()).unwrap()
+            .push_constants(pipeline.layout().clone(), 0, self.push_constants(0, 0)).unwrap();
+        unsafe { builder.dispatch_indirect(self.dispatch.clone().unwrap()).unwrap(); }
+    }
+
+    fn dispatch_grid<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>, stage: u32, iteration: u32) {
+        let set = self.grid_set.as_ref().expect("FlipPipeline: call prepare() before execute()");
+        let count = match stage {
+            GRID_NORMALIZE | GRID_PROJECT => FlipGrid::node_count(self.dims),
+            _ => FlipGrid::cell_count(self.dims),
+        };
+        builder
+            .bind_pipeline_compute(self.grid_pipeline.clone()).unwrap()
+            .bind_descriptor_sets(PipelineBindPoint::Compute, self.grid_pipeline.layout().clone(), 0, set.clone()).unwrap()
+            .push_constants(self.grid_pipeline.layout().clone(), 0, self.push_constants(stage, iteration)).unwrap();
+        unsafe { builder.dispatch([count.div_ceil(256) as u32, 1, 1]).unwrap(); }
+    }
+
+    fn push_constants(&self, stage: u32, iteration: u32) -> FlipPushConstants {
+        FlipPushConstants { dims: self.dims.extend(0).to_array(), stage, iteration }
+    }
+}
+
+impl ComputeStep for FlipPipeline {
+    fn load_shader_module(_device: Arc<Device>) -> EntryPoint {
+        unimplemented!("FlipPipeline uses multiple shaders")
+    }
+    fn from_pipeline(_pipeline: Arc<ComputePipeline>) -> Self {
+        unimplemented!("FlipPipeline uses multiple pipelines")
+    }
+    fn new(device: Arc<Device>) -> Self {
+        let pipeline = |entry_point| {
+            let stage = PipelineShaderStageCreateInfo::new(entry_point);
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+                    .into_pipeline_layout_create_info(device.clone()).unwrap()
+            ).unwrap();
+            ComputePipeline::new(device.clone(), None, ComputePipelineCreateInfo::stage_layout(stage, layout)).unwrap()
+        };
+
+        Self {
+            p2g_pipeline: pipeline(load_shader_entry_point(device.clone(), cs_p2g::load, "main")),
+            grid_pipeline: pipeline(load_shader_entry_point(device.clone(), cs_grid::load, "main")),
+            g2p_pipeline: pipeline(load_shader_entry_point(device.clone(), cs_g2p::load, "main")),
+            p2g_set: None,
+            grid_set: None,
+            g2p_set: None,
+            transfers: None,
+            cells: None,
+            dispatch: None,
+            dims: IVec3::ONE,
+        }
+    }
+    fn prepare(
+        &mut self,
+        allocator: Arc<StandardDescriptorSetAllocator>,
+        physics_data: &GpuPhysicsData,
+        sim_params: &Subbuffer<SimulationParams>,
+    ) {
+        let grid = &physics_data.flip_grid;
+        self.dims = grid.dims;
+        self.dispatch = Some(physics_data.dispatch_command());
+        self.transfers = Some(grid.transfers.clone());
+        self.cells = Some(grid.cells.clone());
+
+        let layout = self.p2g_pipeline.layout().set_layouts().get(0).unwrap();
+        self.p2g_set = Some(DescriptorSet::new(
+            allocator.clone(),
+            layout.clone(),
+            [
+                WriteDescriptorSet::buffer(0, physics_data.position_b.clone()),
+                WriteDescriptorSet::buffer(1, physics_data.velocity_a.clone()),
+                WriteDescriptorSet::buffer(2, sim_params.clone()),
+                WriteDescriptorSet::buffer(3, physics_data.affine_b.clone()),
+                WriteDescriptorSet::buffer(4, grid.transfers.clone()),
+                WriteDescriptorSet::buffer(5, grid.cells.clone()),
+                WriteDescriptorSet::buffer(12, physics_data.particle_counter.clone()),
+            ],
+            []
+        ).unwrap());
+
+        let layout = self.grid_pipeline.layout().set_layouts().get(0).unwrap();
+        self.grid_set = Some(DescriptorSet::new(
+            allocator.clone(),
+            layout.clone(),
+            [
+                WriteDescriptorSet::buffer(2, sim_params.clone()),
+                WriteDescriptorSet::buffer(4, grid.transfers.clone()),
+                WriteDescriptorSet::buffer(5, grid.cells.clone()),
+                WriteDescriptorSet::buffer(6, grid.velocities.clone()),
+                WriteDescriptorSet::buffer(7, grid.old_velocities.clone()),
+                WriteDescriptorSet::buffer(8, grid.divergence.clone()),
+                WriteDescriptorSet::buffer(9, grid.pressures.clone()),
+            ],
+            []
+        ).unwrap());
+
+        let layout = self.g2p_pipeline.layout().set_layouts().get(0).unwrap();
+        self.g2p_set = Some(DescriptorSet::new(
+            allocator,
+            layout.clone(),
+            [
+                WriteDescriptorSet::buffer(0, physics_data.position_b.clone()),
+                WriteDescriptorSet::buffer(1, physics_data.velocity_a.clone()),
+                WriteDescriptorSet::buffer(2, sim_params.clone()),
+                WriteDescriptorSet::buffer(3, physics_data.affine_a.clone()),
+                WriteDescriptorSet::buffer(6, grid.velocities.clone()),
+                WriteDescriptorSet::buffer(7, grid.old_velocities.clone()),
+                WriteDescriptorSet::buffer(12, physics_data.particle_counter.clone()),
+            ],
+            []
+        ).unwrap());
+    }
+    /// Clears the grid, transfers the particles onto it, adds the walls and
+    /// measures the divergence the projection has to remove.
+    fn execute<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>) {
+        let transfers = self.transfers.as_ref().expect("FlipPipeline: call prepare() before execute()");
+        builder.fill_buffer(transfers.clone().reinterpret::<[u32]>(), 0).unwrap();
+        builder.fill_buffer(self.cells.clone().unwrap(), 0).unwrap();
+
+        self.dispatch_particles(builder, &self.p2g_pipeline, self.p2g_set.as_ref());
+        self.dispatch_grid(builder, GRID_NORMALIZE, 0);
+        self.dispatch_grid(builder, GRID_DIVERGENCE, 0);
+    }
+}
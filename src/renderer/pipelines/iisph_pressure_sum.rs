@@ -0,0 +1,66 @@
+use std::sync::Arc;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DispatchIndirectCommand};
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::pipeline::{ComputePipeline, Pipeline, PipelineBindPoint};
+use vulkano::shader::EntryPoint;
+use crate::entities::particle::{GpuPhysicsData, SimulationParams};
+use crate::renderer::pipelines::ComputeStep;
+use crate::utils::shader_loader::load_shader_entry_point;
+
+mod cs {
+    use vulkano_shaders::shader;
+    shader! { ty: "compute", path: "shaders/compute/iisph_pressure_sum.comp" }
+}
+
+/// Σ_j d_ij p_j of every particle, with the pressure it was taken with, for
+/// the following `IisphPressurePipeline`.
+pub struct IisphPressureSumPipeline {
+    pub pipeline: Arc<ComputePipeline>,
+    descriptor_set: Option<Arc<DescriptorSet>>,
+    dispatch: Option<Subbuffer<[DispatchIndirectCommand]>>,
+}
+
+impl ComputeStep for IisphPressureSumPipeline {
+    fn load_shader_module(device: Arc<Device>) -> EntryPoint {
+        load_shader_entry_point(device, cs::load, "main")
+    }
+    fn from_pipeline(pipeline: Arc<ComputePipeline>) -> Self {
+        Self { pipeline, descriptor_set: None, dispatch: None }
+    }
+    fn prepare(
+        &mut self,
+        allocator: Arc<StandardDescriptorSetAllocator>,
+        physics_data: &GpuPhysicsData,
+        sim_params: &Subbuffer<SimulationParams>,
+    ) {
+        self.dispatch = Some(physics_data.dispatch_command());
+
+        let layout = self.pipeline.layout().set_layouts().get(0).unwrap();
+        self.descriptor_set = Some(DescriptorSet::new(
+            allocator,
+            layout.clone(),
+            [
+                WriteDescriptorSet::buffer(0, physics_data.grid_entries.clone()),
+                WriteDescriptorSet::buffer(1, physics_data.grid_start.clone()),
+                WriteDescriptorSet::buffer(2, sim_params.clone()),
+                WriteDescriptorSet::buffer(3, physics_data.position_b.clone()),
+                WriteDescriptorSet::buffer(4, physics_data.densities.clone()),
+                WriteDescriptorSet::buffer(5, physics_data.pressures.clone()),
+                WriteDescriptorSet::buffer(6, physics_data.iisph_pressure_sums.clone()),
+                WriteDescriptorSet::buffer(12, physics_data.particle_counter.clone()),
+            ],
+            []
+        ).unwrap());
+    }
+    fn execute<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>) {
+        let set = self.descriptor_set.as_ref().expect("IisphPressureSumPipeline: call prepare() before execute()");
+        builder
+            .bind_pipeline_compute(self.pipeline.clone()).unwrap()
+            .bind_descriptor_sets(PipelineBindPoint::Compute, self.pipeline.layout().clone(), 0, set.clone())
+            .unwrap();
+        unsafe { builder.dispatch_indirect(self.dispatch.clone().unwrap()).unwrap(); }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,85 @@
+use std::sync::Arc;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::image::view::ImageView;
+use vulkano::pipeline::{ComputePipeline, Pipeline, PipelineBindPoint};
+use vulkano::shader::EntryPoint;
+use crate::entities::particle::{GpuPhysicsData, SimulationParams};
+use crate::entities::surface::GpuSurfaceMesh;
+use crate::renderer::pipelines::ComputeStep;
+use crate::utils::shader_loader::load_shader_entry_point;
+
+mod mc_cs {
+    use vulkano_shaders::shader;
+    shader! {
+        ty: "compute",
+        path: "shaders/compute/marching_cubes.comp",
+        include: ["shaders/include"],
+    }
+}
+
+/// Extracts the surface of the splatted density texture into a
+/// `GpuSurfaceMesh`. Run after `DensityTexturePipeline` and
+/// `GpuSurfaceMesh::record_clear`.
+pub struct MarchingCubesPipeline {
+    pub pipeline: Arc<ComputePipeline>,
+    descriptor_set: Option<Arc<DescriptorSet>>,
+    dispatch_count: [u32; 3],
+}
+
+impl MarchingCubesPipeline {
+    pub fn prepare_with_mesh(
+        &mut self,
+        allocator: Arc<StandardDescriptorSetAllocator>,
+        image: Arc<ImageView>,
+        mesh: &GpuSurfaceMesh,
+        sim_params: &Subbuffer<SimulationParams>,
+    ) {
+        // One extra cell per axis for the empty layer around the grid.
+        let group_size = 4;
+        self.dispatch_count = image.image().extent().map(|n| (n + 1).div_ceil(group_size));
+
+        let layout = self.pipeline.layout().set_layouts().get(0).unwrap();
+        self.descriptor_set = Some(DescriptorSet::new(
+            allocator,
+            layout.clone(),
+            [
+                WriteDescriptorSet::buffer(0, mesh.vertices.clone()),
+                WriteDescriptorSet::image_view(1, image),
+                WriteDescriptorSet::buffer(2, sim_params.clone()),
+                WriteDescriptorSet::buffer(3, mesh.triangle_table.clone()),
+                WriteDescriptorSet::buffer(4, mesh.draw_command.clone()),
+                WriteDescriptorSet::buffer(5, mesh.reserved.clone()),
+            ],
+            []
+        ).unwrap());
+    }
+}
+
+impl ComputeStep for MarchingCubesPipeline {
+    fn load_shader_module(device: Arc<Device>) -> EntryPoint {
+        load_shader_entry_point(device, mc_cs::load, "main")
+    }
+    fn from_pipeline(pipeline: Arc<ComputePipeline>) -> Self {
+        Self { pipeline, descriptor_set: None, dispatch_count: [0; 3] }
+    }
+    fn prepare(
+        &mut self,
+        _allocator: Arc<StandardDescriptorSetAllocator>,
+        _physics_data: &GpuPhysicsData,
+        _sim_params: &Subbuffer<SimulationParams>,
+    ) {
+        // Use prepare_with_mesh() instead, as the density image and mesh buffers are required.
+    }
+    fn execute<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>) {
+        let set = self.descriptor_set.as_ref().expect("MarchingCubesPipeline: call prepare_with_mesh() before execute()");
+        builder
+            .bind_pipeline_compute(self.pipeline.clone()).unwrap()
+            .bind_descriptor_sets(PipelineBindPoint::Compute, self.pipeline.layout().clone(), 0, set.clone())
+            .unwrap();
+        unsafe { builder.dispatch(self.dispatch_count).unwrap(); }
+    }
+}
@@ -6,7 +6,7 @@ use vulkano::descriptor_set::layout::{DescriptorSetLayout, DescriptorSetLayoutBi
 use vulkano::device::Device;
 use vulkano::format::Format;
 use vulkano::pipeline::layout::{PipelineDescriptorSetLayoutCreateInfo, PipelineLayoutCreateInfo, PushConstantRange};
-use vulkano::pipeline::{ComputePipeline, PipelineLayout, PipelineShaderStageCreateInfo};
+use vulkano::pipeline::{ComputePipeline, Pipeline, PipelineLayout, PipelineShaderStageCreateInfo};
 use vulkano::pipeline::compute::ComputePipelineCreateInfo;
 use vulkano::shader::{EntryPoint, ShaderStages};
 use vulkano::memory::allocator::StandardMemoryAllocator;
@@ -14,18 +14,36 @@ use vulkano_util::context::VulkanoContext;
 use crate::entities::particle::{GpuPhysicsData, SimulationParams};
 use crate::renderer::pipelines::collision_pipeline::CollisionPipeline;
 use crate::renderer::pipelines::density_alpha::DensityAlphaPipeline;
+use crate::renderer::pipelines::diffuse::DiffuseRenderPipeline;
 use crate::renderer::pipelines::density_source_term::DensitySourceTermPipeline;
 use crate::renderer::pipelines::divergence_integration::DivergenceIntegrationPipeline;
 use crate::renderer::pipelines::divergence_source_term::DivergenceSourceTermPipeline;
+use crate::renderer::pipelines::emit::EmitPipeline;
+use crate::renderer::pipelines::flip::FlipPipeline;
+use crate::renderer::pipelines::iisph_diagonal::IisphDiagonalPipeline;
+use crate::renderer::pipelines::iisph_pressure::IisphPressurePipeline;
+use crate::renderer::pipelines::iisph_pressure_sum::IisphPressureSumPipeline;
 use crate::renderer::pipelines::neighbor_search::NeighborSearch;
+use crate::renderer::pipelines::pbf_correction::PbfCorrectionPipeline;
+use crate::renderer::pipelines::pbf_lambda::PbfLambdaPipeline;
+use crate::renderer::pipelines::pcisph_pressure::PcisphPressurePipeline;
 use crate::renderer::pipelines::point_pipeline::PointPipeline;
+use crate::renderer::pipelines::pressure_convergence::PressureConvergencePipeline;
 use crate::renderer::pipelines::pressure_force_pipeline::PressureForcePipeline;
 use crate::renderer::pipelines::pressure_integration_pipeline::PressureIntegrationPipeline;
 use crate::renderer::pipelines::pressure_update_pipeline::PressureUpdatePipeline;
+use crate::renderer::pipelines::predict_positions::PredictPositionsPipeline;
+use crate::renderer::pipelines::sink::SinkPipeline;
 use crate::renderer::pipelines::sky_pipeline::SkyPipeline;
+use crate::renderer::pipelines::ssfr_pipeline::ScreenSpaceFluidPipelines;
+use crate::renderer::pipelines::surface_pipeline::SurfacePipeline;
 use crate::renderer::pipelines::viscosity::ViscosityPipeline;
-use crate::renderer::pipelines::density_texture::DensityTexturePipeline;
+use crate::renderer::pipelines::viscosity_solver::ViscositySolverPipeline;
+use crate::renderer::pipelines::surface_normals::SurfaceNormalsPipeline;
+use crate::renderer::pipelines::vorticity::VorticityPipeline;
+use crate::renderer::pipelines::vorticity_confinement::VorticityConfinementPipeline;
 use crate::renderer::pipelines::water_pipeline::WaterRenderPipeline;
+use crate::renderer::pipelines::wcsph_pressure::WcsphPressurePipeline;
 use crate::renderer::pipelines::stats_pipeline::StatsPipeline;
 
 pub mod point_pipeline;
@@ -34,20 +52,45 @@ pub mod collision_pipeline;
 mod neighbor_search;
 mod sorter;
 pub use sorter::SortAlgorithm;
+mod emit;
+mod sink;
 mod density_alpha;
 mod viscosity;
+mod viscosity_solver;
+mod surface_normals;
+mod vorticity;
+mod vorticity_confinement;
 mod density_source_term;
 mod pressure_force_pipeline;
 mod pressure_update_pipeline;
+mod pressure_convergence;
 mod pressure_integration_pipeline;
 mod divergence_source_term;
 mod divergence_integration;
-mod density_texture;
+mod pressure_solver;
+pub use pressure_solver::{Dfsph, FlipProjection, Iisph, Pbf, Pcisph, PressureSolverStep, Wcsph};
+mod wcsph_pressure;
+mod predict_positions;
+mod pcisph_pressure;
+mod iisph_diagonal;
+mod iisph_pressure_sum;
+mod iisph_pressure;
+mod pbf_lambda;
+mod pbf_correction;
+mod flip;
+pub mod density_texture;
+pub mod marching_cubes;
 mod water_pipeline;
+mod surface_pipeline;
+pub mod ssfr_pipeline;
+pub mod ssfr_smooth;
+pub mod diffuse;
 mod stats_pipeline;
 
 #[cfg(test)]
 mod convergence_benchmark;
+#[cfg(test)]
+mod cross_validation;
 
 pub trait ComputeStep: Sized {
     fn load_shader_module(device: Arc<Device>) -> EntryPoint;
@@ -76,16 +119,31 @@ pub trait ComputeStep: Sized {
 }
 
 pub struct ComputePipelines {
+    pub emit: EmitPipeline,
+    pub sink: SinkPipeline,
     pub neighbor_search: NeighborSearch,
     pub density_alpha: DensityAlphaPipeline,
+    pub surface_normals: SurfaceNormalsPipeline,
     pub viscosity: ViscosityPipeline,
+    pub viscosity_solver: ViscositySolverPipeline,
+    pub vorticity: VorticityPipeline,
+    pub vorticity_confinement: VorticityConfinementPipeline,
     pub density_source_term: DensitySourceTermPipeline,
     pub pressure_force: PressureForcePipeline,
     pub pressure_update: PressureUpdatePipeline,
+    pub pressure_convergence: PressureConvergencePipeline,
     pub pressure_integration: PressureIntegrationPipeline,
     pub divergence_source_term: DivergenceSourceTermPipeline,
     pub divergence_integration: DivergenceIntegrationPipeline,
-    pub density_texture: DensityTexturePipeline,
+    pub wcsph_pressure: WcsphPressurePipeline,
+    pub predict_positions: PredictPositionsPipeline,
+    pub pcisph_pressure: PcisphPressurePipeline,
+    pub iisph_diagonal: IisphDiagonalPipeline,
+    pub iisph_pressure_sum: IisphPressureSumPipeline,
+    pub iisph_pressure: IisphPressurePipeline,
+    pub pbf_lambda: PbfLambdaPipeline,
+    pub pbf_correction: PbfCorrectionPipeline,
+    pub flip: FlipPipeline,
     pub stats: StatsPipeline,
 }
 
@@ -95,32 +153,143 @@ impl ComputePipelines {
         memory_allocator: Arc<StandardMemoryAllocator>,
         sort_buffer_size: u32,
     ) -> Self {
+        let emit = EmitPipeline::new(device.clone());
+        let sink = SinkPipeline::new(device.clone());
         let neighbor_search = NeighborSearch::new_with_allocator(device.clone(), memory_allocator, sort_buffer_size);
         let density_alpha = DensityAlphaPipeline::new(device.clone());
+        let surface_normals = SurfaceNormalsPipeline::new(device.clone());
         let viscosity = ViscosityPipeline::new(device.clone());
+        let viscosity_solver = ViscositySolverPipeline::new(device.clone());
+        let vorticity = VorticityPipeline::new(device.clone());
+        let vorticity_confinement = VorticityConfinementPipeline::new(device.clone());
         let density_source_term = DensitySourceTermPipeline::new(device.clone());
         let pressure_force = PressureForcePipeline::new(device.clone());
         let pressure_update = PressureUpdatePipeline::new(device.clone());
+        let pressure_convergence = PressureConvergencePipeline::new(device.clone());
         let pressure_integration = PressureIntegrationPipeline::new(device.clone());
         let divergence_source_term = DivergenceSourceTermPipeline::new(device.clone());
         let divergence_integration = DivergenceIntegrationPipeline::new(device.clone());
-        let density_texture = DensityTexturePipeline::new(device.clone());
+        let wcsph_pressure = WcsphPressurePipeline::new(device.clone());
+        let predict_positions = PredictPositionsPipeline::new(device.clone());
+        let pcisph_pressure = PcisphPressurePipeline::new(device.clone());
+        let iisph_diagonal = IisphDiagonalPipeline::new(device.clone());
+        let iisph_pressure_sum = IisphPressureSumPipeline::new(device.clone());
+        let iisph_pressure = IisphPressurePipeline::new(device.clone());
+        let pbf_lambda = PbfLambdaPipeline::new(device.clone());
+        let pbf_correction = PbfCorrectionPipeline::new(device.clone());
+        let flip = FlipPipeline::new(device.clone());
         let stats = StatsPipeline::new(device.clone());
 
         Self {
+            emit,
+            sink,
             neighbor_search,
             density_alpha,
+            surface_normals,
             viscosity,
+            viscosity_solver,
+            vorticity,
+            vorticity_confinement,
             density_source_term,
             pressure_force,
             pressure_update,
+            pressure_convergence,
             pressure_integration,
             divergence_source_term,
             divergence_integration,
-            density_texture,
+            wcsph_pressure,
+            predict_positions,
+            pcisph_pressure,
+            iisph_diagonal,
+            iisph_pressure_sum,
+            iisph_pressure,
+            pbf_lambda,
+            pbf_correction,
+            flip,
             stats,
         }
     }
+    pub fn prepare(
+        &mut self,
+        allocator: Arc<StandardDescriptorSetAllocator>,
+        physics_data: &GpuPhysicsData,
+        sim_params: &Subbuffer<SimulationParams>,
+    ) {
+        self.sink.prepare(allocator.clone(), physics_data, sim_params);
+        self.neighbor_search.prepare(allocator.clone(), physics_data, sim_params);
+        self.density_alpha.prepare(allocator.clone(), physics_data, sim_params);
+        self.surface_normals.prepare(allocator.clone(), physics_data, sim_params);
+        self.viscosity.prepare(allocator.clone(), physics_data, sim_params);
+        self.viscosity_solver.prepare(allocator.clone(), physics_data, sim_params);
+        self.vorticity.prepare(allocator.clone(), physics_data, sim_params);
+        self.vorticity_confinement.prepare(allocator.clone(), physics_data, sim_params);
+        self.density_source_term.prepare(allocator.clone(), physics_data, sim_params);
+        self.pressure_force.prepare(allocator.clone(), physics_data, sim_params);
+        self.pressure_update.prepare(allocator.clone(), physics_data, sim_params);
+        self.pressure_convergence.prepare(allocator.clone(), physics_data, sim_params);
+        self.pressure_integration.prepare(allocator.clone(), physics_data, sim_params);
+        self.divergence_source_term.prepare(allocator.clone(), physics_data, sim_params);
+        self.divergence_integration.prepare(allocator.clone(), physics_data, sim_params);
+        self.wcsph_pressure.prepare(allocator.clone(), physics_data, sim_params);
+        self.predict_positions.prepare(allocator.clone(), physics_data, sim_params);
+        self.pcisph_pressure.prepare(allocator.clone(), physics_data, sim_params);
+        self.iisph_diagonal.prepare(allocator.clone(), physics_data, sim_params);
+        self.iisph_pressure_sum.prepare(allocator.clone(), physics_data, sim_params);
+        self.iisph_pressure.prepare(allocator.clone(), physics_data, sim_params);
+        self.pbf_lambda.prepare(allocator.clone(), physics_data, sim_params);
+        self.pbf_correction.prepare(allocator.clone(), physics_data, sim_params);
+        self.flip.prepare(allocator.clone(), physics_data, sim_params);
+        self.stats.prepare(allocator, physics_data, sim_params);
+    }
+    /// Re-binds the passes that read the boundary particles after
+    /// `GpuPhysicsData::set_boundary` replaced their buffers.
+    pub fn prepare_boundary(
+        &mut self,
+        allocator: Arc<StandardDescriptorSetAllocator>,
+        physics_data: &GpuPhysicsData,
+        sim_params: &Subbuffer<SimulationParams>,
+    ) {
+        self.density_alpha.prepare(allocator.clone(), physics_data, sim_params);
+        self.density_source_term.prepare(allocator.clone(), physics_data, sim_params);
+        self.pressure_force.prepare(allocator.clone(), physics_data, sim_params);
+        self.pressure_update.prepare(allocator.clone(), physics_data, sim_params);
+        self.pcisph_pressure.prepare(allocator.clone(), physics_data, sim_params);
+        self.iisph_diagonal.prepare(allocator.clone(), physics_data, sim_params);
+        self.iisph_pressure.prepare(allocator.clone(), physics_data, sim_params);
+        self.pbf_lambda.prepare(allocator.clone(), physics_data, sim_params);
+        self.pbf_correction.prepare(allocator.clone(), physics_data, sim_params);
+        self.divergence_source_term.prepare(allocator, physics_data, sim_params);
+    }
+    /// Re-binds the pass that reads the obstacles after
+    /// `GpuPhysicsData::set_obstacles` replaced their buffers.
+    pub fn prepare_obstacles(
+        &mut self,
+        allocator: Arc<StandardDescriptorSetAllocator>,
+        physics_data: &GpuPhysicsData,
+        sim_params: &Subbuffer<SimulationParams>,
+    ) {
+        self.pressure_integration.prepare(allocator, physics_data, sim_params);
+    }
+    /// Re-binds the FLIP passes after `GpuPhysicsData::reserve_flip_grid`
+    /// replaced the grid buffers.
+    pub fn prepare_flip(
+        &mut self,
+        allocator: Arc<StandardDescriptorSetAllocator>,
+        physics_data: &GpuPhysicsData,
+        sim_params: &Subbuffer<SimulationParams>,
+    ) {
+        self.flip.prepare(allocator, physics_data, sim_params);
+    }
+    /// Re-binds the sink pass after `GpuPhysicsData::set_sinks` replaced
+    /// their buffers.
+    pub fn prepare_sinks(
+        &mut self,
+        allocator: Arc<StandardDescriptorSetAllocator>,
+        physics_data: &GpuPhysicsData,
+        sim_params: &Subbuffer<SimulationParams>,
+    ) {
+        self.sink.prepare(allocator, physics_data, sim_params);
+    }
 }
 
 
@@ -131,6 +300,9 @@ pub struct Pipelines {
     pub point_pipeline: Arc<PointPipeline>,
     pub collision_pipeline: Arc<CollisionPipeline>,
     pub water_renderer_pipeline: Arc<WaterRenderPipeline>,
+    pub surface_pipeline: Arc<SurfacePipeline>,
+    pub screen_space_fluid: ScreenSpaceFluidPipelines,
+    pub diffuse: DiffuseRenderPipeline,
 }
 
 impl Pipelines {
@@ -151,6 +323,14 @@ impl Pipelines {
         let collision_pipeline = Arc::new(CollisionPipeline::new(device.clone(), common_layout.clone(), swapchain_format, depth_format));
 
         let water_renderer_pipeline = Arc::new(WaterRenderPipeline::new(device.clone(), swapchain_format, depth_format));
+        let surface_pipeline = Arc::new(SurfacePipeline::new(
+            device.clone(),
+            water_renderer_pipeline.inner.layout().clone(),
+            swapchain_format,
+            depth_format,
+        ));
+        let screen_space_fluid = ScreenSpaceFluidPipelines::new(device.clone(), swapchain_format, depth_format);
+        let diffuse = DiffuseRenderPipeline::new(device.clone(), swapchain_format, depth_format);
 
         Self {
             sky_layout,
@@ -159,6 +339,9 @@ impl Pipelines {
             point_pipeline,
             collision_pipeline,
             water_renderer_pipeline,
+            surface_pipeline,
+            screen_space_fluid,
+            diffuse,
         }
     }
 
@@ -1,6 +1,6 @@
 use std::sync::Arc;
 use vulkano::buffer::Subbuffer;
-use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DispatchIndirectCommand};
 use vulkano::instance::debug::DebugUtilsLabel;
 use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
 use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
@@ -17,19 +17,24 @@ use crate::utils::shader_loader::load_shader_entry_point;
 
 mod cs_hash {
     use vulkano_shaders::shader;
-    shader!(ty: "compute", path: "shaders\\compute\\spatial_hash.comp");
+    shader!(ty: "compute", path: "shaders/compute/spatial_hash.comp");
+}
+mod cs_count {
+    use vulkano_shaders::shader;
+    shader!(ty: "compute", path: "shaders/compute/particle_count.comp");
 }
 mod cs_offsets {
     use vulkano_shaders::shader;
-    shader!(ty: "compute", path: "shaders\\compute\\grid_offsets.comp");
+    shader!(ty: "compute", path: "shaders/compute/grid_offsets.comp");
 }
 mod cs_reorder {
     use vulkano_shaders::shader;
-    shader!(ty: "compute", path: "shaders\\compute\\reorder.comp");
+    shader!(ty: "compute", path: "shaders/compute/reorder.comp");
 }
 
 pub struct NeighborSearch {
     spatial_hash_pipeline: Arc<ComputePipeline>,
+    count_pipeline: Arc<ComputePipeline>,
     offsets_pipeline: Arc<ComputePipeline>,
     reorder_pipeline: Arc<ComputePipeline>,
 
@@ -37,13 +42,15 @@ pub struct NeighborSearch {
     radix_sorter: RadixSorter,
 
     hash_set: Option<Arc<DescriptorSet>>,
+    count_set: Option<Arc<DescriptorSet>>,
     offsets_set: Option<Arc<DescriptorSet>>,
     reorder_set: Option<Arc<DescriptorSet>>,
 
     grid_start: Option<Subbuffer<[u32]>>,
 
     sort_buffer_len: u32,
-    num_particles: u32,
+    capacity: u32,
+    dispatch: Option<Subbuffer<[DispatchIndirectCommand]>>,
 
     pub sort_algorithm: SortAlgorithm,
 }
@@ -68,6 +75,17 @@ impl NeighborSearch {
             device.clone(), None, ComputePipelineCreateInfo::stage_layout(hash_stage, hash_layout)
         ).unwrap();
 
+        let count_shader = load_shader_entry_point(device.clone(), cs_count::load, "main");
+        let count_stage = PipelineShaderStageCreateInfo::new(count_shader);
+        let count_layout = PipelineLayout::new(
+            device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages([&count_stage])
+                .into_pipeline_layout_create_info(device.clone()).unwrap()
+        ).unwrap();
+        let count_pipeline = ComputePipeline::new(
+            device.clone(), None, ComputePipelineCreateInfo::stage_layout(count_stage, count_layout)
+        ).unwrap();
+
         let offsets_shader = load_shader_entry_point(device.clone(), cs_offsets::load, "main");
         let offsets_stage = PipelineShaderStageCreateInfo::new(offsets_shader);
         let offsets_layout = PipelineLayout::new(
@@ -92,17 +110,20 @@ impl NeighborSearch {
 
         Self {
             spatial_hash_pipeline,
+            count_pipeline,
             offsets_pipeline,
             reorder_pipeline,
             sorter,
             radix_sorter,
             sort_algorithm: SortAlgorithm::Bitonic,
             hash_set: None,
+            count_set: None,
             offsets_set: None,
             reorder_set: None,
             grid_start: None,
             sort_buffer_len: 0,
-            num_particles: 0,
+            capacity: 0,
+            dispatch: None,
         }
     }
 }
@@ -123,7 +144,8 @@ impl ComputeStep for NeighborSearch {
         physics_data: &GpuPhysicsData,
         sim_params: &Subbuffer<SimulationParams>,
     ) {
-        self.num_particles = physics_data.count;
+        self.capacity = physics_data.capacity;
+        self.dispatch = Some(physics_data.dispatch_command());
         self.sort_buffer_len = physics_data.grid_entries.len() as u32;
         self.grid_start = Some(physics_data.grid_start.clone());
 
@@ -136,6 +158,19 @@ impl ComputeStep for NeighborSearch {
                     WriteDescriptorSet::buffer(0, physics_data.position_a.clone()),
                     WriteDescriptorSet::buffer(1, physics_data.grid_entries.clone()),
                     WriteDescriptorSet::buffer(2, sim_params.clone()),
+                    WriteDescriptorSet::buffer(12, physics_data.particle_counter.clone()),
+                ],
+                [],
+            ).unwrap());
+        }
+
+        {
+            let layout = self.count_pipeline.layout().set_layouts().get(0).unwrap();
+            self.count_set = Some(DescriptorSet::new(
+                allocator.clone(),
+                layout.clone(),
+                [
+                    WriteDescriptorSet::buffer(12, physics_data.particle_counter.clone()),
                 ],
                 [],
             ).unwrap());
@@ -162,9 +197,13 @@ impl ComputeStep for NeighborSearch {
                 [
                     WriteDescriptorSet::buffer(0, physics_data.grid_entries.clone()),
                     WriteDescriptorSet::buffer(1, physics_data.position_a.clone()),
-                    WriteDescriptorSet::buffer(2, physics_data.velocity_a.clone()),
+                    WriteDescriptorSet::buffer(2, sim_params.clone()),
+                    WriteDescriptorSet::buffer(3, physics_data.velocity_a.clone()),
                     WriteDescriptorSet::buffer(4, physics_data.position_b.clone()),
                     WriteDescriptorSet::buffer(5, physics_data.velocity_b.clone()),
+                    WriteDescriptorSet::buffer(6, physics_data.affine_a.clone()),
+                    WriteDescriptorSet::buffer(7, physics_data.affine_b.clone()),
+                    WriteDescriptorSet::buffer(12, physics_data.particle_counter.clone()),
                 ],
                 [],
             ).unwrap());
@@ -175,12 +214,11 @@ impl ComputeStep for NeighborSearch {
     }
     fn execute<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>) {
         let sort_buffer_len = self.sort_buffer_len;
-        let num_particles = self.num_particles;
         let group_size = 256;
 
         {
             let set = self.hash_set.as_ref().expect("NeighborSearch: call prepare() before execute()");
-            let pc = cs_hash::PushConstants { num_particles, table_size: sort_buffer_len };
+            let pc = cs_hash::PushConstants { table_size: sort_buffer_len };
             let dispatch_count = (sort_buffer_len + group_size - 1) / group_size;
             builder
                 .bind_pipeline_compute(self.spatial_hash_pipeline.clone()).unwrap()
@@ -204,6 +242,18 @@ impl ComputeStep for NeighborSearch {
             },
         }
 
+        // Dead particles and spare capacity are now sorted behind the live
+        // ones, so the live count can drop the sinks' removals.
+        {
+            let set = self.count_set.as_ref().unwrap();
+            let pc = cs_count::PushConstants { capacity: self.capacity };
+            builder
+                .bind_pipeline_compute(self.count_pipeline.clone()).unwrap()
+                .bind_descriptor_sets(PipelineBindPoint::Compute, self.count_pipeline.layout().clone(), 0, set.clone()).unwrap()
+                .push_constants(self.count_pipeline.layout().clone(), 0, pc).unwrap();
+            unsafe { builder.dispatch([1, 1, 1]).unwrap(); }
+        }
+
         let grid_start = self.grid_start.as_ref().expect("NeighborSearch: grid_start not set");
         builder.fill_buffer(grid_start.clone(), 0xFFFFFFFF).unwrap();
 
@@ -220,13 +270,10 @@ impl ComputeStep for NeighborSearch {
 
         {
             let set = self.reorder_set.as_ref().unwrap();
-            let pc = cs_reorder::PushConstants { num_particles };
-            let dispatch_count = (num_particles + group_size - 1) / group_size;
             builder
                 .bind_pipeline_compute(self.reorder_pipeline.clone()).unwrap()
-                .bind_descriptor_sets(PipelineBindPoint::Compute, self.reorder_pipeline.layout().clone(), 0, set.clone()).unwrap()
-                .push_constants(self.reorder_pipeline.layout().clone(), 0, pc).unwrap();
-            unsafe { builder.dispatch([dispatch_count, 1, 1]).unwrap(); }
+                .bind_descriptor_sets(PipelineBindPoint::Compute, self.reorder_pipeline.layout().clone(), 0, set.clone()).unwrap();
+            unsafe { builder.dispatch_indirect(self.dispatch.clone().unwrap()).unwrap(); }
         }
     }
 }
@@ -19,7 +19,7 @@ mod vs {
 
     shader!(
         ty: "vertex",
-        path: "shaders\\simple_shader.vert"
+        path: "shaders/simple_shader.vert"
     );
 }
 
@@ -28,7 +28,7 @@ mod fs {
 
     shader!(
         ty: "fragment",
-        path: "shaders\\simple_shader.frag"
+        path: "shaders/simple_shader.frag"
     );
 }
 
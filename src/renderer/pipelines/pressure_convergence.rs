@@ -0,0 +1,76 @@
+use std::sync::Arc;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::pipeline::{ComputePipeline, Pipeline, PipelineBindPoint};
+use vulkano::shader::EntryPoint;
+use crate::entities::particle::{GpuPhysicsData, PressureSolve, SimulationParams};
+use crate::renderer::pipelines::ComputeStep;
+use crate::utils::shader_loader::load_shader_entry_point;
+
+mod cs {
+    use vulkano_shaders::shader;
+    shader!(ty: "compute", path: "shaders/compute/pressure_convergence.comp");
+}
+
+// CHECK_* in pressure_solver.glsl.
+const CHECK_BEGIN: u32 = 0;
+const CHECK_ITERATION: u32 = 1;
+
+/// The stopping criterion of the DFSPH solves. `execute_begin` arms the
+/// iterations' indirect dispatch for a solve; every `execute` after a
+/// `PressureUpdatePipeline::execute_iteration` averages its residual and
+/// zeroes the dispatch once the error is below η.
+pub struct PressureConvergencePipeline {
+    pub pipeline: Arc<ComputePipeline>,
+    descriptor_set: Option<Arc<DescriptorSet>>,
+}
+
+impl PressureConvergencePipeline {
+    pub fn execute_begin<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>, solve: PressureSolve) {
+        self.dispatch(builder, CHECK_BEGIN, solve as u32);
+    }
+
+    fn dispatch<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>, stage: u32, solve: u32) {
+        let set = self.descriptor_set.as_ref().expect("PressureConvergencePipeline: call prepare() before execute()");
+        builder
+            .bind_pipeline_compute(self.pipeline.clone()).unwrap()
+            .bind_descriptor_sets(PipelineBindPoint::Compute, self.pipeline.layout().clone(), 0, set.clone()).unwrap()
+            .push_constants(self.pipeline.layout().clone(), 0, cs::PushConstants { stage, solve }).unwrap();
+        unsafe { builder.dispatch([1, 1, 1]).unwrap(); }
+    }
+}
+
+impl ComputeStep for PressureConvergencePipeline {
+    fn load_shader_module(device: Arc<Device>) -> EntryPoint {
+        load_shader_entry_point(device, cs::load, "main")
+    }
+    fn from_pipeline(pipeline: Arc<ComputePipeline>) -> Self {
+        Self { pipeline, descriptor_set: None }
+    }
+    fn prepare(
+        &mut self,
+        allocator: Arc<StandardDescriptorSetAllocator>,
+        physics_data: &GpuPhysicsData,
+        sim_params: &Subbuffer<SimulationParams>,
+    ) {
+        let layout = self.pipeline.layout().set_layouts().get(0).unwrap();
+        self.descriptor_set = Some(DescriptorSet::new(
+            allocator,
+            layout.clone(),
+            [
+                WriteDescriptorSet::buffer(2, sim_params.clone()),
+                WriteDescriptorSet::buffer(12, physics_data.particle_counter.clone()),
+                WriteDescriptorSet::buffer(13, physics_data.pressure_partials.clone()),
+                WriteDescriptorSet::buffer(14, physics_data.pressure_solver.clone()),
+            ],
+            []
+        ).unwrap());
+    }
+    /// Checks the iteration just recorded.
+    fn execute<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>) {
+        self.dispatch(builder, CHECK_ITERATION, 0);
+    }
+}
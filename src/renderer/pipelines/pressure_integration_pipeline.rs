@@ -1,6 +1,6 @@
 use std::sync::Arc;
 use vulkano::buffer::Subbuffer;
-use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DispatchIndirectCommand};
 use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
 use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
 use vulkano::device::Device;
@@ -14,14 +14,14 @@ mod cs {
     use vulkano_shaders::shader;
     shader! {
         ty: "compute",
-        path: "shaders\\compute\\pressure_integration.comp"
+        path: "shaders/compute/pressure_integration.comp"
     }
 }
 
 pub struct PressureIntegrationPipeline {
     pub pipeline: Arc<ComputePipeline>,
     descriptor_set: Option<Arc<DescriptorSet>>,
-    dispatch_count: u32,
+    dispatch: Option<Subbuffer<[DispatchIndirectCommand]>>,
 }
 
 impl ComputeStep for PressureIntegrationPipeline {
@@ -29,7 +29,7 @@ impl ComputeStep for PressureIntegrationPipeline {
         load_shader_entry_point(device, cs::load, "main")
     }
     fn from_pipeline(pipeline: Arc<ComputePipeline>) -> Self {
-        Self { pipeline, descriptor_set: None, dispatch_count: 0 }
+        Self { pipeline, descriptor_set: None, dispatch: None }
     }
     fn prepare(
         &mut self,
@@ -37,9 +37,7 @@ impl ComputeStep for PressureIntegrationPipeline {
         physics_data: &GpuPhysicsData,
         sim_params: &Subbuffer<SimulationParams>,
     ) {
-        let num_particles = physics_data.count;
-        let group_size = 256;
-        self.dispatch_count = (num_particles + group_size - 1) / group_size;
+        self.dispatch = Some(physics_data.dispatch_command());
 
         let layout = self.pipeline.layout().set_layouts().get(0).unwrap();
         self.descriptor_set = Some(DescriptorSet::new(
@@ -51,6 +49,10 @@ impl ComputeStep for PressureIntegrationPipeline {
                 WriteDescriptorSet::buffer(2, sim_params.clone()),
                 WriteDescriptorSet::buffer(3, physics_data.velocity_a.clone()),
                 WriteDescriptorSet::buffer(4, physics_data.position_a.clone()),
+                WriteDescriptorSet::buffer(5, physics_data.obstacles.clone()),
+                WriteDescriptorSet::buffer(6, physics_data.obstacle_sdf.clone()),
+                WriteDescriptorSet::buffer(7, physics_data.obstacle_impulses.clone()),
+                WriteDescriptorSet::buffer(12, physics_data.particle_counter.clone()),
             ],
             []
         ).unwrap());
@@ -61,6 +63,6 @@ impl ComputeStep for PressureIntegrationPipeline {
             .bind_pipeline_compute(self.pipeline.clone()).unwrap()
             .bind_descriptor_sets(PipelineBindPoint::Compute, self.pipeline.layout().clone(), 0, set.clone())
             .unwrap();
-        unsafe { builder.dispatch([self.dispatch_count, 1, 1]).unwrap(); }
+        unsafe { builder.dispatch_indirect(self.dispatch.clone().unwrap()).unwrap(); }
     }
 }
\ No newline at end of file
@@ -0,0 +1,183 @@
+use vulkano::buffer::{BufferContents, Subbuffer};
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use crate::entities::particle::{GpuPhysicsData, PressureSolve};
+use crate::renderer::pipelines::{ComputePipelines, ComputeStep};
+
+/// The pressure part of a substep, recorded around the shared passes: the
+/// density solve sits between the non-pressure forces and
+/// `pressure_integration`, the divergence solve between the post-integrate
+/// `density_alpha` and `divergence_integration`. A solver has to leave its
+/// pressure accelerations in `pressure_accelerations` for both.
+pub trait PressureSolverStep {
+    fn record_density_solve<Cb>(
+        &self,
+        pipelines: &ComputePipelines,
+        physics_data: &GpuPhysicsData,
+        builder: &mut AutoCommandBufferBuilder<Cb>,
+        iterations: u32,
+    );
+    /// Solvers without a divergence solve still measure the divergence for
+    /// the stats, and leave no velocity correction.
+    fn record_divergence_solve<Cb>(
+        &self,
+        pipelines: &ComputePipelines,
+        physics_data: &GpuPhysicsData,
+        builder: &mut AutoCommandBufferBuilder<Cb>,
+        _iterations: u32,
+    ) {
+        pipelines.divergence_source_term.execute(builder);
+        clear(&physics_data.pressure_accelerations, builder);
+    }
+}
+
+fn clear<T: BufferContents, Cb>(buffer: &Subbuffer<[T]>, builder: &mut AutoCommandBufferBuilder<Cb>) {
+    builder.fill_buffer(buffer.clone().reinterpret::<[u32]>(), 0).unwrap();
+}
+
+/// Relaxed Jacobi on the density and then the divergence error, both through
+/// `pressure_force` / `pressure_update` with the DFSPH factors. `iterations`
+/// is the cap; each solve stops on the GPU once its average error is below η.
+pub struct Dfsph;
+
+impl Dfsph {
+    fn record_iterations<Cb>(
+        pipelines: &ComputePipelines,
+        builder: &mut AutoCommandBufferBuilder<Cb>,
+        solve: PressureSolve,
+        iterations: u32,
+    ) {
+        pipelines.pressure_convergence.execute_begin(builder, solve);
+        for _ in 0..iterations {
+            pipelines.pressure_force.execute_iteration(builder);
+            pipelines.pressure_update.execute_iteration(builder);
+            pipelines.pressure_convergence.execute(builder);
+        }
+    }
+}
+
+impl PressureSolverStep for Dfsph {
+    fn record_density_solve<Cb>(
+        &self,
+        pipelines: &ComputePipelines,
+        _physics_data: &GpuPhysicsData,
+        builder: &mut AutoCommandBufferBuilder<Cb>,
+        iterations: u32,
+    ) {
+        pipelines.density_source_term.execute(builder);
+        Self::record_iterations(pipelines, builder, PressureSolve::Density, iterations);
+    }
+    fn record_divergence_solve<Cb>(
+        &self,
+        pipelines: &ComputePipelines,
+        _physics_data: &GpuPhysicsData,
+        builder: &mut AutoCommandBufferBuilder<Cb>,
+        iterations: u32,
+    ) {
+        pipelines.divergence_source_term.execute(builder);
+        Self::record_iterations(pipelines, builder, PressureSolve::Divergence, iterations);
+    }
+}
+
+/// Pressure straight from the densities of the substep's start.
+pub struct Wcsph;
+
+impl PressureSolverStep for Wcsph {
+    fn record_density_solve<Cb>(
+        &self,
+        pipelines: &ComputePipelines,
+        _physics_data: &GpuPhysicsData,
+        builder: &mut AutoCommandBufferBuilder<Cb>,
+        _iterations: u32,
+    ) {
+        pipelines.wcsph_pressure.execute(builder);
+        pipelines.pressure_force.execute(builder);
+    }
+}
+
+/// Predict, correct the pressure from the predicted density, and recompute
+/// the pressure accelerations, starting from zero pressure.
+pub struct Pcisph;
+
+impl PressureSolverStep for Pcisph {
+    fn record_density_solve<Cb>(
+        &self,
+        pipelines: &ComputePipelines,
+        physics_data: &GpuPhysicsData,
+        builder: &mut AutoCommandBufferBuilder<Cb>,
+        iterations: u32,
+    ) {
+        clear(&physics_data.pressures, builder);
+        clear(&physics_data.pressure_accelerations, builder);
+        for _ in 0..iterations {
+            pipelines.predict_positions.execute(builder);
+            pipelines.pcisph_pressure.execute(builder);
+            pipelines.pressure_force.execute(builder);
+        }
+    }
+}
+
+/// d_ii, a_ii and the source term once, then per iteration Σ_j d_ij p_j and
+/// a relaxed Jacobi pressure update, starting from zero pressure; the
+/// pressure accelerations follow from the final pressures. No divergence
+/// solve.
+pub struct Iisph;
+
+impl PressureSolverStep for Iisph {
+    fn record_density_solve<Cb>(
+        &self,
+        pipelines: &ComputePipelines,
+        _physics_data: &GpuPhysicsData,
+        builder: &mut AutoCommandBufferBuilder<Cb>,
+        iterations: u32,
+    ) {
+        pipelines.iisph_diagonal.execute(builder);
+        for _ in 0..iterations {
+            pipelines.iisph_pressure_sum.execute(builder);
+            pipelines.iisph_pressure.execute(builder);
+        }
+        pipelines.pressure_force.execute(builder);
+    }
+}
+
+/// Predict, then one Jacobi sweep over the density constraints per iteration,
+/// accumulating the corrections as accelerations.
+pub struct Pbf;
+
+impl PressureSolverStep for Pbf {
+    fn record_density_solve<Cb>(
+        &self,
+        pipelines: &ComputePipelines,
+        physics_data: &GpuPhysicsData,
+        builder: &mut AutoCommandBufferBuilder<Cb>,
+        iterations: u32,
+    ) {
+        clear(&physics_data.pressure_accelerations, builder);
+        for _ in 0..iterations {
+            pipelines.predict_positions.execute(builder);
+            pipelines.pbf_lambda.execute(builder);
+            pipelines.pbf_correction.execute(builder);
+        }
+    }
+}
+
+/// The FLIP backend where the density solve would be: the grid projection
+/// replaces the particle velocities outright and leaves no pressure
+/// acceleration. `iterations` are the Jacobi sweeps.
+pub struct FlipProjection;
+
+impl PressureSolverStep for FlipProjection {
+    fn record_density_solve<Cb>(
+        &self,
+        pipelines: &ComputePipelines,
+        physics_data: &GpuPhysicsData,
+        builder: &mut AutoCommandBufferBuilder<Cb>,
+        iterations: u32,
+    ) {
+        pipelines.flip.execute(builder);
+        for iteration in 0..iterations {
+            pipelines.flip.execute_iteration(builder, iteration);
+        }
+        pipelines.flip.execute_projection(builder, iterations);
+        clear(&physics_data.pressure_accelerations, builder);
+    }
+}
@@ -1,6 +1,6 @@
 use std::sync::Arc;
 use vulkano::buffer::Subbuffer;
-use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DispatchIndirectCommand};
 use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
 use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
 use vulkano::device::Device;
@@ -14,14 +14,34 @@ mod cs {
     use vulkano_shaders::shader;
     shader!(
         ty: "compute",
-        path: "shaders\\compute\\pressure_update.comp"
+        path: "shaders/compute/pressure_update.comp"
     );
 }
 
+/// Relaxed Jacobi update of the DFSPH pressures, which also leaves the
+/// per-workgroup sums of the compression residual for
+/// `PressureConvergencePipeline`. `execute` covers every live particle;
+/// `execute_iteration` only runs while the solve has not converged.
 pub struct PressureUpdatePipeline {
     pub pipeline: Arc<ComputePipeline>,
     descriptor_set: Option<Arc<DescriptorSet>>,
-    dispatch_count: u32,
+    dispatch: Option<Subbuffer<[DispatchIndirectCommand]>>,
+    solver_dispatch: Option<Subbuffer<[DispatchIndirectCommand]>>,
+}
+
+impl PressureUpdatePipeline {
+    pub fn execute_iteration<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>) {
+        self.dispatch_indirect(builder, self.solver_dispatch.clone());
+    }
+
+    fn dispatch_indirect<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>, dispatch: Option<Subbuffer<[DispatchIndirectCommand]>>) {
+        let set = self.descriptor_set.as_ref().expect("PressureUpdatePipeline: call prepare() before execute()");
+        builder
+            .bind_pipeline_compute(self.pipeline.clone()).unwrap()
+            .bind_descriptor_sets(PipelineBindPoint::Compute, self.pipeline.layout().clone(), 0, set.clone())
+            .unwrap();
+        unsafe { builder.dispatch_indirect(dispatch.unwrap()).unwrap(); }
+    }
 }
 
 impl ComputeStep for PressureUpdatePipeline {
@@ -29,7 +49,7 @@ impl ComputeStep for PressureUpdatePipeline {
         load_shader_entry_point(device, cs::load, "main")
     }
     fn from_pipeline(pipeline: Arc<ComputePipeline>) -> Self {
-        Self { pipeline, descriptor_set: None, dispatch_count: 0 }
+        Self { pipeline, descriptor_set: None, dispatch: None, solver_dispatch: None }
     }
     fn prepare(
         &mut self,
@@ -37,9 +57,8 @@ impl ComputeStep for PressureUpdatePipeline {
         physics_data: &GpuPhysicsData,
         sim_params: &Subbuffer<SimulationParams>,
     ) {
-        let num_particles = physics_data.count;
-        let group_size = 256;
-        self.dispatch_count = (num_particles + group_size - 1) / group_size;
+        self.dispatch = Some(physics_data.dispatch_command());
+        self.solver_dispatch = Some(physics_data.pressure_solver_dispatch_command());
 
         let layout = self.pipeline.layout().set_layouts().get(0).unwrap();
         self.descriptor_set = Some(DescriptorSet::new(
@@ -55,16 +74,16 @@ impl ComputeStep for PressureUpdatePipeline {
                 WriteDescriptorSet::buffer(6, physics_data.source_terms.clone()),
                 WriteDescriptorSet::buffer(7, physics_data.densities.clone()),
                 WriteDescriptorSet::buffer(8, physics_data.pressures.clone()),
+                WriteDescriptorSet::buffer(9, physics_data.boundary_particles.clone()),
+                WriteDescriptorSet::buffer(10, physics_data.boundary_cell_ranges.clone()),
+                WriteDescriptorSet::buffer(11, physics_data.boundary_grid.clone()),
+                WriteDescriptorSet::buffer(12, physics_data.particle_counter.clone()),
+                WriteDescriptorSet::buffer(13, physics_data.pressure_partials.clone()),
             ],
             []
         ).unwrap());
     }
     fn execute<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>) {
-        let set = self.descriptor_set.as_ref().expect("PressureUpdatePipeline: call prepare() before execute()");
-        builder
-            .bind_pipeline_compute(self.pipeline.clone()).unwrap()
-            .bind_descriptor_sets(PipelineBindPoint::Compute, self.pipeline.layout().clone(), 0, set.clone())
-            .unwrap();
-        unsafe { builder.dispatch([self.dispatch_count, 1, 1]).unwrap(); }
+        self.dispatch_indirect(builder, self.dispatch.clone());
     }
 }
\ No newline at end of file
@@ -26,7 +26,7 @@ pub enum SortAlgorithm {
 
 mod cs {
     use vulkano_shaders::shader;
-    shader!(ty: "compute", path: "shaders\\compute\\bitonic_sort.comp");
+    shader!(ty: "compute", path: "shaders/compute/bitonic_sort.comp");
 }
 
 pub struct GpuSorter {
@@ -119,15 +119,15 @@ impl ComputeStep for GpuSorter {
 
 mod cs_count {
     use vulkano_shaders::shader;
-    shader!(ty: "compute", path: "shaders\\compute\\radix_count.comp");
+    shader!(ty: "compute", path: "shaders/compute/radix_count.comp");
 }
 mod cs_scan {
     use vulkano_shaders::shader;
-    shader!(ty: "compute", path: "shaders\\compute\\radix_scan.comp");
+    shader!(ty: "compute", path: "shaders/compute/radix_scan.comp");
 }
 mod cs_reorder {
     use vulkano_shaders::shader;
-    shader!(ty: "compute", path: "shaders\\compute\\radix_reorder.comp");
+    shader!(ty: "compute", path: "shaders/compute/radix_reorder.comp");
 }
 
 const ELEMENTS_PER_INVOCATION: u32 = 8;
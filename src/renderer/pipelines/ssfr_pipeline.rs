@@ -0,0 +1,184 @@
+use std::sync::Arc;
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::pipeline::{DynamicState, GraphicsPipeline, PipelineLayout, PipelineShaderStageCreateInfo};
+use vulkano::pipeline::graphics::color_blend::{AttachmentBlend, ColorBlendAttachmentState, ColorBlendState};
+use vulkano::pipeline::graphics::depth_stencil::{DepthState, DepthStencilState};
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::graphics::input_assembly::{InputAssemblyState, PrimitiveTopology};
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::{CullMode, RasterizationState};
+use vulkano::pipeline::graphics::subpass::{PipelineRenderingCreateInfo, PipelineSubpassType};
+use vulkano::pipeline::graphics::vertex_input::{Vertex, VertexDefinition, VertexInputState};
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::shader::EntryPoint;
+use crate::entities::particle::PositionVertex;
+use crate::utils::shader_loader::load_shader_entry_point;
+
+mod sphere_vs {
+    use vulkano_shaders::shader;
+
+    shader! {
+        ty: "vertex",
+        path: "shaders/ssfr_sphere.vert"
+    }
+}
+
+mod depth_fs {
+    use vulkano_shaders::shader;
+
+    shader! {
+        ty: "fragment",
+        path: "shaders/ssfr_depth.frag"
+    }
+}
+
+mod thickness_fs {
+    use vulkano_shaders::shader;
+
+    shader! {
+        ty: "fragment",
+        path: "shaders/ssfr_thickness.frag"
+    }
+}
+
+mod composite_vs {
+    use vulkano_shaders::shader;
+
+    shader! {
+        ty: "vertex",
+        path: "shaders/ssfr_composite.vert"
+    }
+}
+
+mod composite_fs {
+    use vulkano_shaders::shader;
+
+    shader! {
+        ty: "fragment",
+        path: "shaders/ssfr_composite.frag"
+    }
+}
+
+/// Linear eye depth of the fluid, written by the sphere pass and smoothed in place.
+pub const FLUID_DEPTH_FORMAT: Format = Format::R32_SFLOAT;
+/// Additively accumulated fluid thickness along the view ray.
+pub const FLUID_THICKNESS_FORMAT: Format = Format::R16_SFLOAT;
+
+/// Graphics passes of the screen-space fluid render mode. `sphere_depth` and
+/// `thickness` splat particles into offscreen targets, `composite` shades
+/// the smoothed result inside the main pass.
+pub struct ScreenSpaceFluidPipelines {
+    pub sphere_depth: Arc<GraphicsPipeline>,
+    pub thickness: Arc<GraphicsPipeline>,
+    pub composite: Arc<GraphicsPipeline>,
+}
+
+impl ScreenSpaceFluidPipelines {
+    pub fn new(
+        device: Arc<Device>,
+        swapchain_format: Format,
+        depth_format: Format,
+    ) -> Self {
+        let sphere_vs = load_shader_entry_point(device.clone(), sphere_vs::load, "ssfr sphere vertex");
+        let depth_fs = load_shader_entry_point(device.clone(), depth_fs::load, "ssfr depth fragment");
+        let thickness_fs = load_shader_entry_point(device.clone(), thickness_fs::load, "ssfr thickness fragment");
+        let composite_vs = load_shader_entry_point(device.clone(), composite_vs::load, "ssfr composite vertex");
+        let composite_fs = load_shader_entry_point(device.clone(), composite_fs::load, "ssfr composite fragment");
+
+        let sphere_input = PositionVertex::per_vertex().definition(&sphere_vs).unwrap();
+
+        // Nearest sphere wins: depth tested against its own depth attachment.
+        let sphere_depth = Self::create(
+            device.clone(),
+            [sphere_vs.clone(), depth_fs],
+            sphere_input.clone(),
+            PrimitiveTopology::PointList,
+            Some(DepthState::simple()),
+            None,
+            FLUID_DEPTH_FORMAT,
+            Some(depth_format),
+        );
+
+        // Every sphere along the ray contributes, so no depth test.
+        let thickness = Self::create(
+            device.clone(),
+            [sphere_vs, thickness_fs],
+            sphere_input,
+            PrimitiveTopology::PointList,
+            None,
+            Some(AttachmentBlend::additive()),
+            FLUID_THICKNESS_FORMAT,
+            None,
+        );
+
+        let composite = Self::create(
+            device,
+            [composite_vs, composite_fs],
+            VertexInputState::new(),
+            PrimitiveTopology::TriangleList,
+            Some(DepthState::simple()),
+            None,
+            swapchain_format,
+            Some(depth_format),
+        );
+
+        Self { sphere_depth, thickness, composite }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create(
+        device: Arc<Device>,
+        shaders: [EntryPoint; 2],
+        vertex_input_state: VertexInputState,
+        topology: PrimitiveTopology,
+        depth: Option<DepthState>,
+        blend: Option<AttachmentBlend>,
+        color_format: Format,
+        depth_format: Option<Format>,
+    ) -> Arc<GraphicsPipeline> {
+        let stages = shaders.map(PipelineShaderStageCreateInfo::new);
+
+        let layout = PipelineLayout::new(
+            device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(device.clone())
+                .expect("[SSFR Pipeline] Failed to create layout info from shaders")
+        ).expect("[SSFR Pipeline] Failed to create PipelineLayout");
+
+        GraphicsPipeline::new(
+            device,
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(vertex_input_state),
+                input_assembly_state: Some(InputAssemblyState {
+                    topology,
+                    ..InputAssemblyState::default()
+                }),
+                viewport_state: Some(ViewportState::default()),
+                dynamic_state: [DynamicState::Viewport, DynamicState::Scissor].into_iter().collect(),
+                rasterization_state: Some(RasterizationState {
+                    cull_mode: CullMode::None,
+                    ..RasterizationState::default()
+                }),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth,
+                    ..DepthStencilState::default()
+                }),
+                multisample_state: Some(MultisampleState::default()),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    1,
+                    ColorBlendAttachmentState { blend, ..ColorBlendAttachmentState::default() },
+                )),
+                subpass: Some(PipelineSubpassType::BeginRendering(PipelineRenderingCreateInfo {
+                    color_attachment_formats: vec![Some(color_format)],
+                    depth_attachment_format: depth_format,
+                    ..PipelineRenderingCreateInfo::default()
+                })),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            }
+        ).map_err(|e| panic!("[SSFR Pipeline] Failed to create graphics pipeline:\n{:?}", e)).unwrap()
+    }
+}
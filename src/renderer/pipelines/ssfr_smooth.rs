@@ -0,0 +1,111 @@
+use std::sync::Arc;
+use vulkano::buffer::{BufferContents, Subbuffer};
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::image::view::ImageView;
+use vulkano::pipeline::{ComputePipeline, Pipeline, PipelineBindPoint};
+use vulkano::shader::EntryPoint;
+use crate::entities::particle::{GpuPhysicsData, SimulationParams};
+use crate::renderer::pipelines::ComputeStep;
+use crate::utils::shader_loader::load_shader_entry_point;
+
+mod smooth_cs {
+    use vulkano_shaders::shader;
+    shader! {
+        ty: "compute",
+        path: "shaders/compute/ssfr_smooth.comp",
+    }
+}
+
+#[derive(BufferContents, Clone, Copy)]
+#[repr(C)]
+struct SmoothPushConstants {
+    direction: [i32; 2],
+    particle_radius: f32,
+    pixels_per_unit: f32,
+}
+
+/// Separable bilateral filter over the screen-space fluid depth: a
+/// horizontal pass into the scratch image and a vertical pass back.
+pub struct DepthSmoothPipeline {
+    pub pipeline: Arc<ComputePipeline>,
+    /// Depth → scratch, then scratch → depth.
+    descriptor_sets: Option<[Arc<DescriptorSet>; 2]>,
+    dispatch_count: [u32; 3],
+    particle_radius: f32,
+    pixels_per_unit: f32,
+}
+
+impl DepthSmoothPipeline {
+    pub fn prepare_with_images(
+        &mut self,
+        allocator: Arc<StandardDescriptorSetAllocator>,
+        depth: Arc<ImageView>,
+        scratch: Arc<ImageView>,
+    ) {
+        let group_size = 16;
+        let [w, h, _] = depth.image().extent();
+        self.dispatch_count = [w.div_ceil(group_size), h.div_ceil(group_size), 1];
+
+        let layout = self.pipeline.layout().set_layouts().get(0).unwrap();
+        let set = |src: &Arc<ImageView>, dst: &Arc<ImageView>| DescriptorSet::new(
+            allocator.clone(),
+            layout.clone(),
+            [
+                WriteDescriptorSet::image_view(0, src.clone()),
+                WriteDescriptorSet::image_view(1, dst.clone()),
+            ],
+            []
+        ).unwrap();
+        self.descriptor_sets = Some([set(&depth, &scratch), set(&scratch, &depth)]);
+    }
+
+    /// `pixels_per_unit` is the projected size in pixels of one world unit at
+    /// depth 1, i.e. `proj[1][1] * height / 2`.
+    pub fn set_filter_scale(&mut self, particle_radius: f32, pixels_per_unit: f32) {
+        self.particle_radius = particle_radius;
+        self.pixels_per_unit = pixels_per_unit;
+    }
+}
+
+impl ComputeStep for DepthSmoothPipeline {
+    fn load_shader_module(device: Arc<Device>) -> EntryPoint {
+        load_shader_entry_point(device, smooth_cs::load, "main")
+    }
+    fn from_pipeline(pipeline: Arc<ComputePipeline>) -> Self {
+        Self {
+            pipeline,
+            descriptor_sets: None,
+            dispatch_count: [0; 3],
+            particle_radius: 0.0,
+            pixels_per_unit: 0.0,
+        }
+    }
+    fn prepare(
+        &mut self,
+        _allocator: Arc<StandardDescriptorSetAllocator>,
+        _physics_data: &GpuPhysicsData,
+        _sim_params: &Subbuffer<SimulationParams>,
+    ) {
+        // Use prepare_with_images() instead, as the screen-space targets are required.
+    }
+    fn execute<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>) {
+        let sets = self.descriptor_sets.as_ref().expect("DepthSmoothPipeline: call prepare_with_images() before execute()");
+        builder.bind_pipeline_compute(self.pipeline.clone()).unwrap();
+        for (set, direction) in sets.iter().zip([[1, 0], [0, 1]]) {
+            let push = SmoothPushConstants {
+                direction,
+                particle_radius: self.particle_radius,
+                pixels_per_unit: self.pixels_per_unit,
+            };
+            builder
+                .bind_descriptor_sets(PipelineBindPoint::Compute, self.pipeline.layout().clone(), 0, set.clone())
+                .unwrap()
+                .push_constants(self.pipeline.layout().clone(), 0, push)
+                .unwrap();
+            unsafe { builder.dispatch(self.dispatch_count).unwrap(); }
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use std::sync::Arc;
 use vulkano::buffer::Subbuffer;
-use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DispatchIndirectCommand};
 use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
 use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
 use vulkano::device::Device;
@@ -12,13 +12,13 @@ use crate::utils::shader_loader::load_shader_entry_point;
 
 mod cs {
     use vulkano_shaders::shader;
-    shader! { ty: "compute", path: "shaders\\compute\\stats.comp" }
+    shader! { ty: "compute", path: "shaders/compute/stats.comp" }
 }
 
 pub struct StatsPipeline {
     pub pipeline: Arc<ComputePipeline>,
     descriptor_set: Option<Arc<DescriptorSet>>,
-    dispatch_count: u32,
+    dispatch: Option<Subbuffer<[DispatchIndirectCommand]>>,
     stats_buffer: Option<Subbuffer<[u32]>>,
 }
 
@@ -27,7 +27,7 @@ impl ComputeStep for StatsPipeline {
         load_shader_entry_point(device, cs::load, "main")
     }
     fn from_pipeline(pipeline: Arc<ComputePipeline>) -> Self {
-        Self { pipeline, descriptor_set: None, dispatch_count: 0, stats_buffer: None }
+        Self { pipeline, descriptor_set: None, dispatch: None, stats_buffer: None }
     }
     fn prepare(
         &mut self,
@@ -35,8 +35,7 @@ impl ComputeStep for StatsPipeline {
         physics_data: &GpuPhysicsData,
         sim_params: &Subbuffer<SimulationParams>,
     ) {
-        let num_particles = physics_data.count;
-        self.dispatch_count = (num_particles + 255) / 256;
+        self.dispatch = Some(physics_data.dispatch_command());
         self.stats_buffer = Some(physics_data.stats_buffer.clone());
 
         let layout = self.pipeline.layout().set_layouts().get(0).unwrap();
@@ -49,6 +48,8 @@ impl ComputeStep for StatsPipeline {
                 WriteDescriptorSet::buffer(2, sim_params.clone()),
                 WriteDescriptorSet::buffer(3, physics_data.densities.clone()),
                 WriteDescriptorSet::buffer(4, physics_data.source_terms.clone()),
+                WriteDescriptorSet::buffer(5, physics_data.position_b.clone()),
+                WriteDescriptorSet::buffer(12, physics_data.particle_counter.clone()),
             ],
             []
         ).unwrap());
@@ -62,6 +63,6 @@ impl ComputeStep for StatsPipeline {
             .bind_pipeline_compute(self.pipeline.clone()).unwrap()
             .bind_descriptor_sets(PipelineBindPoint::Compute, self.pipeline.layout().clone(), 0, set.clone())
             .unwrap();
-        unsafe { builder.dispatch([self.dispatch_count, 1, 1]).unwrap(); }
+        unsafe { builder.dispatch_indirect(self.dispatch.clone().unwrap()).unwrap(); }
     }
 }
@@ -0,0 +1,89 @@
+use std::sync::Arc;
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::pipeline::{DynamicState, GraphicsPipeline, PipelineLayout, PipelineShaderStageCreateInfo};
+use vulkano::pipeline::graphics::color_blend::{ColorBlendAttachmentState, ColorBlendState};
+use vulkano::pipeline::graphics::depth_stencil::{DepthState, DepthStencilState};
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::{CullMode, RasterizationState};
+use vulkano::pipeline::graphics::subpass::{PipelineRenderingCreateInfo, PipelineSubpassType};
+use vulkano::pipeline::graphics::vertex_input::{Vertex, VertexDefinition};
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use crate::entities::surface::SurfaceVertex;
+use crate::utils::shader_loader::load_shader_entry_point;
+
+mod vs {
+    use vulkano_shaders::shader;
+
+    shader! {
+        ty: "vertex",
+        path: "shaders/surface.vert"
+    }
+}
+
+mod fs {
+    use vulkano_shaders::shader;
+
+    shader! {
+        ty: "fragment",
+        path: "shaders/surface.frag"
+    }
+}
+
+/// Draws the marching-cubes mesh with the raymarcher's water shading. Shares
+/// the water pipeline's layout so `WaterRenderer`'s descriptor set (density,
+/// sky, params) binds to both.
+pub struct SurfacePipeline {
+    pub inner: Arc<GraphicsPipeline>,
+}
+
+impl SurfacePipeline {
+    pub fn new(
+        device: Arc<Device>,
+        layout: Arc<PipelineLayout>,
+        color_format: Format,
+        depth_format: Format,
+    ) -> Self {
+        let vs = load_shader_entry_point(device.clone(), vs::load, "surface vertex");
+        let fs = load_shader_entry_point(device.clone(), fs::load, "surface fragment");
+
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vs.clone()),
+            PipelineShaderStageCreateInfo::new(fs.clone())
+        ];
+
+        let pipeline = GraphicsPipeline::new(
+            device.clone(),
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(SurfaceVertex::per_vertex().definition(&vs).unwrap()),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                dynamic_state: [DynamicState::Viewport, DynamicState::Scissor].into_iter().collect(),
+                rasterization_state: Some(RasterizationState {
+                    cull_mode: CullMode::None,
+                    ..RasterizationState::default()
+                }),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState::simple()),
+                    ..Default::default()
+                }),
+                multisample_state: Some(MultisampleState::default()),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    1, ColorBlendAttachmentState::default()
+                )),
+                subpass: Some(PipelineSubpassType::BeginRendering(PipelineRenderingCreateInfo {
+                    color_attachment_formats: vec![Some(color_format)],
+                    depth_attachment_format: Some(depth_format),
+                    ..PipelineRenderingCreateInfo::default()
+                })),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            }
+        ).map_err(|e| panic!("[Surface Pipeline] Failed to create graphics pipeline:\n{:?}", e)).unwrap();
+
+        Self { inner: pipeline }
+    }
+}
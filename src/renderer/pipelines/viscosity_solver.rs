@@ -0,0 +1,150 @@
+use std::sync::Arc;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, DispatchIndirectCommand};
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::{DescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::pipeline::{ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout, PipelineShaderStageCreateInfo};
+use vulkano::pipeline::compute::ComputePipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::shader::EntryPoint;
+use crate::entities::particle::{GpuPhysicsData, SimulationParams};
+use crate::renderer::pipelines::ComputeStep;
+use crate::utils::shader_loader::load_shader_entry_point;
+
+mod cs_cg {
+    use vulkano_shaders::shader;
+    shader!(ty: "compute", path: "shaders/compute/viscosity_cg.comp");
+}
+mod cs_reduce {
+    use vulkano_shaders::shader;
+    shader!(ty: "compute", path: "shaders/compute/viscosity_cg_reduce.comp");
+}
+
+// CG_* and REDUCE_* in viscosity_solver.glsl.
+const CG_INIT: u32 = 0;
+const CG_APPLY: u32 = 1;
+const CG_UPDATE: u32 = 2;
+const CG_DIRECTION: u32 = 3;
+const REDUCE_INIT: u32 = 0;
+const REDUCE_ALPHA: u32 = 1;
+const REDUCE_BETA: u32 = 2;
+
+/// Implicit viscosity (Weiler et al. 2018) as a Jacobi-preconditioned
+/// conjugate gradient that solves for `velocity_a` in place, after
+/// `ViscosityPipeline` left the unviscous velocities there. `execute` sets
+/// the solve up; every `execute_iteration` is one CG iteration, which does
+/// nothing once the solve has converged.
+pub struct ViscositySolverPipeline {
+    cg_pipeline: Arc<ComputePipeline>,
+    reduce_pipeline: Arc<ComputePipeline>,
+    cg_set: Option<Arc<DescriptorSet>>,
+    reduce_set: Option<Arc<DescriptorSet>>,
+    dispatch: Option<Subbuffer<[DispatchIndirectCommand]>>,
+}
+
+impl ViscositySolverPipeline {
+    /// One CG iteration: p → Ap, α, x and r, β, then the next p.
+    pub fn execute_iteration<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>) {
+        self.dispatch_cg(builder, CG_APPLY);
+        self.dispatch_reduce(builder, REDUCE_ALPHA);
+        self.dispatch_cg(builder, CG_UPDATE);
+        self.dispatch_reduce(builder, REDUCE_BETA);
+        self.dispatch_cg(builder, CG_DIRECTION);
+    }
+
+    fn dispatch_cg<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>, stage: u32) {
+        let set = self.cg_set.as_ref().expect("ViscositySolverPipeline: call prepare() before execute()");
+        builder
+            .bind_pipeline_compute(self.cg_pipeline.clone()).unwrap()
+            .bind_descriptor_sets(PipelineBindPoint::Compute, self.cg_pipeline.layout().clone(), 0, set.clone()).unwrap()
+            .push_constants(self.cg_pipeline.layout().clone(), 0, cs_cg::PushConstants { stage }).unwrap();
+        unsafe { builder.dispatch_indirect(self.dispatch.clone().unwrap()).unwrap(); }
+    }
+
+    fn dispatch_reduce<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>, stage: u32) {
+        let set = self.reduce_set.as_ref().expect("ViscositySolverPipeline: call prepare() before execute()");
+        builder
+            .bind_pipeline_compute(self.reduce_pipeline.clone()).unwrap()
+            .bind_descriptor_sets(PipelineBindPoint::Compute, self.reduce_pipeline.layout().clone(), 0, set.clone()).unwrap()
+            .push_constants(self.reduce_pipeline.layout().clone(), 0, cs_reduce::PushConstants { stage }).unwrap();
+        unsafe { builder.dispatch([1, 1, 1]).unwrap(); }
+    }
+}
+
+impl ComputeStep for ViscositySolverPipeline {
+    fn load_shader_module(_device: Arc<Device>) -> EntryPoint {
+        unimplemented!("ViscositySolverPipeline uses multiple shaders")
+    }
+    fn from_pipeline(_pipeline: Arc<ComputePipeline>) -> Self {
+        unimplemented!("ViscositySolverPipeline uses multiple pipelines")
+    }
+    fn new(device: Arc<Device>) -> Self {
+        let pipeline = |entry_point| {
+            let stage = PipelineShaderStageCreateInfo::new(entry_point);
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages([&stage])
+                    .into_pipeline_layout_create_info(device.clone()).unwrap()
+            ).unwrap();
+            ComputePipeline::new(device.clone(), None, ComputePipelineCreateInfo::stage_layout(stage, layout)).unwrap()
+        };
+
+        Self {
+            cg_pipeline: pipeline(load_shader_entry_point(device.clone(), cs_cg::load, "main")),
+            reduce_pipeline: pipeline(load_shader_entry_point(device.clone(), cs_reduce::load, "main")),
+            cg_set: None,
+            reduce_set: None,
+            dispatch: None,
+        }
+    }
+    fn prepare(
+        &mut self,
+        allocator: Arc<StandardDescriptorSetAllocator>,
+        physics_data: &GpuPhysicsData,
+        sim_params: &Subbuffer<SimulationParams>,
+    ) {
+        self.dispatch = Some(physics_data.dispatch_command());
+
+        let layout = self.cg_pipeline.layout().set_layouts().get(0).unwrap();
+        self.cg_set = Some(DescriptorSet::new(
+            allocator.clone(),
+            layout.clone(),
+            [
+                WriteDescriptorSet::buffer(0, physics_data.grid_entries.clone()),
+                WriteDescriptorSet::buffer(1, physics_data.grid_start.clone()),
+                WriteDescriptorSet::buffer(2, sim_params.clone()),
+                WriteDescriptorSet::buffer(3, physics_data.velocity_a.clone()),
+                WriteDescriptorSet::buffer(4, physics_data.position_b.clone()),
+                WriteDescriptorSet::buffer(5, physics_data.densities.clone()),
+                WriteDescriptorSet::buffer(6, physics_data.viscosity_residuals.clone()),
+                WriteDescriptorSet::buffer(7, physics_data.viscosity_directions.clone()),
+                WriteDescriptorSet::buffer(8, physics_data.viscosity_products.clone()),
+                WriteDescriptorSet::buffer(9, physics_data.viscosity_diagonals.clone()),
+                WriteDescriptorSet::buffer(10, physics_data.viscosity_partials.clone()),
+                WriteDescriptorSet::buffer(11, physics_data.viscosity_solver.clone()),
+                WriteDescriptorSet::buffer(12, physics_data.particle_counter.clone()),
+            ],
+            []
+        ).unwrap());
+
+        let layout = self.reduce_pipeline.layout().set_layouts().get(0).unwrap();
+        self.reduce_set = Some(DescriptorSet::new(
+            allocator,
+            layout.clone(),
+            [
+                WriteDescriptorSet::buffer(2, sim_params.clone()),
+                WriteDescriptorSet::buffer(10, physics_data.viscosity_partials.clone()),
+                WriteDescriptorSet::buffer(11, physics_data.viscosity_solver.clone()),
+                WriteDescriptorSet::buffer(12, physics_data.particle_counter.clone()),
+            ],
+            []
+        ).unwrap());
+    }
+    /// Sets up the solve: preconditioner, initial residual and direction,
+    /// and the scalars, including whether there is anything left to solve.
+    fn execute<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>) {
+        self.dispatch_cg(builder, CG_INIT);
+        self.dispatch_reduce(builder, REDUCE_INIT);
+    }
+}
@@ -21,7 +21,7 @@ mod vs {
 
     shader! {
         ty: "vertex",
-        path: "shaders\\raymarch.vert"
+        path: "shaders/raymarch.vert"
     }
 }
 
@@ -30,7 +30,7 @@ mod fs {
 
     shader! {
         ty: "fragment",
-        path: "shaders\\raymarch.frag"
+        path: "shaders/raymarch.frag"
     }
 }
 
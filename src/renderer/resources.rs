@@ -1,57 +1,42 @@
 use std::sync::Arc;
-use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer};
-use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, CopyBufferInfo};
 use vulkano::format::Format;
 use vulkano::image::{Image, ImageCreateInfo, ImageType, ImageUsage};
 use vulkano::image::view::ImageView;
 use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
+use crate::core::export::PendingSurfaceMesh;
 use crate::core::scene::Scene;
 use crate::entities::camera::CameraData;
 use crate::entities::collision::CollisionBoxData;
-use crate::entities::particle::{GpuPhysicsData, GpuRenderData, SimulationParams};
+use crate::entities::obstacle::ObstacleData;
+use crate::entities::particle::GpuRenderData;
+use crate::entities::surface::GpuSurfaceMesh;
 use crate::renderer::pipelines::Pipelines;
-use crate::utils::constants::MAX_FRAMES_IN_FLIGHT;
+use crate::utils::constants::{MAX_FRAMES_IN_FLIGHT, SURFACE_MESH_MAX_VERTICES};
 
 pub struct GpuSceneResources {
     camera_data: CameraData,
     collision_box_data: CollisionBoxData,
-    pub physics_data: GpuPhysicsData,
+    obstacle_data: ObstacleData,
     pub render_data: GpuRenderData,
 
-    pub sim_params_buffer: Subbuffer<SimulationParams>,
-
     pub density_texture: Arc<Image>,
     pub density_view: Arc<ImageView>,
+    pub surface_mesh: GpuSurfaceMesh,
 
     pub current_frame_idx: usize,
 }
 
 impl GpuSceneResources {
     pub fn new(allocator: Arc<StandardMemoryAllocator>, scene: &Scene) -> Self {
-        let physics_data = GpuPhysicsData::new(
-            allocator.clone(),
-            scene.initial_positions.clone()
-        );
-
         let render_data = GpuRenderData::new(
             allocator.clone(),
             &scene.initial_positions,
+            scene.particle_capacity.max(1),
             scene.sim_params.particle_radius
         );
 
-        let sim_params_buffer = Buffer::from_data(
-            allocator.clone(),
-            BufferCreateInfo {
-                usage: BufferUsage::UNIFORM_BUFFER,
-                ..Default::default()
-            },
-            AllocationCreateInfo {
-                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-                ..Default::default()
-            },
-            scene.sim_params,
-        ).expect("Failed to create simulation params buffer");
-        
         let grid_res = scene.sim_params.grid_res;
         
         let density_texture = Image::new(
@@ -60,43 +45,75 @@ impl GpuSceneResources {
                 image_type: ImageType::Dim3d,
                 format: Format::R32_UINT,
                 extent: [grid_res[0] as u32, grid_res[1] as u32, grid_res[2] as u32],
-                usage: ImageUsage::STORAGE | ImageUsage::SAMPLED | ImageUsage::TRANSFER_DST,
+                usage: ImageUsage::STORAGE | ImageUsage::SAMPLED | ImageUsage::TRANSFER_SRC | ImageUsage::TRANSFER_DST,
                 ..Default::default()
             },
             AllocationCreateInfo::default()
         ).unwrap();
         
         let density_view = ImageView::new_default(density_texture.clone()).unwrap();
+        let surface_mesh = GpuSurfaceMesh::new(allocator.clone(), SURFACE_MESH_MAX_VERTICES);
 
         Self {
             camera_data: CameraData::new(allocator.clone()),
             collision_box_data: CollisionBoxData::new(allocator.clone()),
-            physics_data,
+            obstacle_data: ObstacleData::new(allocator.clone()),
             render_data,
-            sim_params_buffer,
             current_frame_idx: 0,
             density_texture,
             density_view,
+            surface_mesh,
         }
     }
     pub fn camera_addr(&self) -> u64 {
         self.camera_data.uniform_buffer_addr(self.current_frame_idx)
     }
-    pub fn sync_with_scene(&self, scene: &Scene) {
+    pub fn sync_with_scene(&mut self, scene: &Scene) {
         self.camera_data.write_to_buffer(&scene.camera, self.current_frame_idx);
         self.collision_box_data.write_to_buffer(&scene.boundary, self.current_frame_idx);
-
-        if let Ok(mut params) = self.sim_params_buffer.write() {
-            *params = scene.sim_params;
-        }
+        self.obstacle_data.write_to_buffer(&scene.solid_obstacles(), self.current_frame_idx);
     }
 
     pub fn bind_to_command_buffer<Cb>(&self, builder: &mut AutoCommandBufferBuilder<Cb>, pipelines: &Pipelines) {
         self.collision_box_data.bind_to_command_buffer(builder, pipelines, self.camera_addr(), self.current_frame_idx);
-        //self.render_data.bind_to_command_buffer(builder, pipelines, self.camera_addr(), self.current_frame_idx, self.physics_data.count);
+        self.obstacle_data.bind_to_command_buffer(builder, pipelines, self.camera_addr(), self.current_frame_idx);
+        //self.render_data.bind_to_command_buffer(builder, pipelines, self.camera_addr(), self.current_frame_idx);
+    }
+
+    /// Records a copy of the marching-cubes output into host-visible buffers,
+    /// for surface export. Runs after `MarchingCubesPipeline`; the whole
+    /// vertex buffer is copied since its fill is only known on the GPU.
+    pub fn record_surface_readback<Cb>(
+        &self,
+        allocator: Arc<StandardMemoryAllocator>,
+        builder: &mut AutoCommandBufferBuilder<Cb>,
+    ) -> PendingSurfaceMesh {
+        let mesh = &self.surface_mesh;
+        let pending = PendingSurfaceMesh {
+            draw_command: readback_buffer(allocator.clone(), mesh.draw_command.len()),
+            vertices: readback_buffer(allocator, mesh.vertices.len()),
+        };
+        builder.copy_buffer(CopyBufferInfo::buffers(mesh.draw_command.clone(), pending.draw_command.clone())).unwrap();
+        builder.copy_buffer(CopyBufferInfo::buffers(mesh.vertices.clone(), pending.vertices.clone())).unwrap();
+        pending
     }
 
     pub fn prepare_next_frame(&mut self) {
         self.current_frame_idx = (self.current_frame_idx + 1) % MAX_FRAMES_IN_FLIGHT;
     }
-}
\ No newline at end of file
+}
+
+fn readback_buffer<T: BufferContents>(allocator: Arc<StandardMemoryAllocator>, len: u64) -> Subbuffer<[T]> {
+    Buffer::new_slice::<T>(
+        allocator,
+        BufferCreateInfo {
+            usage: BufferUsage::TRANSFER_DST,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_HOST | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+            ..Default::default()
+        },
+        len,
+    ).expect("Failed to create surface readback buffer")
+}
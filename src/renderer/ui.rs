@@ -1,12 +1,23 @@
-use egui::{Context, Slider, Window};
-use glam::Vec4;
+use egui::{ComboBox, Context, DragValue, Slider, Ui, Window};
+use glam::{EulerRot, Quat, Vec3};
+use crate::core::export::{ExportFormat, ExportSettings, SurfaceFormat};
 use crate::core::scene::Scene;
+use crate::entities::obstacle::{Obstacle, ObstacleShape};
+use crate::entities::particle::{GridTransfer, PressureSolver, SimulationBackend, SphKernel, ViscosityMode};
+use crate::entities::rigid_body::RigidBody;
 use crate::renderer::pipelines::SortAlgorithm;
 
+/// ε applied when vorticity confinement is switched on with no strength set.
+const DEFAULT_VORTICITY_CONFINEMENT: f32 = 0.5;
+
 #[derive(PartialEq, Clone, Copy)]
 pub enum RenderMode {
     Raymarching,
     Particles,
+    /// Marching-cubes mesh of the density texture.
+    Surface,
+    /// Particle spheres splatted to screen-space depth and smoothed.
+    ScreenSpace,
 }
 
 pub struct AppUI {
@@ -14,6 +25,8 @@ pub struct AppUI {
     pub render_mode: RenderMode,
     pub sort_algorithm: SortAlgorithm,
     pub use_cfl: bool,
+    /// Gates the vorticity passes without losing the tuned ε.
+    pub use_vorticity_confinement: bool,
     pub display_max_speed: f32,
     pub display_cfl_dt: f32,
 
@@ -26,6 +39,16 @@ pub struct AppUI {
     pub display_avg_divergence_error: f32,
     pub display_density_iters_used: u32,
     pub display_divergence_iters_used: u32,
+    /// Of the last implicit viscosity solve.
+    pub display_viscosity_iters_used: u32,
+    pub display_viscosity_residual: f32,
+
+    pub export_enabled: bool,
+    pub export_settings: ExportSettings,
+    pub display_exported_frames: u32,
+
+    /// Live particles as of the last stats readback.
+    pub display_particle_count: u32,
 }
 
 impl AppUI {
@@ -35,6 +58,7 @@ impl AppUI {
             render_mode: RenderMode::Raymarching,
             sort_algorithm: SortAlgorithm::Radix,
             use_cfl: false,
+            use_vorticity_confinement: false,
             display_max_speed: 0.0,
             display_cfl_dt: 0.0,
 
@@ -45,6 +69,14 @@ impl AppUI {
             display_avg_divergence_error: 0.0,
             display_density_iters_used: 0,
             display_divergence_iters_used: 0,
+            display_viscosity_iters_used: 0,
+            display_viscosity_residual: 0.0,
+
+            export_enabled: false,
+            export_settings: ExportSettings::default(),
+            display_exported_frames: 0,
+
+            display_particle_count: 0,
         }
     }
     pub fn render(&mut self, ctx: &Context, scene: &mut Scene, fps: u32) {
@@ -58,12 +90,92 @@ impl AppUI {
             .collapsible(true)
             .show(ctx, |ui| {
                 ui.heading("Simulation Parameters");
-                ui.add(Slider::new(&mut scene.sim_params.viscosity, 0.0..=0.5).text("Viscosity"));
+                let mut viscosity_mode = scene.sim_params.viscosity_mode();
+                ComboBox::from_label("Viscosity Mode")
+                    .selected_text(viscosity_mode.label())
+                    .show_ui(ui, |ui| {
+                        for option in ViscosityMode::ALL {
+                            ui.selectable_value(&mut viscosity_mode, option, option.label());
+                        }
+                    });
+                scene.sim_params.set_viscosity_mode(viscosity_mode);
+                ui.add(viscosity_slider(&mut scene.sim_params.viscosity, viscosity_mode));
+                if viscosity_mode == ViscosityMode::Implicit {
+                    ui.add(Slider::new(&mut scene.sim_params.viscosity_iterations, 1..=500).text("Viscosity Max Iters"));
+                    ui.add(Slider::new(&mut scene.sim_params.viscosity_tolerance, 1e-6..=1e-1)
+                        .logarithmic(true)
+                        .text("Viscosity Tolerance"));
+                }
+                ui.add(Slider::new(&mut scene.sim_params.surface_tension, 0.0..=2.0).text("Surface Tension (γ)"));
+                ui.add(Slider::new(&mut scene.sim_params.adhesion, 0.0..=20.0).text("Wall Adhesion (β)"));
+                if ui.checkbox(&mut self.use_vorticity_confinement, "Vorticity Confinement").changed()
+                    && self.use_vorticity_confinement
+                    && scene.sim_params.vorticity_confinement == 0.0
+                {
+                    scene.sim_params.vorticity_confinement = DEFAULT_VORTICITY_CONFINEMENT;
+                }
+                if self.use_vorticity_confinement {
+                    ui.add(Slider::new(&mut scene.sim_params.vorticity_confinement, 0.0..=2.0).text("Confinement (ε)"));
+                }
                 ui.add(Slider::new(&mut scene.sim_params.target_density, 500.0..=2000.0).text("Target Density"));
                 ui.add(Slider::new(&mut scene.sim_params.relax_factor, 0.0..=1.0).text("Relaxation Factor"));
                 ui.add(Slider::new(&mut scene.sim_params.smoothing_radius, 0.0001..=0.2).text("Smoothing Radius"));
-                ui.add(Slider::new(&mut scene.sim_params.density_solver_iterations, 1..=100).text("Density Max Iters"));
-                ui.add(Slider::new(&mut scene.sim_params.divergence_solver_iterations, 1..=100).text("Divergence Max Iters"));
+                let mut kernel = scene.sim_params.kernel();
+                ComboBox::from_label("Kernel")
+                    .selected_text(kernel.label())
+                    .show_ui(ui, |ui| {
+                        for option in SphKernel::ALL {
+                            ui.selectable_value(&mut kernel, option, option.label());
+                        }
+                    });
+                scene.sim_params.set_kernel(kernel);
+                let mut backend = scene.sim_params.backend();
+                ComboBox::from_label("Backend")
+                    .selected_text(backend.label())
+                    .show_ui(ui, |ui| {
+                        for option in SimulationBackend::ALL {
+                            ui.selectable_value(&mut backend, option, option.label());
+                        }
+                    });
+                scene.sim_params.set_backend(backend);
+                let mut pressure_solver = scene.sim_params.pressure_solver();
+                if backend == SimulationBackend::Flip {
+                    let mut transfer = scene.sim_params.grid_transfer();
+                    ComboBox::from_label("Transfer")
+                        .selected_text(transfer.label())
+                        .show_ui(ui, |ui| {
+                            for option in GridTransfer::ALL {
+                                ui.selectable_value(&mut transfer, option, option.label());
+                            }
+                        });
+                    scene.sim_params.set_grid_transfer(transfer);
+                    if transfer == GridTransfer::Flip {
+                        ui.add(Slider::new(&mut scene.sim_params.flip_ratio, 0.0..=1.0).text("FLIP Ratio"));
+                    }
+                    ui.add(Slider::new(&mut scene.sim_params.grid_iterations, 1..=200).text("Grid Iterations"));
+                } else {
+                    ComboBox::from_label("Pressure Solver")
+                        .selected_text(pressure_solver.label())
+                        .show_ui(ui, |ui| {
+                            for option in PressureSolver::ALL {
+                                ui.selectable_value(&mut pressure_solver, option, option.label());
+                            }
+                        });
+                    scene.sim_params.set_pressure_solver(pressure_solver);
+                    if pressure_solver == PressureSolver::Wcsph {
+                        ui.add(Slider::new(&mut scene.sim_params.stiffness, 100.0..=100000.0)
+                            .logarithmic(true)
+                            .suffix(" Pa")
+                            .text("Stiffness (B)"));
+                    }
+                    if pressure_solver.is_iterative() {
+                        ui.add(Slider::new(&mut scene.sim_params.density_solver_iterations, 1..=100).text("Density Max Iters"));
+                    }
+                    if pressure_solver == PressureSolver::Dfsph {
+                        ui.add(Slider::new(&mut scene.sim_params.divergence_solver_iterations, 1..=100).text("Divergence Max Iters"));
+                    }
+                }
+                let sph = backend == SimulationBackend::Sph;
                 ui.add(Slider::new(&mut scene.sim_params.dt, 0.0001..=0.1).text("Time Step (dt)"));
 
                 ui.horizontal(|ui| {
@@ -72,9 +184,33 @@ impl AppUI {
                 ui.label(format!("Max speed:  {:.3} m/s", self.display_max_speed));
                 ui.label(format!("CFL limit:  {:.4} s", self.display_cfl_dt));
 
+                if scene.sim_params.phase_count() > 1 {
+                    ui.separator();
+
+                    ui.heading("Phases");
+                    ui.horizontal(|ui| {
+                        ui.label("Base fluid");
+                        phase_color_button(ui, &mut scene.sim_params.phases[0].color);
+                    });
+                    for (k, name) in scene.phase_names.iter().enumerate() {
+                        let phase = &mut scene.sim_params.phases[k + 1];
+                        ui.push_id(k, |ui| {
+                            ui.collapsing(name, |ui| {
+                                ui.horizontal(|ui| {
+                                    ui.label("Colour");
+                                    phase_color_button(ui, &mut phase.color);
+                                });
+                                ui.add(Slider::new(&mut phase.rest_density, 100.0..=3000.0).text("Rest Density"));
+                                ui.add(viscosity_slider(&mut phase.viscosity, viscosity_mode));
+                                ui.add(Slider::new(&mut phase.surface_tension, 0.0..=2.0).text("Surface Tension (γ)"));
+                            });
+                        });
+                    }
+                }
+
                 ui.separator();
 
-                ui.heading("Solver Convergence (DFSPH)");
+                ui.heading(format!("Solver Convergence ({})", if sph { pressure_solver.label() } else { backend.label() }));
                 ui.horizontal(|ui| {
                     ui.checkbox(&mut self.use_solver_error_threshold, "Early-exit by error");
                 });
@@ -89,10 +225,17 @@ impl AppUI {
                         .text("ηv (divergence)"));
                     ui.label(format!("  = {:.3} kg/m³/s", self.divergence_error_pct / 100.0 * rho0));
                 }
-                ui.label(format!("Avg Δρ:      {:.4} kg/m³  ({} iters)",
-                    self.display_avg_density_error, self.display_density_iters_used));
-                ui.label(format!("Avg Dρ/Dt:   {:.4} kg/m³/s ({} iters)",
-                    self.display_avg_divergence_error, self.display_divergence_iters_used));
+                // Comparable across solvers: both are measured after the
+                // position update, whatever solved for the pressure.
+                let iters = |used: u32, applies: bool| if applies { format!("({used} iters)") } else { String::new() };
+                ui.label(format!("Avg Δρ:      {:.4} kg/m³  {}",
+                    self.display_avg_density_error, iters(self.display_density_iters_used, sph && pressure_solver.is_iterative())));
+                ui.label(format!("Avg Dρ/Dt:   {:.4} kg/m³/s {}",
+                    self.display_avg_divergence_error, iters(self.display_divergence_iters_used, sph && pressure_solver == PressureSolver::Dfsph)));
+                if viscosity_mode == ViscosityMode::Implicit {
+                    ui.label(format!("Viscosity:   {:.2e} residual  ({} iters)",
+                        self.display_viscosity_residual, self.display_viscosity_iters_used));
+                }
 
                 ui.separator();
 
@@ -101,6 +244,20 @@ impl AppUI {
 
                 ui.separator();
 
+                ui.heading("Spray, Foam & Bubbles");
+                ui.checkbox(&mut scene.diffuse.enabled, "Diffuse particles");
+                if scene.diffuse.enabled {
+                    let params = &mut scene.diffuse.params;
+                    ui.add(Slider::new(&mut params.trapped_air_rate, 0.0..=200.0).text("Trapped Air Rate"));
+                    ui.add(Slider::new(&mut params.wave_crest_rate, 0.0..=200.0).text("Wave Crest Rate"));
+                    ui.add(Slider::new(&mut params.lifetime, 0.1..=10.0).suffix(" s").text("Foam Lifetime"));
+                    ui.add(Slider::new(&mut params.buoyancy, 0.0..=10.0).text("Bubble Buoyancy"));
+                    ui.add(Slider::new(&mut params.drag, 0.0..=1.0).text("Bubble Drag"));
+                    ui.label(format!("Capacity:   {}", scene.diffuse.capacity));
+                }
+
+                ui.separator();
+
                 ui.heading("Collision Boundary");
                 ui.label("Control the simulation box size:");
 
@@ -122,8 +279,51 @@ impl AppUI {
                     });
                 });
 
-                scene.sim_params.box_min = Vec4::new(scene.boundary.min.x, scene.boundary.min.y, scene.boundary.min.z, 0.0).into();
-                scene.sim_params.box_max = Vec4::new(scene.boundary.max.x, scene.boundary.max.y, scene.boundary.max.z, 0.0).into();
+                ui.separator();
+
+                ui.heading("Obstacles");
+                let mut removed = None;
+                for (i, obstacle) in scene.obstacles.iter_mut().enumerate() {
+                    ui.push_id(i, |ui| {
+                        ui.collapsing(format!("{} {}", obstacle.shape.name(), i), |ui| {
+                            obstacle_controls(ui, obstacle);
+                            if ui.button("Remove").clicked() {
+                                removed = Some(i);
+                            }
+                        });
+                    });
+                }
+                if let Some(i) = removed {
+                    scene.obstacles.remove(i);
+                }
+                ui.horizontal(|ui| {
+                    let center = (scene.boundary.min + scene.boundary.max) / 2.0;
+                    let shape = if ui.button("Add sphere").clicked() {
+                        Some(ObstacleShape::Sphere { radius: 0.2 })
+                    } else if ui.button("Add capsule").clicked() {
+                        Some(ObstacleShape::Capsule { radius: 0.1, half_height: 0.3 })
+                    } else if ui.button("Add box").clicked() {
+                        Some(ObstacleShape::Box { half_extents: Vec3::splat(0.2) })
+                    } else {
+                        None
+                    };
+                    if let Some(shape) = shape {
+                        scene.obstacles.push(Obstacle::new(shape, center, Quat::IDENTITY));
+                    }
+                });
+
+                if !scene.rigid_bodies.is_empty() {
+                    ui.separator();
+
+                    ui.heading("Rigid Bodies");
+                    for (i, body) in scene.rigid_bodies.iter_mut().enumerate() {
+                        ui.push_id(i, |ui| {
+                            ui.collapsing(format!("{} {}", body.shape.name(), i), |ui| {
+                                rigid_body_controls(ui, body);
+                            });
+                        });
+                    }
+                }
 
                 ui.separator();
 
@@ -131,6 +331,8 @@ impl AppUI {
                 ui.horizontal(|ui| {
                     ui.selectable_value(&mut self.render_mode, RenderMode::Raymarching, "Raymarching");
                     ui.selectable_value(&mut self.render_mode, RenderMode::Particles, "Particles");
+                    ui.selectable_value(&mut self.render_mode, RenderMode::Surface, "Surface mesh");
+                    ui.selectable_value(&mut self.render_mode, RenderMode::ScreenSpace, "Screen-space");
                 });
 
                 ui.separator();
@@ -153,15 +355,151 @@ impl AppUI {
                 });
 
                 ui.separator();
-                ui.label(format!("Active Particles: {}", scene.initial_positions.len()));
+                ui.heading("Particle Export");
+                ui.checkbox(&mut self.export_enabled, "Export frames");
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.export_settings.particles, "Particles");
+                    let format = &mut self.export_settings.format;
+                    ui.selectable_value(format, ExportFormat::Vtu, "VTU");
+                    ui.selectable_value(format, ExportFormat::Vtk, "VTK (legacy)");
+                    ui.selectable_value(format, ExportFormat::Ply, "PLY");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Surface:");
+                    let format = &mut self.export_settings.surface_format;
+                    ui.selectable_value(format, None, "Off");
+                    ui.selectable_value(format, Some(SurfaceFormat::Obj), "OBJ");
+                    ui.selectable_value(format, Some(SurfaceFormat::Ply), "PLY");
+                });
+                ui.add(Slider::new(&mut self.export_settings.every_n_frames, 1..=60).text("Every N frames"));
+                ui.horizontal(|ui| {
+                    ui.label("Directory:");
+                    let mut dir = self.export_settings.dir.to_string_lossy().into_owned();
+                    if ui.text_edit_singleline(&mut dir).changed() {
+                        self.export_settings.dir = dir.into();
+                    }
+                });
+                ui.label(format!("Exported frames: {}", self.display_exported_frames));
+
+                ui.separator();
+                ui.label(format!("Active Particles: {} / {}", self.display_particle_count, scene.particle_capacity));
 
                 ui.separator();
 
                 ui.label(format!("FPS: {}", fps));
 
-                if ui.button("Reset Simulation (Console Log)").clicked() {
-                    println!("Reset logic to be implemented!");
-                }
+                ui.separator();
+
+                ui.heading("Playback");
+                ui.horizontal(|ui| {
+                    let label = if scene.playback.paused { "Resume [P]" } else { "Pause [P]" };
+                    if ui.button(label).clicked() {
+                        scene.playback.toggle_pause();
+                    }
+                    if ui.button("Step [N]").clicked() {
+                        scene.playback.request_step();
+                    }
+                    if ui.button("Reset [R]").clicked() {
+                        scene.playback.request_reset();
+                    }
+                });
+                ui.add(Slider::new(&mut scene.playback.substeps_per_step, 1..=100).text("Substeps per step"));
+
+                ui.horizontal(|ui| {
+                    let mut path = scene.playback.checkpoint_path.to_string_lossy().into_owned();
+                    if ui.text_edit_singleline(&mut path).changed() {
+                        scene.playback.checkpoint_path = path.into();
+                    }
+                    if ui.button("Save [F5]").clicked() {
+                        scene.playback.request_save();
+                    }
+                    if ui.button("Load [F9]").clicked() {
+                        scene.playback.request_load();
+                    }
+                });
             });
     }
 }
+
+/// A blend factor in explicit mode, the kinematic viscosity in implicit mode.
+fn viscosity_slider(viscosity: &mut f32, mode: ViscosityMode) -> Slider<'_> {
+    match mode {
+        ViscosityMode::Explicit => Slider::new(viscosity, 0.0..=0.5).text("Viscosity"),
+        ViscosityMode::Implicit => Slider::new(viscosity, 0.0..=50.0)
+            .logarithmic(true)
+            .suffix(" m²/s")
+            .text("Viscosity (ν)"),
+    }
+}
+
+/// Edits the RGB of a phase-table colour; alpha is unused.
+fn phase_color_button(ui: &mut Ui, color: &mut [f32; 4]) {
+    let mut rgb = [color[0], color[1], color[2]];
+    if ui.color_edit_button_rgb(&mut rgb).changed() {
+        color[..3].copy_from_slice(&rgb);
+    }
+}
+
+/// State of one rigid body; it moves on its own, so only a push is offered.
+fn rigid_body_controls(ui: &mut Ui, body: &mut RigidBody) {
+    let p = body.position;
+    let v = body.velocity;
+    let f = body.fluid_force.force;
+    ui.label(format!("Mass: {:.3} kg", body.mass));
+    ui.label(format!("Position: ({:.2}, {:.2}, {:.2})", p.x, p.y, p.z));
+    ui.label(format!("Velocity: ({:.2}, {:.2}, {:.2}) m/s", v.x, v.y, v.z));
+    ui.label(format!("Fluid force: ({:.2}, {:.2}, {:.2}) N", f.x, f.y, f.z));
+    ui.horizontal(|ui| {
+        if ui.button("Push up").clicked() {
+            body.velocity.y += 2.0;
+        }
+        if ui.button("Stop").clicked() {
+            body.velocity = Vec3::ZERO;
+            body.angular_velocity = Vec3::ZERO;
+        }
+    });
+}
+
+/// Pose, size and spin of one obstacle. Angles are shown in degrees.
+fn obstacle_controls(ui: &mut Ui, obstacle: &mut Obstacle) {
+    let vec3_row = |ui: &mut Ui, label: &str, v: &mut Vec3, speed: f64| {
+        ui.horizontal(|ui| {
+            ui.label(label);
+            let x = ui.add(DragValue::new(&mut v.x).speed(speed)).changed();
+            let y = ui.add(DragValue::new(&mut v.y).speed(speed)).changed();
+            let z = ui.add(DragValue::new(&mut v.z).speed(speed)).changed();
+            x || y || z
+        }).inner
+    };
+
+    vec3_row(ui, "Position", &mut obstacle.position, 0.01);
+
+    let (x, y, z) = obstacle.rotation.to_euler(EulerRot::XYZ);
+    let mut rotation = Vec3::from_array([x, y, z].map(f32::to_degrees));
+    if vec3_row(ui, "Rotation [°]", &mut rotation, 1.0) {
+        let [x, y, z] = rotation.to_array().map(f32::to_radians);
+        obstacle.rotation = Quat::from_euler(EulerRot::XYZ, x, y, z);
+    }
+
+    let mut spin = Vec3::from_array(obstacle.angular_velocity.to_array().map(f32::to_degrees));
+    if vec3_row(ui, "Spin [°/s]", &mut spin, 1.0) {
+        obstacle.angular_velocity = Vec3::from_array(spin.to_array().map(f32::to_radians));
+    }
+
+    match &mut obstacle.shape {
+        ObstacleShape::Sphere { radius } => {
+            ui.add(Slider::new(radius, 0.01..=1.0).text("Radius"));
+        }
+        ObstacleShape::Capsule { radius, half_height } => {
+            ui.add(Slider::new(radius, 0.01..=1.0).text("Radius"));
+            ui.add(Slider::new(half_height, 0.0..=2.0).text("Half height"));
+        }
+        ObstacleShape::Box { half_extents } => {
+            vec3_row(ui, "Half extents", half_extents, 0.01);
+            *half_extents = half_extents.max(Vec3::splat(0.01));
+        }
+        ObstacleShape::Mesh(sdf) => {
+            ui.label(format!("Baked mesh, {} edges", sdf.edges.len()));
+        }
+    }
+}
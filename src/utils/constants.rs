@@ -2,4 +2,16 @@
 
 pub const WINDOW_TITLE: &str = "Fluid Simulation Engine";
 pub const MAX_FRAMES_IN_FLIGHT: usize = 3;
-pub const PREFERRED_FPS: u32 = 60;
\ No newline at end of file
+pub const PREFERRED_FPS: u32 = 60;
+pub const DEFAULT_SKY_HDRI: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/hdri/citrus_orchard_road_puresky_4k.exr");
+pub const DEFAULT_CHECKPOINT_PATH: &str = "checkpoint.fchk";
+/// Room left for emitted particles when a scene with emitters does not set
+/// `simulation.max_particles`.
+pub const DEFAULT_EMITTED_PARTICLES: u32 = 1 << 16;
+/// Ring-buffer slots for spray, foam and bubbles when a scene's `[diffuse]`
+/// section does not set `max_particles`.
+pub const DEFAULT_DIFFUSE_PARTICLES: u32 = 1 << 18;
+/// Capacity of the marching-cubes vertex buffer (32 bytes per vertex).
+pub const SURFACE_MESH_MAX_VERTICES: u64 = 1 << 20;
+/// Capacity of the obstacle wireframe vertex buffer (two vertices per line).
+pub const OBSTACLE_MAX_LINE_VERTICES: u64 = 1 << 16;